@@ -1,19 +1,22 @@
 use crate::*;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AsPathSegment {
     pub ordered: bool,  // ordered==true is AS_SEQUENCE, otherwise AS_SET
     pub asns: Vec<u32>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AsPath {
     pub aspath_segments: Vec<AsPathSegment>
 }
 
 impl Display for AsPath {
     fn fmt(&self, f: &mut Formatter<'_>) -> anyhow::Result<(), std::fmt::Error> {
-        for segment in &self.aspath_segments {
+        for (index, segment) in self.aspath_segments.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
             if ! segment.ordered {
                 write!(f, "{{")?;
             };
@@ -41,18 +44,28 @@ impl AsPath {
         }
     }
     pub fn parse<R: Read>(reader: &mut R) -> Result<AsPath> {
+        Self::parse_as(reader, true)
+    }
+
+    // An AS_PATH with 4-byte (as4) or 2-byte ASNs
+    pub fn parse_as<R: Read>(reader: &mut R, as4: bool) -> Result<AsPath> {
         let mut aspath_segments: Vec<AsPathSegment> = Vec::new();
         while let Ok(segment_type) = reader.read_u8() {
             if segment_type != 1 && segment_type != 2 {
                 return Err(anyhow!("AS_PATH attribute: segment ({}) not valid (should be AS_SET(1) or AS_SEQUENCE(2)", segment_type))
             }
             let length = reader.read_u8()?;
-            let mut asns: Vec<u32> = Vec::new();
+            let mut asns: Vec<u32> = Vec::with_capacity(length as usize);
             for _ in 0..length {
-                asns.push(reader.read_u32::<BigEndian>()?);
+                asns.push(if as4 {
+                    reader.read_u32::<BigEndian>()?
+                } else {
+                    reader.read_u16::<BigEndian>()? as u32
+                });
             }
             aspath_segments.push(AsPathSegment { ordered: segment_type==2, asns });
         }
+        aspath_segments.shrink_to_fit();
         Ok(AsPath { aspath_segments })
     }
 
@@ -65,5 +78,36 @@ impl AsPath {
         false
     }
 
+    // Path length for the purposes of comparison: each ASN in an
+    // AS_SEQUENCE counts one, each AS_SET counts one in total
+    pub fn path_length(&self) -> usize {
+        self.aspath_segments
+            .iter()
+            .map(|segment| if segment.ordered { segment.asns.len() } else { 1 })
+            .sum()
+    }
+
+    // The neighbouring AS: the first in the path, if it starts with a sequence
+    pub fn first_asn(&self) -> Option<u32> {
+        self.aspath_segments
+            .first()
+            .filter(|segment| segment.ordered)
+            .and_then(|segment| segment.asns.first().copied())
+    }
+
+    // The originating AS: the last in the path, if it ends with a sequence
+    pub fn last_asn(&self) -> Option<u32> {
+        self.aspath_segments
+            .last()
+            .filter(|segment| segment.ordered)
+            .and_then(|segment| segment.asns.last().copied())
+    }
+
+    pub fn contains_any(&self, asns: &HashSet<u32>) -> bool {
+        self.aspath_segments
+            .iter()
+            .any(|segment| segment.asns.iter().any(|x| asns.contains(x)))
+    }
+
 }
 
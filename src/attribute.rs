@@ -6,20 +6,33 @@ use byteorder::{BigEndian, ReadBytesExt};
 
 use crate::*;
 
-#[derive(Debug, PartialEq)]
+// The AS path and community lists are shared between paths by the
+// routing table's Interner
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MrtAttribute {
-    Unknown,
+    Unknown(u8, u8, Arc<[u8]>),     // flags, type code and data, kept as is
     Origin(u8),
-    AsPath(AsPath),
+    AsPath(Arc<AsPath>),
     NextHop(IpAddr),
     MultiExitDisc(u32),
     LocalPref(u32),
     AtomicAggregate,
-    Community(Vec<Community>)
+    Aggregator(u32, Ipv4Addr),      // AS and router ID of the aggregating speaker
+    MpNextHop(IpAddr),              // global next hop of MP_REACH_NLRI
+    Community(Arc<Vec<Community>>),
+    ExtendedCommunity(Arc<Vec<Community>>),
+    LargeCommunity(Arc<Vec<Community>>)
 }
 
 impl MrtAttribute {
     pub fn parse<R: Read + BufRead>(reader: &mut R) -> anyhow::Result<Vec<MrtAttribute>> {
+        Self::parse_as(reader, true)
+    }
+
+    // Attributes with 2-byte (as4 false) or 4-byte ASNs in the AS_PATH and
+    // AGGREGATOR: RIB entries always have 4-byte ASNs, BGP4MP messages
+    // from a speaker without the capability have 2-byte ones
+    pub fn parse_as<R: Read + BufRead>(reader: &mut R, as4: bool) -> anyhow::Result<Vec<MrtAttribute>> {
         let mut attributes: Vec<MrtAttribute> = vec![];
         loop {
             let flags: u8 = match reader.read_u8() {
@@ -44,7 +57,9 @@ impl MrtAttribute {
 
             // Take the attribute data (backed by a Vec and at least as long as the
             // whole MRT message), and cap it to the reported attribute length
-            let mut data: &[u8] = &reader.fill_buf()?[..length];
+            let mut data: &[u8] = reader.fill_buf()?
+                .get(..length)
+                .ok_or_else(|| anyhow!("attribute {}: length {} overruns the attributes", code, length))?;
 
             let attribute: MrtAttribute = match code {
                 1 => {
@@ -56,7 +71,7 @@ impl MrtAttribute {
                 },
                 2 => {
                     // MrtAttribute::AsPath(AsPath::parse(&mut data.as_slice())?)
-                    MrtAttribute::AsPath(AsPath::parse(&mut data)?)
+                    MrtAttribute::AsPath(Arc::new(AsPath::parse_as(&mut data, as4)?))
                 },
                 3 => {
                     if length == 4 {
@@ -92,16 +107,39 @@ impl MrtAttribute {
                     }
                     MrtAttribute::AtomicAggregate
                 },
+                7 => {
+                    let asn = match length {
+                        6 => data.read_u16::<BigEndian>()? as u32,
+                        8 => data.read_u32::<BigEndian>()?,
+                        _ => return Err(anyhow!("AGGREGATOR attribute: expected length 6 or 8, got {}", length)),
+                    };
+                    MrtAttribute::Aggregator(asn, Ipv4Addr::from_bits(data.read_u32::<BigEndian>()?))
+                },
                 8 => {
                     if length % 4 != 0 {
                         return Err(anyhow!("COMMUNITY attribute: expected length divisible by 4, got {}", length));
                     }
-                    MrtAttribute::Community(Community::parse(&mut data, length / 4)?)
+                    MrtAttribute::Community(Arc::new(Community::parse(&mut data, length / 4)?))
+                },
+                14 => {
+                    MrtAttribute::MpNextHop(mp_reach_nexthop(data)?)
+                },
+                16 => {
+                    if length % 8 != 0 {
+                        return Err(anyhow!("EXTENDED_COMMUNITIES attribute: expected length divisible by 8, got {}", length));
+                    }
+                    MrtAttribute::ExtendedCommunity(Arc::new(Community::parse_extended(&mut data, length / 8)?))
+                },
+                32 => {
+                    if length % 12 != 0 {
+                        return Err(anyhow!("LARGE_COMMUNITY attribute: expected length divisible by 12, got {}", length));
+                    }
+                    MrtAttribute::LargeCommunity(Arc::new(Community::parse_large(&mut data, length / 12)?))
                 }
 
-                // unknown attribute, don't read data, ignore
+                // unknown attribute, kept undecoded
                 _ => {
-                    MrtAttribute::Unknown
+                    MrtAttribute::Unknown(flags, code, Arc::from(data))
                 }
             };
 
@@ -110,10 +148,25 @@ impl MrtAttribute {
             reader.consume(length);
 
             // Store the attribute in the RibEntry attribute list
-            if attribute != MrtAttribute::Unknown {
-                attributes.push(attribute);
-            }
+            attributes.push(attribute);
         }
         Ok(attributes)
     }
 }
+
+// The next hop of an MP_REACH_NLRI attribute. RIB entries carry only the
+// next hop length and address (RFC 6396 4.3.4), though some writers give
+// the whole attribute: AFI, SAFI, next hop length and address, and NLRI.
+// Of a global and link-local IPv6 pair, the global address is taken.
+pub fn mp_reach_nexthop(data: &[u8]) -> Result<IpAddr> {
+    let nexthop = match data.first() {
+        Some(&length) if length as usize == data.len() - 1 => &data[1..],
+        _ if data.len() >= 4 && data.len() > 4 + data[3] as usize => &data[4..4 + data[3] as usize],
+        _ => return Err(anyhow!("MP_REACH_NLRI attribute: next hop not valid")),
+    };
+    match nexthop.len() {
+        4 => Ok(IpAddr::V4(Ipv4Addr::from_bits((&nexthop[..4]).read_u32::<BigEndian>()?))),
+        16 | 32 => Ok(IpAddr::V6(Ipv6Addr::from_bits((&nexthop[..16]).read_u128::<BigEndian>()?))),
+        length => Err(anyhow!("MP_REACH_NLRI attribute: next hop length {} not valid", length)),
+    }
+}
@@ -0,0 +1,83 @@
+use crate::*;
+
+// Compare the binary Trie with the path-compressed Patricia trie now
+// behind RoutingTable, using the prefixes of the loaded table: memory
+// per route, time to build, and longest-prefix-match lookups per second
+// for addresses picked at random from within the loaded prefixes
+
+const LOOKUPS: usize = 1_000_000;
+
+pub fn benchmark(routing_table: &RoutingTable) {
+    let v4 = routing_table.v4.iter().map(|(prefix, plen, _)| (prefix, plen)).collect::<Vec<(Ipv4Addr, u8)>>();
+    let v6 = routing_table.v6.iter().map(|(prefix, plen, _)| (prefix, plen)).collect::<Vec<(Ipv6Addr, u8)>>();
+
+    outln!("{:<6} {:<10} {:>10} {:>10} {:>12} {:>10} {:>14}",
+             "family", "structure", "routes", "nodes", "bytes/route", "build", "lookups/sec");
+    benchmark_family("IPv4", &v4);
+    benchmark_family("IPv6", &v6);
+
+    outln!();
+    memory_report(routing_table);
+}
+
+fn benchmark_family<A: AddressBits>(family: &str, prefixes: &[(A, u8)]) {
+    if prefixes.is_empty() {
+        return;
+    }
+    let addresses = random_addresses(prefixes);
+
+    let start_time = Instant::now();
+    let mut trie: Trie<A, u32> = Trie::new();
+    for (index, (prefix, plen)) in prefixes.iter().enumerate() {
+        trie.insert(prefix, *plen, index as u32);
+    }
+    let trie_build = start_time.elapsed();
+
+    let start_time = Instant::now();
+    let mut patricia: Patricia<A, u32> = Patricia::new();
+    for (index, (prefix, plen)) in prefixes.iter().enumerate() {
+        patricia.insert(prefix, *plen, index as u32);
+    }
+    let patricia_build = start_time.elapsed();
+
+    let (trie_rate, trie_found) = lookup_rate(&addresses, |x| trie.get(x, A::BITS).map(|(_, _, v)| *v));
+    let (patricia_rate, patricia_found) = lookup_rate(&addresses, |x| patricia.get(x, A::BITS).map(|(_, _, v)| *v));
+
+    let row = |structure: &str, nodes: usize, memory: usize, build: Duration, rate: f64| {
+        outln!("{:<6} {:<10} {:>10} {:>10} {:>12.1} {:>10.1?} {:>14.0}",
+                 family, structure, prefixes.len(), nodes, memory as f64 / prefixes.len() as f64, build, rate);
+    };
+    row("trie", trie.node_count(), trie.memory(), trie_build, trie_rate);
+    row("patricia", patricia.node_count(), patricia.memory(), patricia_build, patricia_rate);
+
+    if trie_found != patricia_found {
+        eprintln!("{}: lookup results differ between structures", family);
+    }
+}
+
+// Time the lookups, returning the rate and the values found (so that the
+// structures can be checked against each other)
+fn lookup_rate<A, F: Fn(&A) -> Option<u32>>(addresses: &[A], lookup: F) -> (f64, Vec<Option<u32>>) {
+    let start_time = Instant::now();
+    let found = addresses.iter().map(lookup).collect::<Vec<Option<u32>>>();
+    (addresses.len() as f64 / start_time.elapsed().as_secs_f64(), found)
+}
+
+// Addresses within the loaded prefixes, with random host bits
+fn random_addresses<A: AddressBits>(prefixes: &[(A, u8)]) -> Vec<A> {
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    let mut random = move || {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..LOOKUPS)
+        .map(|_| {
+            let (prefix, plen) = prefixes[random() as usize % prefixes.len()];
+            let host = ((random() as u128) << 64 | random() as u128) >> plen.min(127);
+            A::from_bits(prefix.to_bits() | (host & (u128::MAX << (128 - A::BITS as u32))))
+        })
+        .collect()
+}
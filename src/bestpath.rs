@@ -0,0 +1,337 @@
+use crate::*;
+use std::cmp::Ordering;
+
+// BGP best path selection, in the standard (Cisco IOS) order:
+//
+//   1. highest local preference (100 if missing)
+//   2. shortest AS path (an AS_SET counts one), unless as-path-ignore
+//   3. lowest origin: IGP, EGP, incomplete
+//   4. lowest MED, only between paths from the same neighbour AS unless
+//      always-compare-med. A missing MED is 0, or the worst possible
+//      with med-missing-as-worst. As with IOS deterministic-med, the best
+//      path from each neighbour AS is found first, and then the best of
+//      those, so that the choice doesn't depend on the order of the paths
+//   5. external (eBGP) over internal (iBGP) paths. A peer is internal
+//      when its AS is the configured local-as
+//   6. the oldest of two external paths, unless compare-routerid
+//   7. lowest router ID
+//   8. lowest neighbour address
+//
+// Weight, locally originated routes and IGP metric don't exist in a
+// collector's view of the world, so those steps are skipped. Paths equal
+// to the best down to step 5 are multipath candidates, when multipath is
+// enabled, provided they come from the same neighbour AS (unless
+// multipath-relax) and are of the same type.
+//
+// Options are set with -b, comma separated, eg.
+//   -b always-compare-med,multipath,local-as=65000
+
+#[derive(Debug, Clone, Default)]
+pub struct BestPathConfig {
+    pub always_compare_med: bool,
+    pub med_missing_as_worst: bool,
+    pub as_path_ignore: bool,
+    pub compare_routerid: bool,
+    pub multipath: bool,
+    pub multipath_relax: bool,
+    pub local_as: Option<u32>,
+}
+
+impl FromStr for BestPathConfig {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<BestPathConfig, Self::Err> {
+        let mut config = BestPathConfig::default();
+        for option in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            match option.split_once('=') {
+                Some(("local-as", asn)) => config.local_as = Some(asn.parse()?),
+                _ => match option {
+                    "always-compare-med" => config.always_compare_med = true,
+                    "med-missing-as-worst" => config.med_missing_as_worst = true,
+                    "as-path-ignore" => config.as_path_ignore = true,
+                    "compare-routerid" => config.compare_routerid = true,
+                    "multipath" => config.multipath = true,
+                    "multipath-relax" => {
+                        config.multipath = true;
+                        config.multipath_relax = true;
+                    },
+                    _ => return Err(anyhow!("unknown best path option {}", option)),
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+// The step of the decision process that separated two paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    LocalPref,
+    AsPathLength,
+    Origin,
+    Med,
+    ExternalInternal,
+    Age,
+    RouterId,
+    NeighborAddress,
+    Equal,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Step::LocalPref => "Local Preference",
+            Step::AsPathLength => "AS path",
+            Step::Origin => "Origin",
+            Step::Med => "MED",
+            Step::ExternalInternal => "eBGP over iBGP",
+            Step::Age => "Active preferred",
+            Step::RouterId => "Router ID",
+            Step::NeighborAddress => "Neighbor address",
+            Step::Equal => "Equal",
+        })
+    }
+}
+
+impl Step {
+    // The reason as JUNOS gives it, for "Inactive reason:"
+    pub fn junos_reason(&self) -> String {
+        match self {
+            Step::ExternalInternal => String::from("Interior > Exterior > Exterior via Interior"),
+            step => step.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathStatus {
+    Best,
+    Multipath,
+    NotBest(Step),      // and the step at which it lost to the best
+}
+
+impl PathStatus {
+    pub fn is_best(&self) -> bool {
+        *self == PathStatus::Best
+    }
+}
+
+impl BestPathConfig {
+    fn is_internal(&self, rt: &MrtRibEntry) -> bool {
+        self.local_as == Some(rt.peer.peer_as)
+    }
+
+    fn med(&self, rt: &MrtRibEntry) -> u32 {
+        rt.get_med().unwrap_or(if self.med_missing_as_worst { u32::MAX } else { 0 })
+    }
+
+    // Compare two paths, Less meaning a is preferred, along with the
+    // step which decided it
+    pub fn compare(&self, a: &MrtRibEntry, b: &MrtRibEntry) -> (Ordering, Step) {
+        let local_pref = |x: &MrtRibEntry| x.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF);
+        let steps = [
+            (Step::LocalPref, local_pref(b).cmp(&local_pref(a))),
+            (Step::AsPathLength, if self.as_path_ignore {
+                Ordering::Equal
+            } else {
+                a.get_aspath_length().cmp(&b.get_aspath_length())
+            }),
+            (Step::Origin, a.get_origin().cmp(&b.get_origin())),
+            (Step::Med, if self.always_compare_med || a.get_neighbor_as() == b.get_neighbor_as() {
+                self.med(a).cmp(&self.med(b))
+            } else {
+                Ordering::Equal
+            }),
+            (Step::ExternalInternal, self.is_internal(a).cmp(&self.is_internal(b))),
+            (Step::Age, if self.compare_routerid || self.is_internal(a) || self.is_internal(b) {
+                Ordering::Equal
+            } else {
+                a.origin_time.cmp(&b.origin_time)
+            }),
+            (Step::RouterId, a.peer.peer_id.cmp(&b.peer.peer_id)),
+            (Step::NeighborAddress, a.peer.peer_address.cmp(&b.peer.peer_address)),
+        ];
+        steps
+            .into_iter()
+            .find(|(_, ordering)| *ordering != Ordering::Equal)
+            .map(|(step, ordering)| (ordering, step))
+            .unwrap_or((Ordering::Equal, Step::Equal))
+    }
+
+    // The status of each of the paths for a prefix, in the same order
+    pub fn select(&self, route_entries: &[MrtRibEntry]) -> Vec<PathStatus> {
+        // The best path from each neighbour AS, in the order first seen
+        let mut groups: Vec<(Option<u32>, usize)> = vec![];
+        for (index, rt) in route_entries.iter().enumerate() {
+            let neighbor_as = rt.get_neighbor_as();
+            match groups.iter_mut().find(|(asn, _)| *asn == neighbor_as) {
+                Some((_, best)) => {
+                    if self.compare(rt, &route_entries[*best]).0 == Ordering::Less {
+                        *best = index;
+                    }
+                },
+                None => groups.push((neighbor_as, index)),
+            }
+        }
+        let Some(best) = groups.into_iter().map(|(_, best)| best).reduce(|best, index| {
+            match self.compare(&route_entries[index], &route_entries[best]).0 {
+                Ordering::Less => index,
+                _ => best,
+            }
+        }) else {
+            return vec![];
+        };
+        let best_path = &route_entries[best];
+
+        route_entries
+            .iter()
+            .enumerate()
+            .map(|(index, rt)| {
+                if index == best {
+                    return PathStatus::Best;
+                }
+                let (_, step) = self.compare(rt, best_path);
+                let multipath = self.multipath
+                    && step > Step::ExternalInternal
+                    && (self.multipath_relax || rt.get_neighbor_as() == best_path.get_neighbor_as());
+                if multipath {
+                    PathStatus::Multipath
+                } else {
+                    PathStatus::NotBest(step)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(peer: &str, aspath: &[u32], attributes: Vec<MrtAttribute>) -> MrtRibEntry {
+        MrtRibEntry::test_path(peer, aspath[0], aspath, attributes)
+    }
+
+    fn with_router_id(mut rt: MrtRibEntry, router_id: &str) -> MrtRibEntry {
+        Arc::make_mut(&mut rt.peer).peer_id = IpAddr::from_str(router_id).unwrap();
+        rt
+    }
+
+    fn config(options: &str) -> BestPathConfig {
+        BestPathConfig::from_str(options).unwrap()
+    }
+
+    // The step deciding between a (preferred) and b, both ways round
+    fn decided(config: &BestPathConfig, a: &MrtRibEntry, b: &MrtRibEntry) -> Step {
+        let (ordering, step) = config.compare(a, b);
+        assert_eq!(ordering, Ordering::Less, "{:?} not preferred", step);
+        assert_eq!(config.compare(b, a), (Ordering::Greater, step));
+        step
+    }
+
+    #[test]
+    fn local_pref() {
+        let a = path("192.0.2.1", &[64501, 64500, 64499], vec![MrtAttribute::LocalPref(200)]);
+        let b = path("192.0.2.2", &[64502, 64500], vec![]);
+        assert_eq!(decided(&config(""), &a, &b), Step::LocalPref);
+        // A missing local preference is 100
+        let c = path("192.0.2.3", &[64503, 64500, 64499], vec![MrtAttribute::LocalPref(50)]);
+        assert_eq!(decided(&config(""), &b, &c), Step::LocalPref);
+    }
+
+    #[test]
+    fn aspath_length() {
+        let a = path("192.0.2.2", &[64502, 64500], vec![]);
+        let b = path("192.0.2.1", &[64501, 64499, 64500], vec![]);
+        assert_eq!(decided(&config(""), &a, &b), Step::AsPathLength);
+        assert_ne!(config("as-path-ignore").compare(&a, &b).1, Step::AsPathLength);
+    }
+
+    #[test]
+    fn origin() {
+        let a = path("192.0.2.2", &[64502, 64500], vec![]);
+        let mut b = path("192.0.2.1", &[64501, 64500], vec![]);
+        Arc::make_mut(&mut b.attributes)[0] = MrtAttribute::Origin(2);
+        assert_eq!(decided(&config(""), &a, &b), Step::Origin);
+    }
+
+    #[test]
+    fn med() {
+        let a = path("192.0.2.2", &[64501, 64500], vec![MrtAttribute::MultiExitDisc(10)]);
+        let b = path("192.0.2.1", &[64501, 64500], vec![MrtAttribute::MultiExitDisc(20)]);
+        assert_eq!(decided(&config(""), &a, &b), Step::Med);
+
+        // Only between paths from the same neighbour AS, unless always-compare-med
+        let c = path("192.0.2.1", &[64502, 64500], vec![MrtAttribute::MultiExitDisc(20)]);
+        assert_ne!(config("").compare(&a, &c).1, Step::Med);
+        assert_eq!(decided(&config("always-compare-med"), &a, &c), Step::Med);
+
+        // A missing MED is 0, or the worst with med-missing-as-worst
+        let d = path("192.0.2.1", &[64501, 64500], vec![]);
+        assert_eq!(decided(&config(""), &d, &a), Step::Med);
+        assert_eq!(decided(&config("med-missing-as-worst"), &a, &d), Step::Med);
+    }
+
+    #[test]
+    fn external_over_internal() {
+        let a = path("192.0.2.2", &[64501, 64500], vec![]);
+        let b = path("192.0.2.1", &[65000, 64500], vec![]);
+        assert_eq!(decided(&config("local-as=65000"), &a, &b), Step::ExternalInternal);
+        assert_eq!(Step::ExternalInternal.to_string(), "eBGP over iBGP");
+        assert_eq!(Step::ExternalInternal.junos_reason(), "Interior > Exterior > Exterior via Interior");
+    }
+
+    #[test]
+    fn age() {
+        let a = path("192.0.2.2", &[64501, 64500], vec![]);
+        let mut b = path("192.0.2.1", &[64502, 64500], vec![]);
+        b.origin_time += Duration::from_secs(60);
+        assert_eq!(decided(&config(""), &a, &b), Step::Age);
+        // Not with compare-routerid, nor for internal paths
+        assert_eq!(decided(&config("compare-routerid"), &b, &a), Step::RouterId);
+        let c = path("192.0.2.1", &[65000, 64500], vec![]);
+        let mut d = path("192.0.2.2", &[65000, 64500], vec![]);
+        d.origin_time -= Duration::from_secs(60);
+        assert_eq!(decided(&config("local-as=65000"), &c, &d), Step::RouterId);
+    }
+
+    #[test]
+    fn router_id_and_neighbor_address() {
+        let a = with_router_id(path("192.0.2.2", &[64501, 64500], vec![]), "10.0.0.1");
+        let b = with_router_id(path("192.0.2.1", &[64501, 64500], vec![]), "10.0.0.2");
+        assert_eq!(decided(&config(""), &a, &b), Step::RouterId);
+        let c = with_router_id(path("192.0.2.1", &[64501, 64500], vec![]), "10.0.0.1");
+        assert_eq!(decided(&config(""), &c, &a), Step::NeighborAddress);
+        assert_eq!(config("").compare(&c, &c), (Ordering::Equal, Step::Equal));
+    }
+
+    #[test]
+    fn multipath() {
+        let a = with_router_id(path("192.0.2.1", &[64501, 64500], vec![]), "10.0.0.1");
+        let b = with_router_id(path("192.0.2.2", &[64501, 64500], vec![]), "10.0.0.2");
+        let c = with_router_id(path("192.0.2.3", &[64502, 64500], vec![]), "10.0.0.3");
+        let paths = [a, b, c];
+        assert_eq!(config("").select(&paths),
+                   [PathStatus::Best, PathStatus::NotBest(Step::RouterId), PathStatus::NotBest(Step::RouterId)]);
+        assert_eq!(config("multipath").select(&paths),
+                   [PathStatus::Best, PathStatus::Multipath, PathStatus::NotBest(Step::RouterId)]);
+        assert_eq!(config("multipath-relax").select(&paths),
+                   [PathStatus::Best, PathStatus::Multipath, PathStatus::Multipath]);
+    }
+
+    #[test]
+    fn med_independent_of_order() {
+        // Pairwise, each of these beats another: b beats a on MED, c
+        // beats b and a beats c on router ID. The best of AS 64501's
+        // paths (b) is found first, so c is the best in any order.
+        let a = with_router_id(path("192.0.2.1", &[64501, 64500], vec![MrtAttribute::MultiExitDisc(10)]), "10.0.0.1");
+        let b = with_router_id(path("192.0.2.2", &[64501, 64500], vec![MrtAttribute::MultiExitDisc(5)]), "10.0.0.3");
+        let c = with_router_id(path("192.0.2.3", &[64502, 64500], vec![MrtAttribute::MultiExitDisc(0)]), "10.0.0.2");
+        let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        for order in orders {
+            let paths = order.map(|x| [&a, &b, &c][x].clone());
+            let status = config("").select(&paths);
+            let best = status.iter().position(|x| x.is_best()).unwrap();
+            assert_eq!(paths[best].peer.peer_address, c.peer.peer_address, "order {:?}", order);
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use std::io::Read;
+use std::net::IpAddr;
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::*;
+
+// BGP4MP records (RFC 6396 4.4, RFC 8050): the BGP messages and session
+// state changes seen by a collector, as in an updates file. UPDATE messages
+// are decoded into the routes announced and withdrawn, each announced route
+// a one-path MrtNlri, so that they load (and filter) like RIB entries.
+
+const AS_TRANS: u32 = 23456;
+
+#[derive(Debug)]
+pub struct Bgp4mp {
+    pub timestamp: u32,
+    pub microseconds: Option<u32>,  // BGP4MP_ET only
+    pub peer: Arc<MrtPeer>,         // with the local address as its collector
+    pub local_as: u32,
+    pub event: Bgp4mpEvent,
+}
+
+#[derive(Debug)]
+pub enum Bgp4mpEvent {
+    StateChange(u16, u16),                      // old and new FSM state
+    Update {
+        withdrawn: Vec<(IpAddr, u8, u32)>,      // prefix, length, path ID
+        announced: Vec<MrtNlri>,
+    },
+    Message(u8),                                // any other message, by type
+}
+
+impl Bgp4mp {
+    pub fn parse(timestamp: u32, mrt_type: u16, mrt_subtype: u16, mut reader: &[u8]) -> Result<Bgp4mp> {
+        let microseconds = if mrt_type == 17 {
+            Some(reader.read_u32::<BigEndian>()?)
+        } else {
+            None
+        };
+        let (as4, addpath) = match mrt_subtype {
+            0 | 1 | 6 => (false, false),
+            4 | 5 | 7 => (true, false),
+            8 | 10 => (false, true),
+            9 | 11 => (true, true),
+            _ => return Err(anyhow!("BGP4MP subtype {} not valid", mrt_subtype)),
+        };
+        let read_as = |reader: &mut &[u8]| -> Result<u32> {
+            Ok(if as4 { reader.read_u32::<BigEndian>()? } else { reader.read_u16::<BigEndian>()? as u32 })
+        };
+        let peer_as = read_as(&mut reader)?;
+        let local_as = read_as(&mut reader)?;
+        let _interface_index = reader.read_u16::<BigEndian>()?;
+        let afi = reader.read_u16::<BigEndian>()?;
+        let peer_address = read_address(&mut reader, afi)?;
+        let local_address = read_address(&mut reader, afi)?;
+
+        let peer = Arc::new(MrtPeer {
+            peer_type_a: as4,
+            peer_type_i: afi == 2,
+            peer_id: peer_address,
+            peer_address,
+            peer_as,
+            collector_id: local_address,
+            view_name: String::new(),
+        });
+
+        let event = match mrt_subtype {
+            0 | 5 => Bgp4mpEvent::StateChange(reader.read_u16::<BigEndian>()?, reader.read_u16::<BigEndian>()?),
+            // Messages sent by the collector itself say nothing of the
+            // peer's routes
+            6 | 7 | 10 | 11 => Bgp4mpEvent::Message(message_type(reader)?),
+            _ => match message_type(reader)? {
+                2 => parse_update(&reader[19..], &peer, timestamp, microseconds, as4, addpath)?,
+                message_type => Bgp4mpEvent::Message(message_type),
+            },
+        };
+        Ok(Bgp4mp { timestamp, microseconds, peer, local_as, event })
+    }
+}
+
+// The peers of the BGP4MP records of a file, by local address, peer
+// address and AS. Each is interned into the peers map, as the peers of a
+// PEER_INDEX_TABLE are, with the local address as its collector and the
+// next free index, so that it is listed among the collectors' peers and
+// its paths share one MrtPeer.
+#[derive(Default)]
+pub struct Bgp4mpPeers {
+    interned: HashMap<(IpAddr, IpAddr, u32), (u16, Arc<MrtPeer>)>,
+}
+
+impl Bgp4mpPeers {
+    // The shared peer of a message, and its index, finding it among those
+    // already loaded (eg. from an earlier file) or adding it
+    pub fn intern(&mut self, peer: &Arc<MrtPeer>,
+                  peers: &mut HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) -> (u16, Arc<MrtPeer>) {
+        let key = (peer.collector_id, peer.peer_address, peer.peer_as);
+        let (index, interned) = self.interned.entry(key).or_insert_with(|| {
+            let collector = |key: &(IpAddr, String, u16)| key.0 == peer.collector_id && key.1 == peer.view_name;
+            let existing = peers.iter().find(|(key, x)| {
+                collector(key) && x.peer_address == peer.peer_address && x.peer_as == peer.peer_as
+            });
+            if let Some((key, existing)) = existing {
+                return (key.2, Arc::clone(existing));
+            }
+            let index = peers.keys().filter(|key| collector(key)).map(|key| key.2 as u32 + 1).max().unwrap_or(0);
+            // As for a PEER_INDEX_TABLE, an index too large is left out
+            if index < u16::MAX.into() {
+                peers.insert((peer.collector_id, peer.view_name.clone(), index as u16), Arc::clone(peer));
+            }
+            (index.min(u16::MAX.into()) as u16, Arc::clone(peer))
+        });
+        (*index, Arc::clone(interned))
+    }
+}
+
+fn read_address(reader: &mut &[u8], afi: u16) -> Result<IpAddr> {
+    match afi {
+        1 => Ok(IpAddr::V4(Ipv4Addr::from_bits(reader.read_u32::<BigEndian>()?))),
+        2 => Ok(IpAddr::V6(Ipv6Addr::from_bits(reader.read_u128::<BigEndian>()?))),
+        _ => Err(anyhow!("BGP4MP address family {} not valid", afi)),
+    }
+}
+
+// The type of a BGP message, after its marker and length
+fn message_type(message: &[u8]) -> Result<u8> {
+    message.get(18).copied().ok_or_else(|| anyhow!("BGP message truncated"))
+}
+
+// Prefixes in NLRI encoding, each preceded by its path ID with add-path
+fn read_prefixes(mut reader: &[u8], afi: u16, addpath: bool) -> Result<Vec<(IpAddr, u8, u32)>> {
+    let mut prefixes = vec![];
+    while !reader.is_empty() {
+        let path_id = if addpath { reader.read_u32::<BigEndian>()? } else { 0 };
+        let plen = reader.read_u8()?;
+        let length = plen.div_ceil(8) as usize;
+        let mut buf = [0u8; 16];
+        if length > reader.len() || (afi == 1 && plen > 32) || plen > 128 {
+            return Err(anyhow!("NLRI prefix length {} not valid", plen));
+        }
+        buf[..length].copy_from_slice(&reader[..length]);
+        reader = &reader[length..];
+        let prefix = match afi {
+            1 => IpAddr::V4(Ipv4Addr::from_bits((&buf[..4]).read_u32::<BigEndian>()?)),
+            _ => IpAddr::V6(Ipv6Addr::from_bits((&buf[..]).read_u128::<BigEndian>()?)),
+        };
+        prefixes.push((prefix, plen, path_id));
+    }
+    Ok(prefixes)
+}
+
+// The type codes and data of the attributes, undecoded
+fn raw_attributes(mut reader: &[u8]) -> Result<Vec<(u8, &[u8])>> {
+    let mut attributes = vec![];
+    while !reader.is_empty() {
+        let flags = reader.read_u8()?;
+        let code = reader.read_u8()?;
+        let length = if flags & (1<<4) > 0 {
+            reader.read_u16::<BigEndian>()? as usize
+        } else {
+            reader.read_u8()? as usize
+        };
+        if length > reader.len() {
+            return Err(anyhow!("attribute {} truncated", code));
+        }
+        attributes.push((code, &reader[..length]));
+        reader = &reader[length..];
+    }
+    Ok(attributes)
+}
+
+fn parse_update(mut reader: &[u8], peer: &Arc<MrtPeer>, timestamp: u32, microseconds: Option<u32>,
+                as4: bool, addpath: bool) -> Result<Bgp4mpEvent> {
+    let withdrawn_length = reader.read_u16::<BigEndian>()? as usize;
+    let mut withdrawn = read_prefixes(reader.get(..withdrawn_length).ok_or_else(|| anyhow!("UPDATE truncated"))?,
+                                      1, addpath)?;
+    reader = &reader[withdrawn_length..];
+    let attribute_length = reader.read_u16::<BigEndian>()? as usize;
+    let attribute_data = reader.get(..attribute_length).ok_or_else(|| anyhow!("UPDATE truncated"))?;
+    let nlri = &reader[attribute_length..];
+
+    let mut attributes = MrtAttribute::parse_as(&mut &attribute_data[..], as4)?;
+    if !as4 {
+        merge_as4(&mut attributes)?;
+    }
+
+    // IPv4 unicast routes are in the message itself, any others in the
+    // MP_REACH_NLRI and MP_UNREACH_NLRI attributes
+    let mut announced = read_prefixes(nlri, 1, addpath)?;
+    for (code, mut data) in raw_attributes(attribute_data)? {
+        match code {
+            14 => {
+                let afi = data.read_u16::<BigEndian>()?;
+                let safi = data.read_u8()?;
+                let nexthop_length = data.read_u8()? as usize;
+                // The next hop, then a reserved byte
+                let data = data.get(nexthop_length + 1..).ok_or_else(|| anyhow!("MP_REACH_NLRI truncated"))?;
+                if safi == 1 {
+                    announced.extend(read_prefixes(data, afi, addpath)?);
+                }
+            },
+            15 => {
+                let afi = data.read_u16::<BigEndian>()?;
+                let safi = data.read_u8()?;
+                if safi == 1 {
+                    withdrawn.extend(read_prefixes(data, afi, addpath)?);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // The prefixes of an update share its attributes
+    let attributes = Arc::new(attributes);
+    let origin_time = UNIX_EPOCH.checked_add(Duration::from_secs(timestamp as u64)).unwrap_or(UNIX_EPOCH);
+    let announced = announced
+        .into_iter()
+        .map(|(prefix, plen, path_id)| MrtNlri {
+            sequence: 0,
+            plen,
+            prefix,
+            entry_count: 1,
+            rib_entries: vec![MrtRibEntry {
+                peer_id: 0,
+                peer: Arc::clone(peer),
+                origin_time,
+                path_id,
+                attributes: Arc::clone(&attributes),
+            }],
+            source: NlriSource::Bgp4mp(timestamp, microseconds),
+        })
+        .collect();
+    Ok(Bgp4mpEvent::Update { withdrawn, announced })
+}
+
+// A speaker without 4-byte AS support sends 2-byte ASNs in the AS_PATH and
+// AGGREGATOR, with AS_TRANS in place of any larger ASN, and the real ones
+// in AS4_PATH and AS4_AGGREGATOR: put them back together (RFC 6793 4.2.3)
+fn merge_as4(attributes: &mut Vec<MrtAttribute>) -> Result<()> {
+    let mut as4_path = None;
+    let mut as4_aggregator = None;
+    for attribute in attributes.iter() {
+        match attribute {
+            MrtAttribute::Unknown(_, 17, data) => as4_path = Some(AsPath::parse_as(&mut &data[..], true)?),
+            MrtAttribute::Unknown(_, 18, data) if data.len() == 8 => {
+                let mut data = &data[..];
+                as4_aggregator = Some((data.read_u32::<BigEndian>()?, Ipv4Addr::from_bits(data.read_u32::<BigEndian>()?)));
+            },
+            _ => {},
+        }
+    }
+    attributes.retain(|x| !matches!(x, MrtAttribute::Unknown(_, 17 | 18, _)));
+
+    // The AS4 attributes are ignored if the aggregator was itself a 4-byte
+    // speaker
+    for attribute in attributes.iter_mut() {
+        match attribute {
+            MrtAttribute::Aggregator(asn, _) if *asn != AS_TRANS => return Ok(()),
+            MrtAttribute::Aggregator(asn, id) => {
+                if let Some(aggregator) = as4_aggregator {
+                    (*asn, *id) = aggregator;
+                }
+            },
+            _ => {},
+        }
+    }
+    let Some(as4_path) = as4_path else {
+        return Ok(());
+    };
+    for attribute in attributes.iter_mut() {
+        if let MrtAttribute::AsPath(aspath) = attribute {
+            // Leading ASNs of the AS_PATH that the AS4_PATH doesn't cover
+            let Some(mut keep) = aspath.path_length().checked_sub(as4_path.path_length()) else {
+                continue;
+            };
+            let mut aspath_segments = vec![];
+            for segment in &aspath.aspath_segments {
+                if keep == 0 {
+                    break;
+                }
+                if segment.ordered {
+                    let count = keep.min(segment.asns.len());
+                    aspath_segments.push(AsPathSegment { ordered: true, asns: segment.asns[..count].to_vec() });
+                    keep -= count;
+                } else {
+                    aspath_segments.push(segment.clone());
+                    keep -= 1;
+                }
+            }
+            aspath_segments.extend(as4_path.aspath_segments.iter().cloned());
+            *aspath = Arc::new(AsPath { aspath_segments });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(flags: u8, code: u8, data: &[u8]) -> Vec<u8> {
+        [&[flags, code, data.len() as u8], data].concat()
+    }
+
+    // An AS_SEQUENCE in 2- or 4-byte ASNs
+    fn sequence(asns: &[u32], as4: bool) -> Vec<u8> {
+        let mut data = vec![2, asns.len() as u8];
+        for asn in asns {
+            match as4 {
+                true => data.extend(asn.to_be_bytes()),
+                false => data.extend((*asn as u16).to_be_bytes()),
+            }
+        }
+        data
+    }
+
+    // A prefix in NLRI encoding, with its path ID if given
+    fn prefix(path_id: Option<u32>, prefix: [u8; 4], plen: u8) -> Vec<u8> {
+        let mut data = path_id.map(|x| x.to_be_bytes().to_vec()).unwrap_or_default();
+        data.push(plen);
+        data.extend(&prefix[..plen.div_ceil(8) as usize]);
+        data
+    }
+
+    // A BGP4MP_MESSAGE of the subtype given from 192.0.2.1 (AS 64500) to
+    // 192.0.2.254, carrying an UPDATE
+    fn message(subtype: u16, withdrawn: &[u8], attributes: &[u8], nlri: &[u8]) -> Bgp4mp {
+        message_from(64500, subtype, withdrawn, attributes, nlri)
+    }
+
+    fn message_from(peer_as: u32, subtype: u16, withdrawn: &[u8], attributes: &[u8], nlri: &[u8]) -> Bgp4mp {
+        Bgp4mp::parse(1_700_000_000, 16, subtype, &encode(peer_as, subtype, withdrawn, attributes, nlri)).unwrap()
+    }
+
+    fn encode(peer_as: u32, subtype: u16, withdrawn: &[u8], attributes: &[u8], nlri: &[u8]) -> Vec<u8> {
+        let as4 = matches!(subtype, 4 | 9);
+        let mut data = vec![];
+        for asn in [peer_as, 65000] {
+            match as4 {
+                true => data.extend(asn.to_be_bytes()),
+                false => data.extend((asn as u16).to_be_bytes()),
+            }
+        }
+        data.extend([0, 0, 0, 1, 192, 0, 2, 1, 192, 0, 2, 254]);
+        let body = [&(withdrawn.len() as u16).to_be_bytes()[..], withdrawn,
+                    &(attributes.len() as u16).to_be_bytes(), attributes, nlri].concat();
+        data.extend([0xff; 16]);
+        data.extend((19 + body.len() as u16).to_be_bytes());
+        data.push(2);
+        data.extend(body);
+        data
+    }
+
+    fn attributes(aspath: &[u32]) -> Vec<u8> {
+        [attribute(0x40, 1, &[0]), attribute(0x40, 2, &sequence(aspath, true)),
+         attribute(0x40, 3, &[192, 0, 2, 1])].concat()
+    }
+
+    #[test]
+    fn addpath_withdrawal() {
+        let mut routing_table = RoutingTable::new();
+        let (mut peers, mut bgp4mp_peers) = (HashMap::new(), Bgp4mpPeers::default());
+        let nlri = [prefix(Some(1), [10, 0, 0, 0], 8), prefix(Some(2), [10, 0, 0, 0], 8)].concat();
+        load_bgp4mp(message(9, &[], &attributes(&[64500, 3356]), &nlri), &mut routing_table, &mut peers, &mut bgp4mp_peers);
+        let ten = IpAddr::from_str("10.0.0.0").unwrap();
+        assert_eq!(routing_table.get_exact(&ten, 8).unwrap().len(), 2);
+
+        // Only the path withdrawn goes
+        load_bgp4mp(message(9, &prefix(Some(1), [10, 0, 0, 0], 8), &[], &[]), &mut routing_table, &mut peers, &mut bgp4mp_peers);
+        let paths = routing_table.get_exact(&ten, 8).unwrap();
+        assert_eq!(paths.iter().map(|x| x.path_id).collect::<Vec<u32>>(), [2]);
+
+        load_bgp4mp(message(9, &prefix(Some(2), [10, 0, 0, 0], 8), &[], &[]), &mut routing_table, &mut peers, &mut bgp4mp_peers);
+        assert!(routing_table.get_exact(&ten, 8).is_none());
+    }
+
+    #[test]
+    fn peers_interned() {
+        let mut routing_table = RoutingTable::new();
+        let (mut peers, mut bgp4mp_peers) = (HashMap::new(), Bgp4mpPeers::default());
+        let nlri = |x| prefix(None, [10, x, 0, 0], 16);
+        for (peer_as, x) in [(64500, 1), (64501, 2), (64500, 3)] {
+            let message = message_from(peer_as, 4, &[], &attributes(&[peer_as]), &nlri(x));
+            load_bgp4mp(message, &mut routing_table, &mut peers, &mut bgp4mp_peers);
+        }
+
+        // One peer for each AS, indexed under the local address
+        let local = IpAddr::from_str("192.0.2.254").unwrap();
+        let mut keys = peers.keys().cloned().collect::<Vec<(IpAddr, String, u16)>>();
+        keys.sort();
+        assert_eq!(keys, [(local, String::new(), 0), (local, String::new(), 1)]);
+        assert_eq!(peers[&keys[1]].peer_as, 64501);
+
+        // Paths from the same peer share it
+        let path = |routing_table: &RoutingTable, x| {
+            routing_table.get_exact(&IpAddr::V4(Ipv4Addr::new(10, x, 0, 0)), 16).unwrap()[0].clone()
+        };
+        assert!(Arc::ptr_eq(&path(&routing_table, 1).peer, &path(&routing_table, 3).peer));
+        assert!(Arc::ptr_eq(&path(&routing_table, 1).peer, &peers[&keys[0]]));
+        assert_eq!((path(&routing_table, 1).peer_id, path(&routing_table, 2).peer_id), (0, 1));
+
+        // As does a later file's
+        let mut bgp4mp_peers = Bgp4mpPeers::default();
+        let message = message_from(64501, 4, &[], &attributes(&[64501]), &nlri(4));
+        load_bgp4mp(message, &mut routing_table, &mut peers, &mut bgp4mp_peers);
+        assert_eq!(peers.len(), 2);
+        assert!(Arc::ptr_eq(&path(&routing_table, 4).peer, &peers[&keys[1]]));
+    }
+
+    // The announced routes and withdrawn prefixes of an UPDATE
+    fn update(message: Bgp4mp) -> (Vec<MrtNlri>, Vec<(IpAddr, u8, u32)>) {
+        match message.event {
+            Bgp4mpEvent::Update { withdrawn, announced } => (announced, withdrawn),
+            event => panic!("not an UPDATE: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn update_decode() {
+        let attributes = [
+            attributes(&[3356, 174, 4200000001]),
+            attribute(0x80, 4, &20u32.to_be_bytes()),
+            attribute(0xc0, 8, &[0x0d, 0x1c, 0, 100, 0xff, 0xff, 0xff, 0x01]),
+            attribute(0xc0, 32, &[0, 0, 0x0d, 0x1c, 0, 0, 0, 1, 0, 0, 0, 2]),
+        ].concat();
+        let nlri = [prefix(None, [10, 0, 0, 0], 8), prefix(None, [10, 2, 0, 0], 16)].concat();
+        let message = message(4, &prefix(None, [192, 168, 0, 0], 16), &attributes, &nlri);
+        assert_eq!((message.timestamp, message.microseconds, message.local_as), (1_700_000_000, None, 65000));
+        assert_eq!((message.peer.peer_address, message.peer.peer_as), (IpAddr::from_str("192.0.2.1").unwrap(), 64500));
+        assert_eq!(message.peer.collector_id, IpAddr::from_str("192.0.2.254").unwrap());
+
+        let (announced, withdrawn) = update(message);
+        assert_eq!(withdrawn, [(IpAddr::from_str("192.168.0.0").unwrap(), 16, 0)]);
+        let prefixes = announced.iter().map(|x| format!("{}/{}", x.prefix, x.plen)).collect::<Vec<String>>();
+        assert_eq!(prefixes, ["10.0.0.0/8", "10.2.0.0/16"]);
+        let rt = &announced[1].rib_entries[0];
+        assert!(matches!(announced[1].source, NlriSource::Bgp4mp(1_700_000_000, None)));
+        assert_eq!(rt.origin_time, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert_eq!(rt.get_aspath(), "3356 174 4200000001");
+        assert_eq!((rt.get_origin(), rt.nexthop(), rt.get_med()), (0, Some(IpAddr::from_str("192.0.2.1").unwrap()), Some(20)));
+        let communities = rt.communities().map(|x| x.to_string()).collect::<Vec<String>>();
+        assert_eq!(communities, ["3356:100", "65535:65281", "3356:1:2"]);
+        // The prefixes of an update share its attributes
+        assert!(Arc::ptr_eq(&rt.attributes, &announced[0].rib_entries[0].attributes));
+    }
+
+    #[test]
+    fn mp_reach_and_unreach() {
+        let announced_prefix = [48, 0x20, 0x01, 0x0d, 0xb8, 0, 0x10];
+        let withdrawn_prefix = [48, 0x20, 0x01, 0x0d, 0xb8, 0, 0x20];
+        let nexthop = IpAddr::from_str("2001:db8::1").unwrap();
+        let IpAddr::V6(nexthop_bytes) = nexthop else { unreachable!() };
+        let mp_reach = [&[0, 2, 1, 16][..], &nexthop_bytes.octets(), &[0], &announced_prefix].concat();
+        let mp_unreach = [&[0, 2, 1][..], &withdrawn_prefix].concat();
+        let attributes = [attribute(0x40, 1, &[2]), attribute(0x40, 2, &sequence(&[6939], true)),
+                          attribute(0x80, 14, &mp_reach), attribute(0x80, 15, &mp_unreach)].concat();
+
+        let (announced, withdrawn) = update(message(4, &[], &attributes, &[]));
+        assert_eq!(withdrawn, [(IpAddr::from_str("2001:db8:20::").unwrap(), 48, 0)]);
+        assert_eq!(announced.len(), 1);
+        assert_eq!((announced[0].prefix, announced[0].plen), (IpAddr::from_str("2001:db8:10::").unwrap(), 48));
+        assert_eq!(announced[0].rib_entries[0].get_mp_nexthop(), Some(nexthop));
+        assert_eq!(announced[0].rib_entries[0].get_origin(), 2);
+    }
+
+    #[test]
+    fn other_events() {
+        let mut data = vec![0xfb, 0xf4, 0xfd, 0xe8, 0, 0, 0, 1, 192, 0, 2, 1, 192, 0, 2, 254];
+        data.extend([0, 1, 0, 6]);
+        let message = Bgp4mp::parse(1_700_000_000, 17, 0, &[&[0, 0, 0, 42][..], &data].concat()).unwrap();
+        assert!(matches!(message.event, Bgp4mpEvent::StateChange(1, 6)));
+        assert_eq!(message.microseconds, Some(42));
+
+        // A KEEPALIVE, and a message the collector sent
+        let keepalive = [&data[..16], &[0xff; 16], &[0, 19, 4]].concat();
+        assert!(matches!(Bgp4mp::parse(0, 16, 1, &keepalive).unwrap().event, Bgp4mpEvent::Message(4)));
+        let update = [&data[..16], &[0xff; 16], &[0, 23, 2, 0, 0, 0, 0]].concat();
+        assert!(matches!(Bgp4mp::parse(0, 16, 6, &update).unwrap().event, Bgp4mpEvent::Message(2)));
+
+        assert!(Bgp4mp::parse(0, 16, 3, &data).is_err());
+        assert!(Bgp4mp::parse(0, 16, 1, &data[..10]).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let nlri = prefix(None, [10, 0, 0, 0], 8);
+        let mut data = vec![0xfb, 0xf4, 0xfd, 0xe8, 0, 0, 0, 1, 192, 0, 2, 1, 192, 0, 2, 254];
+        data.extend([0xff; 16]);
+        data.extend([0, 30, 2, 0, 0, 0, 8]);      // 8 bytes of attributes, and none there
+        data.extend(&nlri);
+        assert!(Bgp4mp::parse(0, 16, 1, &data).is_err());
+        // A prefix longer than the address, or than the data
+        for nlri in [&[33, 10, 0, 0, 0, 0][..], &[24, 10, 0]] {
+            let data = encode(64500, 4, &[], &attributes(&[64500]), nlri);
+            assert!(Bgp4mp::parse(0, 16, 4, &data).is_err());
+        }
+    }
+
+    // AS_PATH and AGGREGATOR of a 2-byte speaker, with AS_TRANS for the
+    // 4-byte ASNs, and the AS4_PATH and AS4_AGGREGATOR given
+    fn as2_message(aspath: &[u32], aggregator: u16, as4_path: Option<&[u32]>) -> MrtRibEntry {
+        let mut attributes = [attribute(0x40, 1, &[0]), attribute(0x40, 2, &sequence(aspath, false)),
+                              attribute(0x40, 3, &[192, 0, 2, 1])].concat();
+        attributes.extend(attribute(0xc0, 7, &[&aggregator.to_be_bytes()[..], &[10, 0, 0, 2]].concat()));
+        if let Some(as4_path) = as4_path {
+            attributes.extend(attribute(0xc0, 17, &sequence(as4_path, true)));
+        }
+        attributes.extend(attribute(0xc0, 18, &[0xfa, 0x56, 0xea, 0x02, 10, 0, 0, 2]));
+        let (mut announced, _) = update(message(1, &[], &attributes, &prefix(None, [10, 0, 0, 0], 8)));
+        announced.remove(0).rib_entries.remove(0)
+    }
+
+    #[test]
+    fn as4_path_merge() {
+        let rt = as2_message(&[1299, 3356, 23456], 23456, Some(&[3356, 4200000002]));
+        assert_eq!(rt.get_aspath(), "1299 3356 4200000002");
+        assert_eq!(rt.get_aggregator(), Some((4200000002, Ipv4Addr::new(10, 0, 0, 2))));
+        // The AS4 attributes themselves are gone
+        assert!(!rt.attributes.iter().any(|x| matches!(x, MrtAttribute::Unknown(_, 17 | 18, _))));
+
+        // An AS4_PATH longer than the AS_PATH is ignored
+        let rt = as2_message(&[1299, 23456], 23456, Some(&[64500, 3356, 4200000002]));
+        assert_eq!(rt.get_aspath(), "1299 23456");
+        assert_eq!(rt.get_aggregator(), Some((4200000002, Ipv4Addr::new(10, 0, 0, 2))));
+
+        // As are both when the aggregator was a 4-byte speaker
+        let rt = as2_message(&[1299, 23456], 3356, Some(&[4200000002]));
+        assert_eq!(rt.get_aspath(), "1299 23456");
+        assert_eq!(rt.get_aggregator(), Some((3356, Ipv4Addr::new(10, 0, 0, 2))));
+
+        // Nothing to merge
+        let rt = as2_message(&[1299, 3356], 23456, None);
+        assert_eq!(rt.get_aspath(), "1299 3356");
+    }
+
+    #[test]
+    fn as4_path_merge_with_set() {
+        // An AS_SET counts as one
+        let mut aspath = sequence(&[1299, 23456], false);
+        aspath.extend([1, 1, 0xfb, 0xf4]);
+        let mut as4_path = sequence(&[4200000002], true);
+        as4_path.extend([1, 1, 0, 0, 0xfb, 0xf4]);
+        let attributes = [attribute(0x40, 1, &[0]), attribute(0x40, 2, &aspath), attribute(0x40, 3, &[192, 0, 2, 1]),
+                          attribute(0xc0, 17, &as4_path)].concat();
+        let (announced, _) = update(message(1, &[], &attributes, &prefix(None, [10, 0, 0, 0], 8)));
+        assert_eq!(announced[0].rib_entries[0].get_aspath(), "1299 4200000002 {64500}");
+    }
+}
@@ -1,16 +1,35 @@
 use crate::*;
+use regex::Regex;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Community {
     Standard((u16, u16)),
-    Large((u32, u32, u32))  // Not quite supported yet in attribute parsing
+    Large((u32, u32, u32)),
+    Extended((u8, u8, u64))     // type, sub-type, 48-bit value
 }
+
+// RFC 1997/7999/8326 well-known communities, by name
+pub const WELL_KNOWN_COMMUNITIES: [(&str, (u16, u16)); 9] = [
+    ("graceful-shutdown", (65535, 0)),
+    ("accept-own", (65535, 1)),
+    ("blackhole", (65535, 666)),
+    ("llgr-stale", (65535, 6)),
+    ("no-llgr", (65535, 7)),
+    ("no-export", (65535, 65281)),
+    ("no-advertise", (65535, 65282)),
+    ("no-export-subconfed", (65535, 65283)),
+    ("no-peer", (65535, 65284)),
+];
+
 impl FromStr for Community {
     type Err = ();
     fn from_str(s: &str) -> Result<Community, Self::Err> {
+        if let Some((_, value)) = WELL_KNOWN_COMMUNITIES.iter().find(|(name, _)| *name == s) {
+            return Ok(Community::Standard(*value));
+        }
         let token_split = s.split(":").collect::<Vec<&str>>();
         if token_split.len() == 2 {
-            match token_split.get(0).unwrap().parse::<u16>() {
+            match token_split.first().unwrap().parse::<u16>() {
                 Ok(a) => {
                     match token_split.get(1).unwrap().parse::<u16>() {
                         Ok(b) => Ok(Community::Standard((a, b))),
@@ -20,7 +39,10 @@ impl FromStr for Community {
                 _ => Err(()),
             }
         } else if token_split.len() == 3 {
-            match token_split.get(0).unwrap().parse::<u32>() {
+            if let Some(subtype) = Community::extended_subtype(token_split[0]) {
+                return Community::extended_from_parts(subtype, token_split[1], token_split[2]);
+            }
+            match token_split.first().unwrap().parse::<u32>() {
                 Ok(a) => {
                     match token_split.get(1).unwrap().parse::<u32>() {
                         Ok(b) => {
@@ -48,6 +70,27 @@ impl Display for Community {
             },
             Community::Large((a, b, c)) => {
                 write!(f, "{}:{}:{}", a, b, c)
+            },
+            Community::Extended((t, st, value)) => {
+                let label = match st {
+                    0x02 => "rt",
+                    0x03 => "soo",
+                    _ => "",
+                };
+                // Only the transitive/non-transitive AS and IPv4 specific
+                // types have a well-defined "global:local" split
+                match (t & 0xbf, label) {
+                    (0x00, label) if !label.is_empty() => {
+                        write!(f, "{}:{}:{}", label, value >> 32, value & 0xffffffff)
+                    },
+                    (0x01, label) if !label.is_empty() => {
+                        write!(f, "{}:{}:{}", label, Ipv4Addr::from_bits((value >> 16) as u32), value & 0xffff)
+                    },
+                    (0x02, label) if !label.is_empty() => {
+                        write!(f, "{}:{}:{}", label, value >> 16, value & 0xffff)
+                    },
+                    _ => write!(f, "0x{:02x}{:02x}:{:012x}", t, st, value)
+                }
             }
         }
     }
@@ -62,4 +105,243 @@ impl Community {
         Ok(community_list)
     }
 
+    // RFC 8092 large communities, 12 bytes each
+    pub fn parse_large<R: Read + BufRead>(reader: &mut R, num: usize) -> Result<Vec<Community>> {
+        let mut community_list: Vec<Community> = vec![];
+        for _ in 0..num {
+            community_list.push(Community::Large((
+                reader.read_u32::<BigEndian>()?,
+                reader.read_u32::<BigEndian>()?,
+                reader.read_u32::<BigEndian>()?
+            )))
+        }
+        Ok(community_list)
+    }
+
+    // RFC 4360 extended communities, 8 bytes each
+    pub fn parse_extended<R: Read + BufRead>(reader: &mut R, num: usize) -> Result<Vec<Community>> {
+        let mut community_list: Vec<Community> = vec![];
+        for _ in 0..num {
+            community_list.push(Community::Extended((
+                reader.read_u8()?,
+                reader.read_u8()?,
+                reader.read_u48::<BigEndian>()?
+            )))
+        }
+        Ok(community_list)
+    }
+
+    // Return the short name of a well-known community, if it is one
+    pub fn well_known_name(&self) -> Option<&'static str> {
+        match self {
+            Community::Standard(value) => WELL_KNOWN_COMMUNITIES
+                .iter()
+                .find(|(_, v)| v == value)
+                .map(|(name, _)| *name),
+            _ => None
+        }
+    }
+
+    fn extended_subtype(label: &str) -> Option<u8> {
+        match label {
+            "rt" => Some(0x02),
+            "soo" => Some(0x03),
+            _ => None
+        }
+    }
+
+    // Build an extended community from its textual "global:local" parts,
+    // choosing the AS2, IPv4 or AS4 specific type from the global admin form
+    fn extended_from_parts(subtype: u8, global: &str, local: &str) -> Result<Community, ()> {
+        if let Ok(ip) = Ipv4Addr::from_str(global) {
+            let local = local.parse::<u16>().map_err(|_| ())?;
+            return Ok(Community::Extended((0x01, subtype, ((ip.to_bits() as u64) << 16) | local as u64)));
+        }
+        let global = global.parse::<u32>().map_err(|_| ())?;
+        if global <= u16::MAX as u32 {
+            let local = local.parse::<u32>().map_err(|_| ())?;
+            Ok(Community::Extended((0x00, subtype, ((global as u64) << 32) | local as u64)))
+        } else {
+            let local = local.parse::<u16>().map_err(|_| ())?;
+            Ok(Community::Extended((0x02, subtype, ((global as u64) << 16) | local as u64)))
+        }
+    }
+
+    pub fn kind(&self) -> CommunityKind {
+        match self {
+            Community::Standard(_) => CommunityKind::Standard,
+            Community::Large(_) => CommunityKind::Large,
+            Community::Extended(_) => CommunityKind::Extended,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CommunityKind {
+    Standard,
+    Large,
+    Extended
+}
+
+// One colon-separated field of a community pattern
+#[derive(Debug, Clone)]
+pub enum CommunityField {
+    Any,                    // *
+    Value(u64),             // 65000
+    Range(u64, u64),        // 100-199
+    Digits(String),         // 1xx - 'x' matches any single digit
+    Text(String)            // rt, 192.0.2.1 - literal match
+}
+
+impl FromStr for CommunityField {
+    type Err = ();
+    fn from_str(s: &str) -> Result<CommunityField, Self::Err> {
+        if s == "*" {
+            return Ok(CommunityField::Any);
+        }
+        if let Ok(value) = s.parse::<u64>() {
+            return Ok(CommunityField::Value(value));
+        }
+        if let Some((low, high)) = s.split_once('-') {
+            if let (Ok(low), Ok(high)) = (low.parse::<u64>(), high.parse::<u64>()) {
+                if low <= high {
+                    return Ok(CommunityField::Range(low, high));
+                }
+            }
+            return Err(());
+        }
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == 'x' || c == 'X') {
+            return Ok(CommunityField::Digits(s.to_lowercase()));
+        }
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+            return Ok(CommunityField::Text(s.to_string()));
+        }
+        Err(())
+    }
+}
+
+impl CommunityField {
+    pub fn matches(&self, field: &str) -> bool {
+        match self {
+            CommunityField::Any => true,
+            CommunityField::Value(v) => field.parse::<u64>().is_ok_and(|x| x == *v),
+            CommunityField::Range(low, high) => field.parse::<u64>().is_ok_and(|x| x >= *low && x <= *high),
+            CommunityField::Digits(pattern) => {
+                pattern.len() == field.len() && pattern
+                    .chars()
+                    .zip(field.chars())
+                    .all(|(p, c)| if p == 'x' { c.is_ascii_digit() } else { p == c })
+            },
+            CommunityField::Text(text) => text == field,
+        }
+    }
+}
+
+// Wildcard, range or regex matching of communities, eg.
+//
+//   65000:1xx         standard, any of 65000:100 .. 65000:199
+//   3356:*            standard, anything from AS3356
+//   65000:100-199     standard, numeric range
+//   65000:*:10        large
+//   rt:65000:*        extended (route target, also soo:)
+//   /^3356:[0-9]+$/   regex against the textual form of any community
+#[derive(Debug, Clone)]
+pub enum CommunityPattern {
+    Exact(Community),
+    Fields(CommunityKind, Vec<CommunityField>),
+    Regex(Regex)
+}
+
+impl FromStr for CommunityPattern {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<CommunityPattern, Self::Err> {
+        if s.len() >= 2 && s.starts_with('/') && s.ends_with('/') {
+            return Ok(CommunityPattern::Regex(Regex::new(&s[1..s.len() - 1])?));
+        }
+
+        let tokens = s.split(':').collect::<Vec<&str>>();
+        let kind = match tokens.len() {
+            2 => CommunityKind::Standard,
+            3 if Community::extended_subtype(tokens[0]).is_some() => CommunityKind::Extended,
+            3 => CommunityKind::Large,
+            _ => return Err(anyhow!("community pattern should have 2 or 3 fields: {}", s)),
+        };
+        let fields = tokens
+            .iter()
+            .map(|x| CommunityField::from_str(x).map_err(|_| anyhow!("invalid community pattern field: {}", x)))
+            .collect::<Result<Vec<CommunityField>>>()?;
+        Ok(CommunityPattern::Fields(kind, fields))
+    }
+}
+
+impl CommunityPattern {
+    pub fn matches(&self, community: &Community) -> bool {
+        match self {
+            CommunityPattern::Exact(exact) => exact == community,
+            CommunityPattern::Regex(regex) => regex.is_match(&community.to_string()),
+            CommunityPattern::Fields(kind, fields) => {
+                if community.kind() != *kind {
+                    return false;
+                }
+                let text = community.to_string();
+                let parts = text.split(':').collect::<Vec<&str>>();
+                parts.len() == fields.len() && fields
+                    .iter()
+                    .zip(parts.iter())
+                    .all(|(field, part)| field.matches(part))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, community: &str) -> bool {
+        CommunityPattern::from_str(pattern).unwrap().matches(&Community::from_str(community).unwrap())
+    }
+
+    #[test]
+    fn well_known_names() {
+        assert_eq!(Community::from_str("no-export"), Ok(Community::Standard((65535, 65281))));
+        assert_eq!(Community::Standard((65535, 666)).well_known_name(), Some("blackhole"));
+        assert_eq!(Community::from_str("no-such-name"), Err(()));
+    }
+
+    #[test]
+    fn wildcards_and_digits() {
+        assert!(matches("3356:*", "3356:100"));
+        assert!(!matches("3356:*", "1299:100"));
+        assert!(matches("65000:1xx", "65000:150"));
+        assert!(!matches("65000:1xx", "65000:1500"));
+        assert!(!matches("65000:1xx", "65000:250"));
+        assert!(matches("65000:*:10", "65000:1:10"));
+        assert!(!matches("65000:*:10", "65000:1:11"));
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(matches("65000:100-199", "65000:100"));
+        assert!(matches("65000:100-199", "65000:199"));
+        assert!(!matches("65000:100-199", "65000:200"));
+        assert!(CommunityPattern::from_str("65000:199-100").is_err());
+    }
+
+    #[test]
+    fn kinds_are_kept_apart() {
+        // A large community is not a standard one, even if the fields fit
+        assert!(!matches("65000:*", "65000:1:2"));
+        assert!(matches("rt:65000:*", "rt:65000:1"));
+        assert!(!matches("rt:65000:*", "soo:65000:1"));
+        assert!(!matches("65000:*:*", "rt:65000:1"));
+    }
+
+    #[test]
+    fn regex() {
+        assert!(matches("/^3356:[0-9]+$/", "3356:100"));
+        assert!(!matches("/^3356:[0-9]+$/", "33560:100"));
+        assert!(matches("/^rt:/", "rt:65000:1"));
+        assert!(CommunityPattern::from_str("/(/").is_err());
+    }
 }
@@ -0,0 +1,260 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::*;
+
+// Delimited export (-O csv, and -t for pipe-separated), one row per path,
+// RFC 4180: a header row naming the columns, then the rows, each field
+// quoted if it holds the delimiter, a quote or a line break (with quotes
+// doubled). CSV rows end CRLF, as the RFC has it; terse rows end LF.
+//
+// The header is written once per load, and once per interactive query.
+// The columns and their order are chosen with -C, the delimiter with -d.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsvColumn {
+    Prefix,
+    Peer,
+    PeerAs,
+    RouterId,
+    NextHop,
+    Med,
+    LocalPref,
+    AsPath,
+    Origin,
+    Communities,
+    Collector,
+    View,
+    PathId,
+    Originated,         // RFC 3339
+    OriginatedEpoch,
+    AtomicAggregate,
+    Aggregator,
+    Status,             // best, multipath or not-best
+}
+
+const CSV_COLUMNS: [(&str, CsvColumn); 18] = [
+    ("prefix", CsvColumn::Prefix),
+    ("peer", CsvColumn::Peer),
+    ("peer_as", CsvColumn::PeerAs),
+    ("router_id", CsvColumn::RouterId),
+    ("next_hop", CsvColumn::NextHop),
+    ("med", CsvColumn::Med),
+    ("local_pref", CsvColumn::LocalPref),
+    ("as_path", CsvColumn::AsPath),
+    ("origin", CsvColumn::Origin),
+    ("communities", CsvColumn::Communities),
+    ("collector", CsvColumn::Collector),
+    ("view", CsvColumn::View),
+    ("path_id", CsvColumn::PathId),
+    ("originated", CsvColumn::Originated),
+    ("originated_epoch", CsvColumn::OriginatedEpoch),
+    ("atomic_aggregate", CsvColumn::AtomicAggregate),
+    ("aggregator", CsvColumn::Aggregator),
+    ("status", CsvColumn::Status),
+];
+
+// The columns without -C (with the collector and view for -c)
+const CSV_DEFAULT_COLUMNS: [CsvColumn; 8] = [
+    CsvColumn::Prefix, CsvColumn::Peer, CsvColumn::NextHop, CsvColumn::Med, CsvColumn::LocalPref,
+    CsvColumn::AsPath, CsvColumn::Origin, CsvColumn::Communities,
+];
+
+impl FromStr for CsvColumn {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<CsvColumn, Self::Err> {
+        CSV_COLUMNS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, column)| *column)
+            .ok_or_else(|| anyhow!("unknown column {} (one of {})", s,
+                                   CSV_COLUMNS.map(|(name, _)| name).join(", ")))
+    }
+}
+
+impl CsvColumn {
+    fn name(&self) -> &'static str {
+        CSV_COLUMNS.iter().find(|(_, column)| column == self).map_or("", |(name, _)| name)
+    }
+
+    // Comma separated column names, for -C
+    pub fn parse_list(s: &str) -> Result<Vec<CsvColumn>> {
+        s.split(',').map(|x| CsvColumn::from_str(x.trim())).collect()
+    }
+}
+
+// The delimiter for -d: a single character, or tab
+pub fn parse_delimiter(s: &str) -> Result<char> {
+    match s {
+        "tab" | "\\t" => Ok('\t'),
+        _ => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c != '"' && c != '\r' && c != '\n' => Ok(c),
+                _ => Err(anyhow!("delimiter {} not valid (a single character, or tab)", s)),
+            }
+        }
+    }
+}
+
+// Whether the header is still to be written
+static HEADER: AtomicBool = AtomicBool::new(true);
+
+// Start a table, for a load or an interactive query
+pub fn csv_begin() {
+    HEADER.store(true, Ordering::Relaxed);
+}
+
+fn csv_columns() -> Vec<CsvColumn> {
+    if !GETOPT.columns.is_empty() {
+        GETOPT.columns.clone()
+    } else if GETOPT.by_collector {
+        [&CSV_DEFAULT_COLUMNS[..], &[CsvColumn::Collector, CsvColumn::View]].concat()
+    } else {
+        CSV_DEFAULT_COLUMNS.to_vec()
+    }
+}
+
+fn csv_delimiter() -> char {
+    GETOPT.delimiter.unwrap_or(if GETOPT.dialect == Dialect::Terse { '|' } else { ',' })
+}
+
+// A field, quoted if need be
+fn csv_field(field: &str, delimiter: char) -> String {
+    if field.contains([delimiter, '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// The fields of a row, delimited
+fn csv_line(fields: &[String], delimiter: char) -> String {
+    fields
+        .iter()
+        .map(|x| csv_field(x, delimiter))
+        .collect::<Vec<String>>()
+        .join(&delimiter.to_string())
+}
+
+fn csv_row(fields: &[String]) {
+    let row = csv_line(fields, csv_delimiter());
+    if GETOPT.dialect == Dialect::Csv {
+        out!("{}\r\n", row);
+    } else {
+        outln!("{}", row);
+    }
+}
+
+pub fn csv_show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let columns = csv_columns();
+    // The header, and again at the start of each output file when split or rotated
+    if HEADER.swap(false, Ordering::Relaxed) || writer::at_start() {
+        csv_row(&columns.iter().map(|x| x.name().to_string()).collect::<Vec<String>>());
+    }
+    let status = if columns.contains(&CsvColumn::Status) {
+        GETOPT.bestpath.select(route_entries)
+    } else {
+        vec![]
+    };
+    for (index, rt) in route_entries.iter().enumerate() {
+        let originated = rt.origin_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let fields = columns
+            .iter()
+            .map(|column| match column {
+                CsvColumn::Prefix => format!("{}/{}", prefix, plen),
+                CsvColumn::Peer => rt.peer.peer_address.to_string(),
+                CsvColumn::PeerAs => rt.peer.peer_as.to_string(),
+                CsvColumn::RouterId => rt.peer.peer_id.to_string(),
+                CsvColumn::NextHop => rt.nexthop().map_or(String::new(), |x| x.to_string()),
+                CsvColumn::Med => rt.get_med().map_or(String::new(), |x| x.to_string()),
+                CsvColumn::LocalPref => rt.get_local_pref().map_or(String::new(), |x| x.to_string()),
+                CsvColumn::AsPath => rt.get_aspath(),
+                CsvColumn::Origin => String::from(match rt.get_origin() {
+                    0 => "igp",
+                    1 => "egp",
+                    2 => "incomplete",
+                    _ => "",
+                }),
+                CsvColumn::Communities => rt.get_community().unwrap_or_default(),
+                CsvColumn::Collector => rt.peer.collector_id.to_string(),
+                CsvColumn::View => rt.peer.view_name.clone(),
+                CsvColumn::PathId => rt.path_id.to_string(),
+                CsvColumn::Originated => util::rfc3339(originated),
+                CsvColumn::OriginatedEpoch => originated.to_string(),
+                CsvColumn::AtomicAggregate => rt.is_atomic_aggregate().to_string(),
+                CsvColumn::Aggregator => rt.get_aggregator().map_or(String::new(), |(asn, id)| format!("{} {}", asn, id)),
+                CsvColumn::Status => String::from(match status[index] {
+                    PathStatus::Best => "best",
+                    PathStatus::Multipath => "multipath",
+                    PathStatus::NotBest(_) => "not-best",
+                }),
+            })
+            .collect::<Vec<String>>();
+        csv_row(&fields);
+    }
+}
+
+// The peer summary, as a table of its own
+pub fn csv_show_summary(summary: &[PeerSummary]) {
+    let time = |time: Option<SystemTime>| time.map_or(String::new(), |x| util::rfc3339(util::epoch_secs(x)));
+    csv_row(&["collector", "view", "peer", "peer_as", "router_id", "ipv4_prefixes", "ipv6_prefixes",
+              "paths", "best_paths", "newest", "oldest"].map(String::from));
+    for peer in summary {
+        csv_row(&[peer.peer.collector_id.to_string(),
+                  peer.peer.view_name.clone(),
+                  peer.peer.peer_address.to_string(),
+                  peer.peer.peer_as.to_string(),
+                  peer.peer.peer_id.to_string(),
+                  peer.prefixes[0].to_string(),
+                  peer.prefixes[1].to_string(),
+                  peer.total_paths().to_string(),
+                  peer.total_best().to_string(),
+                  time(peer.newest),
+                  time(peer.oldest)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(fields: &[&str], delimiter: char) -> String {
+        csv_line(&fields.iter().map(|x| x.to_string()).collect::<Vec<String>>(), delimiter)
+    }
+
+    #[test]
+    fn plain_fields() {
+        assert_eq!(line(&["192.0.2.0/24", "64500 64501", "", "igp"], ','), "192.0.2.0/24,64500 64501,,igp");
+        assert_eq!(line(&["192.0.2.0/24", "64500:1 64500:2"], '|'), "192.0.2.0/24|64500:1 64500:2");
+    }
+
+    #[test]
+    fn quoted_fields() {
+        // The delimiter, whichever it is
+        assert_eq!(line(&["a,b", "c|d"], ','), "\"a,b\",c|d");
+        assert_eq!(line(&["a,b", "c|d"], '|'), "a,b|\"c|d\"");
+        assert_eq!(line(&["a\tb", "c d"], '\t'), "\"a\tb\"\tc d");
+        // Quotes, doubled
+        assert_eq!(line(&["say \"hi\""], ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(line(&["\""], ','), "\"\"\"\"");
+        // Line breaks, kept as they are
+        assert_eq!(line(&["a\r\nb", "c\nd", "e\rf"], ','), "\"a\r\nb\",\"c\nd\",\"e\rf\"");
+    }
+
+    #[test]
+    fn delimiters() {
+        assert_eq!(parse_delimiter(";").unwrap(), ';');
+        assert_eq!(parse_delimiter("tab").unwrap(), '\t');
+        assert_eq!(parse_delimiter("\\t").unwrap(), '\t');
+        for s in ["", ",,", "\"", "\n", "\r"] {
+            assert!(parse_delimiter(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(CsvColumn::parse_list("prefix, peer_as,as_path").unwrap(),
+                   [CsvColumn::Prefix, CsvColumn::PeerAs, CsvColumn::AsPath]);
+        assert!(CsvColumn::parse_list("prefix,nexthop").is_err());
+        assert_eq!(CsvColumn::Communities.name(), "communities");
+    }
+}
@@ -0,0 +1,440 @@
+use std::fs::File;
+
+use arrow_array::builder::{ArrayBuilder, BinaryBuilder, BooleanBuilder, ListBuilder, StringBuilder, StructBuilder,
+                           TimestampSecondBuilder, UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Fields};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::*;
+
+// Export of the routes loaded (-E), one row per path, to Parquet or Arrow
+// IPC (by the file's extension: .parquet, or .arrow, .ipc or .feather),
+// for DuckDB, pandas and the like. The columns follow the JSON output:
+//
+//   prefix, prefix_length, status, not_best_reason, collector, view, peer,
+//   peer_as, router_id, path_id, originated (UTC), origin,
+//   as_path            list of {type: sequence or set, asns: list}
+//   origin_as, next_hop, med, local_pref, atomic_aggregate,
+//   aggregator         {as, router_id}, or null
+//   communities        list of {asn, value}
+//   extended_communities   list of {type, subtype, value}
+//   large_communities  list of {global, local1, local2}
+//   unknown_attributes list of {flags, type, data}
+//
+// The rows are written in batches, as the table is walked.
+
+const EXPORT_BATCH_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Parquet,
+    Arrow,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &str) -> Result<ExportFormat> {
+        match path.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase()).as_deref() {
+            Some("parquet") => Ok(ExportFormat::Parquet),
+            Some("arrow" | "ipc" | "feather") => Ok(ExportFormat::Arrow),
+            _ => Err(anyhow!("export file {} not valid (.parquet, or .arrow, .ipc or .feather)", path)),
+        }
+    }
+}
+
+enum ExportWriter {
+    Parquet(ArrowWriter<File>),
+    Arrow(FileWriter<File>),
+}
+
+impl ExportWriter {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            ExportWriter::Parquet(writer) => writer.write(batch)?,
+            ExportWriter::Arrow(writer) => writer.write(batch)?,
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<()> {
+        match self {
+            ExportWriter::Parquet(writer) => { writer.close()?; },
+            ExportWriter::Arrow(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+fn list_of(name: &str, data_type: DataType) -> Field {
+    Field::new(name, DataType::List(Arc::new(Field::new_list_field(data_type, true))), true)
+}
+
+fn struct_builder(fields: Vec<Field>, builders: Vec<Box<dyn ArrayBuilder>>) -> StructBuilder {
+    StructBuilder::new(Fields::from(fields), builders)
+}
+
+// The columns of a batch, as they are built
+struct Columns {
+    prefix: StringBuilder,
+    prefix_length: UInt8Builder,
+    status: StringBuilder,
+    not_best_reason: StringBuilder,
+    collector: StringBuilder,
+    view: StringBuilder,
+    peer: StringBuilder,
+    peer_as: UInt32Builder,
+    router_id: StringBuilder,
+    path_id: UInt32Builder,
+    originated: TimestampSecondBuilder,
+    origin: StringBuilder,
+    as_path: ListBuilder<StructBuilder>,
+    origin_as: UInt32Builder,
+    next_hop: StringBuilder,
+    med: UInt32Builder,
+    local_pref: UInt32Builder,
+    atomic_aggregate: BooleanBuilder,
+    aggregator: StructBuilder,
+    communities: ListBuilder<StructBuilder>,
+    extended_communities: ListBuilder<StructBuilder>,
+    large_communities: ListBuilder<StructBuilder>,
+    unknown_attributes: ListBuilder<StructBuilder>,
+    rows: usize,
+}
+
+impl Columns {
+    fn new() -> Columns {
+        Columns {
+            prefix: StringBuilder::new(),
+            prefix_length: UInt8Builder::new(),
+            status: StringBuilder::new(),
+            not_best_reason: StringBuilder::new(),
+            collector: StringBuilder::new(),
+            view: StringBuilder::new(),
+            peer: StringBuilder::new(),
+            peer_as: UInt32Builder::new(),
+            router_id: StringBuilder::new(),
+            path_id: UInt32Builder::new(),
+            originated: TimestampSecondBuilder::new(),
+            origin: StringBuilder::new(),
+            as_path: ListBuilder::new(struct_builder(
+                vec![Field::new("type", DataType::Utf8, false), list_of("asns", DataType::UInt32)],
+                vec![Box::new(StringBuilder::new()), Box::new(ListBuilder::new(UInt32Builder::new()))])),
+            origin_as: UInt32Builder::new(),
+            next_hop: StringBuilder::new(),
+            med: UInt32Builder::new(),
+            local_pref: UInt32Builder::new(),
+            atomic_aggregate: BooleanBuilder::new(),
+            aggregator: struct_builder(
+                vec![Field::new("as", DataType::UInt32, true), Field::new("router_id", DataType::Utf8, true)],
+                vec![Box::new(UInt32Builder::new()), Box::new(StringBuilder::new())]),
+            communities: ListBuilder::new(struct_builder(
+                vec![Field::new("asn", DataType::UInt16, false), Field::new("value", DataType::UInt16, false)],
+                vec![Box::new(UInt16Builder::new()), Box::new(UInt16Builder::new())])),
+            extended_communities: ListBuilder::new(struct_builder(
+                vec![Field::new("type", DataType::UInt8, false), Field::new("subtype", DataType::UInt8, false),
+                     Field::new("value", DataType::UInt64, false)],
+                vec![Box::new(UInt8Builder::new()), Box::new(UInt8Builder::new()), Box::new(UInt64Builder::new())])),
+            large_communities: ListBuilder::new(struct_builder(
+                vec![Field::new("global", DataType::UInt32, false), Field::new("local1", DataType::UInt32, false),
+                     Field::new("local2", DataType::UInt32, false)],
+                vec![Box::new(UInt32Builder::new()), Box::new(UInt32Builder::new()), Box::new(UInt32Builder::new())])),
+            unknown_attributes: ListBuilder::new(struct_builder(
+                vec![Field::new("flags", DataType::UInt8, false), Field::new("type", DataType::UInt8, false),
+                     Field::new("data", DataType::Binary, false)],
+                vec![Box::new(UInt8Builder::new()), Box::new(UInt8Builder::new()), Box::new(BinaryBuilder::new())])),
+            rows: 0,
+        }
+    }
+
+    fn push(&mut self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry, status: PathStatus) {
+        self.prefix.append_value(format!("{}/{}", prefix, plen));
+        self.prefix_length.append_value(plen);
+        let (status, reason) = match status {
+            PathStatus::Best => ("best", None),
+            PathStatus::Multipath => ("multipath", None),
+            PathStatus::NotBest(step) => ("not-best", Some(step.to_string())),
+        };
+        self.status.append_value(status);
+        self.not_best_reason.append_option(reason);
+        self.collector.append_value(rt.peer.collector_id.to_string());
+        self.view.append_value(&rt.peer.view_name);
+        self.peer.append_value(rt.peer.peer_address.to_string());
+        self.peer_as.append_value(rt.peer.peer_as);
+        self.router_id.append_value(rt.peer.peer_id.to_string());
+        self.path_id.append_value(rt.path_id);
+        self.originated.append_value(util::epoch_secs(rt.origin_time) as i64);
+
+        let mut origin = None;
+        let mut med = None;
+        let mut local_pref = None;
+        let mut atomic_aggregate = false;
+        let mut aggregator = None;
+        for attribute in rt.attributes.iter() {
+            match attribute {
+                MrtAttribute::Origin(value) => origin = match value {
+                    0 => Some("igp"),
+                    1 => Some("egp"),
+                    2 => Some("incomplete"),
+                    _ => None,
+                },
+                MrtAttribute::AsPath(aspath) => {
+                    let segments = self.as_path.values();
+                    for segment in &aspath.aspath_segments {
+                        field::<StringBuilder>(segments, 0).append_value(if segment.ordered { "sequence" } else { "set" });
+                        let asns = field::<ListBuilder<UInt32Builder>>(segments, 1);
+                        asns.values().append_slice(&segment.asns);
+                        asns.append(true);
+                        segments.append(true);
+                    }
+                },
+                MrtAttribute::MultiExitDisc(value) => med = Some(*value),
+                MrtAttribute::LocalPref(value) => local_pref = Some(*value),
+                MrtAttribute::AtomicAggregate => atomic_aggregate = true,
+                MrtAttribute::Aggregator(asn, id) => aggregator = Some((*asn, *id)),
+                MrtAttribute::Community(list) | MrtAttribute::ExtendedCommunity(list) | MrtAttribute::LargeCommunity(list) => {
+                    for community in list.iter() {
+                        match community {
+                            Community::Standard((asn, value)) => {
+                                let communities = self.communities.values();
+                                field::<UInt16Builder>(communities, 0).append_value(*asn);
+                                field::<UInt16Builder>(communities, 1).append_value(*value);
+                                communities.append(true);
+                            },
+                            Community::Extended((ext_type, ext_subtype, value)) => {
+                                let communities = self.extended_communities.values();
+                                field::<UInt8Builder>(communities, 0).append_value(*ext_type);
+                                field::<UInt8Builder>(communities, 1).append_value(*ext_subtype);
+                                field::<UInt64Builder>(communities, 2).append_value(*value);
+                                communities.append(true);
+                            },
+                            Community::Large((global, local1, local2)) => {
+                                let communities = self.large_communities.values();
+                                field::<UInt32Builder>(communities, 0).append_value(*global);
+                                field::<UInt32Builder>(communities, 1).append_value(*local1);
+                                field::<UInt32Builder>(communities, 2).append_value(*local2);
+                                communities.append(true);
+                            },
+                        }
+                    }
+                },
+                MrtAttribute::Unknown(flags, code, data) => {
+                    let unknown = self.unknown_attributes.values();
+                    field::<UInt8Builder>(unknown, 0).append_value(*flags);
+                    field::<UInt8Builder>(unknown, 1).append_value(*code);
+                    field::<BinaryBuilder>(unknown, 2).append_value(data);
+                    unknown.append(true);
+                },
+                MrtAttribute::NextHop(_) | MrtAttribute::MpNextHop(_) => {},
+            }
+        }
+        self.origin.append_option(origin);
+        self.as_path.append(true);
+        self.origin_as.append_option(rt.get_origin_as());
+        self.next_hop.append_option(rt.nexthop().map(|x| x.to_string()));
+        self.med.append_option(med);
+        self.local_pref.append_option(local_pref);
+        self.atomic_aggregate.append_value(atomic_aggregate);
+        field::<UInt32Builder>(&mut self.aggregator, 0).append_option(aggregator.map(|(asn, _)| asn));
+        field::<StringBuilder>(&mut self.aggregator, 1).append_option(aggregator.map(|(_, id)| id.to_string()));
+        self.aggregator.append(aggregator.is_some());
+        self.communities.append(true);
+        self.extended_communities.append(true);
+        self.large_communities.append(true);
+        self.unknown_attributes.append(true);
+        self.rows += 1;
+    }
+
+    // The rows so far, as a batch, leaving the columns empty
+    fn finish(&mut self) -> Result<RecordBatch> {
+        self.rows = 0;
+        let originated: ArrayRef = Arc::new(self.originated.finish().with_timezone_utc());
+        let columns: Vec<(&str, ArrayRef, bool)> = vec![
+            ("prefix", Arc::new(self.prefix.finish()), false),
+            ("prefix_length", Arc::new(self.prefix_length.finish()), false),
+            ("status", Arc::new(self.status.finish()), false),
+            ("not_best_reason", Arc::new(self.not_best_reason.finish()), true),
+            ("collector", Arc::new(self.collector.finish()), false),
+            ("view", Arc::new(self.view.finish()), false),
+            ("peer", Arc::new(self.peer.finish()), false),
+            ("peer_as", Arc::new(self.peer_as.finish()), false),
+            ("router_id", Arc::new(self.router_id.finish()), false),
+            ("path_id", Arc::new(self.path_id.finish()), false),
+            ("originated", originated, false),
+            ("origin", Arc::new(self.origin.finish()), true),
+            ("as_path", Arc::new(self.as_path.finish()), false),
+            ("origin_as", Arc::new(self.origin_as.finish()), true),
+            ("next_hop", Arc::new(self.next_hop.finish()), true),
+            ("med", Arc::new(self.med.finish()), true),
+            ("local_pref", Arc::new(self.local_pref.finish()), true),
+            ("atomic_aggregate", Arc::new(self.atomic_aggregate.finish()), false),
+            ("aggregator", Arc::new(self.aggregator.finish()), true),
+            ("communities", Arc::new(self.communities.finish()), false),
+            ("extended_communities", Arc::new(self.extended_communities.finish()), false),
+            ("large_communities", Arc::new(self.large_communities.finish()), false),
+            ("unknown_attributes", Arc::new(self.unknown_attributes.finish()), false),
+        ];
+        Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+    }
+}
+
+// A field of a struct being built, by its position
+fn field<T: ArrayBuilder>(builder: &mut StructBuilder, index: usize) -> &mut T {
+    builder.field_builder::<T>(index).expect("export column types are consistent")
+}
+
+// Write the routes to the file, returning the number of paths
+pub fn export<'a>(path: &str, routes: impl Iterator<Item = (IpAddr, u8, &'a Vec<MrtRibEntry>)>) -> Result<u64> {
+    let format = ExportFormat::from_path(path)?;
+    let file = File::create(path).with_context(|| format!("cannot create {}", path))?;
+    let mut columns = Columns::new();
+
+    // The schema is that of the (empty) batch the columns start as
+    let schema = columns.finish()?.schema();
+    let mut writer = match format {
+        ExportFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .build();
+            ExportWriter::Parquet(ArrowWriter::try_new(file, schema, Some(properties))?)
+        },
+        ExportFormat::Arrow => ExportWriter::Arrow(FileWriter::try_new(file, &schema)?),
+    };
+
+    let mut count = 0;
+    for (prefix, plen, route_entries) in routes {
+        let status = GETOPT.bestpath.select(route_entries);
+        for (rt, status) in route_entries.iter().zip(status) {
+            columns.push(&prefix, plen, rt, status);
+            count += 1;
+        }
+        if columns.rows >= EXPORT_BATCH_SIZE {
+            writer.write(&columns.finish()?)?;
+        }
+    }
+    if columns.rows > 0 {
+        writer.write(&columns.finish()?)?;
+    }
+    writer.close()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{UInt16Type, UInt32Type, UInt64Type, UInt8Type};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    // The paths of test_paths(), then one with an AS set and an attribute
+    // that isn't decoded
+    fn table() -> RoutingTable {
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&IpAddr::from_str("10.0.0.0").unwrap(), 8, MrtRibEntry::test_paths());
+        let aspath = AsPath { aspath_segments: vec![AsPathSegment { ordered: true, asns: vec![64500] },
+                                                    AsPathSegment { ordered: false, asns: vec![64501, 64502] }] };
+        let rt = MrtRibEntry::test_path("2001:db8::1", 64500, &[64500], vec![]);
+        let rt = MrtRibEntry {
+            attributes: Arc::new(vec![MrtAttribute::Origin(2),
+                                      MrtAttribute::AsPath(Arc::new(aspath)),
+                                      MrtAttribute::MpNextHop(IpAddr::from_str("2001:db8::1").unwrap()),
+                                      MrtAttribute::Unknown(0xc0, 99, Arc::from(&[1, 2, 3][..]))]),
+            ..rt
+        };
+        routing_table.add(&IpAddr::from_str("2001:db8::").unwrap(), 32, vec![rt]);
+        routing_table
+    }
+
+    fn export_and_read(name: &str) -> RecordBatch {
+        let path = std::env::temp_dir().join(format!("mrtdump-{}-{}", std::process::id(), name));
+        let path = path.to_string_lossy().to_string();
+        let routing_table = table();
+        assert_eq!(export(&path, routing_table.iter()).unwrap(), 3);
+        let file = File::open(&path).unwrap();
+        let batches = match ExportFormat::from_path(&path).unwrap() {
+            ExportFormat::Parquet => ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap()
+                .collect::<Result<Vec<RecordBatch>, _>>().unwrap(),
+            ExportFormat::Arrow => arrow_ipc::reader::FileReader::try_new(file, None).unwrap()
+                .collect::<Result<Vec<RecordBatch>, _>>().unwrap(),
+        };
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches.len(), 1);
+        batches.into_iter().next().unwrap()
+    }
+
+    // The structs of a list column's row, each field formatted
+    fn structs(batch: &RecordBatch, column: &str, row: usize) -> Vec<String> {
+        let list = batch.column_by_name(column).unwrap().as_list::<i32>().value(row);
+        let list = list.as_struct();
+        (0..list.len()).map(|i| {
+            list.columns().iter().map(|field| {
+                match field.data_type() {
+                    DataType::Utf8 => field.as_string::<i32>().value(i).to_string(),
+                    DataType::UInt8 => field.as_primitive::<UInt8Type>().value(i).to_string(),
+                    DataType::UInt16 => field.as_primitive::<UInt16Type>().value(i).to_string(),
+                    DataType::UInt32 => field.as_primitive::<UInt32Type>().value(i).to_string(),
+                    DataType::UInt64 => field.as_primitive::<UInt64Type>().value(i).to_string(),
+                    DataType::Binary => format!("{:?}", field.as_binary::<i32>().value(i)),
+                    DataType::List(_) => format!("{:?}", field.as_list::<i32>().value(i).as_primitive::<UInt32Type>().values().to_vec()),
+                    other => panic!("{} not expected", other),
+                }
+            }).collect::<Vec<String>>().join(" ")
+        }).collect()
+    }
+
+    fn round_trip(name: &str) {
+        let batch = export_and_read(name);
+        let expected = Columns::new().finish().unwrap().schema();
+        assert_eq!(batch.schema().fields(), expected.fields());
+        assert_eq!(batch.num_rows(), 3);
+
+        let strings = |column: &str| {
+            let array = batch.column_by_name(column).unwrap().as_string::<i32>();
+            (0..array.len()).map(|i| array.is_valid(i).then(|| array.value(i).to_string())).collect::<Vec<Option<String>>>()
+        };
+        assert_eq!(strings("prefix"), [Some("10.0.0.0/8".into()), Some("10.0.0.0/8".into()), Some("2001:db8::/32".into())]);
+        assert_eq!(strings("status"), [Some("not-best".into()), Some("best".into()), Some("best".into())]);
+        assert_eq!(strings("next_hop"), [Some("192.0.2.1".into()), Some("192.0.2.3".into()), Some("2001:db8::1".into())]);
+
+        assert_eq!(structs(&batch, "as_path", 0), ["sequence [3356, 174, 64500]"]);
+        assert_eq!(structs(&batch, "as_path", 2), ["sequence [64500]", "set [64501, 64502]"]);
+        assert_eq!(structs(&batch, "communities", 0), ["3356 123", "65535 65281"]);
+        assert!(structs(&batch, "communities", 1).is_empty());
+        assert_eq!(structs(&batch, "extended_communities", 0), [format!("0 2 {}", (65000u64 << 32) | 100)]);
+        assert_eq!(structs(&batch, "large_communities", 0), ["3356 1 2"]);
+        assert!(structs(&batch, "unknown_attributes", 0).is_empty());
+        assert_eq!(structs(&batch, "unknown_attributes", 2), ["192 99 [1, 2, 3]"]);
+
+        let aggregator = batch.column_by_name("aggregator").unwrap().as_struct();
+        assert_eq!((aggregator.is_null(0), aggregator.is_null(1)), (true, false));
+        assert_eq!((aggregator.column(0).as_primitive::<UInt32Type>().value(1), aggregator.column(1).as_string::<i32>().value(1)),
+                   (64500, "198.51.100.1"));
+    }
+
+    #[test]
+    fn parquet() {
+        round_trip("export.parquet");
+    }
+
+    #[test]
+    fn arrow() {
+        round_trip("export.arrow");
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(ExportFormat::from_path("routes.parquet").unwrap(), ExportFormat::Parquet);
+        assert_eq!(ExportFormat::from_path("routes.PARQUET").unwrap(), ExportFormat::Parquet);
+        for path in ["routes.arrow", "routes.ipc", "out.d/routes.feather"] {
+            assert_eq!(ExportFormat::from_path(path).unwrap(), ExportFormat::Arrow);
+        }
+        for path in ["routes.csv", "routes", "routes.parquet.gz"] {
+            assert_eq!(ExportFormat::from_path(path).unwrap_err().to_string(),
+                       format!("export file {} not valid (.parquet, or .arrow, .ipc or .feather)", path));
+        }
+    }
+}
@@ -1,86 +1,467 @@
 use crate::*;
-#[derive(Debug)]
+use regex::Regex;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Filter {
-    LPM(IpAddr),
+    LPM(IpAddr),                    // longest match(es) covering the address
+    Covering(IpAddr),               // all prefixes covering the address
     Prefix(Prefix),
     // AsPath(String),
     As(u32),
     Community(Community),
-    Other(String)
+    CommunityPattern(CommunityPattern),
+    List(FilterList),
+    PrefixRange(Prefix, u8, u8),    // prefix-list style prefix, ge, le
+    PrefixLength(u8, u8),           // any prefix, min, max length
+    AsPathRegex(Regex),
+    AsPathLength(usize, usize),     // min, max
+    All(Vec<Filter>),
+    Any(Vec<Filter>),
+    Not(Box<Filter>),
+    Policy(Arc<Policy>),
+    Collector(IpAddr),              // paths from a collector (router ID)
+    View(String),                   // paths from a collector view name
+    Custom(Arc<dyn RoutePredicate>)
+}
+
+// Plug-in point for custom route predicates, for use of the filters
+// through the API rather than the command line, eg.
+//
+//   Filter::custom(|_prefix: &IpAddr, _plen: u8, rt: &MrtRibEntry| rt.get_med().is_some())
+//
+// Any Fn(&IpAddr, u8, &MrtRibEntry) -> bool closure is a predicate
+pub trait RoutePredicate: Send + Sync {
+    fn matches(&self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry) -> bool;
+
+    fn name(&self) -> String {
+        String::from(std::any::type_name::<Self>())
+    }
+}
+
+impl<F> RoutePredicate for F
+where
+    F: Fn(&IpAddr, u8, &MrtRibEntry) -> bool + Send + Sync,
+{
+    fn matches(&self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry) -> bool {
+        self(prefix, plen, rt)
+    }
+}
+
+impl std::fmt::Debug for dyn RoutePredicate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RoutePredicate({})", self.name())
+    }
 }
 
 impl FromStr for Filter {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Filter, Self::Err> {
-        match Prefix::from_str(s) {
-            Ok(prefix) => return Ok(Filter::Prefix(prefix)),
-            _ => {}
+        if let Some(collector) = s.strip_prefix("collector=") {
+            return Ok(Filter::Collector(IpAddr::from_str(collector)?));
         }
-        match IpAddr::from_str(s) {
-            Ok(ipaddr) => return Ok(Filter::LPM(ipaddr)),
-            _ => {}
+        if let Some(view) = s.strip_prefix("view=") {
+            return Ok(Filter::View(view.to_string()));
         }
-
-        match u32::from_str(s) {
-            Ok(asn) => return Ok(Filter::As(asn)),
-            _ => {}
+        if let Ok(prefix) = Prefix::from_str(s) {
+            return Ok(Filter::Prefix(prefix));
+        }
+        if let Ok(ipaddr) = IpAddr::from_str(s) {
+            return Ok(Filter::LPM(ipaddr));
+        }
+        if let Some(ipaddr) = s.strip_suffix('*').and_then(|x| IpAddr::from_str(x).ok()) {
+            return Ok(Filter::Covering(ipaddr));
+        }
+        if let Ok(asn) = u32::from_str(s) {
+            return Ok(Filter::As(asn));
         }
 
-        let community_pair = s.split(":").collect::<Vec<&str>>();
-        if community_pair.len() == 2 {
-            return Ok(Filter::Community(
-                Community::Standard((
-                    community_pair.get(0).context("community MSB")?.parse::<u16>()?,
-                    community_pair.get(1).context("community LSB")?.parse::<u16>()?
-                ))
-            ));
+        // Exact communities (including well-known names like no-export)
+        // first, then wildcards, ranges and regular expressions
+        if let Ok(community) = Community::from_str(s) {
+            return Ok(Filter::Community(community));
+        }
+        if let Ok(pattern) = CommunityPattern::from_str(s) {
+            return Ok(Filter::CommunityPattern(pattern));
         }
 
-        return Err(anyhow!("Invalid filter specification"));
+        Err(anyhow!("Invalid filter specification"))
     }
 }
 
 impl Filter {
-    // True or false to say if the NLRI matches the expression
+    // True or false to say if the NLRI matches the expression.
+    //
+    // Paths (rib entries) not matching the expression are removed
+    // from the NLRI, so that following filters and the output only
+    // see the paths of interest
+    //
+    // Policies may also modify the attributes of the paths they accept,
+    // so are not applied to the routes of a snapshot, which were saved
+    // with any policy already applied
     pub fn eval(&self, nlri: &mut MrtNlri) -> bool {
+        let (prefix, plen) = (nlri.prefix, nlri.plen);
+        match self {
+            Filter::Policy(_) if nlri.source == NlriSource::Table => {},
+            Filter::Policy(policy) => nlri.rib_entries.retain_mut(|x| policy.apply(&prefix, plen, x)),
+            _ => nlri.rib_entries.retain(|x| self.matches(&prefix, plen, x)),
+        }
+        !nlri.rib_entries.is_empty()
+    }
+
+    // True or false to say if a single path for the prefix
+    // matches the expression
+    pub fn matches(&self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry) -> bool {
 
         match self {
 
             // Return true if the assessed NLRI is contained
             // by the specific filter prefix term, eg. NLRI 192.0.2.0/24 would
             // match Prefix(192.0.0.0, 8).
-            Filter::Prefix(p) => {
-                if nlri.prefix.mask(p.len)==p.prefix.mask(p.len) && nlri.plen >= p.len {
-                    true
-                } else {
-                    false
-                }
-            },
+            Filter::Prefix(p) => p.covers(prefix, plen),
 
             // Return true if the filter term IP address is within the
-            // longest-prefix-match routing scope of the NLRI eg.
-            Filter::LPM(ipaddr) => {
-                if ipaddr.mask(nlri.plen)==nlri.prefix {
-                    true
-                } else {
-                    false
-                }
-            }
+            // routing scope of the NLRI, eg. 192.0.2.1 would match
+            // 192.0.2.0/24 and 192.0.0.0/16.
+            //
+            // The longest of those matches can only be known once the
+            // whole table has been seen, so for LPM every covering route
+            // is loaded, and the table then pruned to the longest match
+            // (see longest_match())
+            Filter::LPM(ipaddr) | Filter::Covering(ipaddr) => {
+                ipaddr.is_ipv4() == prefix.is_ipv4() && ipaddr.mask(plen) == *prefix
+            },
+
+            // Include only the paths with the specific ASN
+            Filter::As(asn) => rt.aspath_contains(*asn),
+
+            Filter::Community(comm) => rt.community_contains(comm),
 
-            // Filter (and modify) the NLRI rib entries to include only
-            // the paths with the specific ASN
-            Filter::As(asn) => {
-                nlri.rib_entries.retain(|x| x.aspath_contains(*asn));
-                !nlri.rib_entries.is_empty()
+            Filter::CommunityPattern(pattern) => rt.community_matches(pattern),
+
+            Filter::List(list) => list.matches(prefix, plen, rt),
+
+            // Prefix-list or route-filter style: equal to or more specific
+            // than the prefix, with a length within the given bounds
+            Filter::PrefixRange(p, ge, le) => {
+                p.covers(prefix, plen) && plen >= *ge && plen <= *le
             },
 
-            Filter::Community(comm) => {
-                nlri.rib_entries.retain(|x| x.community_contains(comm));
-                !nlri.rib_entries.is_empty()
+            Filter::PrefixLength(min, max) => plen >= *min && plen <= *max,
+
+            Filter::AsPathRegex(regex) => regex.is_match(&rt.get_aspath()),
+
+            Filter::AsPathLength(min, max) => {
+                let length = rt.get_aspath_length();
+                length >= *min && length <= *max
+            },
+
+            Filter::All(filters) => filters.iter().all(|x| x.matches(prefix, plen, rt)),
+            Filter::Any(filters) => filters.iter().any(|x| x.matches(prefix, plen, rt)),
+            Filter::Not(filter) => !filter.matches(prefix, plen, rt),
+
+            // Routing policy (route-map, policy-statement etc) permits the path
+            Filter::Policy(policy) => policy.permits(prefix, plen, rt),
+
+            Filter::Collector(collector) => rt.peer.collector_id == *collector,
+            Filter::View(view) => rt.peer.view_name == *view,
+
+            Filter::Custom(predicate) => predicate.matches(prefix, plen, rt),
+        }
+    }
+
+    pub fn custom<P: RoutePredicate + 'static>(predicate: P) -> Filter {
+        Filter::Custom(Arc::new(predicate))
+    }
+
+    // The address to look up in the loaded routing table, when only the
+    // longest match(es) for it should be reported
+    pub fn longest_match(&self) -> Option<IpAddr> {
+        match self {
+            Filter::LPM(ipaddr) => Some(*ipaddr),
+            _ => None
+        }
+    }
+
+    // A filter expression: terms combined with and, or and not (not
+    // binding tightest, then and) and grouped with ( ), eg.
+    //
+    //   3356 and not 65535:666
+    //   (10.0.0.0/8 or 192.168.0.0/16) and AS64500
+    //
+    // Each term is as for from_str, with ASNs also written AS12345. The
+    // longest match for an address is only known once the table is
+    // loaded, so it can't be combined with other terms.
+    pub fn parse_expression(s: &str) -> Result<Filter> {
+        let mut tokens = vec![];
+        for word in s.split_whitespace() {
+            let mut word = word;
+            while let Some(rest) = word.strip_prefix('(') {
+                tokens.push("(");
+                word = rest;
+            }
+            let mut closing = 0;
+            while let Some(rest) = word.strip_suffix(')') {
+                closing += 1;
+                word = rest;
+            }
+            if !word.is_empty() {
+                tokens.push(word);
+            }
+            tokens.extend(std::iter::repeat_n(")", closing));
+        }
+        let compound = tokens.iter().filter(|x| **x != "(" && **x != ")").count() > 1;
+        let mut tokens = tokens.into_iter().peekable();
+        let filter = parse_or(&mut tokens)?;
+        if let Some(token) = tokens.next() {
+            return Err(anyhow!("unexpected \"{}\"", token));
+        }
+        if compound && contains_lpm(&filter) {
+            return Err(anyhow!("a longest match (A.B.C.D) can't be combined with other terms"));
+        }
+        Ok(filter)
+    }
+
+    // Load a list of filter terms from a file, one per line, to be
+    // treated as alternatives (ORed)
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Filter> {
+        Ok(Filter::List(FilterList::from_file(path)?))
+    }
+}
+
+type Tokens<'a> = std::iter::Peekable<std::vec::IntoIter<&'a str>>;
+
+fn parse_or(tokens: &mut Tokens) -> Result<Filter> {
+    let mut alternatives = vec![parse_and(tokens)?];
+    while tokens.next_if_eq(&"or").is_some() {
+        alternatives.push(parse_and(tokens)?);
+    }
+    Ok(if alternatives.len() == 1 { alternatives.remove(0) } else { Filter::Any(alternatives) })
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<Filter> {
+    let mut terms = vec![parse_not(tokens)?];
+    while tokens.next_if_eq(&"and").is_some() {
+        terms.push(parse_not(tokens)?);
+    }
+    Ok(if terms.len() == 1 { terms.remove(0) } else { Filter::All(terms) })
+}
+
+fn parse_not(tokens: &mut Tokens) -> Result<Filter> {
+    match tokens.next() {
+        Some("not") => Ok(Filter::Not(Box::new(parse_not(tokens)?))),
+        Some("(") => {
+            let filter = parse_or(tokens)?;
+            match tokens.next() {
+                Some(")") => Ok(filter),
+                _ => Err(anyhow!("expected \")\"")),
             }
+        },
+        Some(token @ (")" | "and" | "or")) => Err(anyhow!("unexpected \"{}\"", token)),
+        Some(token) => {
+            let term = token
+                .strip_prefix("AS")
+                .or(token.strip_prefix("as"))
+                .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+                .unwrap_or(token);
+            Filter::from_str(term).map_err(|e| anyhow!("{}: {}", e, token))
+        },
+        None => Err(anyhow!("expression incomplete")),
+    }
+}
+
+fn contains_lpm(filter: &Filter) -> bool {
+    match filter {
+        Filter::LPM(_) => true,
+        Filter::All(filters) | Filter::Any(filters) => filters.iter().any(contains_lpm),
+        Filter::Not(filter) => contains_lpm(filter),
+        _ => false,
+    }
+}
+
+// A list of alternative filter terms, typically loaded from a file.
+//
+// Prefixes, ASNs and exact communities are held in hashed sets so that
+// evaluation stays fast against lists with many thousands of entries.
+// Anything else (community patterns etc) is evaluated term by term.
+#[derive(Debug, Clone, Default)]
+pub struct FilterList {
+    pub name: String,
+    pub prefixes: PrefixSet,
+    pub asns: HashSet<u32>,
+    pub communities: HashSet<Community>,
+    pub filters: Vec<Filter>,
+}
+
+impl FilterList {
+    pub fn new(name: &str) -> FilterList {
+        FilterList {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<FilterList> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("cannot open filter file {}", path.display()))?;
+        let mut list = FilterList::new(&path.display().to_string());
+
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            list.push_str(&line)
+                .with_context(|| format!("{}:{}: invalid filter \"{}\"", path.display(), line_number + 1, line.trim()))?;
+        }
+        Ok(list)
+    }
+
+    // Add a line of text to the list: a term or an expression (see
+    // Filter::parse_expression). Blank lines and #-comments are ignored.
+    pub fn push_str(&mut self, line: &str) -> Result<()> {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        self.push(Filter::parse_expression(line)?);
+        Ok(())
+    }
 
-            _ => false
+    pub fn push(&mut self, filter: Filter) {
+        match filter {
+            Filter::Prefix(prefix) => self.prefixes.insert(prefix),
+            Filter::As(asn) => { self.asns.insert(asn); },
+            Filter::Community(community) => { self.communities.insert(community); },
+            filter => self.filters.push(filter),
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len() + self.asns.len() + self.communities.len() + self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn matches(&self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry) -> bool {
+        self.prefixes.covers(prefix, plen)
+            || (!self.asns.is_empty() && rt.aspath_contains_any(&self.asns))
+            || (!self.communities.is_empty() && rt.communities().any(|x| self.communities.contains(x)))
+            || self.filters.iter().any(|x| x.matches(prefix, plen, rt))
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn path(aspath: &[u32], communities: &[Community]) -> MrtRibEntry {
+        MrtRibEntry::test_path("198.51.100.1", aspath[0], aspath,
+                               vec![MrtAttribute::Community(Arc::new(communities.to_vec()))])
+    }
+
+    #[test]
+    fn list_lines() {
+        let mut list = FilterList::new("test");
+        for line in ["# customers", "", "   ", "192.0.2.0/24", "10.0.0.0/8   # aggregate",
+                     "AS3356", "as174", "1299", "65000:100", "no-export", "65000:2xx", "view=backup"] {
+            list.push_str(line).unwrap();
+        }
+        // Prefixes, ASNs and exact communities go in the sets
+        assert_eq!((list.prefixes.len(), list.asns.len(), list.communities.len(), list.filters.len()), (2, 3, 2, 2));
+        assert_eq!(list.len(), 9);
+        assert!(list.asns.contains(&3356) && list.asns.contains(&174));
+
+        let other = path(&[64500], &[]);
+        assert!(list.matches(&ip("192.0.2.128"), 25, &other));
+        assert!(!list.matches(&ip("192.0.0.0"), 16, &path(&[64500], &[])));
+        assert!(list.matches(&ip("203.0.113.0"), 24, &path(&[64500, 174], &[])));
+        assert!(list.matches(&ip("203.0.113.0"), 24, &path(&[64500], &[Community::Standard((65535, 65281))])));
+        assert!(list.matches(&ip("203.0.113.0"), 24, &path(&[64500], &[Community::Standard((65000, 250))])));
+        assert!(!list.matches(&ip("203.0.113.0"), 24, &path(&[64500], &[Community::Standard((65000, 300))])));
+        // The collector of every test path
+        let mut list = FilterList::new("test");
+        list.push_str("collector=192.0.2.254").unwrap();
+        assert!(list.matches(&ip("203.0.113.0"), 24, &other));
+    }
+
+    #[test]
+    fn list_errors() {
+        let mut list = FilterList::new("test");
+        for line in ["AS", "ASx1", "192.0.2.0/24 and", "65000:1:2:3:4", "not"] {
+            assert!(list.push_str(line).is_err(), "{}", line);
+        }
+        assert!(list.is_empty());
+
+        let path = std::env::temp_dir().join(format!("mrtdump-filter-{}", std::process::id()));
+        std::fs::write(&path, "# prefixes\n192.0.2.0/24\n\n192.0.2.0/33x\n").unwrap();
+        let error = FilterList::from_file(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.ends_with(":4: invalid filter \"192.0.2.0/33x\""), "{}", error);
+        assert!(FilterList::from_file("/nonexistent/filters").is_err());
+    }
+
+    #[test]
+    fn expressions() {
+        let filter = Filter::parse_expression("3356 and not 65535:666").unwrap();
+        let blackhole = Community::Standard((65535, 666));
+        assert!(filter.matches(&ip("192.0.2.0"), 24, &path(&[64500, 3356], &[])));
+        assert!(!filter.matches(&ip("192.0.2.0"), 24, &path(&[64500, 3356], &[blackhole])));
+        assert!(!filter.matches(&ip("192.0.2.0"), 24, &path(&[64500], &[])));
+
+        // and binds tighter than or; parentheses, attached or not
+        let filter = Filter::parse_expression("AS174 or 3356 and 10.0.0.0/8").unwrap();
+        assert!(filter.matches(&ip("192.0.2.0"), 24, &path(&[174], &[])));
+        assert!(!filter.matches(&ip("192.0.2.0"), 24, &path(&[3356], &[])));
+        assert!(filter.matches(&ip("10.1.0.0"), 16, &path(&[3356], &[])));
+        for s in ["(AS174 or 3356) and 10.0.0.0/8", "( AS174 or 3356 ) and 10.0.0.0/8", "((174 or (3356))) and 10.0.0.0/8"] {
+            let filter = Filter::parse_expression(s).unwrap();
+            assert!(!filter.matches(&ip("192.0.2.0"), 24, &path(&[174], &[])), "{}", s);
+            assert!(filter.matches(&ip("10.1.0.0"), 16, &path(&[174], &[])), "{}", s);
+        }
+        assert!(Filter::parse_expression("not not 174").unwrap().matches(&ip("10.0.0.0"), 8, &path(&[174], &[])));
+
+        // A single term is just the term, an address its longest match
+        assert!(matches!(Filter::parse_expression("AS3356").unwrap(), Filter::As(3356)));
+        assert!(matches!(Filter::parse_expression("(192.0.2.1)").unwrap(), Filter::LPM(_)));
+        assert!(matches!(Filter::parse_expression("/^3356:(1|2)$/").unwrap(), Filter::CommunityPattern(_)));
+
+        for s in ["", "(3356", "3356)", "3356 174", "3356 and or 174", "192.0.2.1 and 3356", "not (192.0.2.1)"] {
+            assert!(Filter::parse_expression(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn covering_and_longest_match() {
+        let route = path(&[64500], &[]);
+        for s in ["10.1.2.3", "10.1.2.3*"] {
+            let filter = Filter::from_str(s).unwrap();
+            assert!(filter.matches(&ip("10.0.0.0"), 8, &route), "{}", s);
+            assert!(filter.matches(&ip("10.1.2.0"), 24, &route), "{}", s);
+            assert!(filter.matches(&ip("0.0.0.0"), 0, &route), "{}", s);
+            assert!(!filter.matches(&ip("10.1.3.0"), 24, &route), "{}", s);
+            assert!(!filter.matches(&ip("::"), 0, &route), "{}", s);
+        }
+        assert_eq!(Filter::from_str("10.1.2.3").unwrap().longest_match(), Some(ip("10.1.2.3")));
+        assert_eq!(Filter::from_str("10.1.2.3*").unwrap().longest_match(), None);
+        assert!(matches!(Filter::from_str("2001:db8::1*").unwrap(), Filter::Covering(_)));
+    }
+
+    #[test]
+    fn custom() {
+        let filter = Filter::custom(|_: &IpAddr, plen: u8, rt: &MrtRibEntry| plen <= 16 && rt.peer.peer_as == 64500);
+        assert!(filter.matches(&ip("10.0.0.0"), 8, &path(&[64500], &[])));
+        assert!(!filter.matches(&ip("10.1.2.0"), 24, &path(&[64500], &[])));
+
+        let mut list = FilterList::new("test");
+        list.push(Filter::Not(Box::new(filter)));
+        assert!(list.matches(&ip("10.1.2.0"), 24, &path(&[64500], &[])));
+        assert!(!list.matches(&ip("10.0.0.0"), 8, &path(&[64500], &[])));
+    }
+}
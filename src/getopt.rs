@@ -21,26 +21,61 @@ pub const DEFAULT_POSITIONAL: &str = "rib.mrt";  // default positional argument
 #[derive(Debug)]
 pub struct Getopt {
     pub verbose: bool,
-    pub juniper_output: bool,   // JUNOS style output
-    pub terse_output: bool,     // pipe-separated CSV
+    pub dialect: Dialect,       // output style: Cisco, JUNOS, pipe-separated, JSON
+    pub by_collector: bool,     // show and group paths by collector/view
     pub interactive: bool,  // interactive query post-load
+    pub benchmark: bool,    // routing table benchmark post-load
+    pub summary: bool,      // per peer summary post-load
+    pub dump: bool,         // show every route as loaded (bgpdump -m)
+    pub delimiter: Option<char>,    // CSV delimiter, if not the dialect's own
+    pub columns: Vec<CsvColumn>,    // CSV columns, if not the default
+    pub template: Option<Template>,         // --format, for each path
+    pub prefix_template: Option<Template>,  // --format-prefix, for each prefix
     // pub interval: u64,
     // pub addr: String,
     pub filter: Vec<Filter>,
+    pub policies: PolicySet,    // named policies for -p, loaded with -P
+    pub bestpath: BestPathConfig,   // best path selection options
+    pub output: Option<String>,     // output file, rather than stdout
+    pub plain: bool,                // no colour or pager on a terminal
+    pub split: Split,               // one output file per peer or collector
+    pub rotate: Option<u64>,        // size at which to start another output file
+    pub load_snapshot: Option<String>,
+    pub save_snapshot: Option<String>,
+    pub export: Option<String>,     // Parquet or Arrow IPC file to export the routes to
+    pub threads: usize,     // decoding threads, 1 for a sequential load
     pub args: Vec<String>,  // there are positional arguments
 }
 
 // Same here, setting the default values
+#[allow(clippy::derivable_impls)]
 impl Default for Getopt {
     fn default() -> Getopt {
         Getopt {
             verbose: false,
-            juniper_output: false,
-            terse_output: false,
+            dialect: Dialect::Cisco,
+            by_collector: false,
             interactive: false,
+            benchmark: false,
+            summary: false,
+            dump: false,
+            delimiter: None,
+            columns: vec![],
+            template: None,
+            prefix_template: None,
             // interval: DEFAULT_INTERVAL,
             // addr: DEFAULT_ADDRESS.to_string(),
             filter: vec![],
+            policies: PolicySet::new(),
+            bestpath: BestPathConfig::default(),
+            output: None,
+            plain: false,
+            split: Split::None,
+            rotate: None,
+            load_snapshot: None,
+            save_snapshot: None,
+            export: None,
+            threads: 1,
             args: vec![],
         }
     }
@@ -73,29 +108,143 @@ pub fn getopt() -> Getopt {
                 continue;
             },
             "-j" => {
-                getopt.juniper_output = true;
+                getopt.dialect = Dialect::Juniper;
                 // getopt.verbose = ! getopt.verbose; // toggle
                 continue;
             },
             "-t" => {
-                getopt.terse_output = true;
+                getopt.dialect = Dialect::Terse;
                 // getopt.verbose = ! getopt.verbose; // toggle
                 continue;
             },
+            "-O" => {
+                getopt.dialect = Dialect::from_str(&args.next().expect("expected output dialect"))
+                    .expect("output dialect not valid");
+                continue;
+            },
+            "-m" => {
+                getopt.dialect = Dialect::Bgpdump;
+                getopt.dump = true;
+                continue;
+            },
+            "-d" => {
+                getopt.delimiter = Some(csv::parse_delimiter(&args.next().expect("expected delimiter"))
+                    .expect("delimiter not valid"));
+                continue;
+            },
+            "-C" => {
+                getopt.columns = CsvColumn::parse_list(&args.next().expect("expected column list"))
+                    .expect("column list not valid");
+                continue;
+            },
+            "--format" => {
+                getopt.template = Some(Template::from_str(&args.next().expect("expected format template"))
+                    .expect("format template not valid"));
+                getopt.dialect = Dialect::Template;
+                continue;
+            },
+            "--format-prefix" => {
+                getopt.prefix_template = Some(Template::from_str(&args.next().expect("expected format template"))
+                    .expect("format template not valid"));
+                getopt.dialect = Dialect::Template;
+                continue;
+            },
+            "-c" => {
+                getopt.by_collector = true;
+                continue;
+            },
 
             "-f" => {
                 getopt.filter.push(
-                    Filter::from_str(&args
+                    Filter::parse_expression(&args
                         .next()
                         .expect("expected query expression")
                     ).expect("query expression not valid")
                 );
                 continue;
             },
+            "-F" => {
+                getopt.filter.push(
+                    Filter::from_file(args
+                        .next()
+                        .expect("expected filter file name")
+                    ).expect("filter file not valid")
+                );
+                continue;
+            },
+            "-P" => {
+                getopt.policies.load(&args
+                    .next()
+                    .expect("expected policy file name")
+                ).expect("policy file not valid");
+                continue;
+            },
+            "-p" => {
+                getopt.filter.push(Filter::Policy(getopt.policies.get(&args
+                    .next()
+                    .expect("expected policy name")
+                ).expect("policy name not valid")));
+                continue;
+            },
+            "-b" => {
+                getopt.bestpath = BestPathConfig::from_str(&args
+                    .next()
+                    .expect("expected best path options")
+                ).expect("best path options not valid");
+                continue;
+            },
+            "-o" => {
+                getopt.output = Some(args.next().expect("expected output file name"));
+                continue;
+            },
+            "-N" => {
+                getopt.plain = true;
+                continue;
+            },
+            "-x" => {
+                getopt.split = Split::from_str(&args.next().expect("expected split (peer or collector)"))
+                    .expect("split not valid");
+                continue;
+            },
+            "-R" => {
+                getopt.rotate = Some(writer::parse_size(&args.next().expect("expected rotation size"))
+                    .expect("rotation size not valid"));
+                continue;
+            },
+            "-L" => {
+                getopt.load_snapshot = Some(args.next().expect("expected snapshot file name"));
+                continue;
+            },
+            "-S" => {
+                getopt.save_snapshot = Some(args.next().expect("expected snapshot file name"));
+                continue;
+            },
+            "-E" => {
+                let path = args.next().expect("expected export file name");
+                export::ExportFormat::from_path(&path).expect("export file not valid");
+                getopt.export = Some(path);
+                continue;
+            },
             "-i" => {
                 getopt.interactive = true;
                 continue;
             },
+            "-s" => {
+                getopt.summary = true;
+                continue;
+            },
+            "-B" => {
+                getopt.benchmark = true;
+                continue;
+            },
+            "-T" => {
+                getopt.threads = match args.next().expect("expected number of threads").parse::<usize>()
+                    .expect("number of threads not valid") {
+                    0 => std::thread::available_parallelism().map_or(1, |x| x.get()),
+                    threads => threads,
+                };
+                continue;
+            },
 
             // usage text
             "-h" => { crate::usage(); break; },
@@ -106,9 +255,42 @@ pub fn getopt() -> Getopt {
         })
     }
 
+    if let Err(e) = check(&getopt) {
+        eprintln!("{}", e);
+        crate::usage();
+    }
+
+    // A JSON document can't be split across files, so its routes are
+    // written one per line instead
+    if getopt.dialect == Dialect::Json && (getopt.split != Split::None || getopt.rotate.is_some()) {
+        getopt.dialect = Dialect::Ndjson;
+    }
+
     // You can add an optional default positional here
-    if getopt.args.len()==0 {
+    if getopt.args.is_empty() && getopt.load_snapshot.is_none() {
         getopt.args.push(String::from(DEFAULT_POSITIONAL));
     }
     getopt
 }
+
+// Options that are only valid with others
+fn check(getopt: &Getopt) -> Result<()> {
+    if getopt.split != Split::None && getopt.output.is_none() {
+        return Err(anyhow!("Splitting the output (-x) needs an output file (-o)"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_needs_output() {
+        let getopt = Getopt { split: Split::Peer, ..Default::default() };
+        assert_eq!(check(&getopt).unwrap_err().to_string(), "Splitting the output (-x) needs an output file (-o)");
+        let getopt = Getopt { split: Split::Collector, output: Some(String::from("routes.txt")), ..Default::default() };
+        assert!(check(&getopt).is_ok());
+        assert!(check(&Getopt::default()).is_ok());
+    }
+}
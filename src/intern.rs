@@ -0,0 +1,224 @@
+use std::hash::Hash;
+use std::mem::size_of;
+use crate::*;
+
+// Interning of path attributes. A full table has millions of paths but only
+// a few hundred thousand distinct attribute sets, and far fewer distinct AS
+// paths and community lists, so the routing table keeps one shared copy of
+// each: paths with identical attributes point at the same set, and sets
+// that differ only in (say) MED still share their AS path and communities.
+//
+// Shared values are never modified in place: policy actions go through
+// Arc::make_mut, which copies a set (or list) before changing it.
+//
+// As paths are replaced or withdrawn the values only they held are left
+// with the interner as their sole owner. These are pruned once enough
+// paths have been released since the last time, to keep the cost of the
+// pass proportional to the releases.
+
+#[derive(Default)]
+pub struct Interner {
+    attribute_sets: HashSet<Arc<Vec<MrtAttribute>>>,
+    aspaths: HashSet<Arc<AsPath>>,
+    community_lists: HashSet<Arc<Vec<Community>>>,
+    released: usize,            // paths released since the last prune
+}
+
+// Fewest releases worth a pass over the interning tables
+const PRUNE_MINIMUM: usize = 1024;
+
+// The shared copy of a value, adding it if it is the first
+fn shared<T: Hash + Eq>(set: &mut HashSet<Arc<T>>, value: &Arc<T>) -> Arc<T> {
+    match set.get(value) {
+        Some(existing) => Arc::clone(existing),
+        None => {
+            set.insert(Arc::clone(value));
+            Arc::clone(value)
+        }
+    }
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    // Replace the attributes with the shared copy of an identical set,
+    // interning the AS path and community lists of a new set first
+    pub fn intern(&mut self, attributes: &mut Arc<Vec<MrtAttribute>>) {
+        if let Some(existing) = self.attribute_sets.get(attributes) {
+            *attributes = Arc::clone(existing);
+            return;
+        }
+        // A set kept for the life of the table is worth trimming to size
+        let set = Arc::make_mut(attributes);
+        set.shrink_to_fit();
+        for attribute in set.iter_mut() {
+            match attribute {
+                MrtAttribute::AsPath(aspath) => *aspath = shared(&mut self.aspaths, aspath),
+                MrtAttribute::Community(list) |
+                MrtAttribute::ExtendedCommunity(list) |
+                MrtAttribute::LargeCommunity(list) => *list = shared(&mut self.community_lists, list),
+                _ => {},
+            }
+        }
+        self.attribute_sets.insert(Arc::clone(attributes));
+    }
+
+    // Note that paths holding interned attributes have been released
+    // (replaced or removed), pruning the unused values if there have been
+    // enough of them. Values still held by the released paths are kept
+    // until the pass after they are dropped.
+    pub fn release(&mut self, count: usize) {
+        self.released += count;
+        if self.released >= PRUNE_MINIMUM.max(self.attribute_sets.len() / 2) {
+            self.prune();
+        }
+    }
+
+    // Drop the values held only by the interner: attribute sets first, as
+    // they hold the AS paths and community lists
+    pub fn prune(&mut self) {
+        self.attribute_sets.retain(|x| Arc::strong_count(x) > 1);
+        self.aspaths.retain(|x| Arc::strong_count(x) > 1);
+        self.community_lists.retain(|x| Arc::strong_count(x) > 1);
+        self.released = 0;
+    }
+
+    // Number of distinct attribute sets, AS paths and community lists
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (self.attribute_sets.len(), self.aspaths.len(), self.community_lists.len())
+    }
+
+    // Memory of the interning tables themselves: a handle and a control
+    // byte for each slot
+    pub fn memory(&self) -> usize {
+        (self.attribute_sets.capacity() + self.aspaths.capacity() + self.community_lists.capacity())
+            * (size_of::<usize>() + 1)
+    }
+}
+
+// Heap memory used by attributes, beyond the handle held by each path
+pub struct AttributeMemory {
+    pub paths: usize,
+    pub interned: usize,        // as held, each shared value counted once
+    pub owned: usize,           // were every path to own its attributes
+}
+
+// Size of an Arc allocation: the two reference counts and the value
+fn arc_size<T>() -> usize {
+    2 * size_of::<usize>() + size_of::<T>()
+}
+
+fn aspath_size(aspath: &AsPath) -> usize {
+    aspath.aspath_segments.capacity() * size_of::<AsPathSegment>()
+        + aspath.aspath_segments.iter().map(|x| x.asns.capacity() * size_of::<u32>()).sum::<usize>()
+}
+
+fn community_list_size(list: &Vec<Community>) -> usize {
+    list.capacity() * size_of::<Community>()
+}
+
+impl AttributeMemory {
+    pub fn measure(routing_table: &RoutingTable) -> AttributeMemory {
+        let mut memory = AttributeMemory { paths: 0, interned: routing_table.interner.memory(), owned: 0 };
+        let mut seen: HashSet<*const ()> = HashSet::new();
+        // Count an allocation only the first time it is seen
+        let mut first = |ptr: *const ()| seen.insert(ptr);
+
+        for (_, _, paths) in routing_table.iter() {
+            for path in paths {
+                memory.paths += 1;
+                let attributes = &path.attributes;
+                let set_size = attributes.capacity() * size_of::<MrtAttribute>();
+                memory.owned += set_size;
+                if first(Arc::as_ptr(attributes) as *const ()) {
+                    memory.interned += arc_size::<Vec<MrtAttribute>>() + set_size;
+                }
+                for attribute in attributes.iter() {
+                    let (ptr, size) = match attribute {
+                        MrtAttribute::AsPath(aspath) => {
+                            (Arc::as_ptr(aspath) as *const (), arc_size::<AsPath>() + aspath_size(aspath))
+                        },
+                        MrtAttribute::Community(list) |
+                        MrtAttribute::ExtendedCommunity(list) |
+                        MrtAttribute::LargeCommunity(list) => {
+                            (Arc::as_ptr(list) as *const (), arc_size::<Vec<Community>>() + community_list_size(list))
+                        },
+                        _ => continue,
+                    };
+                    // Owned, the value would be inline in the attribute
+                    // rather than behind an Arc
+                    memory.owned += size - arc_size::<()>();
+                    if first(ptr) {
+                        memory.interned += size;
+                    }
+                }
+            }
+        }
+        memory
+    }
+}
+
+impl Display for AttributeMemory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let per_path = |bytes: usize| bytes as f64 / self.paths.max(1) as f64;
+        writeln!(f, "{} paths, attribute memory:", self.paths)?;
+        writeln!(f, "  interned   {:>12} bytes {:>8.1} bytes/path", self.interned, per_path(self.interned))?;
+        write!(f, "  owned      {:>12} bytes {:>8.1} bytes/path", self.owned, per_path(self.owned))
+    }
+}
+
+// Report the sharing achieved, and the attribute memory used against what
+// the same paths would use without interning
+pub fn memory_report(routing_table: &RoutingTable) {
+    let (attribute_sets, aspaths, community_lists) = routing_table.interner.counts();
+    let memory = AttributeMemory::measure(routing_table);
+    outln!("{} prefixes, {} distinct attribute sets, {} AS paths, {} community lists",
+             routing_table.len(), attribute_sets, aspaths, community_lists);
+    outln!("{}", memory);
+    if memory.interned > 0 {
+        outln!("  saving     {:>11.1}x", memory.owned as f64 / memory.interned as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(i: u32) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(0x0a000000 + (i << 8)))
+    }
+
+    #[test]
+    fn released_values_pruned() {
+        let mut routing_table = RoutingTable::new();
+        for i in 0..2000 {
+            let path = MrtRibEntry::test_path("198.51.100.1", 64500, &[64500, i],
+                                              vec![MrtAttribute::MultiExitDisc(i)]);
+            routing_table.add(&prefix(i), 24, vec![path]);
+        }
+        assert_eq!(routing_table.interner.counts(), (2000, 2000, 0));
+
+        // Replacing the paths prunes as it goes, the last few on demand
+        for i in 0..2000 {
+            let path = MrtRibEntry::test_path("198.51.100.1", 64500, &[64500], vec![]);
+            routing_table.add(&prefix(i), 24, vec![path]);
+        }
+        assert!(routing_table.interner.counts().0 < 2000);
+        routing_table.interner.prune();
+        assert_eq!(routing_table.interner.counts(), (1, 1, 0));
+
+        // Values still held are kept
+        let peer = IpAddr::from_str("198.51.100.1").unwrap();
+        let key = (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 254)), "", peer, 0);
+        for i in 1..2000 {
+            routing_table.remove_path(&prefix(i), 24, &key);
+        }
+        routing_table.interner.prune();
+        assert_eq!(routing_table.interner.counts(), (1, 1, 0));
+        routing_table.remove_path(&prefix(0), 24, &key);
+        routing_table.interner.prune();
+        assert_eq!(routing_table.interner.counts(), (0, 0, 0));
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt::Write as FmtWrite;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::*;
+
+// JSON output (-O json, -O ndjson). With json, each query (a -f load, or
+// an interactive command) is one document:
+//
+//   {"query": "10.0.0.0/8", "routes": [Route, ...]}
+//
+// with "query" null for a load. With ndjson, each Route is written on a
+// line of its own, as it is found, for streaming.
+//
+// The schema is stable: fields are only ever added, and every field is
+// always present, null when the path doesn't carry it.
+//
+//   Route  {"prefix": "192.0.2.0/24", "paths": [Path, ...]}
+//
+//   Path   {"status": "best" | "multipath" | "not-best",
+//           "not_best_reason": "AS path" | null,
+//           "peer": {"address": "198.51.100.1", "as": 64500,
+//                    "router_id": "198.51.100.1",
+//                    "collector": "192.0.2.254", "view": ""},
+//           "path_id": 0,
+//           "originated": "2023-11-14T22:13:20Z",
+//           "originated_epoch": 1700000000,
+//           "origin": "igp" | "egp" | "incomplete" | null,
+//           "as_path": [{"type": "sequence" | "set", "asns": [64500, 64501]}],
+//           "next_hop": "198.51.100.1" | null,
+//           "med": 0 | null,
+//           "local_pref": 100 | null,
+//           "atomic_aggregate": false,
+//           "aggregator": {"as": 64500, "router_id": "192.0.2.1"} | null,
+//           "communities": [Community, ...],
+//           "unknown_attributes": [{"flags": 192, "type": 99, "data": "0a0b"}]}
+//
+//   Community
+//          {"type": "standard", "asn": 65000, "value": 100,
+//           "name": "no-export" | null, "text": "65000:100"}
+//          {"type": "large", "global": 65000, "local1": 1, "local2": 2,
+//           "text": "65000:1:2"}
+//          {"type": "extended", "ext_type": 0, "ext_subtype": 2,
+//           "value": 281474976710657, "text": "rt:65000:1"}
+//
+// The interactive compare command gives "differences" instead of "routes":
+//
+//   {"change": "removed" | "added" | "changed", "prefix": "192.0.2.0/24",
+//    "path": Path, "other": Path | null}
+//
+// and collectors gives "collectors":
+//
+//   {"collector": "192.0.2.254", "view": "", "peers": [{"index": 0, Peer}]}
+
+// Whether the next item is the first of the document, and needs no comma
+static FIRST: AtomicBool = AtomicBool::new(true);
+
+// Start a document, for the query (None for a load)
+pub fn json_begin(query: Option<&str>, list: &str) {
+    if GETOPT.dialect == Dialect::Json {
+        FIRST.store(true, Ordering::Relaxed);
+        outln!("{{\"query\":{},\"{}\":[", query.map_or(String::from("null"), json_string), list);
+    }
+}
+
+pub fn json_end() {
+    if GETOPT.dialect == Dialect::Json {
+        outln!("]}}");
+    }
+}
+
+// Write an item of the document, or a line of NDJSON
+fn json_item(item: &str) {
+    if GETOPT.dialect == Dialect::Json && !FIRST.swap(false, Ordering::Relaxed) {
+        outln!(",{}", item);
+    } else {
+        outln!("{}", item);
+    }
+}
+
+pub fn json_show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    let paths = route_entries
+        .iter()
+        .zip(status)
+        .map(|(rt, status)| json_path(rt, Some(status)))
+        .collect::<Vec<String>>();
+    json_item(&format!("{{\"prefix\":\"{}/{}\",\"paths\":[{}]}}", prefix, plen, paths.join(",")));
+}
+
+pub fn json_show_difference(difference: &RouteDifference) {
+    let (change, prefix, plen, path, other) = match difference {
+        RouteDifference::Removed(prefix, plen, path) => ("removed", prefix, plen, path, None),
+        RouteDifference::Added(prefix, plen, path) => ("added", prefix, plen, path, None),
+        RouteDifference::Changed(prefix, plen, path, other) => ("changed", prefix, plen, path, Some(other)),
+    };
+    json_item(&format!("{{\"change\":\"{}\",\"prefix\":\"{}/{}\",\"path\":{},\"other\":{}}}",
+                       change, prefix, plen, json_path(path, None),
+                       other.map_or(String::from("null"), |x| json_path(x, None))));
+}
+
+pub fn json_show_collectors(peers: &HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) {
+    let mut keys = peers.keys().collect::<Vec<&(IpAddr, String, u16)>>();
+    keys.sort();
+    let mut collectors: Vec<((&IpAddr, &String), Vec<String>)> = vec![];
+    for key in keys {
+        let (collector_id, view_name, index) = key;
+        let peer = format!("{{\"index\":{},{}}}", index, json_peer_fields(&peers[key]));
+        match collectors.last_mut() {
+            Some((current, list)) if *current == (collector_id, view_name) => list.push(peer),
+            _ => collectors.push(((collector_id, view_name), vec![peer])),
+        }
+    }
+    for ((collector_id, view_name), list) in collectors {
+        json_item(&format!("{{\"collector\":\"{}\",\"view\":{},\"peers\":[{}]}}",
+                           collector_id, json_string(view_name), list.join(",")));
+    }
+}
+
+pub fn json_show_summary(summary: &[PeerSummary]) {
+    // "newest":"2023-11-14T22:13:20Z","newest_epoch":1700000000
+    let time = |name: &str, time: Option<SystemTime>| match time {
+        Some(time) => {
+            let secs = util::epoch_secs(time);
+            format!("\"{}\":\"{}\",\"{}_epoch\":{}", name, util::rfc3339(secs), name, secs)
+        },
+        None => format!("\"{}\":null,\"{}_epoch\":null", name, name),
+    };
+    for peer in summary {
+        json_item(&format!("{{{},\"ipv4_prefixes\":{},\"ipv6_prefixes\":{},\"paths\":{},\"best_paths\":{},{},{}}}",
+                           json_peer_fields(&peer.peer),
+                           peer.prefixes[0],
+                           peer.prefixes[1],
+                           peer.total_paths(),
+                           peer.total_best(),
+                           time("newest", peer.newest),
+                           time("oldest", peer.oldest)));
+    }
+}
+
+fn json_peer_fields(peer: &MrtPeer) -> String {
+    format!("\"address\":\"{}\",\"as\":{},\"router_id\":\"{}\",\"collector\":\"{}\",\"view\":{}",
+            peer.peer_address, peer.peer_as, peer.peer_id, peer.collector_id, json_string(&peer.view_name))
+}
+
+// A path, with its best path status when it is part of a route
+fn json_path(rt: &MrtRibEntry, status: Option<PathStatus>) -> String {
+    let mut json = String::from("{");
+    let (status, reason) = match status {
+        Some(PathStatus::Best) => ("\"best\"", None),
+        Some(PathStatus::Multipath) => ("\"multipath\"", None),
+        Some(PathStatus::NotBest(step)) => ("\"not-best\"", Some(step)),
+        None => ("null", None),
+    };
+    let originated = rt.origin_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let _ = write!(json, "\"status\":{},\"not_best_reason\":{},\"peer\":{{{}}},\"path_id\":{},\
+                          \"originated\":\"{}\",\"originated_epoch\":{},",
+                   status,
+                   reason.map_or(String::from("null"), |x| json_string(&x.to_string())),
+                   json_peer_fields(&rt.peer),
+                   rt.path_id,
+                   util::rfc3339(originated),
+                   originated);
+
+    let mut origin = "null";
+    let mut as_path = vec![];
+    let next_hop = rt.nexthop().map_or(String::from("null"), |x| format!("\"{}\"", x));
+    let mut med = String::from("null");
+    let mut local_pref = String::from("null");
+    let mut atomic_aggregate = false;
+    let mut aggregator = String::from("null");
+    let mut unknown = vec![];
+    for attribute in rt.attributes.iter() {
+        match attribute {
+            MrtAttribute::Origin(value) => origin = match value {
+                0 => "\"igp\"",
+                1 => "\"egp\"",
+                2 => "\"incomplete\"",
+                _ => "null",
+            },
+            MrtAttribute::AsPath(aspath) => {
+                as_path.extend(aspath.aspath_segments.iter().map(|segment| {
+                    format!("{{\"type\":\"{}\",\"asns\":[{}]}}",
+                            if segment.ordered { "sequence" } else { "set" },
+                            segment.asns.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(","))
+                }));
+            },
+            MrtAttribute::MultiExitDisc(value) => med = value.to_string(),
+            MrtAttribute::LocalPref(value) => local_pref = value.to_string(),
+            MrtAttribute::AtomicAggregate => atomic_aggregate = true,
+            MrtAttribute::Aggregator(asn, id) => aggregator = format!("{{\"as\":{},\"router_id\":\"{}\"}}", asn, id),
+            MrtAttribute::Unknown(flags, code, data) => {
+                unknown.push(format!("{{\"flags\":{},\"type\":{},\"data\":\"{}\"}}", flags, code,
+                                     data.iter().map(|x| format!("{:02x}", x)).collect::<String>()));
+            },
+            MrtAttribute::NextHop(_) | MrtAttribute::MpNextHop(_) |
+            MrtAttribute::Community(_) | MrtAttribute::ExtendedCommunity(_) | MrtAttribute::LargeCommunity(_) => {},
+        }
+    }
+    let communities = rt.communities().map(json_community).collect::<Vec<String>>();
+    let _ = write!(json, "\"origin\":{},\"as_path\":[{}],\"next_hop\":{},\"med\":{},\"local_pref\":{},\
+                          \"atomic_aggregate\":{},\"aggregator\":{},\"communities\":[{}],\"unknown_attributes\":[{}]}}",
+                   origin, as_path.join(","), next_hop, med, local_pref,
+                   atomic_aggregate, aggregator, communities.join(","), unknown.join(","));
+    json
+}
+
+fn json_community(community: &Community) -> String {
+    let text = json_string(&community.to_string());
+    match community {
+        Community::Standard((asn, value)) => {
+            format!("{{\"type\":\"standard\",\"asn\":{},\"value\":{},\"name\":{},\"text\":{}}}",
+                    asn, value, community.well_known_name().map_or(String::from("null"), json_string), text)
+        },
+        Community::Large((global, local1, local2)) => {
+            format!("{{\"type\":\"large\",\"global\":{},\"local1\":{},\"local2\":{},\"text\":{}}}",
+                    global, local1, local2, text)
+        },
+        Community::Extended((ext_type, ext_subtype, value)) => {
+            format!("{{\"type\":\"extended\",\"ext_type\":{},\"ext_subtype\":{},\"value\":{},\"text\":{}}}",
+                    ext_type, ext_subtype, value, text)
+        },
+    }
+}
+
+// A JSON string literal, quoted and escaped
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain() {
+        assert_eq!(json_string(""), r#""""#);
+        assert_eq!(json_string("AS3356 Level 3"), r#""AS3356 Level 3""#);
+        assert_eq!(json_string("Zürich ✓"), "\"Zürich ✓\"");
+    }
+
+    #[test]
+    fn quotes_and_backslashes() {
+        assert_eq!(json_string(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(json_string(r"_3356_\d+$"), r#""_3356_\\d+$""#);
+        assert_eq!(json_string(r#"\""#), r#""\\\"""#);
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(json_string("a\nb\r\nc\td"), r#""a\nb\r\nc\td""#);
+        assert_eq!(json_string("\0\x01\x08\x0c\x1b\x1f"), r#""\u0000\u0001\u0008\u000c\u001b\u001f""#);
+        // Only those below space: DEL and beyond are valid as they are
+        assert_eq!(json_string(" \x7f\u{85}"), "\" \x7f\u{85}\"");
+    }
+}
@@ -11,12 +11,14 @@ use std::process;
 use std::time::{Instant, Duration, SystemTime, UNIX_EPOCH};
 use anyhow::__private::kind::TraitKind;
 use time::OffsetDateTime;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use lazy_static::lazy_static;
 
+#[macro_use] mod writer; use writer::*;
 mod mrt; use mrt::*;
 mod rib; use rib::*;
+mod bgp4mp; use bgp4mp::*;
 mod attribute; use attribute::*;
 mod aspath; use aspath::*;
 mod peer;
@@ -25,11 +27,31 @@ mod util; use util::*;
 mod filter; use filter::*;
 mod ipaddrmask; use ipaddrmask::*;
 mod output; use output::*;
+mod output_bird; use output_bird::*;
+mod output_frr; use output_frr::*;
+mod output_eos; use output_eos::*;
+mod output_sros; use output_sros::*;
 
 mod routing_table; use routing_table::*;
 mod trie;
+mod patricia; use patricia::*;
+mod bench;
+mod summary; use summary::*;
+mod terminal; use terminal::*;
+mod bestpath; use bestpath::*;
+mod snapshot;
+mod export;
+mod json; use json::*;
+mod csv; use csv::*;
+mod template; use template::*;
+mod parallel;
+mod intern; use intern::*;
 mod prefix;
 mod community; use community::*;
+mod policy; use policy::*;
+mod policy_ios;
+mod policy_junos;
+mod policy_bird;
 
 use prefix::*;
 
@@ -39,22 +61,104 @@ use peer::*;
 
 lazy_static! { static ref GETOPT: getopt::Getopt = getopt::getopt(); }
 
-const CISCO_DEFAULT_WEIGHT: u32 = 32768;
 const DEFAULT_LOCAL_PREF: u32 = 100;
 
 pub fn usage() {
-    eprintln!("Usage: mrtdump [-v] [-j] [-i] [-f filter] filename");
+    eprintln!("Usage: mrtdump [-v] [-j] [-t] [-m] [-O dialect] [-d delimiter] [-C columns] [--format template] [--format-prefix template] [-c] [-o file [-x peer|collector] [-R size]] [-N] [-i] [-s] [-B] [-T threads] [-b options] [-L snapshot] [-S snapshot] [-E export-file] [-f filter] [-F filter-file] [-P policy-file [-p policy]] filename");
     eprintln!("       -v     verbose/debug (troubleshooting)");
     eprintln!("       -f     filter the routes loaded: (filters are ANDed, with initial default permit-all)");
     eprintln!("                 A.B.C.D/X - any routes equal or more specific");
+    eprintln!("                 A.B.C.D   - the longest match route(s) covering the address");
+    eprintln!("                 A.B.C.D*  - all routes covering the address, longest match or not");
     eprintln!("                 12345     - any routes with path containing the ASN (not full AS Path regex)");
     eprintln!("                 12345:100 - any routes with attached community attribute");
+    eprintln!("                             (also large 1:2:3, extended rt:1:2/soo:1:2, and well-known names");
+    eprintln!("                              no-export, no-advertise, blackhole, graceful-shutdown etc.)");
+    eprintln!("                 3356:*, 65000:1xx, 65000:100-199, 65000:*:10, rt:65000:*");
+    eprintln!("                           - community wildcards ('*' field, 'x' digit) and ranges");
+    eprintln!("                 /regex/   - any routes with a community matching the regular expression");
+    eprintln!("                 collector=A.B.C.D, view=NAME");
+    eprintln!("                           - any routes from the collector (router ID) or collector view");
+    eprintln!("              terms combine with and, or, not and ( ), eg. \"3356 and not 65535:666\"");
+    eprintln!("       -F     filter the routes loaded using a list of filter terms from a file, one per line,");
+    eprintln!("              any of which may match (eg. a prefix list, an ASN list or a community list);");
+    eprintln!("              a line may also be an expression, as for -f, and ASNs may be written AS12345");
+    eprintln!("       -P     load routing policy definitions from a router configuration file, optionally");
+    eprintln!("              prefixed with the dialect (ios:file, junos:file, bird:file) if not obvious");
+    eprintln!("                 IOS:   ip prefix-list, ip as-path access-list, ip community-list, route-map");
+    eprintln!("                 JUNOS: prefix-list, community, as-path, policy-statement");
+    eprintln!("                 BIRD:  define, filter");
+    eprintln!("       -p     filter the routes loaded using a named policy (route-map, prefix-list etc) from -P");
+    eprintln!("              applying its attribute changes (local-pref, MED, communities, AS path prepend,");
+    eprintln!("              next-hop) to the accepted routes, as the router would (but not to the routes of a");
+    eprintln!("              snapshot, -L, which were saved with any policy already applied)");
     eprintln!("       -j     use Juniper-style \"show route\" output (rather than Cisco \"show ip bgp\")");
-    eprintln!("       -i     run interactive shell for IP address queries after loading (default if no load filter)");
+    eprintln!("       -t     use terse, pipe-separated output (as -O csv -d '|', with LF line ends)");
+    eprintln!("       -O     output dialect: cisco (default), juniper (as -j), terse (as -t), csv (RFC 4180),");
+    eprintln!("              json (one document per load or query), ndjson (one route per line)");
+    eprintln!("              or bgpdump (as bgpdump -m), and the router CLIs bird (show route [all]),");
+    eprintln!("              frr (show bgp), eos (show ip bgp [detail]) and sros (show router bgp routes)");
+    eprintln!("       -m     show every route as loaded, one line per path, as bgpdump -m does: RIB entries");
+    eprintln!("              (TABLE_DUMP2), and the announcements, withdrawals and state changes of BGP4MP");
+    eprintln!("              updates (only the announcements that match, with -f)");
+    eprintln!("       -d     delimiter for csv and terse output: a single character, or tab");
+    eprintln!("       -C     columns for csv and terse output, comma separated, in order: prefix, peer,");
+    eprintln!("              peer_as, router_id, next_hop, med, local_pref, as_path, origin, communities,");
+    eprintln!("              collector, view, path_id, originated, originated_epoch, atomic_aggregate,");
+    eprintln!("              aggregator, status (default prefix to communities, then collector and view with -c)");
+    eprintln!("       --format");
+    eprintln!("              show each path with a template of {{field}} placeholders, eg. \"{{prefix}} {{peer_as}}");
+    eprintln!("              {{aspath}} {{communities|join:,}} {{med|default:-}}\"; fields prefix, network, plen, paths,");
+    eprintln!("              index, peer, peer_as, router_id, collector, view, path_id, nexthop, med, localpref,");
+    eprintln!("              aspath, aspath_length, origin, origin_char, origin_as, neighbor_as, communities,");
+    eprintln!("              atomic_aggregate, aggregator, age, originated, originated_epoch, status, status_code,");
+    eprintln!("              active_code, internal_code, not_best_reason, inactive_reason (as JUNOS); helpers");
+    eprintln!("              join:SEP, default:TEXT, left:N, right:N, column:N:INDENT, before:TEXT, after:TEXT, first,");
+    eprintln!("              upper, lower; \\n and \\t escapes; or the built-in template cisco, juniper (with which");
+    eprintln!("              -O cisco and -j show routes) or terse");
+    eprintln!("       --format-prefix");
+    eprintln!("              show each prefix with a template, before its paths (fields of the best path)");
+    eprintln!("       -c     show the collector and view of each path, grouping paths by collector");
+    eprintln!("       -o     write the output to a file, rather than stdout (diagnostics go to stderr)");
+    eprintln!("       -x     split the routes into a file per peer or per collector, named after the -o file");
+    eprintln!("              (routes.txt: routes-192.0.2.1.txt, ...), with anything else in the -o file itself");
+    eprintln!("       -R     start another file when one reaches the size (eg. 100M): routes.txt, routes.txt.1, ...");
+    eprintln!("              (json is written as ndjson when split or rotated)");
+    eprintln!("       -N     plain output on a terminal: no colour, and no pager ($PAGER, or less) for the");
+    eprintln!("              results of interactive queries (neither is used when the output is piped)");
+    eprintln!("       -b     best path selection options, comma separated: always-compare-med,");
+    eprintln!("              med-missing-as-worst, as-path-ignore, compare-routerid, multipath,");
+    eprintln!("              multipath-relax (multipath across neighbour ASes), local-as=N (iBGP peers)");
+    eprintln!("       -L     load a routing table snapshot saved with -S, before any MRT files");
+    eprintln!("       -S     save the loaded routing table to a snapshot file, for fast reloading with -L");
+    eprintln!("       -E     export the routes loaded (those matching the filters) to Parquet (.parquet) or");
+    eprintln!("              Arrow IPC (.arrow), one row per path, with the AS path, communities and unknown");
+    eprintln!("              attributes as list columns, rather than showing them");
+    eprintln!("       -s     show a summary of each peer after loading (as show ip bgp summary, or show bgp");
+    eprintln!("              summary with -j): AS, router ID, IPv4 and IPv6 prefixes, paths, best paths, and");
+    eprintln!("              the oldest and newest paths; as a table with -t or -O csv, and in JSON");
+    eprintln!("       -B     benchmark the routing table structures (memory, lookups/sec) with the loaded routes,");
+    eprintln!("              and report the memory saved by sharing identical path attributes");
+    eprintln!("       -T     decode the MRT files with this many threads (0 for one per CPU), alongside a");
+    eprintln!("              thread reading and decompressing; the output is the same as a sequential load");
+    eprintln!("       -i     run interactive shell for IP address queries after loading (default if no load filter),");
+    eprintln!("              also A.B.C.D/X (exact match), A.B.C.D/X longer-prefixes (more specifics)");
+    eprintln!("              and A.B.C.D/X shorter-prefixes (covering), neighbor A.B.C.D routes (one peer's routes)");
+    eprintln!("              and compare A.B.C.D A.B.C.D (differences between the routes of two peers),");
+    eprintln!("              collectors (the collectors, views and peers loaded), summary (as -s),");
+    eprintln!("              and memory (attribute memory, with and without interning)");
     process::exit(1);
 }
 
+// The output is buffered, so is flushed however the run ends
 fn main() -> Result<()> {
+    writer::open()?;
+    let result = run();
+    writer::flush();
+    result
+}
+
+fn run() -> Result<()> {
     if GETOPT.verbose {
         dbg!(&*GETOPT);
     }
@@ -63,113 +167,192 @@ fn main() -> Result<()> {
 
     // Global
     let mut routing_table = RoutingTable::new();
-    let mut peers: HashMap<(IpAddr, String, u16), Rc<MrtPeer>> = HashMap::new();
+    let mut peers: HashMap<(IpAddr, String, u16), Arc<MrtPeer>> = HashMap::new();
+
+    // Routes are shown as they are loaded when filtering (or dumping), as
+    // one document for JSON
+    let show_load = show_loaded();
+    if show_load {
+        json_begin(None, "routes");
+        csv_begin();
+    }
+
+    let mut sources = vec![];
+    if let Some(filename) = &GETOPT.load_snapshot {
+        let start_time = Instant::now();
+        let count;
+        (count, sources) = snapshot::load(filename, &mut routing_table, &mut peers)?;
+        eprintln!("{} entries from {} in {:?}", count, &filename, start_time.elapsed());
+    }
 
     for filename in &GETOPT.args {
 
-        let mut count: u64 = 0;
         let start_time = Instant::now();
 
-        let mut reader: Box<dyn BufRead> = {
-            if filename.ends_with(".bz2") {
-                Box::new(BufReader::new(BzDecoder::new(BufReader::new(std::fs::File::open(filename)?)))) as Box<dyn BufRead>
-            } else {
-                Box::new(BufReader::new(std::fs::File::open(filename)?)) as Box<dyn BufRead>
-            }
+        let reader = open_mrt(filename)?;
+
+        let loaded = if GETOPT.threads > 1 {
+            parallel::load(reader, GETOPT.threads, &mut routing_table, &mut peers)
+        } else {
+            load_mrt(reader, &mut routing_table, &mut peers)
         };
+        match loaded {
+            Ok(count) => eprintln!("{} entries from {} in {:?}", count, &filename, start_time.elapsed()),
+            Err(e) => eprintln!("Encountered error while reading {}: {}", &filename, &e),
+        }
+    }
 
-        // For each file
-        let mut peer_index_table: MrtPeerIndexTable = MrtPeerIndexTable::default();
+    // Longest match filters can only be resolved against the complete
+    // table, which is pruned to the longest matches before anything else
+    // uses it, and their routes are shown now rather than during the load
+    let longest_match = GETOPT.filter.iter().filter_map(|x| x.longest_match()).collect::<Vec<IpAddr>>();
+    for ipaddr in &longest_match {
+        routing_table.retain_longest_match(ipaddr);
+    }
+    if show_load && !longest_match.is_empty() {
+        for (prefix, plen, route_entries) in routing_table.iter() {
+            show_route(&prefix, plen, route_entries);
+        }
+    }
+    if show_load {
+        json_end();
+    }
 
-        // For each MRT message
-        loop {
-            match Mrt::parse(&mut reader, &peer_index_table) {
-                Ok(mrt) => {
-                    match mrt.data {
-                        MrtRecord::PeerIndexTable(table) => {
-                            let collector_id = table.collector_id.clone();
-                            let view_name = table.view_name.clone();
-
-                            peer_index_table = table;   // store the table
-
-                            // Load all the peers into the global table
-                            for (index, peer) in peer_index_table.peers.iter().enumerate() {
-                                if index < u16::MAX.into() {
-                                    peers.insert((collector_id, view_name.clone(), index as u16), Rc::clone(peer));
-                                }
-                            }
+    if let Some(filename) = &GETOPT.save_snapshot {
+        let start_time = Instant::now();
+        snapshot::save(filename, &routing_table, &peers, &sources, &GETOPT.args)?;
+        eprintln!("Saved snapshot {} in {:?}", &filename, start_time.elapsed());
+    }
 
-                            // If the filter is empty, or we are in verbose mode, then
-                            // show the Cisco header, because we will print summary routes
-                            // as we go
-                            if !GETOPT.filter.is_empty() {
-                                if GETOPT.juniper_output == false && GETOPT.terse_output == false {
-                                    cisco_show_ip_bgp_header(mrt.timestamp,
-                                                             &peer_index_table);
-                                }
-                            }
-                        }
-                        MrtRecord::RibIpv4Unicast(nlri) => {
-                            if load_nlri(nlri, &mut routing_table) {
-                                count += 1;
-                            }
-                        },
-                        MrtRecord::RibIpv6Unicast(nlri) => {
-                            if load_nlri(nlri, &mut routing_table) {
-                                count += 1;
-                            }
-                        },
+    // The routes loaded (those matching the filters) are exported rather
+    // than shown
+    if let Some(filename) = &GETOPT.export {
+        let start_time = Instant::now();
+        let count = export::export(filename, routing_table.iter())?;
+        eprintln!("Exported {} paths to {} in {:?}", count, &filename, start_time.elapsed());
+    }
 
-                        _ => {},
-                    }
-                }
-                Err(e) => {
-                    // what a ball-ache just to catch EOF as a non-error - do better, Adam!
-                    if let Some(e) = e.downcast_ref::<std::io::Error>() {
-                        if e.kind() == ErrorKind::UnexpectedEof {
-                            eprintln!("{} entries from {} in {:?}", count, &filename, start_time.elapsed());
-                            break;
-                        }
-                    }
-                    println!("Encountered error while reading {}: {}", &filename, &e);
-                    break;
-                }
-            }
-        }
+    if GETOPT.summary {
+        json_begin(None, "peers");
+        csv_begin();
+        show_summary(&routing_table, &peers);
+        json_end();
+    }
+
+    if GETOPT.benchmark {
+        bench::benchmark(&routing_table);
     }
 
     // Take interactive queries on the loaded routing table if there are
-    // no filters present, or if the interactive switch is requested
-    if GETOPT.interactive || GETOPT.filter.is_empty() {
+    // no filters present (and no dump), or if the interactive switch is
+    // requested
+    if GETOPT.interactive || (GETOPT.filter.is_empty() && !GETOPT.benchmark && !GETOPT.summary && !GETOPT.dump && GETOPT.export.is_none()) {
         let mut reader = io::stdin().lock();
         loop {
             let mut query = String::new();
-            print!("> "); let _ = io::stdout().flush();
+            // The prompt is kept out of JSON and other machine readable
+            // output, and out of an output file
+            writer::flush();
+            if GETOPT.dialect.is_machine_readable() || GETOPT.output.is_some() {
+                eprint!("> ");
+            } else {
+                print!("> "); let _ = io::stdout().flush();
+            }
             match reader.read_line(&mut query) {
                 Ok(usize) if usize > 0 => {
                     trim_newline(&mut query);
                     if query.is_empty() {
                         continue;
                     }
-                    match IpAddr::from_str(&query) {
-                        Ok(ipaddr) => {
-                            let result = routing_table.get(&ipaddr);
-                            if let Some((ipaddr, plen, route_entries)) = result {
-                                if GETOPT.juniper_output {
-                                    juniper_show_route(&ipaddr, plen, &route_entries);
-                                } else if GETOPT.terse_output {
-                                    csv_show_route(&ipaddr, plen, &route_entries);
+                    if terminal::is_paged() {
+                        writer::capture();
+                    }
+                    let words = query.split_whitespace().collect::<Vec<&str>>();
+                    let list = match words.first().copied().unwrap_or_default() {
+                        "compare" => Some("differences"),
+                        "collectors" => Some("collectors"),
+                        "summary" => Some("peers"),
+                        "memory" => None,
+                        _ => Some("routes"),
+                    };
+                    if let Some(list) = list {
+                        json_begin(Some(&query), list);
+                        csv_begin();
+                    }
+                    match (words.first().map(|x| Prefix::from_str(x)), words.get(1).copied()) {
+                        // A.B.C.D/X longer-prefixes, A.B.C.D/X shorter-prefixes
+                        (Some(Ok(prefix)), Some("longer-prefixes")) => {
+                            for (ipaddr, plen, route_entries) in routing_table.more_specifics(&prefix.prefix, prefix.len) {
+                                show_route(&ipaddr, plen, route_entries);
+                            }
+                        },
+                        (Some(Ok(prefix)), Some("shorter-prefixes")) => {
+                            for (ipaddr, plen, route_entries) in routing_table.less_specifics(&prefix.prefix, prefix.len) {
+                                show_route(&ipaddr, plen, route_entries);
+                            }
+                        },
+                        // neighbor A.B.C.D routes, compare A.B.C.D A.B.C.D
+                        _ if words.len() == 3 && words[0] == "neighbor" && words[2] == "routes" => {
+                            match IpAddr::from_str(words[1]) {
+                                Ok(peer) => {
+                                    for (prefix, plen, path) in routing_table.peer(&peer).iter() {
+                                        show_route(&prefix, plen, std::slice::from_ref(path));
+                                    }
+                                },
+                                _ => show_message(&format!("Invalid IP address: {}", words[1])),
+                            }
+                        },
+                        _ if words.len() == 3 && words[0] == "compare" => {
+                            match (IpAddr::from_str(words[1]), IpAddr::from_str(words[2])) {
+                                (Ok(a), Ok(b)) => {
+                                    for difference in routing_table.peer(&a).compare(&routing_table.peer(&b)) {
+                                        if GETOPT.dialect.is_json() {
+                                            json_show_difference(&difference);
+                                            continue;
+                                        }
+                                        match difference {
+                                            RouteDifference::Removed(prefix, plen, path) => outln!("- {}/{} {}", prefix, plen, path),
+                                            RouteDifference::Added(prefix, plen, path) => outln!("+ {}/{} {}", prefix, plen, path),
+                                            RouteDifference::Changed(prefix, plen, ours, theirs) => {
+                                                outln!("- {}/{} {}", prefix, plen, ours);
+                                                outln!("+ {}/{} {}", prefix, plen, theirs);
+                                            },
+                                        }
+                                    }
+                                },
+                                _ => show_message(&format!("Invalid IP address: {}", &query)),
+                            }
+                        },
+                        _ if query == "collectors" && GETOPT.dialect.is_json() => json_show_collectors(&peers),
+                        _ if query == "collectors" => show_collectors(&peers),
+                        _ if query == "summary" => show_summary(&routing_table, &peers),
+                        _ if query == "memory" => memory_report(&routing_table),
+                        (Some(Ok(prefix)), None) => {
+                            match routing_table.get_exact(&prefix.prefix, prefix.len) {
+                                Some(route_entries) => show_route_detail(&prefix.prefix, prefix.len, route_entries),
+                                None => show_message(&format!("Not found: {}", &query)),
+                            }
+                        },
+                        _ => match IpAddr::from_str(&query) {
+                            Ok(ipaddr) => {
+                                let result = routing_table.get(&ipaddr);
+                                if let Some((ipaddr, plen, route_entries)) = result {
+                                    show_route_detail(&ipaddr, plen, route_entries);
                                 } else {
-                                    cisco_show_ip_bgp_detail(&ipaddr, plen, &route_entries);
+                                    show_message(&format!("Not found: {}", &query));
                                 }
-                            } else {
-                                println!("Not found: {}", &query);
+                            },
+                            _ => {
+                                show_message(&format!("Invalid IP address: {}", &query));
                             }
-                        },
-                        _ => {
-                            println!("Invalid IP address: {}", &query);
                         }
                     }
+                    if list.is_some() {
+                        json_end();
+                    }
+                    if terminal::is_paged() {
+                        terminal::page(&writer::release());
+                    }
                 },
                 _ => { break; }
             }
@@ -182,6 +365,42 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+// Open an MRT file, decompressing it if need be
+pub fn open_mrt(filename: &str) -> Result<Box<dyn BufRead + Send>> {
+    if filename.ends_with(".bz2") {
+        Ok(Box::new(BufReader::new(BzDecoder::new(BufReader::new(std::fs::File::open(filename)?)))))
+    } else {
+        Ok(Box::new(BufReader::new(std::fs::File::open(filename)?)))
+    }
+}
+
+pub fn load_peer_index_table(timestamp: u32,
+                             peer_index_table: &MrtPeerIndexTable,
+                             peers: &mut HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) {
+    let collector_id = peer_index_table.collector_id;
+    let view_name = &peer_index_table.view_name;
+
+    // Load all the peers into the global table
+    for (index, peer) in peer_index_table.peers.iter().enumerate() {
+        if index < u16::MAX.into() {
+            peers.insert((collector_id, view_name.clone(), index as u16), Arc::clone(peer));
+        }
+    }
+
+    // If the filter is empty, or we are in verbose mode, then
+    // show the header of the dialect's summary, because we will
+    // print summary routes as we go
+    if !GETOPT.filter.is_empty() && GETOPT.export.is_none() {
+        match GETOPT.dialect {
+            Dialect::Cisco => cisco_show_ip_bgp_header(timestamp, peer_index_table),
+            Dialect::Frr => frr_show_bgp_header(peer_index_table),
+            Dialect::Eos => eos_show_ip_bgp_header(peer_index_table),
+            Dialect::Sros => sros_show_router_bgp_routes_header(peer_index_table),
+            _ => {},
+        }
+    }
+}
+
 // Before loading the NLRI into the routing table,
 // execute any specified load filters, in the order
 // defined.
@@ -194,40 +413,232 @@ fn main() -> Result<()> {
 // We start with permit (true) logic, so no filters means
 // all routes of course
 //
+// Load the records of an MRT file in order, returning the number of routes
+// loaded, or the first error in the file (the routes before it having been
+// loaded)
+pub fn load_mrt(mut reader: Box<dyn BufRead + Send>, routing_table: &mut RoutingTable,
+                peers: &mut HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) -> Result<u64> {
+    let mut count: u64 = 0;
+    let mut peer_index_table: MrtPeerIndexTable = MrtPeerIndexTable::default();
+    let mut bgp4mp_peers = Bgp4mpPeers::default();
+
+    // For each MRT message
+    loop {
+        let raw = match MrtRaw::read(&mut reader) {
+            Ok(raw) => raw,
+            Err(e) => {
+                // what a ball-ache just to catch EOF as a non-error - do better, Adam!
+                if let Some(e) = e.downcast_ref::<std::io::Error>() {
+                    if e.kind() == ErrorKind::UnexpectedEof {
+                        return Ok(count);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        // A record cut short within is an error, not the end of the file
+        let mrt = Mrt::decode(raw, &peer_index_table)?;
+        match mrt.data {
+            MrtRecord::PeerIndexTable(table) => {
+                peer_index_table = table;   // store the table
+                load_peer_index_table(mrt.timestamp, &peer_index_table, peers);
+            }
+            MrtRecord::RibIpv4Unicast(nlri) |
+            MrtRecord::RibIpv6Unicast(nlri) => {
+                count += load_nlri(nlri, routing_table) as u64;
+            },
+            MrtRecord::Bgp4mp(message) => {
+                count += load_bgp4mp(message, routing_table, peers, &mut bgp4mp_peers);
+            },
+
+            _ => {},
+        }
+    }
+}
+
 // The NLRI is consumed by this operation
 pub fn load_nlri(mut nlri: MrtNlri,
                     routing_table: &mut RoutingTable) -> bool {
+    let matched = filter_nlri(&mut nlri);
+    if matched {
+        add_nlri(nlri, routing_table);
+    }
+    matched
+}
 
-    let matched: bool = GETOPT.filter.iter().fold(true, |x, f| {
+// Apply the filters (and any policy) to the route, as loaded
+pub fn filter_nlri(nlri: &mut MrtNlri) -> bool {
+    GETOPT.filter.iter().fold(true, |x, f| {
         if x {
-            f.eval(&mut nlri)
+            f.eval(nlri)
         } else {
             x
         }
-    });
+    })
+}
 
-    if matched {
+// Whether routes are shown as they are loaded: when filtering (or
+// dumping), unless they are being exported instead
+fn show_loaded() -> bool {
+    (GETOPT.verbose || !GETOPT.filter.is_empty() || GETOPT.dump) && GETOPT.export.is_none()
+}
 
-        // Display the matched route if there are filters in play
-        // or if verbose  is enabled
-        if GETOPT.verbose || GETOPT.filter.len() > 0 {
-            if GETOPT.juniper_output {
-                juniper_show_route(&nlri.prefix, nlri.plen, &nlri.rib_entries);
-            } else if GETOPT.terse_output {
-                csv_show_route(&nlri.prefix, nlri.plen, &nlri.rib_entries);
-            } else {
-                cisco_show_ip_bgp(&nlri.prefix, nlri.plen, &nlri.rib_entries);
+// Add a route that has passed the filters to the table
+pub fn add_nlri(nlri: MrtNlri, routing_table: &mut RoutingTable) {
+    // Display the matched route if there are filters in play
+    // or if verbose  is enabled, unless we are waiting for the
+    // longest match
+    let deferred = GETOPT.filter.iter().any(|x| x.longest_match().is_some());
+    if show_loaded() && !deferred {
+        if GETOPT.dialect == Dialect::Bgpdump {
+            // Echoing the record the route came from
+            show_split(&nlri.rib_entries, |group| bgpdump_show_route(&nlri.prefix, nlri.plen, group, nlri.source));
+        } else {
+            show_route(&nlri.prefix, nlri.plen, &nlri.rib_entries);
+        }
+    }
+
+    routing_table.add(&nlri.prefix, nlri.plen, nlri.rib_entries);
+}
+
+// Apply a BGP4MP record to the table: the routes withdrawn are removed,
+// and those announced loaded as RIB entries are, returning the number
+// loaded. Its peer is interned, as a peer of the collector that is its
+// local address. Withdrawals and state changes have no path to filter, so
+// are only shown (by bgpdump output) when not filtering.
+pub fn load_bgp4mp(mut message: Bgp4mp, routing_table: &mut RoutingTable,
+                   peers: &mut HashMap<(IpAddr, String, u16), Arc<MrtPeer>>,
+                   bgp4mp_peers: &mut Bgp4mpPeers) -> u64 {
+    let (index, peer) = bgp4mp_peers.intern(&message.peer, peers);
+    message.peer = peer;
+    let show = GETOPT.dialect == Dialect::Bgpdump && show_loaded() && GETOPT.filter.is_empty();
+    let source = NlriSource::Bgp4mp(message.timestamp, message.microseconds);
+    match message.event {
+        Bgp4mpEvent::StateChange(old_state, new_state) => {
+            if show {
+                writer::select(writer::split_key(&message.peer));
+                bgpdump_show_state_change(&message.peer, source, old_state, new_state);
+                writer::select(None);
             }
+            0
+        },
+        Bgp4mpEvent::Update { withdrawn, announced } => {
+            let peer = &message.peer;
+            for (prefix, plen, path_id) in withdrawn {
+                if show {
+                    writer::select(writer::split_key(peer));
+                    bgpdump_show_withdrawal(peer, source, &prefix, plen);
+                    writer::select(None);
+                }
+                routing_table.remove_path(&prefix, plen, &(peer.collector_id, &peer.view_name, peer.peer_address, path_id));
+            }
+            announced.into_iter().map(|mut nlri| {
+                for path in nlri.rib_entries.iter_mut() {
+                    path.peer_id = index;
+                    path.peer = Arc::clone(peer);
+                }
+                load_nlri(nlri, routing_table) as u64
+            }).sum()
+        },
+        Bgp4mpEvent::Message(_) => 0,
+    }
+}
+
+// Show a route in the output, or when the output is split, the paths of
+// each peer (or collector) in its file
+fn show_split<F: Fn(&[MrtRibEntry])>(route_entries: &[MrtRibEntry], show: F) {
+    if GETOPT.split == Split::None {
+        writer::select(None);
+        show(route_entries);
+    } else {
+        for (key, group) in writer::split_paths(route_entries) {
+            writer::select(key);
+            show(&group);
         }
+        writer::select(None);
+    }
+}
 
-        match nlri.prefix {
-            IpAddr::V4(ipv4) => {
-                routing_table.v4.add(&ipv4, nlri.plen, nlri.rib_entries);
-            },
-            IpAddr::V6(ipv6) => {
-                routing_table.v6.add(&ipv6, nlri.plen, nlri.rib_entries);
+// Summary display of a route in the selected dialect
+pub fn show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    show_split(route_entries, |route_entries| {
+        // The terse and JSON output have the collector of each path instead
+        if GETOPT.by_collector && GETOPT.dialect.has_collector_headings() {
+            for ((collector_id, view_name), group) in group_by_collector(route_entries) {
+                collector_heading(&collector_id, &view_name);
+                show_route_dialect(prefix, plen, &group);
             }
+        } else {
+            show_route_dialect(prefix, plen, route_entries);
         }
+    });
+}
+
+fn show_route_dialect(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    match GETOPT.dialect {
+        Dialect::Cisco => cisco_show_ip_bgp(prefix, plen, route_entries),
+        Dialect::Juniper => juniper_show_route(prefix, plen, route_entries),
+        Dialect::Terse | Dialect::Csv => csv_show_route(prefix, plen, route_entries),
+        Dialect::Json | Dialect::Ndjson => json_show_route(prefix, plen, route_entries),
+        Dialect::Bgpdump => bgpdump_show_route(prefix, plen, route_entries, NlriSource::Table),
+        Dialect::Bird => bird_show_route(prefix, plen, route_entries),
+        Dialect::Frr => frr_show_bgp(prefix, plen, route_entries),
+        Dialect::Eos => eos_show_ip_bgp(prefix, plen, route_entries),
+        Dialect::Sros => sros_show_router_bgp_routes(prefix, plen, route_entries),
+        Dialect::Template => template_show_route(prefix, plen, route_entries),
     }
-    matched
-}
\ No newline at end of file
+}
+
+// Detailed display of a route in the selected dialect
+pub fn show_route_detail(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    show_split(route_entries, |route_entries| {
+        if GETOPT.by_collector && GETOPT.dialect.has_collector_headings() {
+            for ((collector_id, view_name), group) in group_by_collector(route_entries) {
+                collector_heading(&collector_id, &view_name);
+                show_route_detail_dialect(prefix, plen, &group);
+            }
+        } else {
+            show_route_detail_dialect(prefix, plen, route_entries);
+        }
+    });
+}
+
+fn show_route_detail_dialect(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    match GETOPT.dialect {
+        Dialect::Cisco => cisco_show_ip_bgp_detail(prefix, plen, route_entries),
+        Dialect::Juniper => juniper_show_route(prefix, plen, route_entries),
+        Dialect::Terse | Dialect::Csv => csv_show_route(prefix, plen, route_entries),
+        Dialect::Json | Dialect::Ndjson => json_show_route(prefix, plen, route_entries),
+        Dialect::Bgpdump => bgpdump_show_route(prefix, plen, route_entries, NlriSource::Table),
+        Dialect::Bird => bird_show_route_all(prefix, plen, route_entries),
+        Dialect::Frr => frr_show_bgp_detail(prefix, plen, route_entries),
+        Dialect::Eos => eos_show_ip_bgp_detail(prefix, plen, route_entries),
+        Dialect::Sros => sros_show_router_bgp_routes_detail(prefix, plen, route_entries),
+        Dialect::Template => template_show_route(prefix, plen, route_entries),
+    }
+}
+
+// Messages for the interactive user, kept out of JSON and other machine
+// readable output, and out of an output file
+fn show_message(message: &str) {
+    if GETOPT.dialect.is_machine_readable() || GETOPT.output.is_some() {
+        eprintln!("{}", message);
+    } else {
+        outln!("{}", message);
+    }
+}
+
+// The collectors and views loaded, with their peers
+fn show_collectors(peers: &HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) {
+    let mut keys = peers.keys().collect::<Vec<&(IpAddr, String, u16)>>();
+    keys.sort();
+    let mut current: Option<(&IpAddr, &String)> = None;
+    for key in keys {
+        let (collector_id, view_name, index) = key;
+        if current != Some((collector_id, view_name)) {
+            collector_heading(collector_id, view_name);
+            current = Some((collector_id, view_name));
+        }
+        outln!("  {:5} {}", index, peers[key]);
+    }
+}
@@ -46,12 +46,21 @@ pub enum MrtRecord {
     RibIpv4Multicast(MrtNlri),
     RibIpv6Unicast(MrtNlri),
     RibIpv6Multicast(MrtNlri),
+    Bgp4mp(Bgp4mp),
     RibGeneric,
 }
 
-impl Mrt {
+// An MRT record as read from the file, not yet decoded. Records can be
+// read on one thread and decoded on others.
+pub struct MrtRaw {
+    pub timestamp: u32,
+    pub mrt_type: u16,
+    pub mrt_subtype: u16,
+    pub data: Vec<u8>,
+}
 
-    pub fn parse<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable) -> anyhow::Result<Mrt> {
+impl MrtRaw {
+    pub fn read<R: Read>(reader: &mut R) -> anyhow::Result<MrtRaw> {
         let timestamp = reader.read_u32::<BigEndian>()?;
         let mrt_type = reader.read_u16::<BigEndian>()?;
         let mrt_subtype = reader.read_u16::<BigEndian>()?;
@@ -59,23 +68,56 @@ impl Mrt {
 
         let mut data = vec![0u8; length as usize];
         reader.read_exact(&mut data)?;
+        Ok(MrtRaw { timestamp, mrt_type, mrt_subtype, data })
+    }
+
+    pub fn is_peer_index_table(&self) -> bool {
+        (self.mrt_type, self.mrt_subtype) == (13, 1)
+    }
+}
+
+impl Mrt {
+
+    pub fn parse<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable) -> anyhow::Result<Mrt> {
+        Mrt::decode(MrtRaw::read(reader)?, peer_index_table)
+    }
+
+    pub fn decode(raw: MrtRaw, peer_index_table: &MrtPeerIndexTable) -> anyhow::Result<Mrt> {
+        let MrtRaw { timestamp, mrt_type, mrt_subtype, data } = raw;
+        let length = data.len() as u32;
         let mut slice = data.as_slice();
 
-        let data: MrtRecord = match (mrt_type, mrt_subtype) {
+        let mut data: MrtRecord = match (mrt_type, mrt_subtype) {
             (13, 1) => {
                 MrtRecord::PeerIndexTable(MrtPeerIndexTable::parse(&mut slice)?)
             },
             (13, 2) => {
-                MrtRecord::RibIpv4Unicast(MrtNlri::parse_v4(&mut slice, peer_index_table)?)
+                MrtRecord::RibIpv4Unicast(MrtNlri::parse_v4(&mut slice, peer_index_table, false)?)
             },
             (13, 3) => {
-                MrtRecord::RibIpv4Multicast(MrtNlri::parse_v4(&mut slice, peer_index_table)?)
+                MrtRecord::RibIpv4Multicast(MrtNlri::parse_v4(&mut slice, peer_index_table, false)?)
             },
             (13, 4) => {
-                MrtRecord::RibIpv6Unicast(MrtNlri::parse_v6(&mut slice, peer_index_table)?)
+                MrtRecord::RibIpv6Unicast(MrtNlri::parse_v6(&mut slice, peer_index_table, false)?)
             },
             (13, 5) => {
-                MrtRecord::RibIpv4Multicast(MrtNlri::parse_v6(&mut slice, peer_index_table)?)
+                MrtRecord::RibIpv6Multicast(MrtNlri::parse_v6(&mut slice, peer_index_table, false)?)
+            },
+            // RFC 8050 add-path variants
+            (13, 8) => {
+                MrtRecord::RibIpv4Unicast(MrtNlri::parse_v4(&mut slice, peer_index_table, true)?)
+            },
+            (13, 9) => {
+                MrtRecord::RibIpv4Multicast(MrtNlri::parse_v4(&mut slice, peer_index_table, true)?)
+            },
+            (13, 10) => {
+                MrtRecord::RibIpv6Unicast(MrtNlri::parse_v6(&mut slice, peer_index_table, true)?)
+            },
+            (13, 11) => {
+                MrtRecord::RibIpv6Multicast(MrtNlri::parse_v6(&mut slice, peer_index_table, true)?)
+            },
+            (16 | 17, _) => {
+                MrtRecord::Bgp4mp(Bgp4mp::parse(timestamp, mrt_type, mrt_subtype, slice)?)
             },
             _ => {
                 if GETOPT.verbose {
@@ -84,8 +126,117 @@ impl Mrt {
                 MrtRecord::RibGeneric
             }
         };
+        if let MrtRecord::RibIpv4Unicast(nlri) | MrtRecord::RibIpv4Multicast(nlri) |
+               MrtRecord::RibIpv6Unicast(nlri) | MrtRecord::RibIpv6Multicast(nlri) = &mut data {
+            nlri.source = NlriSource::TableDumpV2(timestamp);
+        }
         // reader.consume(length as usize);
         Ok(Mrt { timestamp, mrt_type, mrt_subtype, length, data })
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl MrtRaw {
+    // A PEER_INDEX_TABLE for collector 192.0.2.254, with the one peer
+    // 192.0.2.1 (AS64500), for tests
+    pub fn test_peer_index_table() -> MrtRaw {
+        let data = vec![192, 0, 2, 254, 0, 0, 0, 1,
+                        2, 192, 0, 2, 1, 192, 0, 2, 1, 0, 0, 0xfb, 0xf4];
+        MrtRaw { timestamp: 1_700_000_000, mrt_type: 13, mrt_subtype: 1, data }
+    }
+
+    // A TABLE_DUMP_V2 RIB record from the peer at the index, with an entry
+    // per path ID (with add-path) or a single entry, each with ORIGIN and
+    // a 4-byte AS_PATH of 64500, for tests
+    pub fn test_rib(subtype: u16, prefix: &[u8], plen: u8, peer_index: u16, path_ids: &[u32]) -> MrtRaw {
+        let addpath = subtype >= 8;
+        let attributes = [0x40, 1, 1, 0, 0x40, 2, 6, 2, 1, 0, 0, 0xfb, 0xf4];
+        let mut data = [&[0, 0, 0, 7, plen][..], prefix].concat();
+        data.extend((path_ids.len() as u16).to_be_bytes());
+        for path_id in path_ids {
+            data.extend(peer_index.to_be_bytes());
+            data.extend(1_700_000_000u32.to_be_bytes());
+            if addpath {
+                data.extend(path_id.to_be_bytes());
+            }
+            data.extend((attributes.len() as u16).to_be_bytes());
+            data.extend(attributes);
+        }
+        MrtRaw { timestamp: 1_700_000_000, mrt_type: 13, mrt_subtype: subtype, data }
+    }
+
+    // The record as written to a file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.timestamp.to_be_bytes()[..], &self.mrt_type.to_be_bytes(), &self.mrt_subtype.to_be_bytes(),
+         &(self.data.len() as u32).to_be_bytes(), &self.data].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn peer_index_table() -> MrtPeerIndexTable {
+        match Mrt::decode(MrtRaw::test_peer_index_table(), &MrtPeerIndexTable::default()).unwrap().data {
+            MrtRecord::PeerIndexTable(table) => table,
+            data => panic!("{:?}", data),
+        }
+    }
+
+    fn rib(subtype: u16, prefix: &[u8], plen: u8, path_ids: &[u32]) -> MrtRaw {
+        MrtRaw::test_rib(subtype, prefix, plen, 0, path_ids)
+    }
+
+    #[test]
+    fn records() {
+        let table = peer_index_table();
+        assert_eq!((table.collector_id, table.view_name.as_str(), table.peer_count), (IpAddr::V4(Ipv4Addr::new(192, 0, 2, 254)), "", 1));
+        assert_eq!(table.peers[0].to_string(), "192.0.2.1 (AS64500)");
+
+        // Read back as written
+        let bytes = rib(2, &[10], 8, &[0]).to_bytes();
+        let raw = MrtRaw::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!((raw.timestamp, raw.mrt_type, raw.mrt_subtype, raw.data), (1_700_000_000, 13, 2, rib(2, &[10], 8, &[0]).data));
+        assert!(MrtRaw::read(&mut &bytes[..bytes.len() - 1]).is_err());
+
+        // A RIB entry for a peer not in the table
+        assert!(Mrt::decode(MrtRaw::test_rib(2, &[10], 8, 1, &[0]), &table).is_err());
+    }
+
+    #[test]
+    fn addpath_rib_entries() {
+        let table = peer_index_table();
+        let v4 = IpAddr::V4(Ipv4Addr::new(10, 1, 0, 0));
+        let v6 = IpAddr::from_str("2001:db8::").unwrap();
+        for (subtype, prefix, plen, path_ids) in [(2, &[10, 1][..], 16, &[0][..]),
+                                                  (5, &[0x20, 0x01, 0x0d, 0xb8], 32, &[0]),
+                                                  (8, &[10, 1], 16, &[1, 2]),
+                                                  (9, &[10, 1], 16, &[3]),
+                                                  (10, &[0x20, 0x01, 0x0d, 0xb8], 32, &[4, 5, 6]),
+                                                  (11, &[0x20, 0x01, 0x0d, 0xb8], 32, &[7])] {
+            let mrt = Mrt::decode(rib(subtype, prefix, plen, path_ids), &table).unwrap();
+            let nlri = match (subtype, &mrt.data) {
+                (2 | 8, MrtRecord::RibIpv4Unicast(nlri)) |
+                (9, MrtRecord::RibIpv4Multicast(nlri)) |
+                (10, MrtRecord::RibIpv6Unicast(nlri)) |
+                (5 | 11, MrtRecord::RibIpv6Multicast(nlri)) => nlri,
+                (subtype, data) => panic!("subtype {}: {:?}", subtype, data),
+            };
+            assert_eq!((nlri.prefix, nlri.plen, nlri.sequence), (if plen == 16 { v4 } else { v6 }, plen, 7), "subtype {}", subtype);
+            assert_eq!(nlri.rib_entries.iter().map(|x| x.path_id).collect::<Vec<u32>>(), path_ids, "subtype {}", subtype);
+            for rt in &nlri.rib_entries {
+                assert_eq!((rt.peer.peer_address, rt.get_aspath()), (table.peers[0].peer_address, "64500".to_string()));
+                assert_eq!(rt.origin_time, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+            }
+        }
+
+        // Read without add-path, the path ID is taken as the attributes
+        let mut raw = rib(8, &[10, 1], 16, &[1]);
+        raw.mrt_subtype = 2;
+        match Mrt::decode(raw, &table).unwrap().data {
+            MrtRecord::RibIpv4Unicast(nlri) => assert!(nlri.rib_entries[0].attributes.is_empty()),
+            data => panic!("{:?}", data),
+        }
+    }
+}
@@ -1,72 +1,118 @@
 use crate::*;
-pub fn cisco_show_ip_bgp_header(version: u32,
-                                peers: &MrtPeerIndexTable) {
-    let (collector_id, view_name): (IpAddr, &String) = {
-        // if let Some(peers) = peers {
-            (peers.collector_id, &peers.view_name)
-        // } else {
-        //     (IpAddr::V4(Ipv4Addr::UNSPECIFIED), &String::from("default"))
-        // }
-    };
-    println!("BGP table version is {}, local router ID is {}, view is \"{}\"",
-             version, collector_id, view_name);
-    println!("Status codes: s suppressed, d damped, h history, * valid, > best, i - internal");
-    println!("Origin codes: i - IGP, e - EGP, ? - incomplete");
-    println!("");
-    println!("  {:24}{:24}\t{} {} {} {}",
-             "Network",
-             "Next Hop",
-             "Metric",
-             "LocPrf",
-             "Weight",
-             "Path"
-    );
-}
-pub fn cisco_show_ip_bgp(
-    //peers: &MrtPeerIndexTable,
-                         prefix: &IpAddr,
-                         plen: u8,
-                         route_entries: &Vec<MrtRibEntry>) {
-    let mut count: u64 = 0;
-    for rt in route_entries {
-        if count==0 {
-            println!("* {:24}{:24}\t{}\t{}\t{}\t{} {}",
-                     format!("{}/{}", prefix, plen),
-                     rt.get_nexthop(),
-                     rt.get_med().map(|x| x.to_string()).unwrap_or(String::new()),
-                     rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF),
-                     CISCO_DEFAULT_WEIGHT,
-                     rt.get_aspath(),
-                    rt.get_origin_char()
-            );
-        } else {
-            println!("* {:24}{:24}\t{}\t{}\t{}\t{} {}",
-                     String::new(),
-                     rt.get_nexthop(),
-                     rt.get_med().map(|x| x.to_string()).unwrap_or(String::new()),
-                     rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF),
-                     CISCO_DEFAULT_WEIGHT,
-                     rt.get_aspath(),
-                     rt.get_origin_char()
-            );
+
+// Output dialects, chosen with -O (or -j, -t)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Cisco,
+    Juniper,
+    Terse,
+    Csv,
+    Json,
+    Ndjson,
+    Bgpdump,
+    Bird,
+    Frr,
+    Eos,
+    Sros,
+    Template,
+}
+
+impl FromStr for Dialect {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Dialect, Self::Err> {
+        match s {
+            "cisco" | "ios" => Ok(Dialect::Cisco),
+            "juniper" | "junos" => Ok(Dialect::Juniper),
+            "terse" => Ok(Dialect::Terse),
+            "csv" => Ok(Dialect::Csv),
+            "json" => Ok(Dialect::Json),
+            "ndjson" => Ok(Dialect::Ndjson),
+            "bgpdump" => Ok(Dialect::Bgpdump),
+            "bird" => Ok(Dialect::Bird),
+            "frr" => Ok(Dialect::Frr),
+            "eos" | "arista" => Ok(Dialect::Eos),
+            "sros" | "nokia" => Ok(Dialect::Sros),
+            _ => Err(anyhow!("unknown output dialect {}", s)),
         }
-        count += 1;
     }
 }
 
+impl Dialect {
+    pub fn is_json(&self) -> bool {
+        matches!(self, Dialect::Json | Dialect::Ndjson)
+    }
+
+    // Dialects read by programs, whose output is kept to the routes: the
+    // prompt and messages of the interactive shell go to stderr
+    pub fn is_machine_readable(&self) -> bool {
+        matches!(self, Dialect::Terse | Dialect::Csv | Dialect::Json | Dialect::Ndjson | Dialect::Bgpdump
+                 | Dialect::Template)
+    }
+
+    // Dialects that show paths grouped under a heading per collector
+    // (the others give the collector of each path)
+    pub fn has_collector_headings(&self) -> bool {
+        !self.is_machine_readable()
+    }
+}
+
+// A column of the given width, or if the text fills it, the text and a
+// new line indented to where the next column starts (as IOS and FRR do)
+pub fn column(text: &str, width: usize, indent: usize) -> String {
+    if text.len() < width {
+        format!("{:width$}", text, width = width)
+    } else {
+        format!("{}\n{:indent$}", text, "", indent = indent)
+    }
+}
+
+// IOS-XE "show bgp" (summary) and "show bgp <prefix>" (detail), with what
+// MRT carries: there is no table version, so the header gives the time of
+// the RIB dump instead, and paths learned from peers have weight 0 and no
+// local preference unless they carry one.
+pub fn cisco_show_ip_bgp_header(timestamp: u32,
+                                peers: &MrtPeerIndexTable) {
+    outln!("BGP RIB dump time is {} UTC, local router ID is {}", util::ctime(timestamp as u64), peers.collector_id);
+    if !peers.view_name.is_empty() {
+        outln!("BGP view {}", peers.view_name);
+    }
+    outln!("Status codes: s suppressed, d damped, h history, * valid, > best, i - internal,");
+    outln!("              r RIB-failure, S Stale, m multipath, b backup-path, f RT-Filter,");
+    outln!("              x best-external, a additional-path, c RIB-compressed,");
+    outln!("              t secondary path, L long-lived-stale,");
+    outln!("Origin codes: i - IGP, e - EGP, ? - incomplete");
+    outln!("RPKI validation codes: V valid, I invalid, N Not found");
+    outln!();
+    outln!("     Network          Next Hop            Metric LocPrf Weight Path");
+}
+
+// Through the built-in template, as --format cisco
+pub fn cisco_show_ip_bgp(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    show_route_with(None, Some(template::built_in("cisco")), prefix, plen, route_entries);
+}
+
 pub fn cisco_show_ip_bgp_detail(
     //peers: &MrtPeerIndexTable,
                                 prefix: &IpAddr,
                                 plen: u8,
-                                route_entries: &Vec<MrtRibEntry>) {
+                                route_entries: &[MrtRibEntry]) {
     // let peers = peers.as_ref().unwrap();
-    println!("BGP routing table entry for {}/{}", prefix, plen);
-    println!("Paths: ({} available)", route_entries.len());
-    println!("  Not advertised to any peer");   // standard Cisco gubbins
+    let status = GETOPT.bestpath.select(route_entries);
+    outln!("BGP routing table entry for {}", paint(Style::Prefix, &format!("{}/{}", prefix, plen)));
+    match status.iter().position(|x| x.is_best()) {
+        Some(best) => outln!("Paths: ({} available, best #{}, table default)", route_entries.len(), best + 1),
+        None => outln!("Paths: ({} available, no best path)", route_entries.len()),
+    }
+    if status.contains(&PathStatus::Multipath) {
+        outln!("Multipath: eBGP");
+    }
+    outln!("  Not advertised to any peer");   // standard Cisco gubbins
 
-    for rt in route_entries {
-        println!("  {}", rt.get_aspath());
-        println!("    {} from {} ({})",
+    for (rt, status) in route_entries.iter().zip(status) {
+        let aspath = paint_aspath(rt);
+        let aggregator = rt.get_aggregator().map_or(String::new(), |(asn, id)| format!(", (aggregated by {} {})", asn, id));
+        outln!("  {}{}", if aspath.is_empty() { String::from("Local") } else { aspath }, aggregator);
+        outln!("    {} from {} ({})",
                  rt.get_nexthop(),
                  &rt.peer.peer_address,
                  &rt.peer.peer_id);
@@ -81,73 +127,224 @@ pub fn cisco_show_ip_bgp_detail(
         if let Some(med) = rt.get_med() {
             rt_text.push(format!("metric {}", med));
         }
-        rt_text.push(format!("localpref {}", rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF)));
-
-        // More standard Cisco gubbins
-        rt_text.push(String::from("weight 32768"));
+        if let Some(local_pref) = rt.get_local_pref() {
+            rt_text.push(format!("localpref {}", local_pref));
+        }
         rt_text.push(String::from("valid"));
+        rt_text.push(String::from(if GETOPT.bestpath.local_as == Some(rt.peer.peer_as) { "internal" } else { "external" }));
+        if rt.is_atomic_aggregate() {
+            rt_text.push(String::from("atomic-aggregate"));
+        }
+        match status {
+            PathStatus::Best => rt_text.push(paint(Style::Best, "best")),
+            PathStatus::Multipath => rt_text.push(String::from("multipath")),
+            PathStatus::NotBest(_) => {}
+        }
 
-        println!("      {}", rt_text.join(", "));
+        outln!("      {}", rt_text.join(", "));
         if let Some(community) = rt.get_community() {
-            println!("      Community: {}", &community);
+            outln!("      Community: {}", paint(Style::Community, &community));
+        }
+        if let PathStatus::NotBest(step) = status {
+            outln!("      Not best: {}", step);
         }
+        outln!("      rx pathid: {}", rt.path_id);
+        outln!("      Last update: {} UTC", util::ctime(util::epoch_secs(rt.origin_time)));
     }
 }
 
 
-pub fn juniper_show_route(
-    //peers: &MrtPeerIndexTable,
-                          prefix: &IpAddr,
-                          plen: u8,
-                          route_entries: &Vec<MrtRibEntry>) {
-    let mut count: u64 = 0;
+// Through the built-in template, as --format juniper
+pub fn juniper_show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    show_route_with(None, Some(template::built_in("juniper")), prefix, plen, route_entries);
+}
+
+// bgpdump -m output, one line per path:
+//
+//   TABLE_DUMP2|1700000000|B|198.51.100.1|64500|192.0.2.0/24|64500 64501|IGP|198.51.100.1|0|0|64500:1|NAG||
+//
+// with the time of the RIB record (B) or BGP4MP update (A), as written
+// by bgpdump 1.6. Routes from the table (a query, or a snapshot) have
+// no record, and give the time each path was originated.
+pub fn bgpdump_show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry], source: NlriSource) {
     for rt in route_entries {
-        let age = rt.origin_time.elapsed().unwrap_or_default();
-        let mut rt_text:Vec<String> = vec![format!("[BGP/170] {}", util::friendly_duration(age))];
-        if let Some(med) = rt.get_med() {
-            rt_text.push(format!("MED {}", med));
-        }
-        rt_text.push(format!("localpref {}", rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF)));
-        rt_text.push(format!("from {}", rt.peer.peer_address));
-
-        if count==0 {
-            println!("{}/{}\t{}",
-                     prefix,
-                     plen, rt_text.join(", ")
-            );
-        } else {
-            println!("\t\t{}",
-                     rt_text.join(", ")
-            );
-        }
-        println!("\t\t AS path: {} {}", rt.get_aspath(), rt.get_origin_char());
-        if let Some(communities) = rt.get_community() {
-            println!("\t\t Communities: {}", &communities);
+        outln!("{}", bgpdump_route(prefix, plen, rt, source));
+    }
+}
+
+fn bgpdump_route(prefix: &IpAddr, plen: u8, rt: &MrtRibEntry, source: NlriSource) -> String {
+    let (record, time) = match source {
+        NlriSource::TableDumpV2(timestamp) => ("TABLE_DUMP2", timestamp.to_string()),
+        NlriSource::Bgp4mp(..) => bgpdump_record(source),
+        NlriSource::Table => {
+            ("TABLE_DUMP2", rt.origin_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().to_string())
+        },
+    };
+    let kind = if record == "TABLE_DUMP2" { "B" } else { "A" };
+    let origin = match rt.get_origin() {
+        0 => "IGP",
+        1 => "EGP",
+        _ => "INCOMPLETE",
+    };
+    // The NEXT_HOP for IPv4, the MP_REACH_NLRI next hop otherwise
+    let nexthop = match prefix {
+        IpAddr::V4(_) => rt.nexthop().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        IpAddr::V6(_) => rt.get_mp_nexthop().unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+    // Standard communities, then large; bgpdump leaves out extended
+    let communities = rt.communities()
+        .filter_map(|x| match x {
+            Community::Standard((65535, 65281)) => Some(String::from("no-export")),
+            Community::Standard((65535, 65282)) => Some(String::from("no-advertise")),
+            Community::Standard((65535, 65283)) => Some(String::from("local-AS")),
+            Community::Standard((asn, value)) => Some(format!("{}:{}", asn, value)),
+            _ => None,
+        })
+        .chain(rt.communities().filter(|x| matches!(x, Community::Large(_))).map(|x| x.to_string()))
+        .collect::<Vec<String>>();
+    format!("{}|{}|{}|{}|{}|{}/{}|{}|{}|{}|{}|{}|{}|{}|{}|",
+            record, time, kind,
+            rt.peer.peer_address, rt.peer.peer_as,
+            prefix, plen,
+            bgpdump_aspath(rt),
+            origin,
+            nexthop,
+            rt.get_local_pref().unwrap_or(0),
+            rt.get_med().unwrap_or(0),
+            communities.join(" "),
+            if rt.is_atomic_aggregate() { "AG" } else { "NAG" },
+            rt.get_aggregator().map_or(String::new(), |(asn, id)| format!("{} {}", asn, id)))
+}
+
+pub fn bgpdump_show_withdrawal(peer: &MrtPeer, source: NlriSource, prefix: &IpAddr, plen: u8) {
+    outln!("{}", bgpdump_withdrawal(peer, source, prefix, plen));
+}
+
+fn bgpdump_withdrawal(peer: &MrtPeer, source: NlriSource, prefix: &IpAddr, plen: u8) -> String {
+    let (record, time) = bgpdump_record(source);
+    format!("{}|{}|W|{}|{}|{}/{}", record, time, peer.peer_address, peer.peer_as, prefix, plen)
+}
+
+pub fn bgpdump_show_state_change(peer: &MrtPeer, source: NlriSource, old_state: u16, new_state: u16) {
+    outln!("{}", bgpdump_state_change(peer, source, old_state, new_state));
+}
+
+fn bgpdump_state_change(peer: &MrtPeer, source: NlriSource, old_state: u16, new_state: u16) -> String {
+    let (record, time) = bgpdump_record(source);
+    format!("{}|{}|STATE|{}|{}|{}|{}", record, time, peer.peer_address, peer.peer_as, old_state, new_state)
+}
+
+// The record name and time of a BGP4MP update, with the microseconds of
+// BGP4MP_ET
+fn bgpdump_record(source: NlriSource) -> (&'static str, String) {
+    match source {
+        NlriSource::Bgp4mp(timestamp, Some(microseconds)) => ("BGP4MP_ET", format!("{}.{:06}", timestamp, microseconds)),
+        NlriSource::Bgp4mp(timestamp, None) => ("BGP4MP", timestamp.to_string()),
+        _ => ("TABLE_DUMP2", String::new()),
+    }
+}
+
+// AS paths as bgpdump writes them: an AS_SET in braces, comma separated
+fn bgpdump_aspath(rt: &MrtRibEntry) -> String {
+    rt.attributes
+        .iter()
+        .filter_map(|x| match x {
+            MrtAttribute::AsPath(aspath) => Some(aspath),
+            _ => None,
+        })
+        .flat_map(|aspath| aspath.aspath_segments.iter())
+        .map(|segment| {
+            let asns = segment.asns.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+            if segment.ordered {
+                asns.join(" ")
+            } else {
+                format!("{{{}}}", asns.join(","))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// Paths grouped by the collector and view they came from, in the order
+// each collector is first seen
+pub fn group_by_collector(route_entries: &[MrtRibEntry]) -> Vec<((IpAddr, String), Vec<MrtRibEntry>)> {
+    let mut groups: Vec<((IpAddr, String), Vec<MrtRibEntry>)> = vec![];
+    for rt in route_entries {
+        let key = (rt.peer.collector_id, rt.peer.view_name.clone());
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(rt.clone()),
+            None => groups.push((key, vec![rt.clone()])),
         }
+    }
+    groups
+}
 
-        println!("\t\t> to {}", rt.get_nexthop());
-        count += 1;
+pub fn collector_heading(collector_id: &IpAddr, view_name: &str) {
+    if view_name.is_empty() {
+        outln!("Collector {}:", collector_id);
+    } else {
+        outln!("Collector {}, view \"{}\":", collector_id, view_name);
     }
 }
 
-pub fn csv_show_route(
-    //peers: &MrtPeerIndexTable,
-                      prefix: &IpAddr,
-                      plen: u8,
-                      route_entries: &Vec<MrtRibEntry>) {
-    // let peers = peers.as_ref().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("route/plen|neighbor|next_hop|med|localpref|aspath|communities");
+    fn prefix(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
 
-    for rt in route_entries {
-        println!("{}/{}|{}|{}|{}|{}|{} {}|{}",
-            prefix, plen,
-            rt.peer.peer_address,
-            rt.get_nexthop(),
-            rt.get_med().map_or(String::from(""), |x| x.to_string()),
-            rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF),
-            rt.get_aspath(), rt.get_origin_char(),
-            rt.get_community().unwrap_or(String::from(""))
-        );
+    #[test]
+    fn bgpdump_rib_entry() {
+        let mut rt = MrtRibEntry::test_path("198.51.100.1", 64500, &[64500, 64501], vec![
+            MrtAttribute::MultiExitDisc(5),
+            MrtAttribute::LocalPref(200),
+            MrtAttribute::Community(Arc::new(vec![Community::Standard((64500, 1)), Community::Standard((65535, 65281)),
+                                                  Community::Standard((65535, 65282)), Community::Standard((65535, 65283))])),
+            MrtAttribute::ExtendedCommunity(Arc::new(vec![Community::Extended((0, 2, 0xfbf400000001))])),
+            MrtAttribute::LargeCommunity(Arc::new(vec![Community::Large((64500, 1, 2))])),
+        ]);
+        assert_eq!(bgpdump_route(&prefix("192.0.2.0"), 24, &rt, NlriSource::TableDumpV2(1_700_000_100)),
+                   "TABLE_DUMP2|1700000100|B|198.51.100.1|64500|192.0.2.0/24|64500 64501|IGP|198.51.100.1|200|5|\
+                    64500:1 no-export no-advertise local-AS 64500:1:2|NAG||");
+
+        // From the table, at the time the path was originated
+        let attributes = Arc::make_mut(&mut rt.attributes);
+        attributes.retain(|x| matches!(x, MrtAttribute::Origin(_) | MrtAttribute::AsPath(_) | MrtAttribute::NextHop(_)));
+        attributes[0] = MrtAttribute::Origin(2);
+        attributes.push(MrtAttribute::AtomicAggregate);
+        attributes.push(MrtAttribute::Aggregator(64501, Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(bgpdump_route(&prefix("192.0.2.0"), 24, &rt, NlriSource::Table),
+                   "TABLE_DUMP2|1700000000|B|198.51.100.1|64500|192.0.2.0/24|64500 64501|INCOMPLETE|198.51.100.1|0|0||\
+                    AG|64501 10.0.0.1|");
+    }
+
+    #[test]
+    fn bgpdump_update() {
+        let mut rt = MrtRibEntry::test_path("2001:db8::1", 64500, &[64500], vec![
+            MrtAttribute::MpNextHop(prefix("2001:db8::2")),
+        ]);
+        let aspath = AsPath { aspath_segments: vec![AsPathSegment { ordered: true, asns: vec![64500, 64501] },
+                                                    AsPathSegment { ordered: false, asns: vec![64502, 64503] }] };
+        Arc::make_mut(&mut rt.attributes)[1] = MrtAttribute::AsPath(Arc::new(aspath));
+        assert_eq!(bgpdump_route(&prefix("2001:db8:10::"), 48, &rt, NlriSource::Bgp4mp(1_700_000_000, None)),
+                   "BGP4MP|1700000000|A|2001:db8::1|64500|2001:db8:10::/48|64500 64501 {64502,64503}|IGP|2001:db8::2|0|0||NAG||");
+        assert_eq!(bgpdump_route(&prefix("2001:db8:10::"), 48, &rt, NlriSource::Bgp4mp(1_700_000_000, Some(42))),
+                   "BGP4MP_ET|1700000000.000042|A|2001:db8::1|64500|2001:db8:10::/48|64500 64501 {64502,64503}|IGP|\
+                    2001:db8::2|0|0||NAG||");
+
+        // An IPv6 route with no MP_REACH_NLRI next hop
+        Arc::make_mut(&mut rt.attributes).pop();
+        assert!(bgpdump_route(&prefix("2001:db8:10::"), 48, &rt, NlriSource::Bgp4mp(0, None)).contains("|IGP|::|"));
+    }
+
+    #[test]
+    fn bgpdump_withdrawal_and_state_change() {
+        let rt = MrtRibEntry::test_path("198.51.100.1", 64500, &[], vec![]);
+        assert_eq!(bgpdump_withdrawal(&rt.peer, NlriSource::Bgp4mp(1_700_000_000, None), &prefix("10.0.0.0"), 8),
+                   "BGP4MP|1700000000|W|198.51.100.1|64500|10.0.0.0/8");
+        assert_eq!(bgpdump_state_change(&rt.peer, NlriSource::Bgp4mp(1_700_000_000, Some(7)), 1, 6),
+                   "BGP4MP_ET|1700000000.000007|STATE|198.51.100.1|64500|1|6");
     }
 }
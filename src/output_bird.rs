@@ -0,0 +1,149 @@
+use crate::*;
+
+// BIRD 2 "show route" (summary) and "show route all" (detail). BIRD names
+// the protocol of each peer in its configuration; the names here are made
+// up from the peer's AS and address.
+//
+//   10.0.0.0/8           unicast [AS3356_192_0_2_1 2023-11-14] * (100) [AS64500i]
+//           via 192.0.2.1
+
+fn bird_protocol(peer: &MrtPeer) -> String {
+    format!("AS{}_{}", peer.peer_as, peer.peer_address.to_string().replace(['.', ':'], "_"))
+}
+
+// When the route last changed: the time of day within the last 20 hours,
+// otherwise the date (as BIRD's default "iso short" format)
+fn bird_time(rt: &MrtRibEntry) -> String {
+    let time = OffsetDateTime::from_unix_timestamp(util::epoch_secs(rt.origin_time) as i64)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    if rt.origin_time.elapsed().unwrap_or_default() < Duration::from_secs(20 * 3600) {
+        format!("{:02}:{:02}:{:02}.000", time.hour(), time.minute(), time.second())
+    } else {
+        format!("{:04}-{:02}-{:02}", time.year(), time.month() as u8, time.day())
+    }
+}
+
+fn bird_route(prefix: &IpAddr, plen: u8, first: bool, rt: &MrtRibEntry, status: PathStatus) {
+    let network = if first { format!("{}/{}", prefix, plen) } else { String::new() };
+    let nexthop = rt.get_nexthop();
+    // The peer is shown only when it isn't the next hop
+    let from = if nexthop != rt.peer.peer_address {
+        format!(" from {}", rt.peer.peer_address)
+    } else {
+        String::new()
+    };
+    outln!("{} unicast [{} {}{}] {}(100) [{}{}]",
+             paint(Style::Prefix, &format!("{:20}", network)),
+             bird_protocol(&rt.peer),
+             bird_time(rt),
+             from,
+             if status.is_best() { paint(Style::Best, "* ") } else { String::new() },
+             rt.get_origin_as().map_or(String::new(), |x| paint(Style::OriginAs, &format!("AS{}", x))),
+             rt.get_origin_char());
+    outln!("\tvia {}", nexthop);
+}
+
+pub fn bird_show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    for (index, rt) in route_entries.iter().enumerate() {
+        bird_route(prefix, plen, index == 0, rt, status[index]);
+    }
+}
+
+pub fn bird_show_route_all(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    for (index, rt) in route_entries.iter().enumerate() {
+        bird_route(prefix, plen, index == 0, rt, status[index]);
+        outln!("\tType: BGP univ");
+        // The attributes as they were received, which is in type code order
+        for attribute in rt.attributes.iter() {
+            match attribute {
+                MrtAttribute::Origin(origin) => outln!("\tBGP.origin: {}", match origin {
+                    0 => "IGP",
+                    1 => "EGP",
+                    _ => "Incomplete",
+                }),
+                MrtAttribute::AsPath(_) => outln!("\tBGP.as_path: {}", paint_aspath(rt)),
+                MrtAttribute::NextHop(nexthop) | MrtAttribute::MpNextHop(nexthop) => {
+                    outln!("\tBGP.next_hop: {}", nexthop)
+                },
+                MrtAttribute::MultiExitDisc(med) => outln!("\tBGP.med: {}", med),
+                MrtAttribute::LocalPref(local_pref) => outln!("\tBGP.local_pref: {}", local_pref),
+                MrtAttribute::AtomicAggregate => outln!("\tBGP.atomic_aggr: "),
+                MrtAttribute::Aggregator(asn, id) => outln!("\tBGP.aggregator: {} AS{}", id, asn),
+                MrtAttribute::Community(list) => {
+                    outln!("\tBGP.community: {}", paint(Style::Community, &bird_communities(list)))
+                },
+                MrtAttribute::ExtendedCommunity(list) => {
+                    outln!("\tBGP.ext_community: {}", paint(Style::Community, &bird_communities(list)))
+                },
+                MrtAttribute::LargeCommunity(list) => {
+                    outln!("\tBGP.large_community: {}", paint(Style::Community, &bird_communities(list)))
+                },
+                MrtAttribute::Unknown(..) => {},
+            }
+        }
+    }
+}
+
+// (65000,100), (rt, 65000, 100), (65000, 1, 2)
+fn bird_communities(list: &[Community]) -> String {
+    list.iter()
+        .map(|community| match community {
+            Community::Standard((asn, value)) => format!("({},{})", asn, value),
+            Community::Large((global, local1, local2)) => format!("({}, {}, {})", global, local1, local2),
+            Community::Extended((ext_type, ext_subtype, value)) => {
+                let text = community.to_string();
+                match text.split(':').collect::<Vec<&str>>()[..] {
+                    ["rt", global, local] => format!("(rt, {}, {})", global, local),
+                    ["soo", global, local] => format!("(ro, {}, {})", global, local),
+                    _ => format!("(generic, 0x{:x}, 0x{:x})", ((*ext_type as u32) << 24) | ((*ext_subtype as u32) << 16)
+                                 | (value >> 32) as u32, value & 0xffffffff),
+                }
+            },
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_route() {
+        let output = writer::test_output(|| bird_show_route(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &MrtRibEntry::test_paths()));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            "10.0.0.0/8           unicast [AS3356_192_0_2_1 2023-11-14] (100) [AS64500i]",
+            "\tvia 192.0.2.1",
+            "                     unicast [AS1299_192_0_2_2 2023-11-14 from 192.0.2.2] * (100) [AS64500i]",
+            "\tvia 192.0.2.3",
+        ]);
+    }
+
+    #[test]
+    fn show_route_all() {
+        let output = writer::test_output(|| bird_show_route_all(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &MrtRibEntry::test_paths()));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            "10.0.0.0/8           unicast [AS3356_192_0_2_1 2023-11-14] (100) [AS64500i]",
+            "\tvia 192.0.2.1",
+            "\tType: BGP univ",
+            "\tBGP.origin: IGP",
+            "\tBGP.as_path: 3356 174 64500",
+            "\tBGP.next_hop: 192.0.2.1",
+            "\tBGP.med: 10",
+            "\tBGP.community: (3356,123) (65535,65281)",
+            "\tBGP.ext_community: (rt, 65000, 100)",
+            "\tBGP.large_community: (3356, 1, 2)",
+            "                     unicast [AS1299_192_0_2_2 2023-11-14 from 192.0.2.2] * (100) [AS64500i]",
+            "\tvia 192.0.2.3",
+            "\tType: BGP univ",
+            "\tBGP.origin: IGP",
+            "\tBGP.as_path: 1299 64500",
+            "\tBGP.next_hop: 192.0.2.3",
+            "\tBGP.local_pref: 200",
+            "\tBGP.atomic_aggr: ",
+            "\tBGP.aggregator: 198.51.100.1 AS64500",
+        ]);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::*;
+
+// Arista EOS "show ip bgp" (summary) and "show ip bgp <prefix> detail"
+// (detail). EOS shows the local preference and MED each path is compared
+// with (100 and 0 when the path carries none), and weight 0 for learned
+// routes.
+
+pub fn eos_show_ip_bgp_header(peers: &MrtPeerIndexTable) {
+    outln!("BGP routing table information for VRF default");
+    outln!("Router identifier {}, local AS number {}", peers.collector_id, GETOPT.bestpath.local_as.unwrap_or(0));
+    outln!("Route status codes: s - suppressed, * - valid, > - active, # - not installed, E - ECMP head, e - ECMP");
+    outln!("                    S - Stale, c - Contributing to ECMP, b - backup, L - labeled-unicast");
+    outln!("Origin codes: i - IGP, e - EGP, ? - incomplete");
+    outln!("AS Path Attributes: Or-ID - Originator ID, C-LST - Cluster List, LL Nexthop - Link Local Nexthop");
+    outln!();
+    outln!("         {:22} {:21} {:7} {:7} {:7} Path", "Network", "Next Hop", "Metric", "LocPref", "Weight");
+}
+
+pub fn eos_show_ip_bgp(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    for (rt, status) in route_entries.iter().zip(status) {
+        let code = match status {
+            PathStatus::Best => paint(Style::Best, &format!("{:8}", "* >")),
+            PathStatus::Multipath => format!("{:8}", "* e"),
+            PathStatus::NotBest(_) => format!("{:8}", "*"),
+        };
+        let aspath = paint_aspath(rt);
+        outln!(" {}{} {:21} {:<7} {:<7} {:<7} {}{}",
+                 code,
+                 paint(Style::Prefix, &format!("{:22}", format!("{}/{}", prefix, plen))),
+                 rt.get_nexthop(),
+                 rt.get_med().unwrap_or(0),
+                 rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF),
+                 0,
+                 if aspath.is_empty() { aspath } else { aspath + " " },
+                 rt.get_origin_char());
+    }
+}
+
+// How long ago the path was received: 00:05:12, or 1d02h
+fn eos_age(rt: &MrtRibEntry) -> String {
+    let secs = rt.origin_time.elapsed().unwrap_or_default().as_secs();
+    if secs < 86400 {
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}d{:02}h", secs / 86400, secs / 3600 % 24)
+    }
+}
+
+pub fn eos_show_ip_bgp_detail(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    outln!("BGP routing table information for VRF default");
+    if let Some(rt) = route_entries.first() {
+        outln!("Router identifier {}, local AS number {}", rt.peer.collector_id, GETOPT.bestpath.local_as.unwrap_or(0));
+    }
+    outln!("BGP routing table entry for {}", paint(Style::Prefix, &format!("{}/{}", prefix, plen)));
+    outln!(" Paths: {} available", route_entries.len());
+
+    for (rt, status) in route_entries.iter().zip(status) {
+        let aspath = paint_aspath(rt);
+        outln!("  {}", if aspath.is_empty() { String::from("Local") } else { aspath });
+        outln!("    {} from {} ({})", rt.get_nexthop(), rt.peer.peer_address, rt.peer.peer_id);
+        outln!("      Origin {}, metric {}, localpref {}, weight 0",
+                 match rt.get_origin() {
+                     0 => "IGP",
+                     1 => "EGP",
+                     _ => "INCOMPLETE",
+                 },
+                 rt.get_med().unwrap_or(0),
+                 rt.get_local_pref().unwrap_or(DEFAULT_LOCAL_PREF));
+        let mut rt_text = vec![format!("Received {} ago", eos_age(rt)), String::from("valid")];
+        rt_text.push(String::from(if GETOPT.bestpath.local_as == Some(rt.peer.peer_as) { "internal" } else { "external" }));
+        match status {
+            PathStatus::Best => rt_text.push(paint(Style::Best, "best")),
+            PathStatus::Multipath => rt_text.push(String::from("ECMP")),
+            PathStatus::NotBest(_) => {},
+        }
+        outln!("      {}", rt_text.join(", "));
+        if rt.is_atomic_aggregate() {
+            outln!("      Atomic aggregate");
+        }
+        if let Some((asn, id)) = rt.get_aggregator() {
+            outln!("      Aggregator: AS {}, {}", asn, id);
+        }
+
+        let communities = |kind: fn(&Community) -> bool| {
+            rt.communities().filter(|x| kind(x)).map(eos_community).collect::<Vec<String>>().join(" ")
+        };
+        for (label, list) in [("Community", communities(|x| matches!(x, Community::Standard(_)))),
+                              ("Extended Community", communities(|x| matches!(x, Community::Extended(_)))),
+                              ("Large Community", communities(|x| matches!(x, Community::Large(_))))] {
+            if !list.is_empty() {
+                outln!("      {}: {}", label, paint(Style::Community, &list));
+            }
+        }
+        outln!("      Rx SAFI: Unicast");
+    }
+}
+
+// Well-known communities by name, route targets as Route-Target-AS:65000:100
+fn eos_community(community: &Community) -> String {
+    match (community, community.well_known_name()) {
+        (_, Some("no-export-subconfed")) => String::from("local-as"),
+        (_, Some(name)) => String::from(name),
+        (Community::Extended((ext_type, _, _)), None) => {
+            let text = community.to_string();
+            let kind = if ext_type & 0xbf == 0x01 { "IP" } else { "AS" };
+            if let Some(rt) = text.strip_prefix("rt:") {
+                format!("Route-Target-{}:{}", kind, rt)
+            } else if let Some(soo) = text.strip_prefix("soo:") {
+                format!("Route-Origin-{}:{}", kind, soo)
+            } else {
+                text
+            }
+        },
+        _ => community.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_ip_bgp() {
+        let output = writer::test_output(|| eos_show_ip_bgp(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &MrtRibEntry::test_paths()));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            " *       10.0.0.0/8             192.0.2.1             10      100     0       3356 174 64500 i",
+            " * >     10.0.0.0/8             192.0.2.3             0       200     0       1299 64500 i",
+        ]);
+    }
+
+    #[test]
+    fn show_ip_bgp_detail() {
+        let paths = MrtRibEntry::test_paths();
+        let output = writer::test_output(|| eos_show_ip_bgp_detail(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &paths));
+        // The paths' age, which is relative to now
+        let output = output.replace(&eos_age(&paths[0]), "AGE");
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            "BGP routing table information for VRF default",
+            "Router identifier 192.0.2.254, local AS number 0",
+            "BGP routing table entry for 10.0.0.0/8",
+            " Paths: 2 available",
+            "  3356 174 64500",
+            "    192.0.2.1 from 192.0.2.1 (192.0.2.1)",
+            "      Origin IGP, metric 10, localpref 100, weight 0",
+            "      Received AGE ago, valid, external",
+            "      Community: 3356:123 no-export",
+            "      Extended Community: Route-Target-AS:65000:100",
+            "      Large Community: 3356:1:2",
+            "      Rx SAFI: Unicast",
+            "  1299 64500",
+            "    192.0.2.3 from 192.0.2.2 (192.0.2.2)",
+            "      Origin IGP, metric 0, localpref 200, weight 0",
+            "      Received AGE ago, valid, external, best",
+            "      Atomic aggregate",
+            "      Aggregator: AS 64500, 198.51.100.1",
+            "      Rx SAFI: Unicast",
+        ]);
+    }
+}
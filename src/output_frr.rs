@@ -0,0 +1,147 @@
+use crate::*;
+
+// FRR "show bgp" (summary) and "show bgp <prefix>" (detail). Learned
+// routes have weight 0 in FRR; the local preference is only shown when the
+// path carries one.
+
+pub fn frr_show_bgp_header(peers: &MrtPeerIndexTable) {
+    outln!("BGP table version is 0, local router ID is {}, vrf id 0", peers.collector_id);
+    outln!("Default local pref {}, local AS {}", DEFAULT_LOCAL_PREF, GETOPT.bestpath.local_as.unwrap_or(0));
+    outln!("Status codes:  s suppressed, d damped, h history, * valid, > best, = multipath,");
+    outln!("               i internal, r RIB-failure, S Stale, R Removed");
+    outln!("Nexthop codes: @NNN nexthop's vrf id, < announce-nh-self");
+    outln!("Origin codes:  i - IGP, e - EGP, ? - incomplete");
+    outln!("RPKI validation codes: V valid, I invalid, N Not found");
+    outln!();
+    outln!("   Network          Next Hop            Metric LocPrf Weight Path");
+}
+
+pub fn frr_show_bgp(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    for (index, rt) in route_entries.iter().enumerate() {
+        let code = match status[index] {
+            PathStatus::Best => paint(Style::Best, ">"),
+            PathStatus::Multipath => String::from("="),
+            PathStatus::NotBest(_) => String::from(" "),
+        };
+        let internal = if GETOPT.bestpath.local_as == Some(rt.peer.peer_as) { "i" } else { " " };
+        let network = if index == 0 { format!("{}/{}", prefix, plen) } else { String::new() };
+        let aspath = paint_aspath(rt);
+        outln!("*{}{}{}{}{:>10}{:>7}{:>7} {}{}",
+                 code,
+                 internal,
+                 paint(Style::Prefix, &column(&network, 17, 20)),
+                 column(&rt.get_nexthop().to_string(), 16, 36),
+                 rt.get_med().map_or(String::new(), |x| x.to_string()),
+                 rt.get_local_pref().map_or(String::new(), |x| x.to_string()),
+                 0,
+                 if aspath.is_empty() { aspath } else { aspath + " " },
+                 rt.get_origin_char());
+    }
+}
+
+pub fn frr_show_bgp_detail(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    outln!("BGP routing table entry for {}", paint(Style::Prefix, &format!("{}/{}", prefix, plen)));
+    match status.iter().position(|x| x.is_best()) {
+        Some(best) => outln!("Paths: ({} available, best #{}, table default)", route_entries.len(), best + 1),
+        None => outln!("Paths: ({} available, no best path)", route_entries.len()),
+    }
+    outln!("  Not advertised to any peer");
+
+    for (rt, status) in route_entries.iter().zip(status) {
+        let aspath = paint_aspath(rt);
+        let aggregator = rt.get_aggregator().map_or(String::new(), |(asn, id)| format!(", (aggregated by {} {})", asn, id));
+        outln!("  {}{}", if aspath.is_empty() { String::from("Local") } else { aspath }, aggregator);
+        outln!("    {} from {} ({})", rt.get_nexthop(), rt.peer.peer_address, rt.peer.peer_id);
+
+        let mut rt_text = vec![format!("Origin {}", match rt.get_origin() {
+            0 => "IGP",
+            1 => "EGP",
+            _ => "incomplete",
+        })];
+        if let Some(med) = rt.get_med() {
+            rt_text.push(format!("metric {}", med));
+        }
+        if let Some(local_pref) = rt.get_local_pref() {
+            rt_text.push(format!("localpref {}", local_pref));
+        }
+        rt_text.push(String::from("valid"));
+        rt_text.push(String::from(if GETOPT.bestpath.local_as == Some(rt.peer.peer_as) { "internal" } else { "external" }));
+        if rt.is_atomic_aggregate() {
+            rt_text.push(String::from("atomic-aggregate"));
+        }
+        match status {
+            PathStatus::Best => rt_text.push(paint(Style::Best, "best")),
+            PathStatus::Multipath => rt_text.push(String::from("multipath")),
+            PathStatus::NotBest(_) => {},
+        }
+        outln!("      {}", rt_text.join(", "));
+
+        let communities = |kind: fn(&Community) -> bool| {
+            rt.communities().filter(|x| kind(x)).map(frr_community).collect::<Vec<String>>().join(" ")
+        };
+        for (label, list) in [("Community", communities(|x| matches!(x, Community::Standard(_)))),
+                              ("Extended Community", communities(|x| matches!(x, Community::Extended(_)))),
+                              ("Large Community", communities(|x| matches!(x, Community::Large(_))))] {
+            if !list.is_empty() {
+                outln!("      {}: {}", label, paint(Style::Community, &list));
+            }
+        }
+        outln!("      Last update: {}", util::ctime(util::epoch_secs(rt.origin_time)));
+    }
+}
+
+// Well-known communities by name, route targets as RT:65000:100
+fn frr_community(community: &Community) -> String {
+    match community.well_known_name() {
+        Some("no-export-subconfed") => String::from("local-AS"),
+        Some("no-peer") => String::from("noPeer"),
+        Some(name) => String::from(name),
+        None => {
+            let text = community.to_string();
+            if let Some(rt) = text.strip_prefix("rt:") {
+                format!("RT:{}", rt)
+            } else if let Some(soo) = text.strip_prefix("soo:") {
+                format!("SoO:{}", soo)
+            } else {
+                text
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_bgp() {
+        let output = writer::test_output(|| frr_show_bgp(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &MrtRibEntry::test_paths()));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            "*  10.0.0.0/8       192.0.2.1               10             0 3356 174 64500 i",
+            "*>                  192.0.2.3                     200      0 1299 64500 i",
+        ]);
+    }
+
+    #[test]
+    fn show_bgp_detail() {
+        let output = writer::test_output(|| frr_show_bgp_detail(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &MrtRibEntry::test_paths()));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            "BGP routing table entry for 10.0.0.0/8",
+            "Paths: (2 available, best #2, table default)",
+            "  Not advertised to any peer",
+            "  3356 174 64500",
+            "    192.0.2.1 from 192.0.2.1 (192.0.2.1)",
+            "      Origin IGP, metric 10, valid, external",
+            "      Community: 3356:123 no-export",
+            "      Extended Community: RT:65000:100",
+            "      Large Community: 3356:1:2",
+            "      Last update: Tue Nov 14 22:13:20 2023",
+            "  1299 64500, (aggregated by 64500 198.51.100.1)",
+            "    192.0.2.3 from 192.0.2.2 (192.0.2.2)",
+            "      Origin IGP, localpref 200, valid, external, atomic-aggregate, best",
+            "      Last update: Tue Nov 14 22:13:20 2023",
+        ]);
+    }
+}
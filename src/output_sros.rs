@@ -0,0 +1,213 @@
+use crate::*;
+
+// Nokia SR OS (classic CLI) "show router bgp routes" (summary) and
+// "show router bgp routes <prefix> detail" (detail). Fields that MRT
+// doesn't carry are shown as SR OS shows them when unset: None or n/a.
+
+const SROS_RULE: &str = "===============================================================================";
+const SROS_LINE: &str = "-------------------------------------------------------------------------------";
+
+fn sros_legend(router_id: &IpAddr) {
+    let local_as = GETOPT.bestpath.local_as.unwrap_or(0);
+    outln!("{}", SROS_RULE);
+    outln!(" BGP Router ID:{:<17}AS:{:<12}Local AS:{}", router_id, local_as, local_as);
+    outln!("{}", SROS_RULE);
+    outln!(" Legend -");
+    outln!(" Status codes  : u - used, s - suppressed, h - history, d - decayed, * - valid");
+    outln!("                 l - leaked, x - stale, > - best, b - backup, p - purge");
+    outln!(" Origin codes  : i - IGP, e - EGP, ? - incomplete");
+    outln!();
+    outln!("{}", SROS_RULE);
+    outln!("BGP Routes");
+    outln!("{}", SROS_RULE);
+}
+
+pub fn sros_show_router_bgp_routes_header(peers: &MrtPeerIndexTable) {
+    sros_legend(&peers.collector_id);
+    outln!("Flag  {:51}{:12}MED", "Network", "LocalPref");
+    outln!("      {:51}{:12}Label", "Nexthop (Router)", "Path-Id");
+    outln!("      As-Path");
+    outln!("{}", SROS_LINE);
+}
+
+fn sros_optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or(String::from("None"), |x| x.to_string())
+}
+
+pub fn sros_show_router_bgp_routes(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    for (rt, status) in route_entries.iter().zip(status) {
+        let code = match status {
+            PathStatus::Best => "u*>",
+            PathStatus::Multipath => "u*",
+            PathStatus::NotBest(_) => "*",
+        };
+        let flags = format!("{:6}", format!("{}{}", code, rt.get_origin_char()));
+        outln!("{}{}{:12}{}",
+                 if status.is_best() { paint(Style::Best, &flags) } else { flags },
+                 paint(Style::Prefix, &format!("{:51}", format!("{}/{}", prefix, plen))),
+                 sros_optional(rt.get_local_pref()),
+                 sros_optional(rt.get_med()));
+        outln!("      {:51}{:12}-",
+                 rt.get_nexthop(),
+                 if rt.path_id == 0 { String::from("None") } else { rt.path_id.to_string() });
+        let aspath = paint_aspath(rt);
+        outln!("      {}", if aspath.is_empty() { String::from("No As-Path") } else { aspath });
+    }
+}
+
+// How long since the path changed: 07h32m17s, or 2d01h32m
+fn sros_age(rt: &MrtRibEntry) -> String {
+    let secs = rt.origin_time.elapsed().unwrap_or_default().as_secs();
+    if secs < 86400 {
+        format!("{:02}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}d{:02}h{:02}m", secs / 86400, secs / 3600 % 24, secs / 60 % 60)
+    }
+}
+
+// Two fields to a line, as SR OS lays out its detail
+fn sros_fields(left: &str, left_value: &str, right: &str, right_value: &str) {
+    outln!("{:15}: {:23}{:15}: {}", left, left_value, right, right_value);
+}
+
+fn sros_field(label: &str, value: &str) {
+    outln!("{:15}: {}", label, value);
+}
+
+pub fn sros_show_router_bgp_routes_detail(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    let status = GETOPT.bestpath.select(route_entries);
+    if let Some(rt) = route_entries.first() {
+        sros_legend(&rt.peer.collector_id);
+    }
+    for (rt, status) in route_entries.iter().zip(status) {
+        outln!("{}", SROS_LINE);
+        outln!("Original Attributes");
+        outln!();
+        sros_field("Network", &paint(Style::Prefix, &format!("{}/{}", prefix, plen)));
+        sros_field("Nexthop", &rt.get_nexthop().to_string());
+        sros_field("Path Id", &if rt.path_id == 0 { String::from("None") } else { rt.path_id.to_string() });
+        sros_field("From", &rt.peer.peer_address.to_string());
+        sros_fields("Local Pref.", &rt.get_local_pref().map_or(String::from("n/a"), |x| x.to_string()),
+                    "Interface Name", "NotAvailable");
+        let (aggregator_as, aggregator) = match rt.get_aggregator() {
+            Some((asn, id)) => (asn.to_string(), id.to_string()),
+            None => (String::from("None"), String::from("None")),
+        };
+        sros_fields("Aggregator AS", &aggregator_as, "Aggregator", &aggregator);
+        sros_fields("Atomic Aggr.", if rt.is_atomic_aggregate() { "Atomic" } else { "Not Atomic" },
+                    "MED", &sros_optional(rt.get_med()));
+        let communities = rt.communities().map(|x| x.to_string()).collect::<Vec<String>>();
+        sros_field("Community", &if communities.is_empty() {
+            String::from("None")
+        } else {
+            paint(Style::Community, &communities.join(" "))
+        });
+        sros_fields("Originator Id", "None", "Peer Router Id", &rt.peer.peer_id.to_string());
+        let mut flags = vec![];
+        if !matches!(status, PathStatus::NotBest(_)) {
+            flags.push("Used");
+        }
+        flags.push("Valid");
+        let best = paint(Style::Best, "Best");
+        if status.is_best() {
+            flags.push(&best);
+        }
+        flags.push(match rt.get_origin() {
+            0 => "IGP",
+            1 => "EGP",
+            _ => "Incomplete",
+        });
+        sros_field("Flags", &flags.join(" "));
+        sros_field("Route Source", if GETOPT.bestpath.local_as == Some(rt.peer.peer_as) { "Internal" } else { "External" });
+        let aspath = paint_aspath(rt);
+        sros_field("AS-Path", &if aspath.is_empty() { String::from("No As-Path") } else { aspath });
+        sros_field("Neighbor-AS", &sros_optional(rt.get_neighbor_as()));
+        sros_field("Last Modified", &sros_age(rt));
+        outln!();
+    }
+    outln!("{}", SROS_LINE);
+    outln!("Routes : {}", route_entries.len());
+    outln!("{}", SROS_RULE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_router_bgp_routes() {
+        let output = writer::test_output(|| sros_show_router_bgp_routes(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &MrtRibEntry::test_paths()));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            "*i    10.0.0.0/8                                         None        10",
+            "      192.0.2.1                                          None        -",
+            "      3356 174 64500",
+            "u*>i  10.0.0.0/8                                         200         None",
+            "      192.0.2.3                                          None        -",
+            "      1299 64500",
+        ]);
+    }
+
+    #[test]
+    fn show_router_bgp_routes_detail() {
+        let paths = MrtRibEntry::test_paths();
+        let output = writer::test_output(|| sros_show_router_bgp_routes_detail(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &paths));
+        // The paths' age, which is relative to now
+        let output = output.replace(&sros_age(&paths[0]), "AGE");
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[..11], [
+            SROS_RULE,
+            " BGP Router ID:192.0.2.254      AS:0           Local AS:0",
+            SROS_RULE,
+            " Legend -",
+            " Status codes  : u - used, s - suppressed, h - history, d - decayed, * - valid",
+            "                 l - leaked, x - stale, > - best, b - backup, p - purge",
+            " Origin codes  : i - IGP, e - EGP, ? - incomplete",
+            "",
+            SROS_RULE,
+            "BGP Routes",
+            SROS_RULE,
+        ]);
+        assert_eq!(lines[11..], [
+            SROS_LINE,
+            "Original Attributes",
+            "",
+            "Network        : 10.0.0.0/8",
+            "Nexthop        : 192.0.2.1",
+            "Path Id        : None",
+            "From           : 192.0.2.1",
+            "Local Pref.    : n/a                    Interface Name : NotAvailable",
+            "Aggregator AS  : None                   Aggregator     : None",
+            "Atomic Aggr.   : Not Atomic             MED            : 10",
+            "Community      : 3356:123 65535:65281 rt:65000:100 3356:1:2",
+            "Originator Id  : None                   Peer Router Id : 192.0.2.1",
+            "Flags          : Valid IGP",
+            "Route Source   : External",
+            "AS-Path        : 3356 174 64500",
+            "Neighbor-AS    : 3356",
+            "Last Modified  : AGE",
+            "",
+            SROS_LINE,
+            "Original Attributes",
+            "",
+            "Network        : 10.0.0.0/8",
+            "Nexthop        : 192.0.2.3",
+            "Path Id        : None",
+            "From           : 192.0.2.2",
+            "Local Pref.    : 200                    Interface Name : NotAvailable",
+            "Aggregator AS  : 64500                  Aggregator     : 198.51.100.1",
+            "Atomic Aggr.   : Atomic                 MED            : None",
+            "Community      : None",
+            "Originator Id  : None                   Peer Router Id : 192.0.2.2",
+            "Flags          : Used Valid Best IGP",
+            "Route Source   : External",
+            "AS-Path        : 1299 64500",
+            "Neighbor-AS    : 1299",
+            "Last Modified  : AGE",
+            "",
+            SROS_LINE,
+            "Routes : 2",
+            SROS_RULE,
+        ]);
+    }
+}
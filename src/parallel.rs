@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+use crate::*;
+
+// Parallel loading of an MRT file (-T). The RIB records that follow a
+// PEER_INDEX_TABLE are independent of each other, so:
+//
+//   - one thread reads and decompresses the file, splitting it into raw
+//     records, in batches
+//   - a pool of workers decodes each batch into routes and applies the
+//     filters and policies to them
+//   - this (the main) thread puts the batches back into file order and
+//     adds the routes to the table
+//
+// The table is built on one thread, in file order, so that a later path
+// replaces an earlier one and routes are shown in the same order as by a
+// sequential load. Decoding dominates the cost of a load; adding the
+// decoded routes is a small part of it.
+//
+// Batches decoded ahead of one still being decoded wait to be added, so the
+// reader is only let WINDOW batches ahead of the table: a slow batch holds
+// up the reader rather than leaving the rest of the file to pile up behind
+// it.
+
+const BATCH_SIZE: usize = 256;
+const WINDOW: usize = 8;        // batches in flight, per thread
+
+// A decoded record, to be added to the table
+enum Loaded {
+    Route(MrtNlri),                 // a RIB entry matching the filters
+    Message(Bgp4mp),                // filtered as it is added, with its withdrawals
+}
+
+// Work for the decoders, numbered in file order
+enum Work {
+    Records(Vec<MrtRaw>, Arc<MrtPeerIndexTable>),
+    PeerIndexTable(u32, Arc<MrtPeerIndexTable>),
+    Error(anyhow::Error),
+}
+
+// A decoded batch: its routes, up to any error in it
+enum Decoded {
+    Routes(Vec<Loaded>, Option<anyhow::Error>),
+    PeerIndexTable(u32, Arc<MrtPeerIndexTable>),
+    Error(anyhow::Error),
+}
+
+// Load the records read, returning the number of routes loaded or the
+// first error in the file (the routes before it having been loaded)
+pub fn load(reader: Box<dyn BufRead + Send>, threads: usize, routing_table: &mut RoutingTable,
+            peers: &mut HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) -> Result<u64> {
+    let (work_sender, work_receiver) = sync_channel::<(u64, Work)>(threads * 4);
+    // Shared by the workers, and dropped with the last of them
+    let work_receiver = Arc::new(Mutex::new(work_receiver));
+    // Bounded too, so that decoded batches don't pile up ahead of the
+    // table being built
+    let (decoded_sender, decoded_receiver) = sync_channel::<(u64, Decoded)>(threads * 4);
+    // A credit for each batch the reader may send ahead of those added,
+    // returned as each is added
+    let (credit_sender, credit_receiver) = sync_channel::<()>(threads * WINDOW);
+    for _ in 0..threads * WINDOW {
+        credit_sender.send(())?;
+    }
+
+    thread::scope(|scope| {
+        scope.spawn(move || read_batches(reader, |sequence, work| {
+            credit_receiver.recv().is_ok() && work_sender.send((sequence, work)).is_ok()
+        }));
+
+        for _ in 0..threads {
+            let decoded_sender = decoded_sender.clone();
+            let work_receiver = Arc::clone(&work_receiver);
+            scope.spawn(move || {
+                loop {
+                    // Hold the lock only to take the next batch
+                    let received = work_receiver.lock().unwrap().recv();
+                    let Ok((sequence, work)) = received else {
+                        break;
+                    };
+                    if decoded_sender.send((sequence, decode(work))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(decoded_sender);
+        drop(work_receiver);
+
+        // Returning (on an error) drops the receiver, which stops the
+        // other threads at their next send
+        add_in_order(decoded_receiver, &credit_sender, routing_table, peers)
+    })
+}
+
+// Split the file into batches of raw records. A PEER_INDEX_TABLE is
+// decoded here, as the records that follow it need it to be decoded.
+fn read_batches<F: FnMut(u64, Work) -> bool>(mut reader: Box<dyn BufRead + Send>, mut send: F) {
+    let mut peer_index_table = Arc::new(MrtPeerIndexTable::default());
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut sequence = 0;
+    let mut next = || { sequence += 1; sequence - 1 };
+
+    loop {
+        let raw = match MrtRaw::read(&mut reader) {
+            Ok(raw) => raw,
+            Err(e) => {
+                if !batch.is_empty() {
+                    send(next(), Work::Records(batch, Arc::clone(&peer_index_table)));
+                }
+                // The end of the file is the end of the records
+                if !matches!(e.downcast_ref::<std::io::Error>(), Some(e) if e.kind() == ErrorKind::UnexpectedEof) {
+                    send(next(), Work::Error(e));
+                }
+                return;
+            }
+        };
+        if raw.is_peer_index_table() {
+            if !batch.is_empty() {
+                let records = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                if !send(next(), Work::Records(records, Arc::clone(&peer_index_table))) {
+                    return;
+                }
+            }
+            let timestamp = raw.timestamp;
+            let work = match Mrt::decode(raw, &peer_index_table) {
+                Ok(Mrt { data: MrtRecord::PeerIndexTable(table), .. }) => {
+                    peer_index_table = Arc::new(table);
+                    Work::PeerIndexTable(timestamp, Arc::clone(&peer_index_table))
+                },
+                Ok(_) => continue,
+                Err(e) => Work::Error(e),
+            };
+            if !send(next(), work) {
+                return;
+            }
+            continue;
+        }
+        batch.push(raw);
+        if batch.len() == BATCH_SIZE {
+            let records = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if !send(next(), Work::Records(records, Arc::clone(&peer_index_table))) {
+                return;
+            }
+        }
+    }
+}
+
+fn decode(work: Work) -> Decoded {
+    match work {
+        Work::Records(records, peer_index_table) => {
+            let mut routes = vec![];
+            for raw in records {
+                match Mrt::decode(raw, &peer_index_table) {
+                    Ok(Mrt { data: MrtRecord::RibIpv4Unicast(mut nlri), .. }) |
+                    Ok(Mrt { data: MrtRecord::RibIpv6Unicast(mut nlri), .. }) => {
+                        if filter_nlri(&mut nlri) {
+                            routes.push(Loaded::Route(nlri));
+                        }
+                    },
+                    Ok(Mrt { data: MrtRecord::Bgp4mp(message), .. }) => routes.push(Loaded::Message(message)),
+                    Ok(_) => {},
+                    Err(e) => return Decoded::Routes(routes, Some(e)),
+                }
+            }
+            Decoded::Routes(routes, None)
+        },
+        Work::PeerIndexTable(timestamp, table) => Decoded::PeerIndexTable(timestamp, table),
+        Work::Error(e) => Decoded::Error(e),
+    }
+}
+
+// Take the decoded batches as they come, adding them in file order (and
+// returning a credit to the reader for each), returning the number of
+// routes loaded
+fn add_in_order(decoded: Receiver<(u64, Decoded)>, credits: &SyncSender<()>, routing_table: &mut RoutingTable,
+                peers: &mut HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) -> Result<u64> {
+    let mut pending: BTreeMap<u64, Decoded> = BTreeMap::new();
+    let mut bgp4mp_peers = Bgp4mpPeers::default();
+    let mut next = 0;
+    let mut count = 0;
+
+    for (sequence, batch) in decoded {
+        pending.insert(sequence, batch);
+        while let Some(batch) = pending.remove(&next) {
+            next += 1;
+            // The reader may have finished, with no use for it
+            let _ = credits.send(());
+            match batch {
+                Decoded::Routes(routes, error) => {
+                    for loaded in routes {
+                        count += match loaded {
+                            Loaded::Route(nlri) => {
+                                add_nlri(nlri, routing_table);
+                                1
+                            },
+                            Loaded::Message(message) => load_bgp4mp(message, routing_table, peers, &mut bgp4mp_peers),
+                        };
+                    }
+                    if let Some(e) = error {
+                        return Err(e);
+                    }
+                },
+                Decoded::PeerIndexTable(timestamp, table) => load_peer_index_table(timestamp, &table, peers),
+                Decoded::Error(e) => return Err(e),
+            }
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // A RIB dump of a /24 for each record, with a record for a peer not in
+    // the table (so failing to decode) at the position given
+    fn dump(records: usize, bad: Option<usize>) -> Vec<u8> {
+        let mut bytes = MrtRaw::test_peer_index_table().to_bytes();
+        for i in 0..records {
+            let peer_index = if Some(i) == bad { 1 } else { 0 };
+            let prefix = [10, (i >> 8) as u8, i as u8];
+            bytes.extend(MrtRaw::test_rib(2, &prefix, 24, peer_index, &[0]).to_bytes());
+        }
+        bytes
+    }
+
+    fn load_both(bytes: &[u8], threads: usize) -> [(Result<u64, String>, Vec<String>); 2] {
+        [true, false].map(|parallel| {
+            let (mut routing_table, mut peers) = (RoutingTable::new(), HashMap::new());
+            let reader = Box::new(Cursor::new(bytes.to_vec()));
+            let loaded = match parallel {
+                true => load(reader, threads, &mut routing_table, &mut peers),
+                false => load_mrt(reader, &mut routing_table, &mut peers),
+            };
+            let routes = routing_table.iter()
+                .map(|(prefix, plen, paths)| format!("{}/{} {}", prefix, plen, paths[0].peer))
+                .collect();
+            (loaded.map_err(|e| e.to_string()), routes)
+        })
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        // More batches than the reader may have in flight
+        let bytes = dump(BATCH_SIZE * WINDOW * 2 + 10, None);
+        for threads in [1, 3] {
+            let [parallel, sequential] = load_both(&bytes, threads);
+            assert_eq!(parallel.0, Ok(BATCH_SIZE as u64 * WINDOW as u64 * 2 + 10));
+            assert_eq!(parallel, sequential, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn bad_record() {
+        // The routes before the bad record are loaded, those of its batch
+        // included, and none after it
+        let bytes = dump(BATCH_SIZE * 3, Some(BATCH_SIZE + 10));
+        for threads in [1, 4] {
+            let [parallel, sequential] = load_both(&bytes, threads);
+            assert_eq!(parallel.0, Err("invalid peer index 1".to_string()));
+            assert_eq!(parallel.1.len(), BATCH_SIZE + 10);
+            assert_eq!(parallel, sequential, "{} threads", threads);
+        }
+
+        // A record cut short by the end of the file ends it, for both
+        let bytes = dump(10, None);
+        let [parallel, sequential] = load_both(&bytes[..bytes.len() - 5], 2);
+        assert_eq!(parallel.0, Ok(9));
+        assert_eq!(parallel, sequential);
+    }
+}
@@ -0,0 +1,412 @@
+use std::any::type_name;
+use std::mem::size_of;
+use crate::trie::AddressBits;
+
+// Path-compressed (PATRICIA) trie of prefixes.
+//
+// Unlike the binary Trie, which has a node for every bit of every prefix,
+// nodes exist only for the prefixes themselves and for the points where
+// two prefixes diverge, each node holding its full prefix. A /48 IPv6
+// route costs at most two nodes rather than 48, and a lookup visits only
+// those nodes rather than one per bit.
+//
+// The interface is that of Trie, so either may be used for a table.
+pub struct Patricia<A, V> {
+    root: Node<V>,
+    phantom: std::marker::PhantomData<A>,
+}
+
+struct Node<V> {
+    bits: u128,             // left-aligned prefix, masked to len
+    len: u8,
+    value: Option<V>,
+    children: [Option<Box<Node<V>>>; 2],
+}
+
+// Left-aligned network mask for a prefix length
+fn mask(len: u8) -> u128 {
+    if len == 0 { 0 } else { u128::MAX << (128 - len as u32) }
+}
+
+fn bit(bits: u128, n: u8) -> usize {
+    ((bits >> (127 - n)) & 1) as usize
+}
+
+// Number of leading bits, up to max, that two addresses have in common
+fn common_len(a: u128, b: u128, max: u8) -> u8 {
+    ((a ^ b).leading_zeros() as u8).min(max)
+}
+
+impl<V> Node<V> {
+    fn new(bits: u128, len: u8) -> Self {
+        Node {
+            bits: bits & mask(len),
+            len,
+            value: None,
+            children: [None, None],
+        }
+    }
+
+    // Does this node's prefix cover the first len bits of the address?
+    fn covers(&self, bits: u128, len: u8) -> bool {
+        self.len <= len && (bits ^ self.bits) & mask(self.len) == 0
+    }
+
+    // Find or create the node for the prefix below this one, splitting
+    // a compressed edge where the prefix diverges from it
+    fn node_for(&mut self, bits: u128, len: u8) -> &mut Node<V> {
+        if self.len == len {
+            return self;
+        }
+        let b = bit(bits, self.len);
+        match self.children[b].take() {
+            None => {
+                self.children[b] = Some(Box::new(Node::new(bits, len)));
+            },
+            Some(child) => {
+                let common = common_len(child.bits, bits, child.len.min(len));
+                if common == child.len {
+                    self.children[b] = Some(child);
+                } else {
+                    let mut branch = Box::new(Node::new(bits, common));
+                    let side = bit(child.bits, common);
+                    branch.children[side] = Some(child);
+                    self.children[b] = Some(branch);
+                }
+            }
+        }
+        self.children[b].as_mut().unwrap().node_for(bits, len)
+    }
+
+    // Remove the value for the prefix from below this node, pruning
+    // nodes left with neither a value nor a reason to branch
+    fn remove(&mut self, bits: u128, len: u8) -> Option<V> {
+        if self.len == len {
+            return self.value.take();
+        }
+        let b = bit(bits, self.len);
+        let child = self.children[b].as_mut().filter(|x| x.covers(bits, len))?;
+        let value = child.remove(bits, len);
+        if child.value.is_none() {
+            match child.children.iter().flatten().count() {
+                0 => self.children[b] = None,
+                1 => {
+                    let [left, right] = &mut child.children;
+                    self.children[b] = left.take().or(right.take());
+                },
+                _ => {}
+            }
+        }
+        value
+    }
+
+    // The node for exactly the prefix, if there is one
+    fn find(&self, bits: u128, len: u8) -> Option<&Node<V>> {
+        let mut node = Some(self);
+        while let Some(n) = node.filter(|n| n.covers(bits, len)) {
+            if n.len == len {
+                return Some(n);
+            }
+            node = n.child(bits);
+        }
+        None
+    }
+
+    fn find_mut(&mut self, bits: u128, len: u8) -> Option<&mut Node<V>> {
+        if !self.covers(bits, len) {
+            return None;
+        }
+        if self.len == len {
+            return Some(self);
+        }
+        let b = bit(bits, self.len);
+        self.children[b].as_mut()?.find_mut(bits, len)
+    }
+
+    fn child(&self, bits: u128) -> Option<&Node<V>> {
+        if self.len >= 128 {
+            return None;
+        }
+        self.children[bit(bits, self.len)].as_deref()
+    }
+}
+
+impl<A: AddressBits, V> Patricia<A, V> {
+    pub fn new() -> Self {
+        Patricia {
+            root: Node::new(0, 0),
+            phantom: std::marker::PhantomData,
+        }
+    }
+
+    // Return the maximum bit depth of the trie
+    pub fn max_depth() -> u8 {
+        A::BITS
+    }
+
+    // Return the value for the prefix, inserting one made by the
+    // given function if there isn't one yet
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, ip: &A, depth: u8, default: F) -> &mut V {
+        let depth = depth.min(A::BITS);
+        self.root.node_for(ip.to_bits() & mask(depth), depth).value.get_or_insert_with(default)
+    }
+
+    // Set the value for the prefix, returning any previous value
+    pub fn insert(&mut self, ip: &A, depth: u8, value: V) -> Option<V> {
+        let depth = depth.min(A::BITS);
+        self.root.node_for(ip.to_bits() & mask(depth), depth).value.replace(value)
+    }
+
+    // Longest prefix match of the address, considering prefixes no
+    // longer than depth
+    pub fn get(&self, ip: &A, depth: u8) -> Option<(A, u8, &V)> {
+        let depth = depth.min(A::BITS);
+        let bits = ip.to_bits();
+        let mut best = None;
+        let mut node = Some(&self.root);
+
+        while let Some(n) = node.filter(|n| n.covers(bits, depth)) {
+            if let Some(v) = &n.value {
+                best = Some((A::from_bits(n.bits), n.len, v));
+            }
+            node = n.child(bits);
+        }
+        best
+    }
+
+    // Exact match of the prefix
+    pub fn get_exact(&self, ip: &A, depth: u8) -> Option<&V> {
+        let depth = depth.min(A::BITS);
+        self.root.find(ip.to_bits(), depth)?.value.as_ref()
+    }
+
+    pub fn get_exact_mut(&mut self, ip: &A, depth: u8) -> Option<&mut V> {
+        let depth = depth.min(A::BITS);
+        self.root.find_mut(ip.to_bits(), depth)?.value.as_mut()
+    }
+
+    // Remove the prefix, returning its value
+    pub fn remove(&mut self, ip: &A, depth: u8) -> Option<V> {
+        let depth = depth.min(A::BITS);
+        self.root.remove(ip.to_bits() & mask(depth), depth)
+    }
+
+    // All prefixes in the trie, in prefix order: each prefix is followed
+    // by its more-specifics, lower addresses first
+    pub fn iter(&self) -> Iter<'_, A, V> {
+        Iter::new(Some(&self.root))
+    }
+
+    // Prefixes equal to or more specific than the given one, in
+    // prefix order
+    pub fn more_specifics(&self, ip: &A, depth: u8) -> Iter<'_, A, V> {
+        let depth = depth.min(A::BITS);
+        let bits = ip.to_bits();
+        let mut node = Some(&self.root);
+
+        // Descend to the first node at or below the prefix
+        while let Some(n) = node {
+            if n.len >= depth {
+                break;
+            }
+            node = n.child(bits);
+        }
+        Iter::new(node.filter(|n| (n.bits ^ bits) & mask(depth) == 0))
+    }
+
+    // Prefixes equal to or less specific than (covering) the given one,
+    // shortest first
+    pub fn less_specifics(&self, ip: &A, depth: u8) -> std::vec::IntoIter<(A, u8, &V)> {
+        let depth = depth.min(A::BITS);
+        let bits = ip.to_bits();
+        let mut covering = vec![];
+        let mut node = Some(&self.root);
+
+        while let Some(n) = node.filter(|n| n.covers(bits, depth)) {
+            if let Some(v) = &n.value {
+                covering.push((A::from_bits(n.bits), n.len, v));
+            }
+            node = n.child(bits);
+        }
+        covering.into_iter()
+    }
+
+    // Number of prefixes with a value
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    // Number of nodes, and the memory they occupy (not counting any
+    // heap memory owned by the values themselves)
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            count += 1;
+            stack.extend(node.children.iter().flatten().map(|x| x.as_ref()));
+        }
+        count
+    }
+
+    pub fn memory(&self) -> usize {
+        self.node_count() * size_of::<Node<V>>()
+    }
+}
+
+// Tries of lists (eg. the paths for a route) accumulate values
+// added against the same prefix
+impl<A: AddressBits, T> Patricia<A, Vec<T>> {
+    pub fn add(&mut self, ip: &A, depth: u8, mut value: Vec<T>) {
+        self.get_or_insert_with(ip, depth, Vec::new).append(&mut value);
+    }
+}
+
+impl<A: AddressBits, V> Default for Patricia<A, V> {
+    fn default() -> Self {
+        Patricia::new()
+    }
+}
+
+// Depth-first, pre-order walk of a (sub-)trie
+pub struct Iter<'a, A, V> {
+    stack: Vec<&'a Node<V>>,
+    phantom: std::marker::PhantomData<A>,
+}
+
+impl<'a, A: AddressBits, V> Iter<'a, A, V> {
+    fn new(node: Option<&'a Node<V>>) -> Self {
+        Iter { stack: node.into_iter().collect(), phantom: std::marker::PhantomData }
+    }
+}
+
+impl<'a, A: AddressBits, V> Iterator for Iter<'a, A, V> {
+    type Item = (A, u8, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            // Right pushed first so that the left is visited first
+            self.stack.extend(node.children.iter().rev().flatten().map(|x| x.as_ref()));
+            if let Some(v) = &node.value {
+                return Some((A::from_bits(node.bits), node.len, v));
+            }
+        }
+        None
+    }
+}
+
+use std::fmt;
+
+impl<A, V> fmt::Display for Patricia<A, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Patricia<{}, {}>", type_name::<A>(), type_name::<V>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trie::Trie;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // A repeatable spread of addresses, from a linear congruential generator
+    fn addresses(count: usize, seed: u64) -> Vec<u128> {
+        let mut state = seed;
+        (0..count).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let high = state;
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((high as u128) << 64) | state as u128
+        }).collect()
+    }
+
+    // An address sharing some leading bits with one of a few others
+    fn cluster<A: AddressBits>(bits: u128) -> u128 {
+        let bases = addresses(8, 3);
+        let shared = 8 + (bits % (A::BITS as u128 - 8)) as u8;
+        bases[(bits >> 120) as usize % bases.len()] & mask(shared) | bits & !mask(shared)
+    }
+
+    // The same prefixes in both tries, with clusters of nested prefixes
+    // so that the walks have covering and covered prefixes to find
+    fn both<A: AddressBits>(lengths: &[u8]) -> (Trie<A, usize>, Patricia<A, usize>) {
+        let mut trie = Trie::new();
+        let mut patricia = Patricia::new();
+        for (i, bits) in addresses(2000, 1).into_iter().enumerate() {
+            let bits = cluster::<A>(bits);
+            let depth = lengths[i % lengths.len()];
+            let ip = A::from_bits(bits & mask(depth));
+            assert_eq!(trie.insert(&ip, depth, i).is_some(), patricia.insert(&ip, depth, i).is_some());
+        }
+        (trie, patricia)
+    }
+
+    fn equivalent<A: AddressBits + PartialEq + std::fmt::Debug>(lengths: &[u8]) {
+        let (mut trie, mut patricia) = both::<A>(lengths);
+        assert_eq!(trie.len(), patricia.len());
+        assert!(trie.iter().eq(patricia.iter()), "iteration order differs");
+        assert!(trie.iter().any(|(ip, depth, _)| trie.less_specifics(&ip, depth).count() > 2), "no nested prefixes");
+
+        assert!(trie.more_specifics(&A::from_bits(0), 0).eq(patricia.more_specifics(&A::from_bits(0), 0)));
+        let inserted = trie.iter().map(|(ip, _, _)| ip.to_bits()).step_by(10).collect::<Vec<u128>>();
+        for bits in addresses(200, 2).into_iter().chain(inserted) {
+            let ip = A::from_bits(cluster::<A>(bits));
+            assert_eq!(trie.get(&ip, A::BITS), patricia.get(&ip, A::BITS), "longest match for {:?}", ip);
+            for depth in [8, 16, lengths[0], A::BITS] {
+                let ip = A::from_bits(ip.to_bits() & mask(depth));
+                assert_eq!(trie.get_exact(&ip, depth), patricia.get_exact(&ip, depth));
+                assert!(trie.more_specifics(&ip, depth).eq(patricia.more_specifics(&ip, depth)),
+                        "more specifics of {:?}/{}", ip, depth);
+                assert!(trie.less_specifics(&ip, depth).eq(patricia.less_specifics(&ip, depth)),
+                        "less specifics of {:?}/{}", ip, depth);
+            }
+        }
+
+        // Removing every other prefix leaves the same tries
+        let prefixes = trie.iter().map(|(ip, depth, _)| (ip, depth)).collect::<Vec<(A, u8)>>();
+        for (ip, depth) in prefixes.iter().step_by(2) {
+            assert_eq!(trie.remove(ip, *depth), patricia.remove(ip, *depth));
+        }
+        assert_eq!(trie.len(), patricia.len());
+        assert!(trie.iter().eq(patricia.iter()));
+        for (ip, _) in &prefixes {
+            assert_eq!(trie.get(ip, A::BITS), patricia.get(ip, A::BITS));
+        }
+    }
+
+    #[test]
+    fn ipv4_equivalent_to_trie() {
+        equivalent::<Ipv4Addr>(&[24, 16, 20, 22, 8, 32, 23, 0]);
+    }
+
+    #[test]
+    fn ipv6_equivalent_to_trie() {
+        equivalent::<Ipv6Addr>(&[48, 32, 29, 44, 64, 128, 19]);
+    }
+
+    #[test]
+    fn lookups() {
+        let mut patricia: Patricia<Ipv4Addr, &str> = Patricia::new();
+        patricia.insert(&Ipv4Addr::new(10, 0, 0, 0), 8, "10/8");
+        patricia.insert(&Ipv4Addr::new(10, 1, 0, 0), 16, "10.1/16");
+        patricia.insert(&Ipv4Addr::new(10, 1, 2, 0), 24, "10.1.2/24");
+        patricia.insert(&Ipv4Addr::new(10, 2, 0, 0), 16, "10.2/16");
+
+        assert_eq!(patricia.get(&Ipv4Addr::new(10, 1, 2, 3), 32).map(|x| *x.2), Some("10.1.2/24"));
+        assert_eq!(patricia.get(&Ipv4Addr::new(10, 1, 3, 3), 32).map(|x| *x.2), Some("10.1/16"));
+        assert_eq!(patricia.get(&Ipv4Addr::new(10, 3, 0, 0), 32).map(|x| *x.2), Some("10/8"));
+        assert_eq!(patricia.get(&Ipv4Addr::new(11, 0, 0, 0), 32), None);
+        assert_eq!(patricia.get_exact(&Ipv4Addr::new(10, 1, 0, 0), 16), Some(&"10.1/16"));
+        assert_eq!(patricia.get_exact(&Ipv4Addr::new(10, 1, 0, 0), 17), None);
+        assert_eq!(patricia.more_specifics(&Ipv4Addr::new(10, 1, 0, 0), 16).map(|x| *x.2).collect::<Vec<&str>>(),
+                   ["10.1/16", "10.1.2/24"]);
+        assert_eq!(patricia.less_specifics(&Ipv4Addr::new(10, 1, 2, 0), 24).map(|x| *x.2).collect::<Vec<&str>>(),
+                   ["10/8", "10.1/16", "10.1.2/24"]);
+        assert_eq!(patricia.remove(&Ipv4Addr::new(10, 1, 0, 0), 16), Some("10.1/16"));
+        assert_eq!(patricia.get(&Ipv4Addr::new(10, 1, 3, 3), 32).map(|x| *x.2), Some("10/8"));
+        assert_eq!(patricia.len(), 3);
+    }
+}
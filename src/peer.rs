@@ -9,7 +9,9 @@ pub struct MrtPeer {
     pub peer_type_i: bool,
     pub peer_id: IpAddr,
     pub peer_address: IpAddr,
-    pub peer_as: u32
+    pub peer_as: u32,
+    pub collector_id: IpAddr,   // from the peer index table
+    pub view_name: String,
 }
 
 #[derive(Debug)]
@@ -17,7 +19,7 @@ pub struct MrtPeerIndexTable {
     pub collector_id: IpAddr,
     pub view_name: String,
     pub peer_count: u16,
-    pub peers: Vec<Rc<MrtPeer>>
+    pub peers: Vec<Arc<MrtPeer>>
 }
 
 
@@ -35,6 +37,8 @@ impl Default for MrtPeer {
             peer_id: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             peer_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             peer_as: 0,
+            collector_id: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            view_name: String::new(),
         }
     }
 }
@@ -71,9 +75,17 @@ impl MrtPeerIndexTable {
             } else {
                 reader.read_u16::<BigEndian>()? as u32
             };
-            let peer = MrtPeer { peer_type_a, peer_type_i, peer_id, peer_address, peer_as };
+            let peer = MrtPeer {
+                peer_type_a,
+                peer_type_i,
+                peer_id,
+                peer_address,
+                peer_as,
+                collector_id,
+                view_name: peer_index_table.view_name.clone(),
+            };
             // dbg!(&peer);
-            peer_index_table.peers.push(Rc::new(peer));
+            peer_index_table.peers.push(Arc::new(peer));
         }
         Ok(peer_index_table)
     }
@@ -85,7 +97,7 @@ impl Default for MrtPeerIndexTable {
             collector_id: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
             view_name: String::new(),
             peer_count: 0,
-            peers: Vec::<std::rc::Rc<peer::MrtPeer>>::new()
+            peers: Vec::<Arc<peer::MrtPeer>>::new()
         }
     }
 }
@@ -94,7 +106,7 @@ impl Default for MrtPeerIndexTable {
 // Don't want a panicking index operator in the middle of an MRT file, thank you
 //
 // impl std::ops::Index<usize> for MrtPeerIndexTable {
-//     type Output = Rc<MrtPeer>;
+//     type Output = Arc<MrtPeer>;
 
     //
     // fn index(&self, index: usize) -> &Self::Output {
@@ -0,0 +1,404 @@
+use crate::*;
+use regex::Regex;
+use std::path::Path;
+use std::sync::Arc;
+
+// Routing policy model.
+//
+// IOS route-maps, prefix-lists, as-path and community lists, JUNOS
+// policy-statements and BIRD filters all reduce to an ordered list of
+// terms, each with ANDed match conditions (expressed as Filters),
+// attribute modifications and an action. The first term whose
+// conditions all match and whose action is terminal decides the fate
+// of the path. Modifications made by matching terms along the way are
+// applied to the path, and seen by the terms that follow.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyAction {
+    Accept,
+    Reject,
+    Next        // no decision, carry on with the following term
+}
+
+// Attribute modifications (route-map "set", JUNOS "then", BIRD assignments)
+#[derive(Debug, Clone)]
+pub enum SetAction {
+    LocalPref(u32),
+    Med(u32),
+    AddCommunities(Vec<Community>),
+    RemoveCommunities(Vec<CommunityPattern>),
+    SetCommunities(Vec<Community>),
+    Prepend(u32, u8),       // ASN, count
+    NextHop(IpAddr),
+}
+
+impl SetAction {
+    pub fn apply(&self, rt: &mut MrtRibEntry) {
+        match self {
+            SetAction::LocalPref(local_pref) => rt.set_local_pref(*local_pref),
+            SetAction::Med(med) => rt.set_med(*med),
+            SetAction::AddCommunities(communities) => rt.add_communities(communities),
+            SetAction::RemoveCommunities(patterns) => {
+                rt.remove_communities(|x| patterns.iter().any(|p| p.matches(x)))
+            },
+            SetAction::SetCommunities(communities) => {
+                rt.remove_communities(|_| true);
+                rt.add_communities(communities);
+            },
+            SetAction::Prepend(asn, count) => rt.prepend_aspath(*asn, *count),
+            SetAction::NextHop(nexthop) => rt.set_nexthop(*nexthop),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PolicyTerm {
+    pub name: String,
+    pub conditions: Vec<Filter>,
+    pub actions: Vec<SetAction>,
+    pub action: PolicyAction,
+}
+
+#[derive(Debug)]
+pub struct Policy {
+    pub name: String,
+    pub terms: Vec<PolicyTerm>,
+    pub default_action: PolicyAction,
+}
+
+impl Policy {
+    pub fn new(name: &str, default_action: PolicyAction) -> Policy {
+        Policy {
+            name: name.to_string(),
+            terms: vec![],
+            default_action,
+        }
+    }
+
+    // A policy accepting only what matches the condition, for named
+    // lists used by other policies
+    pub fn condition(name: &str, condition: Filter) -> Policy {
+        let mut policy = Policy::new(name, PolicyAction::Reject);
+        policy.push(name, vec![condition], vec![], PolicyAction::Accept);
+        policy
+    }
+
+    pub fn push(&mut self, name: &str, conditions: Vec<Filter>, actions: Vec<SetAction>, action: PolicyAction) {
+        self.terms.push(PolicyTerm { name: name.to_string(), conditions, actions, action });
+    }
+
+    // Run the path through the policy terms in order, returning the
+    // final decision. The modifications of the matching terms are made to
+    // a copy of the path (sharing its attributes until one is changed),
+    // so that later terms see them just as apply() has them.
+    pub fn evaluate(&self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry) -> PolicyAction {
+        self.run(prefix, plen, &mut rt.clone())
+    }
+
+    pub fn permits(&self, prefix: &IpAddr, plen: u8, rt: &MrtRibEntry) -> bool {
+        self.evaluate(prefix, plen, rt) == PolicyAction::Accept
+    }
+
+    // As evaluate(), but carrying out the attribute modifications of the
+    // matching terms on the path itself. Returns true if it is accepted.
+    pub fn apply(&self, prefix: &IpAddr, plen: u8, rt: &mut MrtRibEntry) -> bool {
+        self.run(prefix, plen, rt) == PolicyAction::Accept
+    }
+
+    fn run(&self, prefix: &IpAddr, plen: u8, rt: &mut MrtRibEntry) -> PolicyAction {
+        for term in &self.terms {
+            if term.conditions.iter().all(|x| x.matches(prefix, plen, rt)) {
+                if term.action != PolicyAction::Reject {
+                    term.actions.iter().for_each(|x| x.apply(rt));
+                }
+                match term.action {
+                    PolicyAction::Next => continue,
+                    action => return action,
+                }
+            }
+        }
+        self.default_action
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyDialect {
+    Ios,
+    Junos,
+    Bird
+}
+
+impl PolicyDialect {
+    // Make a reasonable guess at the configuration syntax
+    pub fn detect(text: &str) -> PolicyDialect {
+        if text.contains("policy-statement") || text.contains("policy-options") {
+            PolicyDialect::Junos
+        } else if text.contains("bgp_path") || text.contains("net ~") ||
+            (text.contains("filter ") && text.contains('{') && !text.contains("route-map")) {
+            PolicyDialect::Bird
+        } else {
+            PolicyDialect::Ios
+        }
+    }
+}
+
+// Named policies loaded from router configuration files
+#[derive(Debug, Default)]
+pub struct PolicySet {
+    pub policies: HashMap<String, Arc<Policy>>,
+}
+
+impl PolicySet {
+    pub fn new() -> PolicySet {
+        PolicySet::default()
+    }
+
+    // Load policy definitions from "[ios:|junos:|bird:]filename", guessing
+    // the dialect from the content if it isn't given
+    pub fn load(&mut self, spec: &str) -> Result<()> {
+        let (dialect, filename) = match spec.split_once(':') {
+            Some(("ios", filename)) => (Some(PolicyDialect::Ios), filename),
+            Some(("junos", filename)) => (Some(PolicyDialect::Junos), filename),
+            Some(("bird", filename)) => (Some(PolicyDialect::Bird), filename),
+            _ => (None, spec),
+        };
+        let text = std::fs::read_to_string(Path::new(filename))
+            .with_context(|| format!("cannot read policy file {}", filename))?;
+        let dialect = dialect.unwrap_or_else(|| PolicyDialect::detect(&text));
+
+        match dialect {
+            PolicyDialect::Ios => policy_ios::parse(&text, self),
+            PolicyDialect::Junos => policy_junos::parse(&text, self),
+            PolicyDialect::Bird => policy_bird::parse(&text, self),
+        }.with_context(|| format!("{}: invalid {:?} policy", filename, dialect))
+    }
+
+    // Route-maps, policy-statements and filters take precedence over
+    // lists (prefix-lists, community-lists etc.) of the same name
+    pub fn insert(&mut self, policy: Policy) -> Arc<Policy> {
+        let policy = Arc::new(policy);
+        self.policies.insert(policy.name.clone(), Arc::clone(&policy));
+        policy
+    }
+
+    pub fn insert_list(&mut self, policy: Policy) -> Arc<Policy> {
+        let policy = Arc::new(policy);
+        Arc::clone(self.policies.entry(policy.name.clone()).or_insert(policy))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<Policy>> {
+        self.policies
+            .get(name)
+            .cloned()
+            .ok_or(anyhow!("policy {} is not defined", name))
+    }
+}
+
+// Longest prefix length for the address family
+pub fn max_plen(prefix: &IpAddr) -> u8 {
+    match prefix {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+// Cisco regular expressions use '_' to match any delimiter, including
+// the start and end of the string
+pub fn cisco_regex(s: &str) -> Result<Regex> {
+    Ok(Regex::new(&s.replace('_', "(?:^|$|[ {},])"))?)
+}
+
+// An element of an AS path pattern expressed in terms of whole ASNs
+// (JUNOS as-path regular expressions, BIRD path masks)
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsPathToken {
+    Asn(u32),
+    AnyAsn,             // JUNOS '.', BIRD '?'
+    Range(u32, u32),    // JUNOS 65000-65100, BIRD 65000..65100
+    Operator(String),   // repetition, alternation, grouping
+}
+
+// Translate an ASN-oriented pattern into a regular expression on the
+// textual AS path, as displayed (eg. "3356 174 {64510 64511}"). The
+// pattern is anchored at both ends.
+pub fn aspath_token_regex(tokens: &[AsPathToken]) -> Result<Regex> {
+    const DELIMITER: &str = r"\}?(?: |$)";
+    let mut expression = String::from("^");
+    for token in tokens {
+        match token {
+            AsPathToken::Asn(asn) => expression.push_str(&format!(r"(?:\{{?{}{})", asn, DELIMITER)),
+            AsPathToken::AnyAsn => expression.push_str(&format!(r"(?:\{{?\d+{})", DELIMITER)),
+            AsPathToken::Range(low, high) => {
+                if high.saturating_sub(*low) > 1024 {
+                    return Err(anyhow!("AS range {}-{} is too large", low, high));
+                }
+                let alternatives = (*low..=*high)
+                    .map(|x| x.to_string())
+                    .collect::<Vec<String>>()
+                    .join("|");
+                expression.push_str(&format!(r"(?:\{{?(?:{}){})", alternatives, DELIMITER));
+            },
+            AsPathToken::Operator(op) if op == "(" => expression.push_str("(?:"),
+            AsPathToken::Operator(op) => expression.push_str(op),
+        }
+    }
+    expression.push('$');
+    Ok(Regex::new(&expression)?)
+}
+
+// Convert IOS-style optional ge/le bounds into a PrefixRange filter. With
+// neither, the match is exact
+pub fn prefix_range(prefix: Prefix, ge: Option<u8>, le: Option<u8>) -> Result<Filter> {
+    let max = max_plen(&prefix.prefix);
+    let (ge, le) = match (ge, le) {
+        (None, None) => (prefix.len, prefix.len),
+        (Some(ge), None) => (ge, max),
+        (None, Some(le)) => (prefix.len, le),
+        (Some(ge), Some(le)) => (ge, le),
+    };
+    if prefix.len > max || ge < prefix.len || le > max || ge > le {
+        return Err(anyhow!("invalid prefix length range {} ge {} le {}", prefix, ge, le));
+    }
+    Ok(Filter::PrefixRange(Prefix::new(prefix.prefix, prefix.len), ge, le))
+}
+
+// Combine alternative conditions into one
+pub fn any_of(mut filters: Vec<Filter>) -> Filter {
+    if filters.len() == 1 {
+        filters.remove(0)
+    } else {
+        Filter::Any(filters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cisco_regex_underscore() {
+        let regex = cisco_regex("_3356_").unwrap();
+        assert!(regex.is_match("3356"));
+        assert!(regex.is_match("174 3356 64500"));
+        assert!(regex.is_match("174 {3356,64500}"));
+        assert!(!regex.is_match("33560 64500"));
+        assert!(!regex.is_match("174 13356"));
+
+        // ^ and $ anchor as usual alongside it
+        let regex = cisco_regex("^174_").unwrap();
+        assert!(regex.is_match("174 3356"));
+        assert!(!regex.is_match("3356 174"));
+    }
+
+    #[test]
+    fn detect_dialect() {
+        assert_eq!(PolicyDialect::detect("policy-options { policy-statement X { then accept; } }"), PolicyDialect::Junos);
+        assert_eq!(PolicyDialect::detect("filter x { if net ~ [ 10.0.0.0/8+ ] then accept; reject; }"), PolicyDialect::Bird);
+        assert_eq!(PolicyDialect::detect("route-map X permit 10\n match as-path 1\n"), PolicyDialect::Ios);
+    }
+
+    #[test]
+    fn prefix_range_bounds() {
+        let prefix = Prefix::from_str("10.0.0.0/8").unwrap();
+        assert!(prefix_range(prefix, Some(16), Some(24)).is_ok());
+        assert!(prefix_range(prefix, Some(4), None).is_err());
+        assert!(prefix_range(prefix, None, Some(33)).is_err());
+        assert!(prefix_range(prefix, Some(24), Some(16)).is_err());
+    }
+
+    fn community(s: &str) -> Community {
+        Community::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn set_actions() {
+        let mut rt = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 64500],
+            vec![MrtAttribute::Community(Arc::new(vec![community("174:1"), community("174:2")]))]);
+        SetAction::LocalPref(200).apply(&mut rt);
+        SetAction::Med(50).apply(&mut rt);
+        SetAction::Prepend(64496, 2).apply(&mut rt);
+        SetAction::NextHop(IpAddr::from_str("198.51.100.1").unwrap()).apply(&mut rt);
+        SetAction::AddCommunities(vec![community("64496:1"), community("64496:1:2")]).apply(&mut rt);
+        SetAction::RemoveCommunities(vec![CommunityPattern::from_str("174:*").unwrap()]).apply(&mut rt);
+
+        assert_eq!(rt.get_local_pref(), Some(200));
+        assert_eq!(rt.get_med(), Some(50));
+        assert_eq!(rt.get_aspath(), "64496 64496 174 64500");
+        assert_eq!(rt.get_nexthop(), IpAddr::from_str("198.51.100.1").unwrap());
+        assert_eq!(rt.get_community().unwrap(), "64496:1 64496:1:2");
+
+        SetAction::SetCommunities(vec![community("no-export")]).apply(&mut rt);
+        assert_eq!(rt.get_community().unwrap(), "65535:65281");
+    }
+
+    #[test]
+    fn actions_of_matching_terms() {
+        // A modification by a term that doesn't decide is seen by the
+        // terms that follow; a rejecting term modifies nothing
+        let mut policy = Policy::new("test", PolicyAction::Reject);
+        policy.push("a", vec![Filter::As(174)], vec![SetAction::LocalPref(200)], PolicyAction::Next);
+        policy.push("b", vec![Filter::As(1299)], vec![SetAction::Med(1)], PolicyAction::Reject);
+        policy.push("c", vec![Filter::custom(|_: &IpAddr, _: u8, rt: &MrtRibEntry| rt.get_local_pref() == Some(200))],
+                    vec![SetAction::Med(10)], PolicyAction::Accept);
+
+        let prefix = IpAddr::from_str("10.0.0.0").unwrap();
+        let mut rt = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 64500], vec![]);
+        let shared = Arc::clone(&rt.attributes);
+        assert!(policy.apply(&prefix, 8, &mut rt));
+        assert_eq!((rt.get_local_pref(), rt.get_med()), (Some(200), Some(10)));
+        // The attributes shared with other paths are left alone
+        assert!(!shared.iter().any(|x| matches!(x, MrtAttribute::LocalPref(_))));
+
+        let mut rt = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![]);
+        assert!(!policy.apply(&prefix, 8, &mut rt));
+        assert_eq!(rt.get_med(), None);
+    }
+
+    #[test]
+    fn permits_as_applied() {
+        // A later term matching a community added by an earlier one
+        let tagged = Community::Standard((65000, 1));
+        let mut policy = Policy::new("tag", PolicyAction::Reject);
+        policy.push("tag", vec![Filter::As(174)], vec![SetAction::AddCommunities(vec![tagged])], PolicyAction::Next);
+        policy.push("accept", vec![Filter::Community(tagged)], vec![], PolicyAction::Accept);
+        let policy = Arc::new(policy);
+
+        let prefix = IpAddr::from_str("10.0.0.0").unwrap();
+        let rt = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 64500], vec![]);
+        assert_eq!(policy.evaluate(&prefix, 8, &rt), PolicyAction::Accept);
+        assert!(policy.permits(&prefix, 8, &rt));
+        // Nested in other filters, as at the top level, and the path unchanged
+        assert!(Filter::Any(vec![Filter::Policy(Arc::clone(&policy))]).matches(&prefix, 8, &rt));
+        assert!(!Filter::Not(Box::new(Filter::Policy(Arc::clone(&policy)))).matches(&prefix, 8, &rt));
+        assert_eq!(rt.communities().count(), 0);
+        assert!(policy.apply(&prefix, 8, &mut rt.clone()));
+
+        let other = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![]);
+        assert!(!policy.permits(&prefix, 8, &other));
+    }
+
+    #[test]
+    fn not_reapplied_to_snapshot() {
+        let mut policy = Policy::new("prepend", PolicyAction::Reject);
+        policy.push("174", vec![Filter::As(174)], vec![SetAction::Prepend(65000, 1)], PolicyAction::Accept);
+        let filter = Filter::Policy(Arc::new(policy));
+
+        let nlri = |source| MrtNlri {
+            sequence: 0,
+            plen: 8,
+            prefix: IpAddr::from_str("10.0.0.0").unwrap(),
+            entry_count: 2,
+            rib_entries: vec![MrtRibEntry::test_path("192.0.2.1", 174, &[174, 64500], vec![]),
+                              MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![])],
+            source,
+        };
+        let mut loaded = nlri(NlriSource::TableDumpV2(1_700_000_000));
+        assert!(filter.eval(&mut loaded));
+        assert_eq!(loaded.rib_entries.iter().map(|x| x.get_aspath()).collect::<Vec<String>>(), ["65000 174 64500"]);
+
+        // Routes from a snapshot, saved with the policy applied, are kept as they are
+        let mut snapshot = MrtNlri { rib_entries: loaded.rib_entries.clone(), ..nlri(NlriSource::Table) };
+        assert!(filter.eval(&mut snapshot));
+        assert_eq!(snapshot.rib_entries.iter().map(|x| x.get_aspath()).collect::<Vec<String>>(), ["65000 174 64500"]);
+    }
+}
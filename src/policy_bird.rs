@@ -0,0 +1,595 @@
+use crate::*;
+
+// BIRD filter definitions:
+//
+//   define NAME = [ PREFIX-PATTERN, ... ];        prefix set constant
+//   define NAME = [= PATH-MASK =];                AS path mask constant
+//   filter NAME {
+//       if net ~ [ 10.0.0.0/8+, 192.0.2.0/24{24,28}, 198.51.100.0/24 ] then accept;
+//       if net.len > 24 then reject;
+//       if bgp_path ~ [= * 3356 * =] then reject;
+//       if bgp_path.first = 174 && bgp_path.len < 10 then accept;
+//       if (65000, 100) ~ bgp_community || (65000,1,*) ~ bgp_large_community then { accept; }
+//       if ! (bgp_community ~ [(65535, 666)]) then accept; else reject;
+//       if bgp_path.first = 3356 then { bgp_local_pref = 300; bgp_community.add((65000, 3356)); }
+//       reject;
+//   }
+//
+// Conditions may be combined with &&, || and ! and grouped with ( ).
+// Assignments to bgp_local_pref, bgp_med and bgp_next_hop, and
+// bgp_community/bgp_large_community .add()/.delete() and bgp_path.prepend()
+// modify the route; other statements (print etc.) are ignored.
+// Other top-level configuration (protocols etc.) is skipped. A filter
+// that finishes without accept or reject rejects the route, as BIRD does.
+
+fn tokenize(text: &str) -> Result<Vec<String>> {
+    let mut tokens: Vec<String> = vec![];
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut i = 0;
+
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':' || c == '/';
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied().unwrap_or(' ');
+        match c {
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '/' if next == '*' => {
+                i += 2;
+                while i < chars.len() && !(chars[i - 1] == '*' && chars[i] == '/') {
+                    i += 1;
+                }
+                i += 1;
+            },
+            '"' => {
+                let start = i + 1;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err(anyhow!("unterminated quoted string"));
+                }
+                tokens.push(chars[start..i].iter().collect());
+                i += 1;
+            },
+            c if c.is_whitespace() => i += 1,
+            c if is_word(c) => {
+                let start = i;
+                while i < chars.len() && is_word(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                // Split numeric ranges, 100..200
+                match word.split_once("..") {
+                    Some((low, high)) if !low.is_empty() && !high.is_empty() => {
+                        tokens.push(low.to_string());
+                        tokens.push(String::from(".."));
+                        tokens.push(high.to_string());
+                    },
+                    _ => tokens.push(word),
+                }
+            },
+            _ => {
+                let pair: String = [c, next].iter().collect();
+                if ["[=", "=]", "&&", "||", "!~", "!=", "<=", ">="].contains(&pair.as_str()) {
+                    tokens.push(pair);
+                    i += 2;
+                } else {
+                    tokens.push(c.to_string());
+                    i += 1;
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    index: usize,
+    defines: HashMap<String, Vec<String>>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.index).map(|x| x.as_str())
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&str> {
+        self.tokens.get(self.index + offset).map(|x| x.as_str())
+    }
+
+    fn next(&mut self) -> Result<String> {
+        let token = self.tokens.get(self.index).cloned().context("unexpected end of configuration")?;
+        self.index += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(anyhow!("expected \"{}\", found \"{}\"", expected, token));
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, expected: &str) -> bool {
+        if self.peek() == Some(expected) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T> {
+        let token = self.next()?;
+        token.parse::<T>().map_err(|_| anyhow!("expected a number, found \"{}\"", token))
+    }
+
+    // Skip a top-level statement we don't understand, including any
+    // { } blocks it has
+    fn skip_statement(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next()?.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.accept(";");
+                        return Ok(());
+                    }
+                },
+                ";" if depth == 0 => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn parse(text: &str, policies: &mut PolicySet) -> Result<()> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens: &tokens, index: 0, defines: HashMap::new() };
+
+    while let Some(token) = parser.peek() {
+        match token {
+            "define" => {
+                parser.next()?;
+                let name = parser.next()?;
+                parser.expect("=")?;
+                let mut value = vec![];
+                loop {
+                    let token = parser.next()?;
+                    if token == ";" {
+                        break;
+                    }
+                    value.push(token);
+                }
+                parser.defines.insert(name, value);
+            },
+            "filter" => {
+                parser.next()?;
+                let name = parser.next()?;
+                let mut policy = Policy::new(&name, PolicyAction::Reject);
+                parser.expect("{")?;
+                parse_block(&mut parser, &[], &mut policy)
+                    .with_context(|| format!("filter {}", name))?;
+                policies.insert(policy);
+            },
+            _ => parser.skip_statement()?,
+        }
+    }
+    Ok(())
+}
+
+// Flatten the statements of a block into policy terms, each carrying the
+// conditions of the enclosing if statements. The opening { has been read.
+// Assignments are held back and attached to the next term, so that a
+// block's conditions are tested once, before its own modifications.
+fn parse_block(parser: &mut Parser, conditions: &[Filter], policy: &mut Policy) -> Result<()> {
+    let mut actions = vec![];
+    while !parser.accept("}") {
+        parse_statement(parser, conditions, &mut actions, policy)?;
+    }
+    push_actions(conditions, &mut actions, policy);
+    Ok(())
+}
+
+// Modifications not followed by accept or reject in the same block
+fn push_actions(conditions: &[Filter], actions: &mut Vec<SetAction>, policy: &mut Policy) {
+    if !actions.is_empty() {
+        policy.push(&format!("term {}", policy.terms.len() + 1), conditions.to_vec(), std::mem::take(actions), PolicyAction::Next);
+    }
+}
+
+fn parse_statement(parser: &mut Parser, conditions: &[Filter], actions: &mut Vec<SetAction>, policy: &mut Policy) -> Result<()> {
+    let token = parser.next()?;
+    match token.as_str() {
+        "{" => {
+            push_actions(conditions, actions, policy);
+            parse_block(parser, conditions, policy)?
+        },
+        "accept" | "reject" => {
+            // optional reason string
+            if parser.peek() != Some(";") {
+                parser.next()?;
+            }
+            parser.expect(";")?;
+            let action = if token == "accept" { PolicyAction::Accept } else { PolicyAction::Reject };
+            policy.push(&format!("term {}", policy.terms.len() + 1), conditions.to_vec(), std::mem::take(actions), action);
+        },
+        "if" => {
+            push_actions(conditions, actions, policy);
+            let condition = parse_expression(parser)?;
+            parser.expect("then")?;
+
+            let mut then_conditions = conditions.to_vec();
+            then_conditions.push(condition.clone());
+            let mut then_actions = vec![];
+            parse_statement(parser, &then_conditions, &mut then_actions, policy)?;
+            push_actions(&then_conditions, &mut then_actions, policy);
+
+            if parser.accept("else") {
+                let mut else_conditions = conditions.to_vec();
+                else_conditions.push(Filter::Not(Box::new(condition)));
+                let mut else_actions = vec![];
+                parse_statement(parser, &else_conditions, &mut else_actions, policy)?;
+                push_actions(&else_conditions, &mut else_actions, policy);
+            }
+        },
+        ";" => {},
+        "bgp_local_pref" | "bgp_med" | "bgp_next_hop" | "bgp_community.add" | "bgp_community.delete"
+            | "bgp_large_community.add" | "bgp_large_community.delete" | "bgp_path.prepend" => {
+            actions.push(parse_assignment(parser, &token)?);
+            parser.expect(";")?;
+        },
+        // Other assignments, print etc. don't affect the route
+        _ => {
+            while parser.next()? != ";" {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_assignment(parser: &mut Parser, attribute: &str) -> Result<SetAction> {
+    if let Some((attribute, method)) = attribute.split_once('.') {
+        parser.expect("(")?;
+        let action = match (attribute, method) {
+            ("bgp_path", _) => SetAction::Prepend(parser.number()?, 1),
+            (_, "add") => {
+                let communities = parse_community_list(parser, attribute)?
+                    .into_iter()
+                    .map(|x| match x {
+                        CommunityPattern::Exact(community) => Ok(community),
+                        _ => Err(anyhow!("only literal communities may be added to {}", attribute)),
+                    })
+                    .collect::<Result<Vec<Community>>>()?;
+                SetAction::AddCommunities(communities)
+            },
+            _ => SetAction::RemoveCommunities(parse_community_list(parser, attribute)?),
+        };
+        parser.expect(")")?;
+        return Ok(action);
+    }
+
+    parser.expect("=")?;
+    Ok(match attribute {
+        "bgp_local_pref" => SetAction::LocalPref(parser.number()?),
+        "bgp_med" => SetAction::Med(parser.number()?),
+        _ => SetAction::NextHop(parser.number()?),
+    })
+}
+
+// (a, b) or [ (a, b), (c, *) ] as the argument of .add() or .delete()
+fn parse_community_list(parser: &mut Parser, attribute: &str) -> Result<Vec<CommunityPattern>> {
+    let set = parser.accept("[");
+    let mut patterns = vec![];
+    loop {
+        patterns.push(parse_community(parser, attribute)?);
+        if !set || !parser.accept(",") {
+            break;
+        }
+    }
+    if set {
+        parser.expect("]")?;
+    }
+    Ok(patterns)
+}
+
+fn parse_community(parser: &mut Parser, attribute: &str) -> Result<CommunityPattern> {
+    parser.expect("(")?;
+    let mut fields = vec![parse_community_field(parser)?];
+    while parser.accept(",") {
+        fields.push(parse_community_field(parser)?);
+    }
+    parser.expect(")")?;
+    community_pattern(fields, attribute)
+}
+
+fn parse_expression(parser: &mut Parser) -> Result<Filter> {
+    let mut alternatives = vec![parse_conjunction(parser)?];
+    while parser.accept("||") {
+        alternatives.push(parse_conjunction(parser)?);
+    }
+    Ok(any_of(alternatives))
+}
+
+fn parse_conjunction(parser: &mut Parser) -> Result<Filter> {
+    let mut terms = vec![parse_unary(parser)?];
+    while parser.accept("&&") {
+        terms.push(parse_unary(parser)?);
+    }
+    Ok(if terms.len() == 1 { terms.remove(0) } else { Filter::All(terms) })
+}
+
+fn parse_unary(parser: &mut Parser) -> Result<Filter> {
+    if parser.accept("!") {
+        return Ok(Filter::Not(Box::new(parse_unary(parser)?)));
+    }
+    // A parenthesised expression, unless it is a community (a, b)
+    if parser.peek() == Some("(") && parser.peek_at(2) != Some(",") {
+        parser.next()?;
+        let expression = parse_expression(parser)?;
+        parser.expect(")")?;
+        return Ok(expression);
+    }
+    parse_comparison(parser)
+}
+
+fn negate_if(negate: bool, filter: Filter) -> Filter {
+    if negate {
+        Filter::Not(Box::new(filter))
+    } else {
+        filter
+    }
+}
+
+fn parse_match_operator(parser: &mut Parser) -> Result<bool> {
+    match parser.next()?.as_str() {
+        "~" => Ok(false),
+        "!~" => Ok(true),
+        token => Err(anyhow!("expected ~ or !~, found \"{}\"", token)),
+    }
+}
+
+fn parse_comparison(parser: &mut Parser) -> Result<Filter> {
+    let token = parser.next()?;
+    match token.as_str() {
+        "net" => {
+            let negate = parse_match_operator(parser)?;
+            Ok(negate_if(negate, with_constant(parser, parse_prefix_set)?))
+        },
+        "bgp_path" => {
+            let negate = parse_match_operator(parser)?;
+            Ok(negate_if(negate, with_constant(parser, parse_path_mask)?))
+        },
+        "net.len" => {
+            let (negate, min, max) = parse_numeric_comparison(parser, 128)?;
+            Ok(negate_if(negate, Filter::PrefixLength(min as u8, max as u8)))
+        },
+        "bgp_path.len" => {
+            let (negate, min, max) = parse_numeric_comparison(parser, u32::MAX)?;
+            Ok(negate_if(negate, Filter::AsPathLength(min as usize, max as usize)))
+        },
+        "bgp_path.first" | "bgp_path.last" => {
+            let negate = match parser.next()?.as_str() {
+                "=" => false,
+                "!=" => true,
+                op => return Err(anyhow!("unsupported operator {} for {}", op, token)),
+            };
+            let asn = AsPathToken::Asn(parser.number()?);
+            let any = AsPathToken::AnyAsn;
+            let repeat = AsPathToken::Operator(String::from("*"));
+            let tokens = if token == "bgp_path.first" {
+                vec![asn, any, repeat]
+            } else {
+                vec![any, repeat, asn]
+            };
+            Ok(negate_if(negate, Filter::AsPathRegex(aspath_token_regex(&tokens)?)))
+        },
+        "(" => {
+            // (a, b) ~ bgp_community, (a, b, c) ~ bgp_large_community
+            let mut fields = vec![parse_community_field(parser)?];
+            while parser.accept(",") {
+                fields.push(parse_community_field(parser)?);
+            }
+            parser.expect(")")?;
+            let negate = parse_match_operator(parser)?;
+            let attribute = parser.next()?;
+            let filter = Filter::CommunityPattern(community_pattern(fields, &attribute)?);
+            Ok(negate_if(negate, filter))
+        },
+        "bgp_community" | "bgp_large_community" => {
+            // bgp_community ~ [ (a, b), (c, d) ]
+            let negate = parse_match_operator(parser)?;
+            if parser.peek() != Some("[") {
+                return Err(anyhow!("expected a community set after {}", token));
+            }
+            let alternatives = parse_community_list(parser, &token)?
+                .into_iter()
+                .map(Filter::CommunityPattern)
+                .collect();
+            Ok(negate_if(negate, any_of(alternatives)))
+        },
+        _ => Err(anyhow!("unsupported condition \"{}\"", token)),
+    }
+}
+
+// If the next token is a defined constant, parse its value instead
+fn with_constant(parser: &mut Parser, parse: fn(&mut Parser) -> Result<Filter>) -> Result<Filter> {
+    let name = parser.peek().unwrap_or_default().to_string();
+    if let Some(value) = parser.defines.get(&name).cloned() {
+        parser.next()?;
+        let mut constant = Parser { tokens: &value, index: 0, defines: HashMap::new() };
+        return parse(&mut constant).with_context(|| format!("constant {}", name));
+    }
+    parse(parser)
+}
+
+// =, !=, <, >, <=, >= against a number, as an inclusive range
+fn parse_numeric_comparison(parser: &mut Parser, max: u32) -> Result<(bool, u32, u32)> {
+    let op = parser.next()?;
+    let n: u32 = parser.number()?;
+    Ok(match op.as_str() {
+        "=" => (false, n, n),
+        "!=" => (true, n, n),
+        "<" => (false, 0, n.saturating_sub(1)),
+        "<=" => (false, 0, n),
+        ">" => (false, n.saturating_add(1), max),
+        ">=" => (false, n, max),
+        _ => return Err(anyhow!("unsupported comparison operator \"{}\"", op)),
+    })
+}
+
+// [ 10.0.0.0/8+, 192.0.2.0/24{24,28}, 198.51.100.0/24 ]
+fn parse_prefix_set(parser: &mut Parser) -> Result<Filter> {
+    parser.expect("[")?;
+    let mut alternatives = vec![];
+    loop {
+        let token = parser.next()?;
+        let prefix = Prefix::from_str(&token).map_err(|_| anyhow!("invalid prefix {}", token))?;
+        let max = max_plen(&prefix.prefix);
+        let (ge, le) = if parser.accept("+") {
+            (prefix.len, max)
+        } else if parser.accept("{") {
+            let ge = parser.number()?;
+            parser.expect(",")?;
+            let le = parser.number()?;
+            parser.expect("}")?;
+            (ge, le)
+        } else if parser.peek() == Some("-") {
+            return Err(anyhow!("less-specific prefix patterns ({}-) are not supported", prefix));
+        } else {
+            (prefix.len, prefix.len)
+        };
+        alternatives.push(prefix_range(prefix, Some(ge), Some(le))?);
+        if !parser.accept(",") {
+            break;
+        }
+    }
+    parser.expect("]")?;
+    Ok(any_of(alternatives))
+}
+
+// [= * 3356 ? 65000..65100 * =]
+fn parse_path_mask(parser: &mut Parser) -> Result<Filter> {
+    parser.expect("[=")?;
+    let mut tokens = vec![];
+    loop {
+        let token = parser.next()?;
+        match token.as_str() {
+            "=]" => break,
+            "*" => {
+                tokens.push(AsPathToken::AnyAsn);
+                tokens.push(AsPathToken::Operator(String::from("*")));
+            },
+            "?" => tokens.push(AsPathToken::AnyAsn),
+            number => {
+                let low = number.parse::<u32>().map_err(|_| anyhow!("invalid path mask element {}", number))?;
+                if parser.accept("..") {
+                    tokens.push(AsPathToken::Range(low, parser.number()?));
+                } else {
+                    tokens.push(AsPathToken::Asn(low));
+                }
+            }
+        }
+    }
+    Ok(Filter::AsPathRegex(aspath_token_regex(&tokens)?))
+}
+
+fn parse_community_field(parser: &mut Parser) -> Result<CommunityField> {
+    if parser.accept("*") {
+        return Ok(CommunityField::Any);
+    }
+    let low: u64 = parser.number()?;
+    if parser.accept("..") {
+        return Ok(CommunityField::Range(low, parser.number()?));
+    }
+    Ok(CommunityField::Value(low))
+}
+
+fn community_pattern(fields: Vec<CommunityField>, attribute: &str) -> Result<CommunityPattern> {
+    let kind = match (attribute, fields.len()) {
+        ("bgp_community", 2) => CommunityKind::Standard,
+        ("bgp_large_community", 3) => CommunityKind::Large,
+        _ => return Err(anyhow!("{}-part community doesn't match {}", fields.len(), attribute)),
+    };
+    let values = fields
+        .iter()
+        .map(|x| match x {
+            CommunityField::Value(value) => Some(value.to_string()),
+            _ => None,
+        })
+        .collect::<Option<Vec<String>>>();
+    if let Some(values) = values {
+        if let Ok(community) = Community::from_str(&values.join(":")) {
+            return Ok(CommunityPattern::Exact(community));
+        }
+    }
+    Ok(CommunityPattern::Fields(kind, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+define CUSTOMERS = [ 10.0.0.0/8{8,24}, 192.0.2.0/24 ];
+define LEVEL3 = [= * 3356 * =];
+
+protocol bgp upstream {
+    local as 64500;
+    neighbor 192.0.2.1 as 174;
+}
+
+filter import {
+    if (65535, 666) ~ bgp_community then reject;
+    if net.len > 24 then reject;
+    if net ~ CUSTOMERS && bgp_path ~ LEVEL3 then {
+        bgp_local_pref = 200;
+        accept;
+    }
+    if bgp_path.first = 1299 && ! (bgp_path.len > 3) then accept;
+    reject;
+}
+";
+
+    fn import() -> Arc<Policy> {
+        let mut policies = PolicySet::new();
+        parse(CONFIG, &mut policies).unwrap();
+        policies.get("import").unwrap()
+    }
+
+    #[test]
+    fn filter() {
+        let import = import();
+        let prefix = IpAddr::from_str("10.1.0.0").unwrap();
+        let other = IpAddr::from_str("198.51.100.0").unwrap();
+        let via_3356 = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500], vec![]);
+        let via_1299 = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![]);
+        let long_1299 = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 2914, 174, 64500], vec![]);
+        let blackholed = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500],
+            vec![MrtAttribute::Community(Arc::new(vec![Community::Standard((65535, 666))]))]);
+
+        assert!(import.permits(&prefix, 16, &via_3356));
+        assert!(!import.permits(&prefix, 25, &via_3356));
+        assert!(!import.permits(&other, 24, &via_3356));
+        assert!(import.permits(&other, 24, &via_1299));
+        assert!(!import.permits(&other, 24, &long_1299));
+        assert!(!import.permits(&prefix, 16, &blackholed));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut policies = PolicySet::new();
+        assert!(parse("filter x { if net.len > 24 reject; }", &mut policies).is_err());
+        assert!(parse("filter x { if net ~ UNDEFINED then accept; }", &mut policies).is_err());
+        assert!(parse("filter x { accept;", &mut policies).is_err());
+    }
+}
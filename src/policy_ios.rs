@@ -0,0 +1,414 @@
+use crate::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Cisco IOS/IOS-XE policy configuration, as found in "show running-config":
+//
+//   ip prefix-list NAME [seq N] permit|deny PREFIX [ge N] [le N]
+//   ipv6 prefix-list NAME [seq N] permit|deny PREFIX [ge N] [le N]
+//   ip as-path access-list N permit|deny REGEX
+//   ip community-list standard|expanded NAME permit|deny COMMUNITIES|REGEX
+//   ip community-list 1-99|100-500 permit|deny COMMUNITIES|REGEX
+//   ip large-community-list standard|expanded NAME permit|deny ...
+//   route-map NAME permit|deny SEQ
+//    match ip address prefix-list NAME...
+//    match ipv6 address prefix-list NAME...
+//    match as-path N...
+//    match community NAME...
+//    match large-community NAME...
+//    set local-preference N
+//    set metric N
+//    set community COMMUNITIES... [additive] | none
+//    set comm-list NAME delete
+//    set large-community COMMUNITIES... [additive] | none
+//    set large-comm-list NAME delete
+//    set as-path prepend ASN...
+//    set ip|ipv6 next-hop ADDRESS
+//
+// Anything else in the file is ignored, so a whole router configuration
+// can be loaded. Lists and route-maps all end with an implicit deny.
+
+#[derive(Default)]
+struct IosList {
+    entries: BTreeMap<u32, (PolicyAction, Vec<Filter>)>,
+}
+
+impl IosList {
+    fn push(&mut self, seq: Option<u32>, action: PolicyAction, conditions: Vec<Filter>) {
+        let seq = seq.unwrap_or_else(|| self.entries.keys().last().map_or(5, |x| x + 5));
+        self.entries.insert(seq, (action, conditions));
+    }
+
+    fn into_policy(self, name: &str) -> Policy {
+        let mut policy = Policy::new(name, PolicyAction::Reject);
+        for (seq, (action, conditions)) in self.entries {
+            policy.push(&format!("seq {}", seq), conditions, vec![], action);
+        }
+        policy
+    }
+}
+
+#[derive(Default)]
+struct RouteMapEntry {
+    action: Option<PolicyAction>,
+    matches: Vec<Vec<String>>,
+    sets: Vec<Vec<String>>,
+}
+
+#[derive(Default)]
+struct IosConfig {
+    prefix_lists: BTreeMap<String, IosList>,
+    aspath_lists: BTreeMap<String, IosList>,
+    community_lists: BTreeMap<String, IosList>,
+    large_community_lists: BTreeMap<String, IosList>,
+    // the communities selected by the permit entries of each list, for "delete"
+    community_patterns: BTreeMap<String, Vec<CommunityPattern>>,
+    large_community_patterns: BTreeMap<String, Vec<CommunityPattern>>,
+    route_maps: BTreeMap<String, BTreeMap<u32, RouteMapEntry>>,
+}
+
+pub fn parse(text: &str, policies: &mut PolicySet) -> Result<()> {
+    let mut config = IosConfig::default();
+    let mut route_map: Option<(String, u32)> = None;
+
+    for (line_number, line) in text.lines().enumerate() {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        if words.is_empty() || words[0].starts_with('!') {
+            continue;
+        }
+
+        // route-map sub-mode commands
+        if let Some((name, seq)) = &route_map {
+            let entry = config.route_maps.get_mut(name).unwrap().get_mut(seq).unwrap();
+            match words[0] {
+                "match" => {
+                    entry.matches.push(words[1..].iter().map(|x| x.to_string()).collect());
+                    continue;
+                },
+                "set" => {
+                    entry.sets.push(words[1..].iter().map(|x| x.to_string()).collect());
+                    continue;
+                },
+                "description" | "continue" => continue,
+                _ => route_map = None,
+            }
+        }
+
+        parse_line(&words, &mut config, &mut route_map)
+            .with_context(|| format!("line {}: {}", line_number + 1, line.trim()))?;
+    }
+
+    build(config, policies)
+}
+
+fn parse_action(word: Option<&&str>) -> Result<PolicyAction> {
+    match word {
+        Some(&"permit") => Ok(PolicyAction::Accept),
+        Some(&"deny") => Ok(PolicyAction::Reject),
+        _ => Err(anyhow!("expected permit or deny")),
+    }
+}
+
+fn parse_line(words: &[&str], config: &mut IosConfig, route_map: &mut Option<(String, u32)>) -> Result<()> {
+    match words {
+        ["ip" | "ipv6", "prefix-list", name, rest @ ..] => {
+            let mut rest = rest;
+            let mut seq = None;
+            match rest {
+                ["description", ..] => return Ok(()),
+                ["seq", n, tail @ ..] => {
+                    seq = Some(n.parse::<u32>()?);
+                    rest = tail;
+                },
+                _ => {}
+            }
+            let action = parse_action(rest.first())?;
+            let prefix = Prefix::from_str(rest.get(1).context("expected prefix")?)
+                .map_err(|_| anyhow!("invalid prefix"))?;
+            let (mut ge, mut le) = (None, None);
+            for pair in rest[2..].chunks(2) {
+                match pair {
+                    ["ge", n] => ge = Some(n.parse::<u8>()?),
+                    ["le", n] => le = Some(n.parse::<u8>()?),
+                    _ => return Err(anyhow!("expected ge or le")),
+                }
+            }
+            config.prefix_lists
+                .entry(name.to_string())
+                .or_default()
+                .push(seq, action, vec![prefix_range(prefix, ge, le)?]);
+        },
+
+        ["ip" | "bgp", "as-path", "access-list", name, action, regex @ ..] => {
+            let action = parse_action(Some(action))?;
+            let regex = cisco_regex(&regex.join(" "))?;
+            config.aspath_lists
+                .entry(name.to_string())
+                .or_default()
+                .push(None, action, vec![Filter::AsPathRegex(regex)]);
+        },
+
+        ["ip", list_type @ ("community-list" | "large-community-list"), rest @ ..] => {
+            let (expanded, name, rest) = match rest {
+                ["standard", name, rest @ ..] => (false, *name, rest),
+                ["expanded", name, rest @ ..] => (true, *name, rest),
+                [name, rest @ ..] => {
+                    // Numbered lists: 1-99 standard, 100-500 expanded
+                    let number = name.parse::<u32>().context("expected standard, expanded or list number")?;
+                    (number >= 100, *name, rest)
+                },
+                _ => return Err(anyhow!("incomplete community-list")),
+            };
+            let action = parse_action(rest.first())?;
+            let patterns = if expanded {
+                vec![CommunityPattern::Regex(cisco_regex(&rest[1..].join(" "))?)]
+            } else {
+                rest[1..]
+                    .iter()
+                    .filter(|x| **x != "internet")
+                    .map(|x| Community::from_str(x)
+                        .map(CommunityPattern::Exact)
+                        .map_err(|_| anyhow!("invalid community {}", x)))
+                    .collect::<Result<Vec<CommunityPattern>>>()?
+            };
+            let conditions = patterns.iter().cloned().map(Filter::CommunityPattern).collect();
+            let (lists, selected) = if *list_type == "community-list" {
+                (&mut config.community_lists, &mut config.community_patterns)
+            } else {
+                (&mut config.large_community_lists, &mut config.large_community_patterns)
+            };
+            lists.entry(name.to_string()).or_default().push(None, action, conditions);
+            if action == PolicyAction::Accept {
+                selected.entry(name.to_string()).or_default().extend(patterns);
+            }
+        },
+
+        ["route-map", name, rest @ ..] => {
+            let action = match rest.first() {
+                None => PolicyAction::Accept,
+                action => parse_action(action)?,
+            };
+            let seq = match rest.get(1) {
+                Some(seq) => seq.parse::<u32>()?,
+                None => 10,
+            };
+            let entry = config.route_maps
+                .entry(name.to_string())
+                .or_default()
+                .entry(seq)
+                .or_default();
+            entry.action = Some(action);
+            *route_map = Some((name.to_string(), seq));
+        },
+
+        _ => {}
+    }
+    Ok(())
+}
+
+// Build the lists first, then the route-maps that refer to them
+fn build(config: IosConfig, policies: &mut PolicySet) -> Result<()> {
+    fn insert_lists(lists: BTreeMap<String, IosList>, policies: &mut PolicySet) -> HashMap<String, Arc<Policy>> {
+        lists
+            .into_iter()
+            .map(|(name, list)| (name.clone(), policies.insert_list(list.into_policy(&name))))
+            .collect()
+    }
+    let prefix_lists = insert_lists(config.prefix_lists, policies);
+    let aspath_lists = insert_lists(config.aspath_lists, policies);
+    let community_lists = insert_lists(config.community_lists, policies);
+    let large_community_lists = insert_lists(config.large_community_lists, policies);
+
+    let lookup = |lists: &HashMap<String, Arc<Policy>>, kind: &str, names: &[String]| -> Result<Filter> {
+        if names.is_empty() {
+            return Err(anyhow!("expected {} name", kind));
+        }
+        Ok(any_of(names
+            .iter()
+            .map(|name| lists
+                .get(name)
+                .map(|x| Filter::Policy(Arc::clone(x)))
+                .ok_or(anyhow!("{} {} is not defined", kind, name)))
+            .collect::<Result<Vec<Filter>>>()?))
+    };
+
+    for (name, entries) in config.route_maps {
+        let mut policy = Policy::new(&name, PolicyAction::Reject);
+        for (seq, entry) in entries {
+            let mut conditions = vec![];
+            for clause in &entry.matches {
+                let clause = clause.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+                let condition = match clause.as_slice() {
+                    ["ip" | "ipv6", "address", "prefix-list", names @ ..] => {
+                        lookup(&prefix_lists, "prefix-list", &to_strings(names))?
+                    },
+                    ["as-path", names @ ..] => {
+                        lookup(&aspath_lists, "as-path access-list", &to_strings(names))?
+                    },
+                    ["community", names @ ..] if !names.contains(&"exact-match") => {
+                        lookup(&community_lists, "community-list", &to_strings(names))?
+                    },
+                    ["large-community", names @ ..] if !names.contains(&"exact-match") => {
+                        lookup(&large_community_lists, "large-community-list", &to_strings(names))?
+                    },
+                    _ => return Err(anyhow!("route-map {} {}: unsupported match clause \"{}\"",
+                                            name, seq, clause.join(" "))),
+                };
+                conditions.push(condition);
+            }
+            let mut actions = vec![];
+            for clause in &entry.sets {
+                let clause = clause.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+                match parse_set(&clause, &config.community_patterns, &config.large_community_patterns)
+                    .with_context(|| format!("route-map {} {}", name, seq))? {
+                    Some(mut set) => actions.append(&mut set),
+                    None => eprintln!("route-map {} {}: ignoring unsupported \"set {}\"", name, seq, clause.join(" ")),
+                }
+            }
+            policy.push(&format!("{} {}", name, seq), conditions, actions, entry.action.unwrap_or(PolicyAction::Accept));
+        }
+        policies.insert(policy);
+    }
+    Ok(())
+}
+
+// Translate a route-map set clause into attribute modifications
+fn parse_set(clause: &[&str],
+             community_patterns: &BTreeMap<String, Vec<CommunityPattern>>,
+             large_community_patterns: &BTreeMap<String, Vec<CommunityPattern>>) -> Result<Option<Vec<SetAction>>> {
+    let communities = |words: &[&str]| -> Result<Vec<Community>> {
+        words
+            .iter()
+            .map(|x| Community::from_str(x).map_err(|_| anyhow!("invalid community {}", x)))
+            .collect()
+    };
+    let delete = |lists: &BTreeMap<String, Vec<CommunityPattern>>, name: &str| -> Result<Vec<SetAction>> {
+        let patterns = lists.get(name).ok_or(anyhow!("community-list {} is not defined", name))?;
+        Ok(vec![SetAction::RemoveCommunities(patterns.clone())])
+    };
+    // Every community of the kind
+    let kind_of = |kind: CommunityKind| -> CommunityPattern {
+        let fields = if kind == CommunityKind::Large { 3 } else { 2 };
+        CommunityPattern::Fields(kind, vec![CommunityField::Any; fields])
+    };
+
+    Ok(Some(match clause {
+        ["local-preference", n] => vec![SetAction::LocalPref(n.parse()?)],
+        ["metric", n] => vec![SetAction::Med(n.parse()?)],
+        ["community" | "large-community", "none"] => {
+            let kind = if clause[0] == "community" { CommunityKind::Standard } else { CommunityKind::Large };
+            vec![SetAction::RemoveCommunities(vec![kind_of(kind)])]
+        },
+        ["community" | "large-community", values @ .., "additive"] => {
+            vec![SetAction::AddCommunities(communities(values)?)]
+        },
+        ["community" | "large-community", values @ ..] => {
+            // Replace the existing communities of the same kind
+            let kind = if clause[0] == "community" { CommunityKind::Standard } else { CommunityKind::Large };
+            vec![SetAction::RemoveCommunities(vec![kind_of(kind)]),
+                 SetAction::AddCommunities(communities(values)?)]
+        },
+        ["comm-list", name, "delete"] => delete(community_patterns, name)?,
+        ["large-comm-list", name, "delete"] => delete(large_community_patterns, name)?,
+        ["as-path", "prepend", asns @ ..] if !asns.is_empty() && asns[0] != "last-as" => {
+            // Prepend in reverse so the path reads as configured
+            asns.iter()
+                .rev()
+                .map(|x| Ok(SetAction::Prepend(x.parse()?, 1)))
+                .collect::<Result<Vec<SetAction>>>()?
+        },
+        ["ip" | "ipv6", "next-hop", address] => {
+            vec![SetAction::NextHop(IpAddr::from_str(address)?)]
+        },
+        _ => return Ok(None),
+    }))
+}
+
+fn to_strings(words: &[&str]) -> Vec<String> {
+    words.iter().map(|x| x.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+hostname edge1
+!
+ip prefix-list CUSTOMERS seq 5 permit 10.0.0.0/8 le 24
+ip prefix-list CUSTOMERS seq 10 deny 0.0.0.0/0 le 32
+ip as-path access-list 1 permit _3356_
+ip community-list standard BLACKHOLE permit blackhole
+ip community-list expanded TRANSIT permit ^3356:
+!
+route-map IMPORT deny 10
+ match community BLACKHOLE
+route-map IMPORT permit 20
+ description customers via Level3
+ match ip address prefix-list CUSTOMERS
+ match as-path 1
+ set local-preference 200
+ set comm-list TRANSIT delete
+!
+interface Loopback0
+ ip address 192.0.2.1 255.255.255.255
+";
+
+    fn import() -> Arc<Policy> {
+        let mut policies = PolicySet::new();
+        parse(CONFIG, &mut policies).unwrap();
+        policies.get("IMPORT").unwrap()
+    }
+
+    fn communities(list: &[&str]) -> Vec<MrtAttribute> {
+        let list = list.iter().map(|x| Community::from_str(x).unwrap()).collect();
+        vec![MrtAttribute::Community(Arc::new(list))]
+    }
+
+    #[test]
+    fn lists_and_route_map() {
+        let mut policies = PolicySet::new();
+        parse(CONFIG, &mut policies).unwrap();
+        for name in ["CUSTOMERS", "1", "BLACKHOLE", "TRANSIT", "IMPORT"] {
+            assert!(policies.get(name).is_ok(), "{} not loaded", name);
+        }
+        let import = policies.get("IMPORT").unwrap();
+        assert_eq!(import.terms.len(), 2);
+        assert_eq!(import.terms[0].action, PolicyAction::Reject);
+        assert_eq!(import.terms[1].conditions.len(), 2);
+        assert_eq!(import.terms[1].actions.len(), 2);
+    }
+
+    #[test]
+    fn route_map_matches() {
+        let import = import();
+        let prefix = IpAddr::from_str("10.1.0.0").unwrap();
+        let via_3356 = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500], vec![]);
+        let via_1299 = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![]);
+        let blackholed = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500], communities(&["65535:666"]));
+
+        assert!(import.permits(&prefix, 16, &via_3356));
+        assert!(!import.permits(&prefix, 25, &via_3356), "longer than the prefix-list allows");
+        assert!(!import.permits(&IpAddr::from_str("192.168.0.0").unwrap(), 16, &via_3356));
+        assert!(!import.permits(&prefix, 16, &via_1299));
+        assert!(!import.permits(&prefix, 16, &blackholed));
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let mut policies = PolicySet::new();
+        assert!(parse("ip prefix-list X permit 10.0.0.0/8 ge 4\n", &mut policies).is_err());
+        assert!(parse("ip prefix-list X allow 10.0.0.0/8\n", &mut policies).is_err());
+        assert!(parse("route-map X permit 10\n match as-path 99\n", &mut policies).is_err());
+    }
+
+    #[test]
+    fn route_map_sets() {
+        let import = import();
+        let prefix = IpAddr::from_str("10.1.0.0").unwrap();
+        let mut rt = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500], communities(&["3356:100", "174:1"]));
+        assert!(import.apply(&prefix, 16, &mut rt));
+        assert_eq!(rt.get_local_pref(), Some(200));
+        assert_eq!(rt.get_community().unwrap(), "174:1");
+    }
+}
@@ -0,0 +1,480 @@
+use crate::*;
+use std::sync::Arc;
+
+// JUNOS policy configuration, in the curly-brace form shown by
+// "show configuration policy-options":
+//
+//   prefix-list NAME { PREFIX; ... }
+//   community NAME members [ COMMUNITY|REGEX ... ];
+//   as-path NAME "REGEX";
+//   policy-statement NAME {
+//       term NAME {
+//           from {
+//               route-filter PREFIX exact|orlonger|longer|upto /N|prefix-length-range /A-/B;
+//               prefix-list NAME;
+//               prefix-list-filter NAME exact|orlonger|longer;
+//               community [ NAME ... ];
+//               as-path [ NAME ... ];
+//           }
+//           then {
+//               local-preference N;
+//               metric N;
+//               community add|delete|set NAME;
+//               as-path-prepend "ASN ...";
+//               next-hop ADDRESS;
+//               accept|reject|next term|next policy;
+//           }
+//       }
+//       then reject;
+//   }
+//
+// Other statements are ignored. A policy that ends without a decision
+// accepts the route, as the default BGP import policy does.
+
+#[derive(Debug)]
+struct Statement {
+    words: Vec<String>,
+    block: Option<Vec<Statement>>,
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut word = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            '/' if word.is_empty() && chars.peek() == Some(&'*') => {
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            },
+            '"' => {
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => quoted.push(c),
+                        None => return Err(anyhow!("unterminated quoted string")),
+                    }
+                }
+                tokens.push(quoted);
+            },
+            '{' | '}' | ';' | '[' | ']' => {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+                tokens.push(c.to_string());
+            },
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    tokens.push(std::mem::take(&mut word));
+                }
+            },
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    Ok(tokens)
+}
+
+fn parse_block(tokens: &[String], index: &mut usize, nested: bool) -> Result<Vec<Statement>> {
+    let mut statements = vec![];
+    let mut words: Vec<String> = vec![];
+
+    while *index < tokens.len() {
+        let token = &tokens[*index];
+        *index += 1;
+        match token.as_str() {
+            ";" => {
+                if !words.is_empty() {
+                    statements.push(Statement { words: std::mem::take(&mut words), block: None });
+                }
+            },
+            "{" => {
+                let block = parse_block(tokens, index, true)?;
+                statements.push(Statement { words: std::mem::take(&mut words), block: Some(block) });
+            },
+            "}" => {
+                if !nested {
+                    return Err(anyhow!("unexpected }}"));
+                }
+                return Ok(statements);
+            },
+            "inactive:" => {},
+            _ => words.push(token.clone()),
+        }
+    }
+    if nested {
+        return Err(anyhow!("missing }}"));
+    }
+    Ok(statements)
+}
+
+// Words following the keyword, with [ ... ] lists flattened
+fn arguments(words: &[String]) -> Vec<String> {
+    words
+        .iter()
+        .filter(|x| *x != "[" && *x != "]")
+        .cloned()
+        .collect()
+}
+
+#[derive(Default)]
+struct JunosConfig<'a> {
+    prefix_lists: HashMap<String, Vec<Prefix>>,
+    communities: HashMap<String, Arc<Policy>>,
+    community_members: HashMap<String, Vec<CommunityPattern>>,
+    aspaths: HashMap<String, Arc<Policy>>,
+    policies: Vec<&'a Statement>,
+}
+
+pub fn parse(text: &str, policies: &mut PolicySet) -> Result<()> {
+    let tokens = tokenize(text)?;
+    let statements = parse_block(&tokens, &mut 0, false)?;
+
+    let mut config = JunosConfig::default();
+    collect(&statements, &mut config)?;
+
+    for statement in &config.policies {
+        let name = statement.words.get(1).context("policy-statement without a name")?;
+        let policy = build_policy(name, statement.block.as_deref().unwrap_or_default(), &config)
+            .with_context(|| format!("policy-statement {}", name))?;
+        policies.insert(policy);
+    }
+
+    // Make the prefix lists available for direct use as well
+    for (name, prefixes) in &config.prefix_lists {
+        policies.insert_list(Policy::condition(name, prefix_list_filter(prefixes, "exact")?));
+    }
+    Ok(())
+}
+
+// Find the definitions, wherever they are in the configuration hierarchy
+fn collect<'a>(statements: &'a [Statement], config: &mut JunosConfig<'a>) -> Result<()> {
+    for statement in statements {
+        let words = statement.words.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+        match words.as_slice() {
+            ["prefix-list", name] => {
+                let prefixes = statement.block
+                    .as_deref()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|x| x.words.first())
+                    .map(|x| Prefix::from_str(x).map_err(|_| anyhow!("prefix-list {}: invalid prefix {}", name, x)))
+                    .collect::<Result<Vec<Prefix>>>()?;
+                config.prefix_lists.insert(name.to_string(), prefixes);
+            },
+            ["community", name, "members", members @ ..] => {
+                let members = arguments(&to_strings(members))
+                    .iter()
+                    .map(|x| community_member(x))
+                    .collect::<Result<Vec<CommunityPattern>>>()
+                    .with_context(|| format!("community {}", name))?;
+                let condition = Filter::All(members.iter().cloned().map(Filter::CommunityPattern).collect());
+                config.communities.insert(name.to_string(), Arc::new(Policy::condition(name, condition)));
+                config.community_members.insert(name.to_string(), members);
+            },
+            ["as-path", name, regex] => {
+                let regex = junos_aspath_regex(regex).with_context(|| format!("as-path {}", name))?;
+                config.aspaths.insert(name.to_string(), Arc::new(Policy::condition(name, Filter::AsPathRegex(regex))));
+            },
+            ["policy-statement", _] => config.policies.push(statement),
+            _ => {
+                if let Some(block) = &statement.block {
+                    collect(block, config)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_strings(words: &[&str]) -> Vec<String> {
+    words.iter().map(|x| x.to_string()).collect()
+}
+
+// A community member is either a literal community or a regular expression
+fn community_member(member: &str) -> Result<CommunityPattern> {
+    let literal = member
+        .replace("target:", "rt:")
+        .replace("origin:", "soo:")
+        .replace("large:", "");
+    if let Ok(community) = Community::from_str(&literal) {
+        return Ok(CommunityPattern::Exact(community));
+    }
+    Ok(CommunityPattern::Regex(regex::Regex::new(&format!("^(?:{})$", literal))?))
+}
+
+// JUNOS as-path regular expressions work on whole ASNs rather than
+// characters, and are implicitly anchored
+pub fn junos_aspath_regex(s: &str) -> Result<regex::Regex> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '0'..='9' => {
+                let mut number = c.to_string();
+                while let Some(c) = chars.next_if(|x| x.is_ascii_digit() || *x == '-') {
+                    number.push(c);
+                }
+                tokens.push(match number.split_once('-') {
+                    Some((low, high)) => AsPathToken::Range(low.parse()?, high.parse()?),
+                    None => AsPathToken::Asn(number.parse()?),
+                });
+            },
+            '.' => tokens.push(AsPathToken::AnyAsn),
+            '*' | '+' | '?' | '(' | ')' | '|' => tokens.push(AsPathToken::Operator(c.to_string())),
+            '{' => {
+                let mut repeat = c.to_string();
+                for c in chars.by_ref() {
+                    repeat.push(c);
+                    if c == '}' {
+                        break;
+                    }
+                }
+                tokens.push(AsPathToken::Operator(repeat));
+            },
+            '^' | '$' => {},
+            c if c.is_whitespace() => {},
+            c => return Err(anyhow!("unsupported as-path regular expression character '{}'", c)),
+        }
+    }
+    aspath_token_regex(&tokens)
+}
+
+fn prefix_list_filter(prefixes: &[Prefix], modifier: &str) -> Result<Filter> {
+    Ok(any_of(prefixes
+        .iter()
+        .map(|x| route_filter(x, &[modifier.to_string()]))
+        .collect::<Result<Vec<Filter>>>()?))
+}
+
+fn route_filter(prefix: &Prefix, modifier: &[String]) -> Result<Filter> {
+    let max = max_plen(&prefix.prefix);
+    let slash = |x: &str| -> Result<u8> {
+        Ok(x.strip_prefix('/').unwrap_or(x).parse::<u8>()?)
+    };
+    let (ge, le) = match modifier.iter().map(|x| x.as_str()).collect::<Vec<&str>>().as_slice() {
+        ["exact"] => (prefix.len, prefix.len),
+        ["orlonger"] => (prefix.len, max),
+        ["longer"] => (prefix.len + 1, max),
+        ["upto", n] => (prefix.len, slash(n)?),
+        ["prefix-length-range", range] => {
+            let (low, high) = range.split_once('-').context("expected /A-/B")?;
+            (slash(low)?, slash(high)?)
+        },
+        _ => return Err(anyhow!("unsupported route-filter \"{} {}\"", prefix, modifier.join(" "))),
+    };
+    prefix_range(*prefix, Some(ge), Some(le))
+}
+
+fn build_policy(name: &str, statements: &[Statement], config: &JunosConfig) -> Result<Policy> {
+    let mut policy = Policy::new(name, PolicyAction::Accept);
+
+    for statement in statements {
+        match statement.words.first().map(|x| x.as_str()) {
+            Some("term") => {
+                let term = statement.words.get(1).context("term without a name")?;
+                let (conditions, actions, action) = build_term(statement.block.as_deref().unwrap_or_default(), config)
+                    .with_context(|| format!("term {}", term))?;
+                policy.push(term, conditions, actions, action);
+            },
+            Some("from") | Some("then") => {
+                // Un-named term directly in the policy-statement
+                let (conditions, actions, action) = build_term(std::slice::from_ref(statement), config)?;
+                policy.push("", conditions, actions, action);
+            },
+            _ => {}
+        }
+    }
+    Ok(policy)
+}
+
+fn build_term(statements: &[Statement], config: &JunosConfig) -> Result<(Vec<Filter>, Vec<SetAction>, PolicyAction)> {
+    let mut conditions = vec![];
+    let mut actions = vec![];
+    let mut prefix_conditions = vec![];     // route-filters etc are ORed together
+    let mut action = PolicyAction::Next;
+
+    for statement in statements {
+        let keyword = statement.words.first().map(|x| x.as_str());
+        // Either "from { a; b; }" or "from a;"
+        let clauses: Vec<Vec<String>> = match &statement.block {
+            Some(block) => block.iter().map(|x| x.words.clone()).collect(),
+            None => vec![statement.words[1..].to_vec()],
+        };
+        for clause in clauses {
+            let words = clause.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+            match (keyword, words.as_slice()) {
+                (Some("from"), ["route-filter", prefix, modifier @ ..]) => {
+                    let prefix = Prefix::from_str(prefix).map_err(|_| anyhow!("invalid prefix {}", prefix))?;
+                    prefix_conditions.push(route_filter(&prefix, &to_strings(modifier))?);
+                },
+                (Some("from"), ["prefix-list", names @ ..]) => {
+                    for name in arguments(&to_strings(names)) {
+                        let prefixes = config.prefix_lists.get(&name).ok_or(anyhow!("prefix-list {} is not defined", name))?;
+                        prefix_conditions.push(prefix_list_filter(prefixes, "exact")?);
+                    }
+                },
+                (Some("from"), ["prefix-list-filter", name, modifier]) => {
+                    let prefixes = config.prefix_lists.get(*name).ok_or(anyhow!("prefix-list {} is not defined", name))?;
+                    prefix_conditions.push(prefix_list_filter(prefixes, modifier)?);
+                },
+                (Some("from"), ["community", names @ ..]) => {
+                    conditions.push(any_of(arguments(&to_strings(names))
+                        .iter()
+                        .map(|name| lookup(&config.communities, "community", name))
+                        .collect::<Result<Vec<Filter>>>()?));
+                },
+                (Some("from"), ["as-path", names @ ..]) => {
+                    conditions.push(any_of(arguments(&to_strings(names))
+                        .iter()
+                        .map(|name| lookup(&config.aspaths, "as-path", name))
+                        .collect::<Result<Vec<Filter>>>()?));
+                },
+                (Some("from"), ["protocol", ..] | ["family", ..]) => {},
+                (Some("from"), _) => return Err(anyhow!("unsupported from condition \"{}\"", clause.join(" "))),
+
+                (Some("then"), ["accept"]) => action = PolicyAction::Accept,
+                (Some("then"), ["reject"]) => action = PolicyAction::Reject,
+                (Some("then"), ["next", "term"]) => action = PolicyAction::Next,
+                // A single policy: evaluation ends with the default (accept)
+                (Some("then"), ["next", "policy"]) => action = PolicyAction::Accept,
+                (Some("then"), _) => match then_action(&words, config)? {
+                    Some(mut set) => actions.append(&mut set),
+                    None => eprintln!("ignoring unsupported \"then {}\"", clause.join(" ")),
+                },
+                _ => {}
+            }
+        }
+    }
+    if !prefix_conditions.is_empty() {
+        conditions.insert(0, any_of(prefix_conditions));
+    }
+    Ok((conditions, actions, action))
+}
+
+// Translate an attribute modification in a then clause
+fn then_action(words: &[&str], config: &JunosConfig) -> Result<Option<Vec<SetAction>>> {
+    let members = |name: &str| -> Result<&Vec<CommunityPattern>> {
+        config.community_members.get(name).ok_or(anyhow!("community {} is not defined", name))
+    };
+    // Communities to be added must be literal, not regular expressions
+    let literals = |name: &str| -> Result<Vec<Community>> {
+        members(name)?
+            .iter()
+            .map(|x| match x {
+                CommunityPattern::Exact(community) => Ok(*community),
+                _ => Err(anyhow!("community {} has a regular expression member", name)),
+            })
+            .collect()
+    };
+    Ok(Some(match words {
+        ["local-preference", n] => vec![SetAction::LocalPref(n.parse()?)],
+        ["metric", n] => vec![SetAction::Med(n.parse()?)],
+        ["community", "add" | "+", name] => vec![SetAction::AddCommunities(literals(name)?)],
+        ["community", "delete" | "-", name] => vec![SetAction::RemoveCommunities(members(name)?.clone())],
+        ["community", "set" | "=", name] => vec![SetAction::SetCommunities(literals(name)?)],
+        ["as-path-prepend", asns] => {
+            asns.split_whitespace()
+                .rev()
+                .map(|x| Ok(SetAction::Prepend(x.parse()?, 1)))
+                .collect::<Result<Vec<SetAction>>>()?
+        },
+        ["next-hop", address] if IpAddr::from_str(address).is_ok() => {
+            vec![SetAction::NextHop(IpAddr::from_str(address)?)]
+        },
+        _ => return Ok(None),
+    }))
+}
+
+fn lookup(definitions: &HashMap<String, Arc<Policy>>, kind: &str, name: &str) -> Result<Filter> {
+    let policy = definitions.get(name).ok_or(anyhow!("{} {} is not defined", kind, name))?;
+    Ok(Filter::Policy(Arc::clone(policy)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+policy-options {
+    prefix-list CUSTOMERS {
+        10.0.0.0/8;
+        192.0.2.0/24;
+    }
+    community BLACKHOLE members 65535:666;
+    community TRANSIT members "^3356:.*$";
+    as-path VIA-LEVEL3 ".* 3356 .*";
+    policy-statement IMPORT {
+        term blackhole {
+            from community BLACKHOLE;
+            then reject;
+        }
+        term customers {
+            from {
+                route-filter 10.0.0.0/8 upto /24;
+                as-path VIA-LEVEL3;
+            }
+            then {
+                local-preference 200;
+                community delete TRANSIT;
+                accept;
+            }
+        }
+        then reject;
+    }
+}
+"#;
+
+    #[test]
+    fn policy_statement() {
+        let mut policies = PolicySet::new();
+        parse(CONFIG, &mut policies).unwrap();
+        assert!(policies.get("CUSTOMERS").is_ok());
+        let import = policies.get("IMPORT").unwrap();
+        assert_eq!(import.terms.iter().map(|x| x.name.as_str()).collect::<Vec<&str>>(), ["blackhole", "customers", ""]);
+
+        let prefix = IpAddr::from_str("10.1.0.0").unwrap();
+        let via_3356 = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500], vec![]);
+        let via_1299 = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![]);
+        let blackholed = MrtRibEntry::test_path("192.0.2.1", 174, &[174, 3356, 64500],
+            vec![MrtAttribute::Community(Arc::new(vec![Community::Standard((65535, 666))]))]);
+        assert!(import.permits(&prefix, 16, &via_3356));
+        assert!(!import.permits(&prefix, 25, &via_3356));
+        assert!(!import.permits(&prefix, 16, &via_1299));
+        assert!(!import.permits(&prefix, 16, &blackholed));
+    }
+
+    #[test]
+    fn aspath_regex_on_whole_asns() {
+        let regex = junos_aspath_regex(".* 3356 .*").unwrap();
+        assert!(regex.is_match("174 3356 64500"));
+        assert!(!regex.is_match("174 33560 64500"));
+        let regex = junos_aspath_regex("174 64500-64510").unwrap();
+        assert!(regex.is_match("174 64505"));
+        assert!(!regex.is_match("174 64511"));
+        assert!(junos_aspath_regex("174 [0-9]").is_err());
+    }
+
+    #[test]
+    fn undefined_names() {
+        let mut policies = PolicySet::new();
+        let config = "policy-statement X { term a { from community NOPE; then accept; } }";
+        assert!(parse(config, &mut policies).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Prefix {
     pub prefix: IpAddr,
     pub len: u8
@@ -10,7 +10,7 @@ impl FromStr for Prefix {
     fn from_str(s: &str) -> Result<Prefix, Self::Err> {
         let token_split = s.split("/").collect::<Vec<&str>>();
         if token_split.len() == 2 {
-            match IpAddr::from_str(token_split.get(0).unwrap()) {
+            match IpAddr::from_str(token_split.first().unwrap()) {
                 Ok(prefix) => {
                     match token_split.get(1).unwrap().parse::<u8>() {
                         Ok(len) => Ok(Prefix { prefix, len }),
@@ -29,4 +29,119 @@ impl Display for Prefix {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}/{}", self.prefix, self.len)
     }
-}
\ No newline at end of file
+}
+
+impl Prefix {
+    pub fn new(prefix: IpAddr, len: u8) -> Prefix {
+        Prefix { prefix: prefix.mask(len), len }
+    }
+
+    // Is the given prefix equal to, or more specific than, this one?
+    pub fn covers(&self, prefix: &IpAddr, plen: u8) -> bool {
+        plen >= self.len && prefix.mask(self.len) == self.prefix.mask(self.len)
+    }
+}
+
+// A hashed set of prefixes for matching NLRIs against very large prefix
+// lists: the cost of a lookup is one hash probe per distinct prefix
+// length in the set, rather than one comparison per prefix
+#[derive(Debug, Clone, Default)]
+pub struct PrefixSet {
+    prefixes: HashSet<Prefix>,
+    lengths: Vec<u8>,
+}
+
+impl PrefixSet {
+    pub fn new() -> PrefixSet {
+        PrefixSet::default()
+    }
+
+    pub fn insert(&mut self, prefix: Prefix) {
+        let prefix = Prefix::new(prefix.prefix, prefix.len);
+        if !self.lengths.contains(&prefix.len) {
+            self.lengths.push(prefix.len);
+            self.lengths.sort();
+        }
+        self.prefixes.insert(prefix);
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    // Exact membership
+    pub fn contains(&self, prefix: &IpAddr, plen: u8) -> bool {
+        self.prefixes.contains(&Prefix::new(*prefix, plen))
+    }
+
+    // Is the given prefix equal to, or more specific than, any in the set?
+    pub fn covers(&self, prefix: &IpAddr, plen: u8) -> bool {
+        self.lengths
+            .iter()
+            .take_while(|len| **len <= plen)
+            .any(|len| self.prefixes.contains(&Prefix::new(*prefix, *len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(prefixes: &[&str]) -> PrefixSet {
+        let mut set = PrefixSet::new();
+        for prefix in prefixes {
+            set.insert(Prefix::from_str(prefix).unwrap());
+        }
+        set
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Prefix::from_str("192.0.2.0/24"), Ok(Prefix { prefix: ip("192.0.2.0"), len: 24 }));
+        assert_eq!(Prefix::from_str("2001:db8::/32").unwrap().to_string(), "2001:db8::/32");
+        for s in ["192.0.2.0", "192.0.2.0/", "192.0.2.0/x", "192.0.2.0/24/1", "3356"] {
+            assert!(Prefix::from_str(s).is_err(), "{}", s);
+        }
+        // Host bits are cleared by new()
+        assert_eq!(Prefix::new(ip("192.0.2.77"), 24).prefix, ip("192.0.2.0"));
+    }
+
+    #[test]
+    fn covers_by_length() {
+        let set = set(&["10.0.0.0/8", "192.0.2.0/24", "198.51.100.128/25", "2001:db8::/32"]);
+        assert_eq!(set.len(), 4);
+        // Equal or more specific, at any length from the set's
+        assert!(set.covers(&ip("10.0.0.0"), 8));
+        assert!(set.covers(&ip("10.20.0.0"), 16));
+        assert!(set.covers(&ip("192.0.2.128"), 25));
+        assert!(set.covers(&ip("198.51.100.192"), 26));
+        assert!(set.covers(&ip("2001:db8:1::"), 48));
+        // Less specific, or outside
+        assert!(!set.covers(&ip("10.0.0.0"), 7));
+        assert!(!set.covers(&ip("192.0.0.0"), 16));
+        assert!(!set.covers(&ip("198.51.100.0"), 24));
+        assert!(!set.covers(&ip("11.0.0.0"), 8));
+        assert!(!set.covers(&ip("2001:db9::"), 32));
+        // The same bits in the other family
+        assert!(!set.covers(&ip("::"), 8));
+    }
+
+    #[test]
+    fn contains_exactly() {
+        let set = set(&["10.1.2.3/8", "192.0.2.0/24", "192.0.2.0/24"]);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&ip("10.0.0.0"), 8));
+        assert!(!set.contains(&ip("10.0.0.0"), 16));
+        assert!(!set.contains(&ip("192.0.2.0"), 25));
+        assert!(PrefixSet::new().is_empty());
+        assert!(!PrefixSet::new().covers(&ip("0.0.0.0"), 0));
+    }
+}
@@ -5,18 +5,27 @@ use std::time::SystemTime;
 
 use crate::*;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MrtRibEntry {
     pub peer_id: u16,
-    pub peer: Rc<MrtPeer>,
+    pub peer: Arc<MrtPeer>,
     pub origin_time: SystemTime,
-    pub attributes: Vec<MrtAttribute>
+    pub path_id: u32,           // RFC 7911 path identifier, 0 without add-path
+    pub attributes: Arc<Vec<MrtAttribute>>     // shared, see Interner
 }
 
+// Identity of a path within a prefix: collector, view, peer address and
+// path identifier. A newer path with the same key replaces the older one.
+pub type PathKey<'a> = (IpAddr, &'a str, IpAddr, u32);
+
 impl MrtRibEntry {
+    pub fn key(&self) -> PathKey<'_> {
+        (self.peer.collector_id, &self.peer.view_name, self.peer.peer_address, self.path_id)
+    }
+
     pub fn get_aspath(&self) -> String {
         let empty = String::new();
-        for attrib in &self.attributes {
+        for attrib in self.attributes.iter() {
             if let MrtAttribute::AsPath(ref aspath) = attrib {
                 return aspath.to_string();
             }
@@ -24,21 +33,60 @@ impl MrtRibEntry {
         empty
     }
 
+    // All standard, extended and large communities attached to the path,
+    // in attribute order
+    pub fn communities(&self) -> impl Iterator<Item = &Community> {
+        self.attributes.iter().flat_map(|attrib| match attrib {
+            MrtAttribute::Community(ref community_list) |
+            MrtAttribute::ExtendedCommunity(ref community_list) |
+            MrtAttribute::LargeCommunity(ref community_list) => community_list.iter(),
+            _ => [].iter(),
+        })
+    }
+
     pub fn get_community(&self) -> Option<String> {
-        for attrib in &self.attributes {
-            if let MrtAttribute::Community(ref community_list) = attrib {
-                return Some(community_list
-                    .iter()
-                    .map(|x| x.to_string())
-                    .collect::<Vec<String>>().join(" "));
+        let communities = self.communities()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        if communities.is_empty() {
+            None
+        } else {
+            Some(communities.join(" "))
+        }
+    }
+
+
+    pub fn get_aspath_length(&self) -> usize {
+        for attrib in self.attributes.iter() {
+            if let MrtAttribute::AsPath(ref aspath) = attrib {
+                return aspath.path_length();
+            }
+        }
+        0
+    }
+
+    // The AS the path was learned from, for MED comparison
+    pub fn get_neighbor_as(&self) -> Option<u32> {
+        for attrib in self.attributes.iter() {
+            if let MrtAttribute::AsPath(ref aspath) = attrib {
+                return aspath.first_asn();
             }
         }
         None
     }
 
+    // The AS that originated the route
+    pub fn get_origin_as(&self) -> Option<u32> {
+        for attrib in self.attributes.iter() {
+            if let MrtAttribute::AsPath(ref aspath) = attrib {
+                return aspath.last_asn();
+            }
+        }
+        None
+    }
 
     pub fn aspath_contains(&self, asn: u32) -> bool {
-        for attrib in &self.attributes {
+        for attrib in self.attributes.iter() {
             if let MrtAttribute::AsPath(ref aspath) = attrib {
                 if aspath.contains(asn) {
                     return true;
@@ -48,18 +96,27 @@ impl MrtRibEntry {
         false
     }
 
-    pub fn community_contains(&self, community: &Community) -> bool {
-        for attrib in &self.attributes {
-            if let MrtAttribute::Community(ref community_list) = attrib {
-                if community_list.contains(community) {
+    pub fn aspath_contains_any(&self, asns: &HashSet<u32>) -> bool {
+        for attrib in self.attributes.iter() {
+            if let MrtAttribute::AsPath(ref aspath) = attrib {
+                if aspath.contains_any(asns) {
                     return true;
                 }
             }
         }
         false
     }
+
+    pub fn community_contains(&self, community: &Community) -> bool {
+        self.communities().any(|x| x == community)
+    }
+
+    pub fn community_matches(&self, pattern: &CommunityPattern) -> bool {
+        self.communities().any(|x| pattern.matches(x))
+    }
+
     pub fn get_med(&self) -> Option<u32> {
-        for attrib in &self.attributes {
+        for attrib in self.attributes.iter() {
             if let MrtAttribute::MultiExitDisc(med) = attrib {
                 return Some(*med);
             }
@@ -67,23 +124,45 @@ impl MrtRibEntry {
         None
     }
     pub fn get_local_pref(&self) -> Option<u32> {
-        for attrib in &self.attributes {
+        for attrib in self.attributes.iter() {
             if let MrtAttribute::LocalPref(local_pref) = attrib {
                 return Some(*local_pref);
             }
         }
         None
     }
+    // The NEXT_HOP, or failing that the MP_REACH_NLRI next hop (IPv6)
     pub fn get_nexthop(&self) -> IpAddr {
-        for attrib in &self.attributes {
-            if let MrtAttribute::NextHop(nh) = attrib {
-                return *nh;
+        self.nexthop().unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+    pub fn nexthop(&self) -> Option<IpAddr> {
+        let mut mp_nexthop = None;
+        for attrib in self.attributes.iter() {
+            match attrib {
+                MrtAttribute::NextHop(nh) => return Some(*nh),
+                MrtAttribute::MpNextHop(nh) => mp_nexthop = Some(*nh),
+                _ => {},
             }
         }
-        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        mp_nexthop
+    }
+    pub fn get_mp_nexthop(&self) -> Option<IpAddr> {
+        self.attributes.iter().find_map(|x| match x {
+            MrtAttribute::MpNextHop(nh) => Some(*nh),
+            _ => None,
+        })
+    }
+    pub fn get_aggregator(&self) -> Option<(u32, Ipv4Addr)> {
+        self.attributes.iter().find_map(|x| match x {
+            MrtAttribute::Aggregator(asn, id) => Some((*asn, *id)),
+            _ => None,
+        })
+    }
+    pub fn is_atomic_aggregate(&self) -> bool {
+        self.attributes.iter().any(|x| matches!(x, MrtAttribute::AtomicAggregate))
     }
     pub fn get_origin(&self) -> u8 {
-        for attrib in &self.attributes {
+        for attrib in self.attributes.iter() {
             if let MrtAttribute::Origin(origin) = attrib {
                 return *origin;
             }
@@ -94,14 +173,14 @@ impl MrtRibEntry {
     pub fn get_origin_char(&self) -> char {
         match self.get_origin() {
             0 => {
-                if GETOPT.juniper_output {
+                if GETOPT.dialect == Dialect::Juniper {
                     'I'
                 } else {
                     'i'
                 }
             },
             1 => {
-                if GETOPT.juniper_output {
+                if GETOPT.dialect == Dialect::Juniper {
                     'E'
                 } else {
                     'e'
@@ -113,6 +192,94 @@ impl MrtRibEntry {
     }
 }
 
+// Attribute modification, for routing policy simulation
+impl MrtRibEntry {
+    pub fn set_local_pref(&mut self, local_pref: u32) {
+        let attributes = Arc::make_mut(&mut self.attributes);
+        attributes.retain(|x| !matches!(x, MrtAttribute::LocalPref(_)));
+        attributes.push(MrtAttribute::LocalPref(local_pref));
+    }
+
+    pub fn set_med(&mut self, med: u32) {
+        let attributes = Arc::make_mut(&mut self.attributes);
+        attributes.retain(|x| !matches!(x, MrtAttribute::MultiExitDisc(_)));
+        attributes.push(MrtAttribute::MultiExitDisc(med));
+    }
+
+    pub fn set_nexthop(&mut self, nexthop: IpAddr) {
+        let attributes = Arc::make_mut(&mut self.attributes);
+        attributes.retain(|x| !matches!(x, MrtAttribute::NextHop(_)));
+        attributes.push(MrtAttribute::NextHop(nexthop));
+    }
+
+    // Add communities not already present, each to the attribute
+    // for its kind (standard, extended, large)
+    pub fn add_communities(&mut self, communities: &[Community]) {
+        for community in communities {
+            if self.community_contains(community) {
+                continue;
+            }
+            let attributes = Arc::make_mut(&mut self.attributes);
+            let list = attributes.iter_mut().find_map(|attrib| match (attrib, community) {
+                (MrtAttribute::Community(list), Community::Standard(_)) |
+                (MrtAttribute::ExtendedCommunity(list), Community::Extended(_)) |
+                (MrtAttribute::LargeCommunity(list), Community::Large(_)) => Some(list),
+                _ => None,
+            });
+            match list {
+                Some(list) => Arc::make_mut(list).push(*community),
+                None => attributes.push(match community {
+                    Community::Standard(_) => MrtAttribute::Community(Arc::new(vec![*community])),
+                    Community::Extended(_) => MrtAttribute::ExtendedCommunity(Arc::new(vec![*community])),
+                    Community::Large(_) => MrtAttribute::LargeCommunity(Arc::new(vec![*community])),
+                }),
+            }
+        }
+    }
+
+    // Remove the selected communities, and any community attributes
+    // left empty
+    pub fn remove_communities<F: Fn(&Community) -> bool>(&mut self, selected: F) {
+        if !self.communities().any(&selected) {
+            return;
+        }
+        let attributes = Arc::make_mut(&mut self.attributes);
+        for attrib in attributes.iter_mut() {
+            if let MrtAttribute::Community(list) |
+                MrtAttribute::ExtendedCommunity(list) |
+                MrtAttribute::LargeCommunity(list) = attrib {
+                Arc::make_mut(list).retain(|x| !selected(x));
+            }
+        }
+        attributes.retain(|attrib| match attrib {
+            MrtAttribute::Community(list) |
+            MrtAttribute::ExtendedCommunity(list) |
+            MrtAttribute::LargeCommunity(list) => !list.is_empty(),
+            _ => true,
+        });
+    }
+
+    pub fn prepend_aspath(&mut self, asn: u32, count: u8) {
+        let prepend = vec![asn; count as usize];
+        let attributes = Arc::make_mut(&mut self.attributes);
+        for attrib in attributes.iter_mut() {
+            if let MrtAttribute::AsPath(aspath) = attrib {
+                let aspath = Arc::make_mut(aspath);
+                match aspath.aspath_segments.first_mut() {
+                    Some(segment) if segment.ordered => {
+                        segment.asns.splice(0..0, prepend);
+                    },
+                    _ => aspath.aspath_segments.insert(0, AsPathSegment { ordered: true, asns: prepend }),
+                }
+                return;
+            }
+        }
+        attributes.push(MrtAttribute::AsPath(Arc::new(AsPath {
+            aspath_segments: vec![AsPathSegment { ordered: true, asns: prepend }]
+        })));
+    }
+}
+
 impl Display for MrtRibEntry {
     fn fmt(&self, f: &mut Formatter<'_>) -> anyhow::Result<(), std::fmt::Error> {
         write!(f, "{} \"{} {}\"", self.get_nexthop(), self.get_aspath(), self.get_origin_char())
@@ -125,7 +292,17 @@ pub struct MrtNlri {
     pub plen: u8,
     pub prefix: IpAddr,
     pub entry_count: u16,
-    pub rib_entries: Vec<MrtRibEntry>
+    pub rib_entries: Vec<MrtRibEntry>,
+    pub source: NlriSource,
+}
+
+// The MRT record a route was read from, for output that echoes it
+// (bgpdump -m)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NlriSource {
+    Table,                          // not read from a record (a snapshot)
+    TableDumpV2(u32),               // a RIB record, with its timestamp
+    Bgp4mp(u32, Option<u32>),       // an UPDATE, with its timestamp (and microseconds)
 }
 
 impl Display for MrtNlri {
@@ -142,11 +319,17 @@ impl Display for MrtNlri {
 }
 
 impl MrtNlri {
-    pub fn parse_rib_entry<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable) -> Result<MrtRibEntry> {
+    pub fn parse_rib_entry<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable, addpath: bool) -> Result<MrtRibEntry> {
 
         let peer_id = reader.read_u16::<BigEndian>()?;
         // need to look it up here
         let origin_time = reader.read_u32::<BigEndian>()?;
+        // RFC 8050 add-path RIB entries carry the path identifier
+        let path_id = if addpath {
+            reader.read_u32::<BigEndian>()?
+        } else {
+            0
+        };
         let attribute_length = reader.read_u16::<BigEndian>()?;
 
         let mut attributes: &[u8] = &reader.fill_buf()?[..attribute_length as usize];
@@ -156,50 +339,97 @@ impl MrtNlri {
         Ok(
             MrtRibEntry {
                 peer_id,
-                peer: Rc::clone(peer_index_table
+                peer: Arc::clone(peer_index_table
                     .peers
                     .get(peer_id as usize)
-                    .ok_or(anyhow!("invalid peer index {}", peer_id))?),
+                    .ok_or_else(|| anyhow!("invalid peer index {}", peer_id))?),
                 origin_time: UNIX_EPOCH.checked_add(Duration::from_secs(origin_time as u64)).unwrap_or(UNIX_EPOCH),
-                attributes
+                path_id,
+                attributes: Arc::new(attributes)
             }
         )
     }
 
-    pub fn parse_v4<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable) -> Result<MrtNlri> {
+    pub fn parse_v4<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable, addpath: bool) -> Result<MrtNlri> {
         let mut addr_buf: [u8; 4] = [0u8; 4];
         let sequence = reader.read_u32::<BigEndian>()?;
 
         let plen: u8 = reader.read_u8()?;
-        reader.read_exact(&mut addr_buf[..((plen + 7)/8) as usize])?;
+        reader.read_exact(&mut addr_buf[..plen.div_ceil(8) as usize])?;
         let mut slice = &addr_buf[..];
         let prefix = IpAddr::V4(Ipv4Addr::from_bits(slice.read_u32::<BigEndian>()?));
 
         let entry_count = reader.read_u16::<BigEndian>()?;
         let mut rib_entries = vec![];
         for _ in 0..entry_count {
-            rib_entries.push(Self::parse_rib_entry(reader, peer_index_table)?);
+            rib_entries.push(Self::parse_rib_entry(reader, peer_index_table, addpath)?);
         }
 
-        Ok(MrtNlri { sequence, plen, prefix, entry_count, rib_entries })
+        Ok(MrtNlri { sequence, plen, prefix, entry_count, rib_entries, source: NlriSource::Table })
     }
 
-    pub fn parse_v6<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable) -> Result<MrtNlri> {
+    pub fn parse_v6<R: Read + BufRead>(reader: &mut R, peer_index_table: &MrtPeerIndexTable, addpath: bool) -> Result<MrtNlri> {
         let mut addr_buf: [u8; 16] = [0u8; 16];
         let sequence = reader.read_u32::<BigEndian>()?;
 
         let plen: u8 = reader.read_u8()?;
-        reader.read_exact(&mut addr_buf[..((plen + 7)/8) as usize])?;
+        reader.read_exact(&mut addr_buf[..plen.div_ceil(8) as usize])?;
         let mut slice = &addr_buf[..];
         let prefix = IpAddr::V6(Ipv6Addr::from_bits(slice.read_u128::<BigEndian>()?));
 
         let entry_count = reader.read_u16::<BigEndian>()?;
         let mut rib_entries = vec![];
         for _ in 0..entry_count {
-            rib_entries.push(Self::parse_rib_entry(reader, peer_index_table)?);
+            rib_entries.push(Self::parse_rib_entry(reader, peer_index_table, addpath)?);
         }
 
-       Ok(MrtNlri { sequence, plen, prefix, entry_count, rib_entries })
+       Ok(MrtNlri { sequence, plen, prefix, entry_count, rib_entries, source: NlriSource::Table })
+    }
+
+}
+
+#[cfg(test)]
+impl MrtRibEntry {
+    // A path from the peer with an AS_SEQUENCE path, next hop the peer,
+    // and the attributes given, for tests
+    pub fn test_path(peer_address: &str, peer_as: u32, aspath: &[u32], mut attributes: Vec<MrtAttribute>) -> MrtRibEntry {
+        let peer_address = IpAddr::from_str(peer_address).unwrap();
+        let peer = MrtPeer {
+            peer_id: peer_address,
+            peer_address,
+            peer_as,
+            collector_id: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 254)),
+            ..Default::default()
+        };
+        let aspath = AsPath { aspath_segments: vec![AsPathSegment { ordered: true, asns: aspath.to_vec() }] };
+        attributes.splice(0..0, [MrtAttribute::Origin(0),
+                                 MrtAttribute::AsPath(Arc::new(aspath)),
+                                 MrtAttribute::NextHop(peer_address)]);
+        MrtRibEntry {
+            peer_id: 0,
+            peer: Arc::new(peer),
+            origin_time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            path_id: 0,
+            attributes: Arc::new(attributes),
+        }
     }
 
+    // The paths of a route as the dialects show them, for tests: one with
+    // a MED and every kind of community, then the best path, with a local
+    // preference, a next hop other than the peer, and an aggregator
+    pub fn test_paths() -> Vec<MrtRibEntry> {
+        let first = MrtRibEntry::test_path("192.0.2.1", 3356, &[3356, 174, 64500], vec![
+            MrtAttribute::MultiExitDisc(10),
+            MrtAttribute::Community(Arc::new(vec![Community::Standard((3356, 123)), Community::Standard((65535, 65281))])),
+            MrtAttribute::ExtendedCommunity(Arc::new(vec![Community::Extended((0, 2, 0xfde800000064))])),
+            MrtAttribute::LargeCommunity(Arc::new(vec![Community::Large((3356, 1, 2))])),
+        ]);
+        let mut second = MrtRibEntry::test_path("192.0.2.2", 1299, &[1299, 64500], vec![
+            MrtAttribute::LocalPref(200),
+            MrtAttribute::AtomicAggregate,
+            MrtAttribute::Aggregator(64500, Ipv4Addr::new(198, 51, 100, 1)),
+        ]);
+        Arc::make_mut(&mut second.attributes)[2] = MrtAttribute::NextHop(IpAddr::from_str("192.0.2.3").unwrap());
+        vec![first, second]
+    }
 }
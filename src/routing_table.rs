@@ -10,8 +10,9 @@ use std::time::Instant;
 use crate::*;
 
 pub struct RoutingTable {
-    pub v4: Trie<Ipv4Addr>,
-    pub v6: Trie<Ipv6Addr>,
+    pub v4: Patricia<Ipv4Addr, Vec<MrtRibEntry>>,
+    pub v6: Patricia<Ipv6Addr, Vec<MrtRibEntry>>,
+    pub interner: Interner,     // shared attributes of the paths held
 }
 
 impl RoutingTable
@@ -30,14 +31,392 @@ impl RoutingTable
             },
         }
     }
+
+    // Add the paths for a prefix, each replacing any path already held
+    // from the same collector, view, peer and path identifier, as an
+    // Adj-RIB-In would on an update. Their attributes are interned, and
+    // those of the paths replaced released.
+    pub fn add(&mut self, ip: &IpAddr, plen: u8, mut paths: Vec<MrtRibEntry>) {
+        for path in paths.iter_mut() {
+            self.interner.intern(&mut path.attributes);
+        }
+        let existing = match ip {
+            IpAddr::V4(ip) => self.v4.get_or_insert_with(ip, plen, Vec::new),
+            IpAddr::V6(ip) => self.v6.get_or_insert_with(ip, plen, Vec::new),
+        };
+        let mut replaced = 0;
+        if !existing.is_empty() {
+            let keys = paths.iter().map(|x| x.key()).collect::<HashSet<PathKey>>();
+            let count = existing.len();
+            existing.retain(|x| !keys.contains(&x.key()));
+            replaced = count - existing.len();
+        }
+        existing.append(&mut paths);
+        self.interner.release(replaced);
+    }
+
+    // The paths of a single peer
+    pub fn peer(&self, peer: &IpAddr) -> PeerView<'_> {
+        PeerView { table: self, peer: *peer }
+    }
+
+    // Exact match of the prefix
+    pub fn get_exact(&self, ip: &IpAddr, plen: u8) -> Option<&Vec<MrtRibEntry>> {
+        match ip {
+            IpAddr::V4(ip) => self.v4.get_exact(ip, plen),
+            IpAddr::V6(ip) => self.v6.get_exact(ip, plen),
+        }
+    }
+
+    // Remove the prefix and all of its paths
+    pub fn remove(&mut self, ip: &IpAddr, plen: u8) -> Option<Vec<MrtRibEntry>> {
+        let removed = match ip {
+            IpAddr::V4(ip) => self.v4.remove(ip, plen),
+            IpAddr::V6(ip) => self.v6.remove(ip, plen),
+        };
+        self.interner.release(removed.as_ref().map_or(0, |x| x.len()));
+        removed
+    }
+
+    // Remove the path for the prefix with the key given (eg. on a
+    // withdrawal), removing the prefix itself when no paths remain
+    pub fn remove_path(&mut self, ip: &IpAddr, plen: u8, key: &PathKey) -> Vec<MrtRibEntry> {
+        self.remove_paths_where(ip, plen, |x| x.key() == *key)
+    }
+
+    // Remove every path for the prefix from the peer, across collectors,
+    // views and path identifiers (eg. when the peer is dropped from a
+    // view of the table), removing the prefix itself when none remain
+    pub fn remove_peer_paths(&mut self, ip: &IpAddr, plen: u8, peer: &IpAddr) -> Vec<MrtRibEntry> {
+        self.remove_paths_where(ip, plen, |x| x.peer.peer_address == *peer)
+    }
+
+    fn remove_paths_where<F: Fn(&MrtRibEntry) -> bool>(&mut self, ip: &IpAddr, plen: u8, remove: F) -> Vec<MrtRibEntry> {
+        let paths = match ip {
+            IpAddr::V4(ip) => self.v4.get_exact_mut(ip, plen),
+            IpAddr::V6(ip) => self.v6.get_exact_mut(ip, plen),
+        };
+        let Some(paths) = paths else {
+            return vec![];
+        };
+        let (removed, kept) = std::mem::take(paths)
+            .into_iter()
+            .partition(|x| remove(x));
+        *paths = kept;
+        if paths.is_empty() {
+            self.remove(ip, plen);
+        }
+        self.interner.release(removed.len());
+        removed
+    }
+
+    // Keep only the longest match for the address, removing every other
+    // prefix: a longest match filter can only choose once the load is
+    // complete, having loaded every prefix covering the address
+    pub fn retain_longest_match(&mut self, ip: &IpAddr) {
+        let longest = self.get(ip).map(|(prefix, plen, _)| (prefix, plen));
+        let others = self.iter()
+            .map(|(prefix, plen, _)| (prefix, plen))
+            .filter(|x| Some(*x) != longest)
+            .collect::<Vec<(IpAddr, u8)>>();
+        for (prefix, plen) in others {
+            self.remove(&prefix, plen);
+        }
+    }
+
+    // Every route in the table, IPv4 then IPv6, each in prefix order
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddr, u8, &Vec<MrtRibEntry>)> {
+        self.v4.iter().map(|(route, plen, desc)| (IpAddr::V4(route), plen, desc))
+            .chain(self.v6.iter().map(|(route, plen, desc)| (IpAddr::V6(route), plen, desc)))
+    }
+
+    // Routes equal to or more specific than the prefix, in prefix order
+    pub fn more_specifics(&self, ip: &IpAddr, plen: u8) -> Box<dyn Iterator<Item = (IpAddr, u8, &Vec<MrtRibEntry>)> + '_> {
+        match ip {
+            IpAddr::V4(ip) => Box::new(self.v4.more_specifics(ip, plen).map(|(route, plen, desc)| (IpAddr::V4(route), plen, desc))),
+            IpAddr::V6(ip) => Box::new(self.v6.more_specifics(ip, plen).map(|(route, plen, desc)| (IpAddr::V6(route), plen, desc))),
+        }
+    }
+
+    // Routes equal to or less specific than (covering) the prefix,
+    // shortest first
+    pub fn less_specifics(&self, ip: &IpAddr, plen: u8) -> Box<dyn Iterator<Item = (IpAddr, u8, &Vec<MrtRibEntry>)> + '_> {
+        match ip {
+            IpAddr::V4(ip) => Box::new(self.v4.less_specifics(ip, plen).map(|(route, plen, desc)| (IpAddr::V4(route), plen, desc))),
+            IpAddr::V6(ip) => Box::new(self.v6.less_specifics(ip, plen).map(|(route, plen, desc)| (IpAddr::V6(route), plen, desc))),
+        }
+    }
+
+    // Number of prefixes, and of paths, in the table
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    pub fn path_count(&self) -> usize {
+        self.iter().map(|(_, _, paths)| paths.len()).sum()
+    }
 }
 
 impl RoutingTable {
     pub fn new() -> RoutingTable {
         RoutingTable {
-            v4: Trie::new(),
-            v6: Trie::new(),
+            v4: Patricia::new(),
+            v6: Patricia::new(),
+            interner: Interner::new(),
         }
     }
 }
 
+
+// A per-peer view of the table (its Adj-RIB-In), by peer address across
+// all collectors and views
+pub struct PeerView<'a> {
+    table: &'a RoutingTable,
+    pub peer: IpAddr,
+}
+
+// A difference between the routes of two peers
+pub enum RouteDifference<'a> {
+    Removed(IpAddr, u8, &'a MrtRibEntry),               // only in this peer
+    Added(IpAddr, u8, &'a MrtRibEntry),                 // only in the other peer
+    Changed(IpAddr, u8, &'a MrtRibEntry, &'a MrtRibEntry),
+}
+
+impl<'a> PeerView<'a> {
+    fn paths(&self, paths: &'a [MrtRibEntry]) -> impl Iterator<Item = &'a MrtRibEntry> + use<'a> {
+        let peer = self.peer;
+        paths.iter().filter(move |x| x.peer.peer_address == peer)
+    }
+
+    // Every path from the peer, in prefix order
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddr, u8, &'a MrtRibEntry)> + use<'a> {
+        let peer = self.peer;
+        self.table.iter().flat_map(move |(prefix, plen, paths)| {
+            paths.iter().filter(move |x| x.peer.peer_address == peer).map(move |x| (prefix, plen, x))
+        })
+    }
+
+    pub fn get_exact(&self, ip: &IpAddr, plen: u8) -> Vec<&'a MrtRibEntry> {
+        match self.table.get_exact(ip, plen) {
+            Some(paths) => self.paths(paths).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    // Compare the routes of this peer against another, in prefix order,
+    // pairing paths by their key less the peer: the same collector, view
+    // and path identifier
+    pub fn compare(&self, other: &PeerView<'a>) -> Vec<RouteDifference<'a>> {
+        fn paired(a: &MrtRibEntry, b: &MrtRibEntry) -> bool {
+            let (a, b) = (a.key(), b.key());
+            (a.0, a.1, a.3) == (b.0, b.1, b.3)
+        }
+        let mut differences = vec![];
+        for (prefix, plen, paths) in self.table.iter() {
+            let ours = self.paths(paths).collect::<Vec<&MrtRibEntry>>();
+            let theirs = other.paths(paths).collect::<Vec<&MrtRibEntry>>();
+            for path in &ours {
+                match theirs.iter().find(|x| paired(x, path)) {
+                    None => differences.push(RouteDifference::Removed(prefix, plen, path)),
+                    Some(other) if other.attributes != path.attributes => {
+                        differences.push(RouteDifference::Changed(prefix, plen, path, other))
+                    },
+                    _ => {}
+                }
+            }
+            for path in theirs.iter().filter(|x| !ours.iter().any(|y| paired(x, y))) {
+                differences.push(RouteDifference::Added(prefix, plen, path));
+            }
+        }
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn path(peer: &str) -> MrtRibEntry {
+        MrtRibEntry::test_path(peer, 64500, &[64500], vec![])
+    }
+
+    fn prefixes(routing_table: &RoutingTable) -> Vec<String> {
+        routing_table.iter().map(|(prefix, plen, _)| format!("{}/{}", prefix, plen)).collect()
+    }
+
+    fn path_id(peer: &str, path_id: u32) -> MrtRibEntry {
+        MrtRibEntry { path_id, ..path(peer) }
+    }
+
+    fn peers(paths: &[MrtRibEntry]) -> Vec<String> {
+        paths.iter().map(|x| format!("{}#{}", x.peer.peer_address, x.path_id)).collect()
+    }
+
+    #[test]
+    fn get_exact() {
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&ip("10.0.0.0"), 8, vec![path("192.0.2.1")]);
+        routing_table.add(&ip("2001:db8::"), 32, vec![path("2001:db8::1")]);
+        assert_eq!(peers(routing_table.get_exact(&ip("10.0.0.0"), 8).unwrap()), ["192.0.2.1#0"]);
+        assert_eq!(peers(routing_table.get_exact(&ip("2001:db8::"), 32).unwrap()), ["2001:db8::1#0"]);
+        assert!(routing_table.get_exact(&ip("10.0.0.0"), 9).is_none());
+        assert!(routing_table.get_exact(&ip("10.1.0.0"), 16).is_none());
+        assert!(routing_table.get_exact(&ip("2001:db8::"), 48).is_none());
+
+        // A path with the same key replaces the one held, others add to it
+        routing_table.add(&ip("10.0.0.0"), 8, vec![path("192.0.2.1"), path("192.0.2.2"), path_id("192.0.2.1", 1)]);
+        assert_eq!(peers(routing_table.get_exact(&ip("10.0.0.0"), 8).unwrap()), ["192.0.2.1#0", "192.0.2.2#0", "192.0.2.1#1"]);
+        assert_eq!(routing_table.path_count(), 4);
+    }
+
+    #[test]
+    fn remove() {
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&ip("10.0.0.0"), 8, vec![path("192.0.2.1"), path("192.0.2.2")]);
+        routing_table.add(&ip("10.1.0.0"), 16, vec![path("192.0.2.1")]);
+        assert_eq!(peers(&routing_table.remove(&ip("10.0.0.0"), 8).unwrap()), ["192.0.2.1#0", "192.0.2.2#0"]);
+        assert!(routing_table.remove(&ip("10.0.0.0"), 8).is_none());
+        assert!(routing_table.remove(&ip("10.2.0.0"), 16).is_none());
+        assert_eq!(prefixes(&routing_table), ["10.1.0.0/16"]);
+        assert!(routing_table.get(&ip("10.2.0.1")).is_none());
+    }
+
+    #[test]
+    fn remove_path() {
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&ip("10.0.0.0"), 8, vec![path("192.0.2.1"), path_id("192.0.2.1", 1), path("192.0.2.2")]);
+        let collector = ip("192.0.2.254");
+
+        // Only the path with the key is removed, not the others of the peer
+        let removed = routing_table.remove_path(&ip("10.0.0.0"), 8, &(collector, "", ip("192.0.2.1"), 1));
+        assert_eq!(peers(&removed), ["192.0.2.1#1"]);
+        assert_eq!(peers(routing_table.get_exact(&ip("10.0.0.0"), 8).unwrap()), ["192.0.2.1#0", "192.0.2.2#0"]);
+        assert!(routing_table.remove_path(&ip("10.0.0.0"), 8, &(collector, "backup", ip("192.0.2.1"), 0)).is_empty());
+        assert!(routing_table.remove_path(&ip("10.1.0.0"), 16, &(collector, "", ip("192.0.2.1"), 0)).is_empty());
+
+        // The prefix goes with the last of its paths
+        routing_table.remove_path(&ip("10.0.0.0"), 8, &(collector, "", ip("192.0.2.1"), 0));
+        routing_table.remove_path(&ip("10.0.0.0"), 8, &(collector, "", ip("192.0.2.2"), 0));
+        assert!(routing_table.is_empty());
+    }
+
+    #[test]
+    fn remove_peer_paths() {
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&ip("10.0.0.0"), 8, vec![path("192.0.2.1"), path_id("192.0.2.1", 1), path("192.0.2.2")]);
+        let removed = routing_table.remove_peer_paths(&ip("10.0.0.0"), 8, &ip("192.0.2.1"));
+        assert_eq!(peers(&removed), ["192.0.2.1#0", "192.0.2.1#1"]);
+        assert_eq!(peers(routing_table.get_exact(&ip("10.0.0.0"), 8).unwrap()), ["192.0.2.2#0"]);
+        assert!(routing_table.remove_peer_paths(&ip("10.0.0.0"), 8, &ip("192.0.2.1")).is_empty());
+        routing_table.remove_peer_paths(&ip("10.0.0.0"), 8, &ip("192.0.2.2"));
+        assert!(routing_table.is_empty());
+    }
+
+    #[test]
+    fn empty_nodes_pruned() {
+        let mut routing_table = RoutingTable::new();
+        let (v4, v6) = (routing_table.v4.node_count(), routing_table.v6.node_count());
+        let prefixes = [("10.0.0.0", 8), ("10.1.0.0", 16), ("10.1.2.0", 24), ("10.2.0.0", 16), ("2001:db8::", 32), ("2001:db8:1::", 48)];
+        for (prefix, plen) in prefixes {
+            routing_table.add(&ip(prefix), plen, vec![path("192.0.2.1")]);
+        }
+        assert!(routing_table.v4.node_count() > v4 && routing_table.v6.node_count() > v6);
+
+        // Whether by prefix, path or peer, nothing is left behind
+        let collector = ip("192.0.2.254");
+        routing_table.remove(&ip("10.1.2.0"), 24);
+        routing_table.remove_path(&ip("10.1.0.0"), 16, &(collector, "", ip("192.0.2.1"), 0));
+        routing_table.remove_peer_paths(&ip("10.2.0.0"), 16, &ip("192.0.2.1"));
+        routing_table.remove(&ip("10.0.0.0"), 8);
+        routing_table.remove_peer_paths(&ip("2001:db8:1::"), 48, &ip("192.0.2.1"));
+        routing_table.remove(&ip("2001:db8::"), 32);
+        assert!(routing_table.is_empty());
+        assert_eq!((routing_table.v4.node_count(), routing_table.v6.node_count()), (v4, v6));
+    }
+
+    #[test]
+    fn interner_released() {
+        let mut routing_table = RoutingTable::new();
+        for i in 0..2000u32 {
+            let prefix = IpAddr::V4(Ipv4Addr::from(0x0a000000 | i << 8));
+            routing_table.add(&prefix, 24, vec![MrtRibEntry::test_path("192.0.2.1", 64500, &[64500, i], vec![])]);
+        }
+        // Identical attributes are shared
+        routing_table.add(&ip("192.168.0.0"), 16, vec![MrtRibEntry::test_path("192.0.2.1", 64500, &[64500, 0], vec![])]);
+        assert_eq!(routing_table.interner.counts(), (2000, 2000, 0));
+
+        // Removing paths releases their attributes, but only prunes the
+        // unused once there have been enough releases (the last of them
+        // still held by the path being released)
+        for i in 0..1500u32 {
+            let prefix = IpAddr::V4(Ipv4Addr::from(0x0a000000 | i << 8));
+            routing_table.remove(&prefix, 24);
+        }
+        let (sets, aspaths, _) = routing_table.interner.counts();
+        assert!(sets < 2000 && aspaths < 2000, "{} {}", sets, aspaths);
+        routing_table.interner.prune();
+        assert_eq!(routing_table.interner.counts(), (501, 501, 0));
+
+        // The last of the first paths replaced by one sharing the
+        // attributes of another
+        routing_table.add(&ip("192.168.0.0"), 16, vec![MrtRibEntry::test_path("192.0.2.1", 64500, &[64500, 1999], vec![])]);
+        routing_table.interner.prune();
+        assert_eq!(routing_table.interner.counts(), (500, 500, 0));
+    }
+
+    #[test]
+    fn peer_compare() {
+        // Paths with the same next hop, so differing only in AS path
+        fn view(peer: &str, aspath: &[u32], view_name: &str) -> MrtRibEntry {
+            let peer = MrtPeer { view_name: view_name.to_string(), ..(*path(peer).peer).clone() };
+            MrtRibEntry { peer: Arc::new(peer), ..MrtRibEntry::test_path("192.0.2.100", 64500, aspath, vec![]) }
+        }
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&ip("10.0.0.0"), 8, vec![view("192.0.2.1", &[64500], ""), view("192.0.2.2", &[64500], "")]);
+        routing_table.add(&ip("10.1.0.0"), 16, vec![view("192.0.2.1", &[64500], ""), view("192.0.2.2", &[64501], "")]);
+        // The same path identifier, but in different views of the table
+        routing_table.add(&ip("10.2.0.0"), 16, vec![view("192.0.2.1", &[64500], ""), view("192.0.2.2", &[64500], "backup")]);
+        routing_table.add(&ip("10.3.0.0"), 16, vec![view("192.0.2.2", &[64500], "")]);
+
+        let (first, second) = (routing_table.peer(&ip("192.0.2.1")), routing_table.peer(&ip("192.0.2.2")));
+        assert_eq!((first.len(), second.len()), (3, 4));
+        let differences = first.compare(&second).iter().map(|x| match x {
+            RouteDifference::Removed(prefix, plen, rt) => format!("- {}/{} {}", prefix, plen, rt.peer.view_name),
+            RouteDifference::Added(prefix, plen, rt) => format!("+ {}/{} {}", prefix, plen, rt.peer.view_name),
+            RouteDifference::Changed(prefix, plen, a, b) => format!("~ {}/{} {} {}", prefix, plen, a.get_aspath(), b.get_aspath()),
+        }).collect::<Vec<String>>();
+        assert_eq!(differences, ["~ 10.1.0.0/16 64500 64501", "- 10.2.0.0/16 ", "+ 10.2.0.0/16 backup", "+ 10.3.0.0/16 "]);
+        assert!(first.compare(&first).is_empty());
+    }
+
+    #[test]
+    fn retain_longest_match() {
+        let mut routing_table = RoutingTable::new();
+        for (prefix, plen) in [("0.0.0.0", 0), ("10.0.0.0", 8), ("10.1.0.0", 16), ("10.1.2.0", 24), ("10.1.3.0", 24)] {
+            routing_table.add(&ip(prefix), plen, vec![path("192.0.2.1"), path("192.0.2.2")]);
+        }
+        routing_table.retain_longest_match(&ip("10.1.2.3"));
+        assert_eq!(prefixes(&routing_table), ["10.1.2.0/24"]);
+        assert_eq!(routing_table.get_exact(&ip("10.1.2.0"), 24).unwrap().len(), 2);
+
+        // Nothing matching, nothing kept
+        routing_table.retain_longest_match(&ip("192.168.0.1"));
+        assert!(routing_table.is_empty());
+    }
+}
@@ -0,0 +1,538 @@
+use crate::*;
+use std::io::BufWriter;
+use std::path::Path;
+use byteorder::WriteBytesExt;
+
+// Binary snapshot of a loaded routing table, so that the interactive shell
+// can be restarted against the same data without decompressing and parsing
+// the MRT files again.
+//
+//   header      "MRTDSNAP", format version (u16)
+//   sources     the MRT files loaded (by absolute path), with their size
+//               and modification time, including those of a snapshot the
+//               table was itself loaded from
+//   peers       every peer referenced, with its collector, view and index
+//   attributes  each distinct set of path attributes, once
+//   routes      prefix, then each path: peer, time, path-id and attribute set
+//
+// All integers are big-endian. A snapshot written by a different format
+// version, or from source files that have changed since, is rejected. One
+// whose source files can't be found is loaded, with a warning.
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"MRTDSNAP";
+const SNAPSHOT_VERSION: u16 = 3;
+
+pub type PeerMap = HashMap<(IpAddr, String, u16), Arc<MrtPeer>>;
+
+// A source MRT file, to tell if the snapshot is out of date
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    path: String,
+    size: u64,
+    modified: u64,
+}
+
+impl Source {
+    // The file as it is now, by its absolute path so that it can be found
+    // from another working directory
+    fn from_file(path: &str) -> Result<Source> {
+        let canonical = std::fs::canonicalize(path).with_context(|| format!("cannot find {}", path))?;
+        let metadata = std::fs::metadata(&canonical)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        Ok(Source { path: canonical.to_string_lossy().into_owned(), size: metadata.len(), modified })
+    }
+}
+
+// Save the table, recording as its sources the MRT files given and those
+// of any snapshot it was loaded from
+pub fn save<P: AsRef<Path>>(path: P, routing_table: &RoutingTable, peers: &PeerMap, loaded: &[Source], files: &[String]) -> Result<()> {
+    let mut sources = loaded.to_vec();
+    for file in files {
+        let source = Source::from_file(file)?;
+        sources.retain(|x| x.path != source.path);
+        sources.push(source);
+    }
+
+    let path = path.as_ref();
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("cannot create snapshot {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_u16::<BigEndian>(SNAPSHOT_VERSION)?;
+
+    writer.write_u32::<BigEndian>(sources.len() as u32)?;
+    for source in &sources {
+        write_string(&mut writer, &source.path)?;
+        writer.write_u64::<BigEndian>(source.size)?;
+        writer.write_u64::<BigEndian>(source.modified)?;
+    }
+
+    // Number the peers, by identity, in index order for each collector,
+    // adding any not in the peer index (which can't be looked up later
+    // but still need saving)
+    let mut peer_list: Vec<(u16, &Arc<MrtPeer>)> = vec![];
+    let mut peer_numbers: HashMap<*const MrtPeer, u32> = HashMap::new();
+    let mut keys = peers.keys().collect::<Vec<&(IpAddr, String, u16)>>();
+    keys.sort();
+    for key in keys {
+        let peer = &peers[key];
+        peer_numbers.insert(Arc::as_ptr(peer), peer_list.len() as u32);
+        peer_list.push((key.2, peer));
+    }
+    for (_, _, paths) in routing_table.iter() {
+        for rt in paths {
+            if let std::collections::hash_map::Entry::Vacant(entry) = peer_numbers.entry(Arc::as_ptr(&rt.peer)) {
+                entry.insert(peer_list.len() as u32);
+                peer_list.push((u16::MAX, &rt.peer));
+            }
+        }
+    }
+    writer.write_u32::<BigEndian>(peer_list.len() as u32)?;
+    for (index, peer) in &peer_list {
+        write_peer(&mut writer, *index, peer)?;
+    }
+
+    // Each distinct attribute set is written once
+    let mut attribute_sets: Vec<Vec<u8>> = vec![];
+    let mut attribute_numbers: HashMap<Vec<u8>, u32> = HashMap::new();
+    let mut routes: Vec<u8> = vec![];
+    let mut route_count: u64 = 0;
+    for (prefix, plen, paths) in routing_table.iter() {
+        route_count += 1;
+        write_ipaddr(&mut routes, &prefix)?;
+        routes.write_u8(plen)?;
+        routes.write_u32::<BigEndian>(paths.len() as u32)?;
+        for rt in paths {
+            let mut encoded = vec![];
+            write_attributes(&mut encoded, &rt.attributes)?;
+            let number = *attribute_numbers.entry(encoded).or_insert_with_key(|encoded| {
+                attribute_sets.push(encoded.clone());
+                attribute_sets.len() as u32 - 1
+            });
+            routes.write_u32::<BigEndian>(peer_numbers[&Arc::as_ptr(&rt.peer)])?;
+            routes.write_u16::<BigEndian>(rt.peer_id)?;
+            routes.write_u64::<BigEndian>(rt.origin_time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())?;
+            routes.write_u32::<BigEndian>(rt.path_id)?;
+            routes.write_u32::<BigEndian>(number)?;
+        }
+    }
+
+    writer.write_u32::<BigEndian>(attribute_sets.len() as u32)?;
+    for attributes in &attribute_sets {
+        writer.write_all(attributes)?;
+    }
+    writer.write_u64::<BigEndian>(route_count)?;
+    writer.write_all(&routes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Load the table, returning the number of routes that matched the filters
+// and the sources of the snapshot
+pub fn load<P: AsRef<Path>>(path: P, routing_table: &mut RoutingTable, peers: &mut PeerMap) -> Result<(u64, Vec<Source>)> {
+    let path = path.as_ref();
+    let data = std::fs::read(path)
+        .with_context(|| format!("cannot read snapshot {}", path.display()))?;
+    let mut reader = data.as_slice();
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != SNAPSHOT_MAGIC {
+        return Err(anyhow!("{} is not a snapshot", path.display()));
+    }
+    let version = reader.read_u16::<BigEndian>()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(anyhow!("{} is a version {} snapshot, expected version {}: please recreate it",
+                           path.display(), version, SNAPSHOT_VERSION));
+    }
+
+    let mut sources = vec![];
+    for _ in 0..reader.read_u32::<BigEndian>()? {
+        let source = Source {
+            path: read_string(&mut reader)?,
+            size: reader.read_u64::<BigEndian>()?,
+            modified: reader.read_u64::<BigEndian>()?,
+        };
+        match Source::from_file(&source.path) {
+            Ok(current) if current.size != source.size || current.modified != source.modified => {
+                return Err(anyhow!("{} is out of date: {} has changed since it was made",
+                                   path.display(), source.path));
+            },
+            Ok(_) => {},
+            Err(_) => eprintln!("Warning: {} may be out of date: its source {} can't be found",
+                                path.display(), source.path),
+        }
+        sources.push(source);
+    }
+
+    let mut peer_list: Vec<Arc<MrtPeer>> = vec![];
+    for _ in 0..reader.read_u32::<BigEndian>()? {
+        let (index, peer) = read_peer(&mut reader)?;
+        let peer = Arc::new(peer);
+        if index != u16::MAX {
+            peers.insert((peer.collector_id, peer.view_name.clone(), index), Arc::clone(&peer));
+        }
+        peer_list.push(peer);
+    }
+
+    let mut attribute_sets: Vec<Arc<Vec<MrtAttribute>>> = vec![];
+    for _ in 0..reader.read_u32::<BigEndian>()? {
+        attribute_sets.push(Arc::new(read_attributes(&mut reader)?));
+    }
+
+    // Routes go through the same filters and display as those read from
+    // MRT files, returning the number that matched
+    let mut count = 0;
+    for sequence in 0..reader.read_u64::<BigEndian>()? {
+        let prefix = read_ipaddr(&mut reader)?;
+        let plen = reader.read_u8()?;
+        let path_count = reader.read_u32::<BigEndian>()?;
+        let mut paths = Vec::with_capacity(path_count as usize);
+        for _ in 0..path_count {
+            let peer = peer_list
+                .get(reader.read_u32::<BigEndian>()? as usize)
+                .ok_or_else(|| anyhow!("invalid peer in snapshot"))?;
+            let peer_id = reader.read_u16::<BigEndian>()?;
+            let origin_time = UNIX_EPOCH + Duration::from_secs(reader.read_u64::<BigEndian>()?);
+            let path_id = reader.read_u32::<BigEndian>()?;
+            let attributes = attribute_sets
+                .get(reader.read_u32::<BigEndian>()? as usize)
+                .ok_or_else(|| anyhow!("invalid attribute set in snapshot"))?;
+            paths.push(MrtRibEntry {
+                peer_id,
+                peer: Arc::clone(peer),
+                origin_time,
+                path_id,
+                attributes: Arc::clone(attributes),
+            });
+        }
+        let nlri = MrtNlri {
+            sequence: sequence as u32,
+            plen,
+            prefix,
+            entry_count: paths.len() as u16,
+            rib_entries: paths,
+            source: NlriSource::Table,
+        };
+        count += load_nlri(nlri, routing_table) as u64;
+    }
+    Ok((count, sources))
+}
+
+fn write_string<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    writer.write_u32::<BigEndian>(s.len() as u32)?;
+    writer.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let mut buffer = vec![0u8; reader.read_u32::<BigEndian>()? as usize];
+    reader.read_exact(&mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+fn write_ipaddr<W: Write>(writer: &mut W, ipaddr: &IpAddr) -> Result<()> {
+    match ipaddr {
+        IpAddr::V4(ip) => {
+            writer.write_u8(4)?;
+            writer.write_u32::<BigEndian>(ip.to_bits())?;
+        },
+        IpAddr::V6(ip) => {
+            writer.write_u8(6)?;
+            writer.write_u128::<BigEndian>(ip.to_bits())?;
+        },
+    }
+    Ok(())
+}
+
+fn read_ipaddr<R: Read>(reader: &mut R) -> Result<IpAddr> {
+    match reader.read_u8()? {
+        4 => Ok(IpAddr::V4(Ipv4Addr::from_bits(reader.read_u32::<BigEndian>()?))),
+        6 => Ok(IpAddr::V6(Ipv6Addr::from_bits(reader.read_u128::<BigEndian>()?))),
+        family => Err(anyhow!("invalid address family {} in snapshot", family)),
+    }
+}
+
+fn write_peer<W: Write>(writer: &mut W, index: u16, peer: &MrtPeer) -> Result<()> {
+    write_ipaddr(writer, &peer.collector_id)?;
+    write_string(writer, &peer.view_name)?;
+    writer.write_u16::<BigEndian>(index)?;
+    writer.write_u8(peer.peer_type_a as u8 | (peer.peer_type_i as u8) << 1)?;
+    write_ipaddr(writer, &peer.peer_id)?;
+    write_ipaddr(writer, &peer.peer_address)?;
+    writer.write_u32::<BigEndian>(peer.peer_as)?;
+    Ok(())
+}
+
+fn read_peer<R: Read>(reader: &mut R) -> Result<(u16, MrtPeer)> {
+    let collector_id = read_ipaddr(reader)?;
+    let view_name = read_string(reader)?;
+    let index = reader.read_u16::<BigEndian>()?;
+    let peer_type = reader.read_u8()?;
+    Ok((index, MrtPeer {
+        peer_type_a: peer_type & 1 != 0,
+        peer_type_i: peer_type & 2 != 0,
+        peer_id: read_ipaddr(reader)?,
+        peer_address: read_ipaddr(reader)?,
+        peer_as: reader.read_u32::<BigEndian>()?,
+        collector_id,
+        view_name,
+    }))
+}
+
+fn write_communities<W: Write>(writer: &mut W, communities: &[Community]) -> Result<()> {
+    writer.write_u32::<BigEndian>(communities.len() as u32)?;
+    for community in communities {
+        match community {
+            Community::Standard((a, b)) => {
+                writer.write_u16::<BigEndian>(*a)?;
+                writer.write_u16::<BigEndian>(*b)?;
+            },
+            Community::Large((a, b, c)) => {
+                writer.write_u32::<BigEndian>(*a)?;
+                writer.write_u32::<BigEndian>(*b)?;
+                writer.write_u32::<BigEndian>(*c)?;
+            },
+            Community::Extended((t, st, value)) => {
+                writer.write_u8(*t)?;
+                writer.write_u8(*st)?;
+                writer.write_u48::<BigEndian>(*value)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+fn read_communities<R: Read + BufRead>(reader: &mut R, kind: CommunityKind) -> Result<Vec<Community>> {
+    let count = reader.read_u32::<BigEndian>()? as usize;
+    match kind {
+        CommunityKind::Standard => Community::parse(reader, count),
+        CommunityKind::Large => Community::parse_large(reader, count),
+        CommunityKind::Extended => Community::parse_extended(reader, count),
+    }
+}
+
+// Attributes are tagged with their BGP type code
+fn write_attributes<W: Write>(writer: &mut W, attributes: &[MrtAttribute]) -> Result<()> {
+    writer.write_u16::<BigEndian>(attributes.len() as u16)?;
+    for attribute in attributes {
+        match attribute {
+            MrtAttribute::Unknown(flags, code, data) => {
+                writer.write_u8(0)?;
+                writer.write_u8(*flags)?;
+                writer.write_u8(*code)?;
+                writer.write_u16::<BigEndian>(data.len() as u16)?;
+                writer.write_all(data)?;
+            },
+            MrtAttribute::Origin(origin) => {
+                writer.write_u8(1)?;
+                writer.write_u8(*origin)?;
+            },
+            MrtAttribute::AsPath(aspath) => {
+                writer.write_u8(2)?;
+                writer.write_u16::<BigEndian>(aspath.aspath_segments.len() as u16)?;
+                for segment in &aspath.aspath_segments {
+                    writer.write_u8(segment.ordered as u8)?;
+                    writer.write_u16::<BigEndian>(segment.asns.len() as u16)?;
+                    for asn in &segment.asns {
+                        writer.write_u32::<BigEndian>(*asn)?;
+                    }
+                }
+            },
+            MrtAttribute::NextHop(nexthop) => {
+                writer.write_u8(3)?;
+                write_ipaddr(writer, nexthop)?;
+            },
+            MrtAttribute::MultiExitDisc(med) => {
+                writer.write_u8(4)?;
+                writer.write_u32::<BigEndian>(*med)?;
+            },
+            MrtAttribute::LocalPref(local_pref) => {
+                writer.write_u8(5)?;
+                writer.write_u32::<BigEndian>(*local_pref)?;
+            },
+            MrtAttribute::AtomicAggregate => writer.write_u8(6)?,
+            MrtAttribute::Aggregator(asn, id) => {
+                writer.write_u8(7)?;
+                writer.write_u32::<BigEndian>(*asn)?;
+                writer.write_u32::<BigEndian>(id.to_bits())?;
+            },
+            MrtAttribute::MpNextHop(nexthop) => {
+                writer.write_u8(14)?;
+                write_ipaddr(writer, nexthop)?;
+            },
+            MrtAttribute::Community(communities) => {
+                writer.write_u8(8)?;
+                write_communities(writer, communities)?;
+            },
+            MrtAttribute::ExtendedCommunity(communities) => {
+                writer.write_u8(16)?;
+                write_communities(writer, communities)?;
+            },
+            MrtAttribute::LargeCommunity(communities) => {
+                writer.write_u8(32)?;
+                write_communities(writer, communities)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+fn read_attributes<R: Read + BufRead>(reader: &mut R) -> Result<Vec<MrtAttribute>> {
+    let count = reader.read_u16::<BigEndian>()?;
+    let mut attributes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        attributes.push(match reader.read_u8()? {
+            0 => {
+                let flags = reader.read_u8()?;
+                let code = reader.read_u8()?;
+                let mut data = vec![0u8; reader.read_u16::<BigEndian>()? as usize];
+                reader.read_exact(&mut data)?;
+                MrtAttribute::Unknown(flags, code, Arc::from(data))
+            },
+            1 => MrtAttribute::Origin(reader.read_u8()?),
+            2 => {
+                let segment_count = reader.read_u16::<BigEndian>()?;
+                let mut aspath_segments = Vec::with_capacity(segment_count as usize);
+                for _ in 0..segment_count {
+                    let ordered = reader.read_u8()? != 0;
+                    let asn_count = reader.read_u16::<BigEndian>()?;
+                    let mut asns = Vec::with_capacity(asn_count as usize);
+                    for _ in 0..asn_count {
+                        asns.push(reader.read_u32::<BigEndian>()?);
+                    }
+                    aspath_segments.push(AsPathSegment { ordered, asns });
+                }
+                MrtAttribute::AsPath(Arc::new(AsPath { aspath_segments }))
+            },
+            3 => MrtAttribute::NextHop(read_ipaddr(reader)?),
+            4 => MrtAttribute::MultiExitDisc(reader.read_u32::<BigEndian>()?),
+            5 => MrtAttribute::LocalPref(reader.read_u32::<BigEndian>()?),
+            6 => MrtAttribute::AtomicAggregate,
+            7 => MrtAttribute::Aggregator(reader.read_u32::<BigEndian>()?,
+                                          Ipv4Addr::from_bits(reader.read_u32::<BigEndian>()?)),
+            14 => MrtAttribute::MpNextHop(read_ipaddr(reader)?),
+            8 => MrtAttribute::Community(Arc::new(read_communities(reader, CommunityKind::Standard)?)),
+            16 => MrtAttribute::ExtendedCommunity(Arc::new(read_communities(reader, CommunityKind::Extended)?)),
+            32 => MrtAttribute::LargeCommunity(Arc::new(read_communities(reader, CommunityKind::Large)?)),
+            code => return Err(anyhow!("invalid attribute {} in snapshot", code)),
+        });
+    }
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own for each test, with a source file in it
+    fn directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("mrtdump-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("source.mrt"), b"MRT").unwrap();
+        directory
+    }
+
+    fn table() -> (RoutingTable, PeerMap) {
+        let mut routing_table = RoutingTable::new();
+        let communities = vec![Community::Standard((65000, 1)), Community::Standard((65535, 0))];
+        routing_table.add(&IpAddr::from_str("192.0.2.0").unwrap(), 24, vec![
+            MrtRibEntry::test_path("198.51.100.1", 64500, &[64500, 3356], vec![
+                MrtAttribute::MultiExitDisc(10),
+                MrtAttribute::Community(Arc::new(communities)),
+            ]),
+            MrtRibEntry::test_path("198.51.100.2", 64501, &[64501, 1299, 3356], vec![
+                MrtAttribute::LocalPref(200),
+                MrtAttribute::Aggregator(3356, Ipv4Addr::new(203, 0, 113, 1)),
+            ]),
+        ]);
+        routing_table.add(&IpAddr::from_str("2001:db8::").unwrap(), 32, vec![
+            MrtRibEntry::test_path("2001:db8:1::1", 64502, &[64502], vec![
+                MrtAttribute::MpNextHop(IpAddr::from_str("2001:db8:1::1").unwrap()),
+                MrtAttribute::Unknown(0xc0, 99, Arc::from(&[1u8, 2, 3][..])),
+            ]),
+        ]);
+        (routing_table, PeerMap::new())
+    }
+
+    fn routes(routing_table: &RoutingTable) -> Vec<String> {
+        routing_table.iter()
+            .flat_map(|(prefix, plen, paths)| paths.iter().map(move |path| {
+                format!("{}/{} {:?} {:?} {:?}", prefix, plen, path.key(), path.origin_time, path.attributes)
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = directory("round-trip");
+        let snapshot = directory.join("table.snapshot");
+        let source = directory.join("source.mrt").to_string_lossy().into_owned();
+        let (routing_table, peers) = table();
+        save(&snapshot, &routing_table, &peers, &[], &[source]).unwrap();
+
+        let (mut loaded, mut loaded_peers) = (RoutingTable::new(), PeerMap::new());
+        let (count, sources) = load(&snapshot, &mut loaded, &mut loaded_peers).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(routes(&loaded), routes(&routing_table));
+        assert_eq!(sources.len(), 1);
+        assert!(Path::new(&sources[0].path).is_absolute());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn out_of_date() {
+        let directory = directory("out-of-date");
+        let snapshot = directory.join("table.snapshot");
+        let source = directory.join("source.mrt");
+        let (routing_table, peers) = table();
+        save(&snapshot, &routing_table, &peers, &[], &[source.to_string_lossy().into_owned()]).unwrap();
+
+        std::fs::write(&source, b"MRT, and more").unwrap();
+        let error = load(&snapshot, &mut RoutingTable::new(), &mut PeerMap::new()).unwrap_err();
+        assert!(error.to_string().contains("out of date"), "{}", error);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn missing_source() {
+        let directory = directory("missing-source");
+        let snapshot = directory.join("table.snapshot");
+        let source = directory.join("source.mrt");
+        let (routing_table, peers) = table();
+        save(&snapshot, &routing_table, &peers, &[], &[source.to_string_lossy().into_owned()]).unwrap();
+
+        // Loaded, with a warning, and the source kept
+        std::fs::remove_file(&source).unwrap();
+        let (count, sources) = load(&snapshot, &mut RoutingTable::new(), &mut PeerMap::new()).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(sources.len(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn sources_carried_forward() {
+        let directory = directory("carried-forward");
+        let first = directory.join("first.snapshot");
+        let second = directory.join("second.snapshot");
+        let source = directory.join("source.mrt");
+        let (routing_table, peers) = table();
+        save(&first, &routing_table, &peers, &[], &[source.to_string_lossy().into_owned()]).unwrap();
+
+        // Saving again, with no files, keeps the sources of the first
+        let (mut loaded, mut loaded_peers) = (RoutingTable::new(), PeerMap::new());
+        let (_, sources) = load(&first, &mut loaded, &mut loaded_peers).unwrap();
+        save(&second, &loaded, &loaded_peers, &sources, &[]).unwrap();
+        std::fs::write(&source, b"MRT, and more").unwrap();
+        let error = load(&second, &mut RoutingTable::new(), &mut PeerMap::new()).unwrap_err();
+        assert!(error.to_string().contains("out of date"), "{}", error);
+
+        // Nor is a source recorded twice
+        let source = source.to_string_lossy().into_owned();
+        save(&second, &loaded, &loaded_peers, &sources, &[source]).unwrap();
+        let (_, sources) = load(&second, &mut RoutingTable::new(), &mut PeerMap::new()).unwrap();
+        assert_eq!(sources.len(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+
+use crate::*;
+
+// Per peer statistics of the routes loaded (-s, or the summary query), as
+// "show ip bgp summary" or with -j "show bgp summary". Every peer of the
+// peer index tables is included, along with the peers of BGP4MP updates.
+
+#[derive(Debug)]
+pub struct PeerSummary {
+    pub peer: Arc<MrtPeer>,
+    pub prefixes: [usize; 2],   // IPv4, IPv6
+    pub paths: [usize; 2],
+    pub best: [usize; 2],       // paths selected as best
+    pub newest: Option<SystemTime>,     // origin_time of the most recent path
+    pub oldest: Option<SystemTime>,
+}
+
+impl PeerSummary {
+    fn new(peer: &Arc<MrtPeer>) -> PeerSummary {
+        PeerSummary {
+            peer: Arc::clone(peer),
+            prefixes: [0; 2],
+            paths: [0; 2],
+            best: [0; 2],
+            newest: None,
+            oldest: None,
+        }
+    }
+
+    pub fn total_prefixes(&self) -> usize {
+        self.prefixes.iter().sum()
+    }
+
+    pub fn total_paths(&self) -> usize {
+        self.paths.iter().sum()
+    }
+
+    pub fn total_best(&self) -> usize {
+        self.best.iter().sum()
+    }
+}
+
+// The peers, ordered by collector, view and address
+pub fn peer_summary(routing_table: &RoutingTable,
+                    peers: &HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) -> Vec<PeerSummary> {
+    let mut summary: Vec<PeerSummary> = vec![];
+    let mut by_value: BTreeMap<(IpAddr, String, IpAddr, u32), usize> = BTreeMap::new();
+    let mut by_identity: HashMap<*const MrtPeer, usize> = HashMap::new();
+
+    // Paths mostly share the peer's Arc; those that don't are matched by value
+    let mut index_of = |peer: &Arc<MrtPeer>, summary: &mut Vec<PeerSummary>| -> usize {
+        *by_identity.entry(Arc::as_ptr(peer)).or_insert_with(|| {
+            *by_value
+                .entry((peer.collector_id, peer.view_name.clone(), peer.peer_address, peer.peer_as))
+                .or_insert_with(|| {
+                    summary.push(PeerSummary::new(peer));
+                    summary.len() - 1
+                })
+        })
+    };
+    let mut keys = peers.keys().collect::<Vec<&(IpAddr, String, u16)>>();
+    keys.sort();
+    for key in keys {
+        index_of(&peers[key], &mut summary);
+    }
+
+    let mut counted: Vec<usize> = vec![];
+    for (prefix, _, route_entries) in routing_table.iter() {
+        let family = if prefix.is_ipv4() { 0 } else { 1 };
+        let status = GETOPT.bestpath.select(route_entries);
+        counted.clear();
+        for (rt, status) in route_entries.iter().zip(status) {
+            let index = index_of(&rt.peer, &mut summary);
+            let peer = &mut summary[index];
+            // A peer's prefix is counted once, however many paths (add-path)
+            if !counted.contains(&index) {
+                counted.push(index);
+                peer.prefixes[family] += 1;
+            }
+            peer.paths[family] += 1;
+            if status.is_best() {
+                peer.best[family] += 1;
+            }
+            peer.newest = peer.newest.max(Some(rt.origin_time));
+            peer.oldest = Some(peer.oldest.map_or(rt.origin_time, |x| x.min(rt.origin_time)));
+        }
+    }
+
+    let mut summary = summary.into_iter().map(Some).collect::<Vec<Option<PeerSummary>>>();
+    by_value.into_values().filter_map(|index| summary[index].take()).collect()
+}
+
+pub fn show_summary(routing_table: &RoutingTable, peers: &HashMap<(IpAddr, String, u16), Arc<MrtPeer>>) {
+    let summary = peer_summary(routing_table, peers);
+    match GETOPT.dialect {
+        Dialect::Json | Dialect::Ndjson => json_show_summary(&summary),
+        Dialect::Terse | Dialect::Csv | Dialect::Bgpdump | Dialect::Template => csv_show_summary(&summary),
+        Dialect::Juniper => juniper_show_bgp_summary(&summary),
+        _ => cisco_show_ip_bgp_summary(&summary),
+    }
+}
+
+// The peers of each collector and view in turn
+fn by_collector(summary: &[PeerSummary]) -> Vec<&[PeerSummary]> {
+    summary
+        .chunk_by(|a, b| a.peer.collector_id == b.peer.collector_id && a.peer.view_name == b.peer.view_name)
+        .collect()
+}
+
+// How long ago, as IOS shows it: 00:05:12, 1d02h, 3w2d, or never
+fn cisco_age(time: Option<SystemTime>) -> String {
+    let secs = match time {
+        Some(time) => time.elapsed().unwrap_or_default().as_secs(),
+        None => return String::from("never"),
+    };
+    if secs < 86400 {
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs < 7 * 86400 {
+        format!("{}d{:02}h", secs / 86400, secs / 3600 % 24)
+    } else {
+        format!("{}w{}d", secs / (7 * 86400), secs / 86400 % 7)
+    }
+}
+
+// IOS's table, with the router ID, the prefixes of each address family,
+// and when the oldest and newest paths were received in place of the
+// message counters and session state that MRT doesn't record
+fn cisco_show_ip_bgp_summary(summary: &[PeerSummary]) {
+    for peers in by_collector(summary) {
+        let collector = &peers[0].peer;
+        outln!("BGP router identifier {}, local AS number {}",
+                 collector.collector_id, GETOPT.bestpath.local_as.unwrap_or(0));
+        if !collector.view_name.is_empty() {
+            outln!("BGP view {}", collector.view_name);
+        }
+        outln!("{} network entries, {} paths",
+                 peers.iter().map(|x| x.total_prefixes()).sum::<usize>(),
+                 peers.iter().map(|x| x.total_paths()).sum::<usize>());
+        outln!();
+        outln!("{:15} V {:>12} {:15} {:>8} {:>8} {:>8} {:>8} {:8} Newest",
+                 "Neighbor", "AS", "Router ID", "IPv4 Pfx", "IPv6 Pfx", "Paths", "Best", "Oldest");
+        for peer in peers {
+            let address = peer.peer.peer_address.to_string();
+            // Long (IPv6) addresses on a line of their own, as IOS does
+            if address.len() > 15 {
+                outln!("{}", address);
+            }
+            outln!("{:15} 4 {:>12} {:15} {:>8} {:>8} {:>8} {:>8} {:8} {}",
+                     if address.len() > 15 { "" } else { &address },
+                     peer.peer.peer_as,
+                     peer.peer.peer_id.to_string(),
+                     peer.prefixes[0],
+                     peer.prefixes[1],
+                     peer.total_paths(),
+                     peer.total_best(),
+                     cisco_age(peer.oldest),
+                     cisco_age(peer.newest));
+        }
+        outln!();
+        outln!("Total number of neighbors {}", peers.len());
+        outln!();
+    }
+}
+
+// JUNOS's table, with the RIB counts of each address family under each
+// peer (active/received/accepted/damped); the age is of the oldest path
+fn juniper_show_bgp_summary(summary: &[PeerSummary]) {
+    let tables = ["inet.0", "inet6.0"];
+    let groups = by_collector(summary);
+    for peers in &groups {
+        let collector = &peers[0].peer;
+        if groups.len() > 1 {
+            collector_heading(&collector.collector_id, &collector.view_name);
+        }
+        let down = peers.iter().filter(|x| x.total_paths() == 0).count();
+        outln!("Groups: 1 Peers: {} Down peers: {}", peers.len(), down);
+        outln!("Table          Tot Paths  Act Paths Suppressed    History Damp State    Pending");
+        for (family, table) in tables.iter().enumerate() {
+            let paths = peers.iter().map(|x| x.paths[family]).sum::<usize>();
+            if paths > 0 {
+                outln!("{:15}{:>9}{:>11}{:>11}{:>11}{:>11}{:>11}",
+                         table, paths, peers.iter().map(|x| x.best[family]).sum::<usize>(), 0, 0, 0, 0);
+            }
+        }
+        outln!("Peer                     AS      InPkt     OutPkt    OutQ   Flaps Last Up/Dwn State|#Active/Received/Accepted/Damped...");
+        for peer in peers.iter() {
+            outln!("{:23}{:>7}{:>11}{:>11}{:>8}{:>8} {:>11} {}",
+                     peer.peer.peer_address.to_string(),
+                     peer.peer.peer_as,
+                     0, 0, 0, 0,
+                     peer.oldest.map_or(String::from("never"), |x| util::friendly_duration(x.elapsed().unwrap_or_default())),
+                     if peer.total_paths() > 0 { "Establ" } else { "Idle" });
+            for (family, table) in tables.iter().enumerate() {
+                if peer.paths[family] > 0 {
+                    outln!("  {}: {}/{}/{}/0", table, peer.best[family], peer.paths[family], peer.paths[family]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        IpAddr::from_str(s).unwrap()
+    }
+
+    fn path(peer: &str, path_id: u32, secs: u64) -> MrtRibEntry {
+        MrtRibEntry {
+            path_id,
+            origin_time: UNIX_EPOCH + Duration::from_secs(secs),
+            ..MrtRibEntry::test_path(peer, 3356, &[3356, 64500], vec![])
+        }
+    }
+
+    // 192.0.2.1 with two IPv4 prefixes and an IPv6 prefix over two paths
+    // (add-path), 192.0.2.2 the best path of one, and 192.0.2.9 idle
+    fn summary() -> Vec<PeerSummary> {
+        let mut routing_table = RoutingTable::new();
+        routing_table.add(&ip("10.0.0.0"), 8, MrtRibEntry::test_paths());
+        routing_table.add(&ip("10.1.0.0"), 16, vec![path("192.0.2.1", 0, 1_700_000_600)]);
+        routing_table.add(&ip("2001:db8::"), 32, vec![path("192.0.2.1", 1, 1_699_999_000),
+                                                      path("192.0.2.1", 2, 1_700_000_000)]);
+        let mut peers = HashMap::new();
+        let collector = ip("192.0.2.254");
+        for (index, peer) in ["192.0.2.9", "192.0.2.1"].iter().enumerate() {
+            peers.insert((collector, String::new(), index as u16), Arc::clone(&path(peer, 0, 0).peer));
+        }
+        peer_summary(&routing_table, &peers)
+    }
+
+    #[test]
+    fn counts() {
+        let summary = summary();
+        let counts = summary
+            .iter()
+            .map(|x| (x.peer.peer_address.to_string(), x.prefixes, x.paths, x.best))
+            .collect::<Vec<(String, [usize; 2], [usize; 2], [usize; 2])>>();
+        assert_eq!(counts, [(String::from("192.0.2.1"), [2, 1], [2, 2], [1, 1]),
+                            (String::from("192.0.2.2"), [1, 0], [1, 0], [1, 0]),
+                            (String::from("192.0.2.9"), [0, 0], [0, 0], [0, 0])]);
+        assert_eq!((summary[0].total_prefixes(), summary[0].total_paths(), summary[0].total_best()), (3, 4, 2));
+        assert_eq!(by_collector(&summary).len(), 1);
+    }
+
+    #[test]
+    fn origin_time() {
+        let summary = summary();
+        let secs = |time: Option<SystemTime>| time.map(util::epoch_secs);
+        assert_eq!((secs(summary[0].oldest), secs(summary[0].newest)), (Some(1_699_999_000), Some(1_700_000_600)));
+        assert_eq!((secs(summary[1].oldest), secs(summary[1].newest)), (Some(1_700_000_000), Some(1_700_000_000)));
+        assert_eq!((summary[2].oldest, summary[2].newest), (None, None));
+        assert_eq!(cisco_age(None), "never");
+    }
+
+    #[test]
+    fn json() {
+        let summary = summary();
+        let json = writer::test_output(|| json::json_show_summary(&summary));
+        assert_eq!(json.lines().collect::<Vec<&str>>(), [
+            "{\"address\":\"192.0.2.1\",\"as\":3356,\"router_id\":\"192.0.2.1\",\"collector\":\"192.0.2.254\",\"view\":\"\",\
+             \"ipv4_prefixes\":2,\"ipv6_prefixes\":1,\"paths\":4,\"best_paths\":2,\
+             \"newest\":\"2023-11-14T22:23:20Z\",\"newest_epoch\":1700000600,\
+             \"oldest\":\"2023-11-14T21:56:40Z\",\"oldest_epoch\":1699999000}",
+            "{\"address\":\"192.0.2.2\",\"as\":1299,\"router_id\":\"192.0.2.2\",\"collector\":\"192.0.2.254\",\"view\":\"\",\
+             \"ipv4_prefixes\":1,\"ipv6_prefixes\":0,\"paths\":1,\"best_paths\":1,\
+             \"newest\":\"2023-11-14T22:13:20Z\",\"newest_epoch\":1700000000,\
+             \"oldest\":\"2023-11-14T22:13:20Z\",\"oldest_epoch\":1700000000}",
+            "{\"address\":\"192.0.2.9\",\"as\":3356,\"router_id\":\"192.0.2.9\",\"collector\":\"192.0.2.254\",\"view\":\"\",\
+             \"ipv4_prefixes\":0,\"ipv6_prefixes\":0,\"paths\":0,\"best_paths\":0,\
+             \"newest\":null,\"newest_epoch\":null,\"oldest\":null,\"oldest_epoch\":null}",
+        ]);
+    }
+}
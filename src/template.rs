@@ -0,0 +1,511 @@
+use std::sync::OnceLock;
+use crate::*;
+
+// Output templates (--format, --format-prefix): text with {field}
+// placeholders, written once per path (--format) and once per prefix
+// (--format-prefix, before its paths). In the prefix scope, the path fields
+// are those of the best path.
+//
+//   {prefix} {peer_as} {origin_as} {aspath} {communities} {nexthop} {age}
+//
+// A field can be passed through helpers, in order, each after a '|':
+//
+//   {communities|join:,}    join a list with the separator (default space)
+//   {med|default:-}         the text if the field is empty
+//   {prefix|left:20}        pad to the width, aligned left
+//   {med|right:6}           pad to the width, aligned right
+//   {prefix|column:17:22}   pad to the width, or if the text fills it, follow
+//                           it with a new line indented to the next column
+//   {med|before:MED }       text before the field, only if it isn't empty
+//   {med|after:, }          text after the field, only if it isn't empty
+//   {prefix|first}          the field on a prefix's first path, blank after
+//   {origin_char|upper}     upper (or lower) case
+//
+// \n, \t, \\, \{, \} and \| stand for themselves in the template, and {{
+// and }} for braces. A --format of cisco, juniper or terse gives the
+// built-in template of the same name, with which the cisco and juniper
+// dialects show routes. On a terminal, those fields the dialects highlight
+// are coloured as they are.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Prefix,
+    Network,
+    Plen,
+    Paths,
+    Index,
+    Peer,
+    PeerAs,
+    RouterId,
+    Collector,
+    View,
+    PathId,
+    Nexthop,
+    Med,
+    LocalPref,
+    AsPath,
+    AsPathLength,
+    Origin,
+    OriginChar,
+    OriginAs,
+    NeighborAs,
+    Communities,
+    AtomicAggregate,
+    Aggregator,
+    Age,
+    Originated,
+    OriginatedEpoch,
+    Status,
+    StatusCode,
+    ActiveCode,
+    InternalCode,
+    NotBestReason,
+    InactiveReason,
+}
+
+const FIELDS: [(&str, Field); 32] = [
+    ("prefix", Field::Prefix),                  // 192.0.2.0/24
+    ("network", Field::Network),                // 192.0.2.0
+    ("plen", Field::Plen),                      // 24
+    ("paths", Field::Paths),                    // number of paths for the prefix
+    ("index", Field::Index),                    // of the path, from 1
+    ("peer", Field::Peer),
+    ("peer_as", Field::PeerAs),
+    ("router_id", Field::RouterId),
+    ("collector", Field::Collector),
+    ("view", Field::View),
+    ("path_id", Field::PathId),
+    ("nexthop", Field::Nexthop),
+    ("med", Field::Med),
+    ("localpref", Field::LocalPref),
+    ("aspath", Field::AsPath),                  // a list, of ASNs and {sets}
+    ("aspath_length", Field::AsPathLength),
+    ("origin", Field::Origin),                  // igp, egp, incomplete
+    ("origin_char", Field::OriginChar),         // i, e, ?
+    ("origin_as", Field::OriginAs),
+    ("neighbor_as", Field::NeighborAs),
+    ("communities", Field::Communities),        // a list
+    ("atomic_aggregate", Field::AtomicAggregate),
+    ("aggregator", Field::Aggregator),          // AS and router ID
+    ("age", Field::Age),                        // since originated, 1d 02:03:04
+    ("originated", Field::Originated),          // RFC 3339
+    ("originated_epoch", Field::OriginatedEpoch),
+    ("status", Field::Status),                  // best, multipath, not-best
+    ("status_code", Field::StatusCode),         // >, m, or a space
+    ("active_code", Field::ActiveCode),         // * for the best path, or a space
+    ("internal_code", Field::InternalCode),     // i for an iBGP path, or a space
+    ("not_best_reason", Field::NotBestReason),  // the step that lost, for a path not the best
+    ("inactive_reason", Field::InactiveReason), // the same, as JUNOS words it
+];
+
+const BUILT_IN_TEMPLATES: [(&str, &str); 3] = [
+    ("cisco", " *{status_code}{internal_code} {prefix|first|column:17:22}{nexthop|column:20:42}{med|right:6} {localpref|right:6}      0 {aspath|after: }{origin_char}"),
+    ("juniper", "{prefix|first|default:\\t}\\t{active_code}[BGP/170] {age}, {med|before:MED |after:, }localpref {localpref|default:100}, from {peer}\\n\
+                 \\t\\t AS path: {aspath} {origin_char|upper}\\n\
+                 {communities|before:\\t\\t Communities: |after:\\n}\
+                 {inactive_reason|before:\\t\\t Inactive reason: |after:\\n}\
+                 \\t\\t> to {nexthop}"),
+    ("terse", "{prefix}|{peer}|{nexthop}|{med}|{localpref}|{aspath}|{origin}|{communities}"),
+];
+
+#[derive(Debug, Clone)]
+enum Helper {
+    Join(String),
+    Default(String),
+    Left(usize),
+    Right(usize),
+    Column(usize, usize),
+    Before(String),
+    After(String),
+    First,
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field, Vec<Helper>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+// A field's value: text, or a list to be joined
+enum Value {
+    Text(String),
+    List(Vec<String>),
+}
+
+impl Helper {
+    fn parse(s: &str) -> Result<Helper> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let width = || -> Result<usize> {
+            arg.ok_or_else(|| anyhow!("{} needs a width", name))?
+                .parse::<usize>()
+                .map_err(|_| anyhow!("{} width {} not valid", name, arg.unwrap_or_default()))
+        };
+        let text = || arg.ok_or_else(|| anyhow!("{} needs a value", name)).map(String::from);
+        match name {
+            "join" => Ok(Helper::Join(arg.unwrap_or(" ").to_string())),
+            "default" => Ok(Helper::Default(text()?)),
+            "left" => Ok(Helper::Left(width()?)),
+            "right" => Ok(Helper::Right(width()?)),
+            "column" => {
+                let (width, indent) = arg.and_then(|x| x.split_once(':'))
+                    .and_then(|(width, indent)| Some((width.parse::<usize>().ok()?, indent.parse::<usize>().ok()?)))
+                    .ok_or_else(|| anyhow!("column needs a width and indent, eg. column:17:22"))?;
+                Ok(Helper::Column(width, indent))
+            },
+            "before" => Ok(Helper::Before(text()?)),
+            "after" => Ok(Helper::After(text()?)),
+            "first" => Ok(Helper::First),
+            "upper" => Ok(Helper::Upper),
+            "lower" => Ok(Helper::Lower),
+            _ => Err(anyhow!("unknown template helper {}", name)),
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Template, Self::Err> {
+        let s = BUILT_IN_TEMPLATES.iter().find(|(name, _)| *name == s).map_or(s, |(_, template)| template);
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+
+        // Take the next character, resolving a backslash escape
+        fn escaped(c: char, chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<char> {
+            if c != '\\' {
+                return Ok(c);
+            }
+            match chars.next() {
+                Some('n') => Ok('\n'),
+                Some('t') => Ok('\t'),
+                Some(c @ ('\\' | '{' | '}' | '|')) => Ok(c),
+                Some(c) => Err(anyhow!("unknown escape \\{} in template", c)),
+                None => Err(anyhow!("template ends with \\")),
+            }
+        }
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '}' => return Err(anyhow!("unmatched }} in template")),
+                '{' => {
+                    // The field name and helpers, split at each unescaped '|'
+                    let mut items = vec![String::new()];
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('|') => items.push(String::new()),
+                            Some(c) => {
+                                let c = escaped(c, &mut chars)?;
+                                items.last_mut().unwrap().push(c);
+                            },
+                            None => return Err(anyhow!("unmatched {{ in template")),
+                        }
+                    }
+                    let name = items[0].trim();
+                    let field = FIELDS
+                        .iter()
+                        .find(|(x, _)| *x == name)
+                        .map(|(_, field)| *field)
+                        .ok_or_else(|| anyhow!("unknown template field {} (one of {})", name,
+                                               FIELDS.map(|(name, _)| name).join(", ")))?;
+                    let helpers = items[1..].iter().map(|x| Helper::parse(x)).collect::<Result<Vec<Helper>>>()?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field, helpers));
+                },
+                c => text.push(escaped(c, &mut chars)?),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+}
+
+// What the fields of a template are drawn from
+struct Context<'a> {
+    prefix: &'a IpAddr,
+    plen: u8,
+    route_entries: &'a [MrtRibEntry],
+    status: &'a [PathStatus],
+    index: usize,       // of the path
+}
+
+impl Context<'_> {
+    fn value(&self, field: Field) -> Value {
+        let rt = &self.route_entries[self.index];
+        let status = self.status[self.index];
+        let optional = |value: Option<u32>| value.map_or(String::new(), |x| x.to_string());
+        let originated = util::epoch_secs(rt.origin_time);
+        Value::Text(match field {
+            Field::Prefix => format!("{}/{}", self.prefix, self.plen),
+            Field::Network => self.prefix.to_string(),
+            Field::Plen => self.plen.to_string(),
+            Field::Paths => self.route_entries.len().to_string(),
+            Field::Index => (self.index + 1).to_string(),
+            Field::Peer => rt.peer.peer_address.to_string(),
+            Field::PeerAs => rt.peer.peer_as.to_string(),
+            Field::RouterId => rt.peer.peer_id.to_string(),
+            Field::Collector => rt.peer.collector_id.to_string(),
+            Field::View => rt.peer.view_name.clone(),
+            Field::PathId => rt.path_id.to_string(),
+            Field::Nexthop => rt.get_nexthop().to_string(),
+            Field::Med => optional(rt.get_med()),
+            Field::LocalPref => optional(rt.get_local_pref()),
+            Field::AsPath => {
+                let aspath = rt.get_aspath();
+                // Sets are kept whole, as one item
+                let mut items: Vec<String> = vec![];
+                for word in aspath.split(' ').filter(|x| !x.is_empty()) {
+                    match items.last_mut() {
+                        Some(last) if last.starts_with('{') && !last.ends_with('}') => {
+                            last.push(' ');
+                            last.push_str(word);
+                        },
+                        _ => items.push(word.to_string()),
+                    }
+                }
+                return Value::List(items);
+            },
+            Field::AsPathLength => rt.get_aspath_length().to_string(),
+            Field::Origin => String::from(match rt.get_origin() {
+                0 => "igp",
+                1 => "egp",
+                2 => "incomplete",
+                _ => "",
+            }),
+            Field::OriginChar => rt.get_origin_char().to_string(),
+            Field::OriginAs => optional(rt.get_origin_as()),
+            Field::NeighborAs => optional(rt.get_neighbor_as()),
+            Field::Communities => return Value::List(rt.communities().map(|x| x.to_string()).collect()),
+            Field::AtomicAggregate => rt.is_atomic_aggregate().to_string(),
+            Field::Aggregator => rt.get_aggregator().map_or(String::new(), |(asn, id)| format!("{} {}", asn, id)),
+            Field::Age => util::friendly_duration(rt.origin_time.elapsed().unwrap_or_default()),
+            Field::Originated => util::rfc3339(originated),
+            Field::OriginatedEpoch => originated.to_string(),
+            Field::Status => String::from(match status {
+                PathStatus::Best => "best",
+                PathStatus::Multipath => "multipath",
+                PathStatus::NotBest(_) => "not-best",
+            }),
+            Field::StatusCode => String::from(match status {
+                PathStatus::Best => ">",
+                PathStatus::Multipath => "m",
+                PathStatus::NotBest(_) => " ",
+            }),
+            Field::ActiveCode => String::from(if status.is_best() { "*" } else { " " }),
+            Field::InternalCode => String::from(if GETOPT.bestpath.local_as == Some(rt.peer.peer_as) { "i" } else { " " }),
+            Field::NotBestReason => match status {
+                PathStatus::NotBest(step) => step.to_string(),
+                _ => String::new(),
+            },
+            Field::InactiveReason => match status {
+                PathStatus::NotBest(step) => step.junos_reason(),
+                _ => String::new(),
+            },
+        })
+    }
+
+    // The field as the dialects highlight it, once padded
+    fn paint(&self, field: Field, text: String) -> String {
+        match field {
+            Field::Prefix | Field::Network => paint(Style::Prefix, &text),
+            Field::StatusCode | Field::ActiveCode => paint(Style::Best, &text),
+            Field::AsPath => paint_origin_as(&text, &self.route_entries[self.index]),
+            Field::Communities => paint(Style::Community, &text),
+            _ => text,
+        }
+    }
+
+    fn render(&self, template: &Template) -> String {
+        let mut output = String::new();
+        for part in &template.parts {
+            match part {
+                Part::Text(text) => output.push_str(text),
+                Part::Field(field, helpers) => {
+                    let mut value = self.value(*field);
+                    for helper in helpers {
+                        let text = match (value, helper) {
+                            (Value::List(list), Helper::Join(separator)) => list.join(separator),
+                            (Value::List(list), _) => list.join(" "),
+                            (Value::Text(text), _) => text,
+                        };
+                        value = Value::Text(match helper {
+                            Helper::Join(_) => text,
+                            Helper::Default(default) if text.is_empty() => default.clone(),
+                            Helper::Default(_) => text,
+                            Helper::Left(width) => format!("{:<width$}", text, width = width),
+                            Helper::Right(width) => format!("{:>width$}", text, width = width),
+                            Helper::Column(width, indent) => column(&text, *width, *indent),
+                            Helper::Before(before) if !text.is_empty() => format!("{}{}", before, text),
+                            Helper::After(after) if !text.is_empty() => format!("{}{}", text, after),
+                            Helper::Before(_) | Helper::After(_) => text,
+                            Helper::First if self.index > 0 => String::new(),
+                            Helper::First => text,
+                            Helper::Upper => text.to_uppercase(),
+                            Helper::Lower => text.to_lowercase(),
+                        });
+                    }
+                    let text = match value {
+                        Value::Text(text) => text,
+                        Value::List(list) => list.join(" "),
+                    };
+                    output.push_str(&self.paint(*field, text));
+                },
+            }
+        }
+        output
+    }
+}
+
+// The built-in template of the name, parsed once
+pub fn built_in(name: &str) -> &'static Template {
+    static TEMPLATES: OnceLock<Vec<(&str, Template)>> = OnceLock::new();
+    let templates = TEMPLATES.get_or_init(|| {
+        BUILT_IN_TEMPLATES.iter().map(|(name, text)| (*name, Template::from_str(text).unwrap())).collect()
+    });
+    templates.iter().find(|(x, _)| *x == name).map(|(_, template)| template).expect("no such built-in template")
+}
+
+pub fn template_show_route(prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    show_route_with(GETOPT.prefix_template.as_ref(), GETOPT.template.as_ref(), prefix, plen, route_entries);
+}
+
+// Show the route with a template for the prefix, and one for each path
+pub fn show_route_with(prefix_template: Option<&Template>, template: Option<&Template>,
+                       prefix: &IpAddr, plen: u8, route_entries: &[MrtRibEntry]) {
+    if route_entries.is_empty() {
+        return;
+    }
+    let status = GETOPT.bestpath.select(route_entries);
+    if let Some(template) = prefix_template {
+        let best = status.iter().position(|x| x.is_best()).unwrap_or(0);
+        outln!("{}", Context { prefix, plen, route_entries, status: &status, index: best }.render(template));
+    }
+    if let Some(template) = template {
+        for index in 0..route_entries.len() {
+            outln!("{}", Context { prefix, plen, route_entries, status: &status, index }.render(template));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, index: usize) -> String {
+        let paths = MrtRibEntry::test_paths();
+        let status = GETOPT.bestpath.select(&paths);
+        let prefix = IpAddr::from_str("10.0.0.0").unwrap();
+        let context = Context { prefix: &prefix, plen: 8, route_entries: &paths, status: &status, index };
+        context.render(&Template::from_str(template).unwrap())
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(render("{prefix} {network} {plen} {paths} {index} {peer} {peer_as} {router_id}", 0),
+                   "10.0.0.0/8 10.0.0.0 8 2 1 192.0.2.1 3356 192.0.2.1");
+        assert_eq!(render("{collector}|{view}|{path_id}|{nexthop}|{med}|{localpref}|{aspath}|{aspath_length}", 1),
+                   "192.0.2.254||0|192.0.2.3||200|1299 64500|2");
+        assert_eq!(render("{origin} {origin_char} {origin_as} {neighbor_as} {atomic_aggregate} {aggregator}", 1),
+                   "igp i 64500 1299 true 64500 198.51.100.1");
+        assert_eq!(render("{communities}", 0), "3356:123 65535:65281 rt:65000:100 3356:1:2");
+        assert_eq!(render("{originated} {originated_epoch}", 0), "2023-11-14T22:13:20Z 1700000000");
+        assert_eq!(render("{status}:{status_code}:{active_code}:{internal_code}:{not_best_reason}:{inactive_reason}", 1),
+                   "best:>:*: ::");
+        assert_eq!(render("{status}:{status_code}:{active_code}:{not_best_reason}:{inactive_reason}", 0),
+                   "not-best: : :Local Preference:Local Preference");
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(render("{communities|join:,}", 0), "3356:123,65535:65281,rt:65000:100,3356:1:2");
+        assert_eq!(render("{aspath|join}", 0), "3356 174 64500");
+        assert_eq!(render("{med|default:-} {localpref|default:-}", 0), "10 -");
+        assert_eq!(render("[{peer_as|left:6}][{peer_as|right:6}]", 0), "[3356  ][  3356]");
+        assert_eq!(render("{med|before:MED |after:, }{localpref|before:localpref |after:, }", 0), "MED 10, ");
+        assert_eq!(render("{prefix|first|default:-} {prefix|first|default:-}", 1), "- -");
+        assert_eq!(render("{origin|upper} {status|upper|lower}", 1), "IGP best");
+        // Helpers in order: padding counts the text before it
+        assert_eq!(render("[{med|before:MED |left:8}]", 0), "[MED 10  ]");
+        assert_eq!(render("[{med|left:8|before:MED }]", 0), "[MED 10      ]");
+        // A column wraps text that fills it
+        assert_eq!(render("{prefix|column:12:2}|", 0), "10.0.0.0/8  |");
+        assert_eq!(render("{prefix|column:10:2}|", 0), "10.0.0.0/8\n  |");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render("a\\tb\\nc\\\\d \\{peer\\} \\| {{peer}}", 0), "a\tb\nc\\d {peer} | {peer}");
+        assert_eq!(render("{med|before:\\|\\}}", 0), "|}10");
+        assert_eq!(render("{communities|join:\\|}", 0), "3356:123|65535:65281|rt:65000:100|3356:1:2");
+    }
+
+    #[test]
+    fn errors() {
+        for (template, error) in [("{peer", "unmatched { in template"),
+                                  ("peer}", "unmatched } in template"),
+                                  ("{peers}", "unknown template field peers"),
+                                  ("{peer|pad:3}", "unknown template helper pad"),
+                                  ("{peer|left}", "left needs a width"),
+                                  ("{peer|right:x}", "right width x not valid"),
+                                  ("{peer|default}", "default needs a value"),
+                                  ("{peer|column:17}", "column needs a width and indent"),
+                                  ("\\x", "unknown escape \\x in template"),
+                                  ("{peer}\\", "template ends with \\")] {
+            let e = Template::from_str(template).unwrap_err().to_string();
+            assert!(e.starts_with(error), "{}: {}", template, e);
+        }
+    }
+
+    #[test]
+    fn built_in() {
+        let paths = MrtRibEntry::test_paths();
+        let cisco = writer::test_output(|| cisco_show_ip_bgp(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &paths));
+        assert_eq!(cisco.lines().collect::<Vec<&str>>(), [
+            " *   10.0.0.0/8       192.0.2.1               10             0 3356 174 64500 i",
+            " *>                   192.0.2.3                     200      0 1299 64500 i",
+        ]);
+        // As --format cisco, and IOS, a prefix filling its column wraps
+        let prefix = IpAddr::from_str("2001:db8:1234::").unwrap();
+        let output = writer::test_output(|| show_route_with(None, Some(&Template::from_str("cisco").unwrap()), &prefix, 48, &paths[..1]));
+        assert_eq!(output.lines().collect::<Vec<&str>>(), [
+            " *>  2001:db8:1234::/48",
+            "                      192.0.2.1               10             0 3356 174 64500 i",
+        ]);
+
+        let paths = MrtRibEntry::test_paths();
+        let juniper = writer::test_output(|| juniper_show_route(&IpAddr::from_str("10.0.0.0").unwrap(), 8, &paths));
+        let age = util::friendly_duration(paths[0].origin_time.elapsed().unwrap_or_default());
+        assert_eq!(juniper.replace(&age, "AGE").lines().collect::<Vec<&str>>(), [
+            "10.0.0.0/8\t [BGP/170] AGE, MED 10, localpref 100, from 192.0.2.1",
+            "\t\t AS path: 3356 174 64500 I",
+            "\t\t Communities: 3356:123 65535:65281 rt:65000:100 3356:1:2",
+            "\t\t Inactive reason: Local Preference",
+            "\t\t> to 192.0.2.1",
+            "\t\t*[BGP/170] AGE, localpref 200, from 192.0.2.2",
+            "\t\t AS path: 1299 64500 I",
+            "\t\t> to 192.0.2.3",
+        ]);
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::IsTerminal;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::*;
+
+// Presentation on a terminal: the router dialects highlight the prefix,
+// the origin AS, the best path and the communities, and the results of
+// interactive queries go through a pager ($PAGER, or less) that shows
+// them directly when they fit the screen. Neither is used when the
+// output is piped or written to a file, with -N, or for the machine
+// readable dialects (which are paged but never coloured). NO_COLOR turns
+// off the colour alone.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Prefix,
+    OriginAs,
+    Best,
+    Community,
+}
+
+impl Style {
+    fn code(&self) -> &'static str {
+        match self {
+            Style::Prefix => "1",           // bold
+            Style::OriginAs => "1;33",      // bold yellow
+            Style::Best => "1;32",          // bold green
+            Style::Community => "36",       // cyan
+        }
+    }
+}
+
+// Whether the output is to a terminal, and not turned off with -N
+fn is_terminal() -> bool {
+    static TERMINAL: OnceLock<bool> = OnceLock::new();
+    *TERMINAL.get_or_init(|| {
+        // Tests compare the output as plain text, whatever runs them
+        !cfg!(test)
+            && !GETOPT.plain
+            && GETOPT.output.is_none()
+            && io::stdout().is_terminal()
+            && env::var("TERM").map_or(true, |x| x != "dumb")
+    })
+}
+
+fn is_coloured() -> bool {
+    static COLOURED: OnceLock<bool> = OnceLock::new();
+    *COLOURED.get_or_init(|| {
+        is_terminal()
+            && !GETOPT.dialect.is_machine_readable()
+            && env::var("NO_COLOR").map_or(true, |x| x.is_empty())
+    })
+}
+
+// The text in the style, on a terminal. Padding is applied before, as the
+// escape codes have no width.
+pub fn paint(style: Style, text: &str) -> String {
+    if is_coloured() && !text.trim().is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+// The AS path, with the origin AS highlighted
+pub fn paint_aspath(rt: &MrtRibEntry) -> String {
+    paint_origin_as(&rt.get_aspath(), rt)
+}
+
+// The AS path of the path, as text (perhaps padded), with the origin AS
+// highlighted
+pub fn paint_origin_as(text: &str, rt: &MrtRibEntry) -> String {
+    let aspath = text.trim_end();
+    match rt.get_origin_as().map(|x| x.to_string()) {
+        Some(origin) if is_coloured() && aspath.ends_with(&origin) => {
+            let (path, origin) = aspath.split_at(aspath.len() - origin.len());
+            format!("{}{}{}", path, paint(Style::OriginAs, origin), &text[aspath.len()..])
+        },
+        _ => text.to_string(),
+    }
+}
+
+// Whether the results of interactive queries are paged
+pub fn is_paged() -> bool {
+    is_terminal()
+}
+
+// Show the results of a query through the pager, or directly if there is
+// none to be had
+pub fn page(text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let pager = env::var("PAGER").unwrap_or_else(|_| String::from("less"));
+    let child = if pager.is_empty() {
+        None
+    } else {
+        // As git does: quit if one screen, pass colour, don't clear the screen
+        Command::new("sh")
+            .arg("-c")
+            .arg(&pager)
+            .env("LESS", env::var("LESS").unwrap_or_else(|_| String::from("FRX")))
+            .stdin(Stdio::piped())
+            .spawn()
+            .ok()
+    };
+    match child {
+        Some(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                // A pager quit early closes the pipe, which is no error
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            let _ = child.wait();
+        },
+        None => {
+            print!("{}", text);
+            let _ = io::stdout().flush();
+        },
+    }
+}
@@ -3,67 +3,97 @@ use std::marker::PhantomData;
 use std::mem::size_of;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-pub struct Trie<A> {
-    left: Option<Box<Trie<A>>>,
-    right: Option<Box<Trie<A>>>,
-    value: Option<Vec<MrtRibEntry>>,
+// An address family that can key a Trie: a fixed number of bits, which
+// are handled left-aligned in a u128 so that one implementation of the
+// trie walks serves both IPv4 and IPv6
+pub trait AddressBits: Copy {
+    const BITS: u8;
+
+    fn to_bits(self) -> u128;
+    fn from_bits(bits: u128) -> Self;
+
+    // Is bit n (0 being the most significant) of the address set?
+    fn bit(bits: u128, n: u8) -> bool {
+        bits & (1u128 << (127 - n)) != 0
+    }
+}
+
+impl AddressBits for Ipv4Addr {
+    const BITS: u8 = 32;
+
+    fn to_bits(self) -> u128 {
+        (u32::from(self) as u128) << 96
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Ipv4Addr::from((bits >> 96) as u32)
+    }
+}
+
+impl AddressBits for Ipv6Addr {
+    const BITS: u8 = 128;
+
+    fn to_bits(self) -> u128 {
+        u128::from(self)
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Ipv6Addr::from(bits)
+    }
+}
+
+// Binary trie of prefixes, one level per bit, holding any value type
+// against each prefix: routes, but also RPKI VRPs, IRR objects or
+// other metadata keyed by prefix
+pub struct Trie<A, V> {
+    left: Option<Box<Trie<A, V>>>,
+    right: Option<Box<Trie<A, V>>>,
+    value: Option<V>,
     phantom: PhantomData<A>,
 }
 
-impl Trie<Ipv4Addr>
-{
-    // pub fn walk<F: Fn(IpAddr, u8, &V)>(&self, address: u32, depth: u8, handler: &F) {
-    //     let trie: &Trie<Ipv4Addr, V> = self;
-    //
-    //     if let Some(value) = &trie.value {
-    //         println!("{}/{}: {}", Ipv4Addr::from(address), depth, value);
-    //         handler(IpAddr::V4(Ipv4Addr::from(address)), depth, value);
-    //     }
-    //     if let Some(left) = &trie.left {
-    //         left.walk(address, depth + 1, handler);
-    //     }
-    //     if let Some(right) = &trie.right {
-    //         let address =
-    //             address + (2_u32.pow((Trie::<Ipv4Addr, V>::max_depth() - (depth + 1)) as u32));
-    //         right.walk(address, depth + 1, handler);
-    //     }
-    // }
+impl<A: AddressBits, V> Trie<A, V> {
+    pub fn new() -> Self {
+        Trie {
+            left: None,
+            right: None,
+            value: None,
+            phantom: PhantomData,
+        }
+    }
+
     // Return the maximum bit depth of the trie
-    pub fn add(&mut self, ip: &Ipv4Addr, depth: u8, mut value: Vec<MrtRibEntry>) {
-        let mut trie: &mut Trie<Ipv4Addr> = self;
-        let address: u32 = (*ip).into();
+    pub fn max_depth() -> u8 {
+        A::BITS
+    }
 
-        for d in 0..depth {
-            trie = match address & 2_u32.pow((Trie::<Ipv4Addr>::max_depth() - (d + 1)) as u32) {
-                0 => match trie.left {
-                    Some(ref mut t) => t,
-                    None => {
-                        trie.left = Some(Box::new(Trie::new()));
-                        trie.left.as_mut().unwrap()
-                    }
-                },
-                _ => match trie.right {
-                    Some(ref mut t) => t,
-                    None => {
-                        trie.right = Some(Box::new(Trie::new()));
-                        trie.right.as_mut().unwrap()
-                    }
-                },
-            };
-        }
-        if let Some(current) = &mut trie.value {
-            current.append(&mut value);
-        } else {
-            trie.value = Some(value);
+    // Return the value for the prefix, inserting one made by the
+    // given function if there isn't one yet
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, ip: &A, depth: u8, default: F) -> &mut V {
+        let address = ip.to_bits();
+        let mut trie: &mut Trie<A, V> = self;
+
+        for d in 0..depth.min(A::BITS) {
+            let branch = if A::bit(address, d) { &mut trie.right } else { &mut trie.left };
+            trie = branch.get_or_insert_with(|| Box::new(Trie::new()));
         }
-        // trie.value = Some(value);
+        trie.value.get_or_insert_with(default)
+    }
+
+    // Set the value for the prefix, returning any previous value
+    pub fn insert(&mut self, ip: &A, depth: u8, value: V) -> Option<V> {
+        let mut value = Some(value);
+        let slot = self.get_or_insert_with(ip, depth, || value.take().unwrap());
+        value.map(|value| std::mem::replace(slot, value))
     }
 
-    pub fn get(&self, ip: &Ipv4Addr, depth: u8) -> Option<(Ipv4Addr, u8, &Vec<MrtRibEntry>)> {
-        let address: u32 = (*ip).into();
-        let mut trie: &Trie<Ipv4Addr> = self;
-        let mut best: Option<(Ipv4Addr, u8, &Vec<MrtRibEntry>)> = None;
-        let mut current: u32 = 0;
+    // Longest prefix match of the address, considering prefixes no
+    // longer than depth
+    pub fn get(&self, ip: &A, depth: u8) -> Option<(A, u8, &V)> {
+        let address = ip.to_bits();
+        let mut trie: &Trie<A, V> = self;
+        let mut best: Option<(A, u8, &V)> = None;
+        let mut current: u128 = 0;
 
         let mut d: u8 = 0;
 
@@ -71,129 +101,273 @@ impl Trie<Ipv4Addr>
             // If the current position in the trie has an associated value,
             // record it as the current best candidate
             if let Some(v) = &trie.value {
-                best = Some((Ipv4Addr::from(current), d, v))
-                // best = Some(v)
+                best = Some((A::from_bits(current), d, v))
             }
 
-            if d == depth || d == Trie::<Ipv4Addr>::max_depth() {
+            if d == depth || d == A::BITS {
                 break;
             }
 
             // Then choose the next direction, updating the effective
             // address for that branch
-            trie = match address & 2_u32.pow((Trie::<Ipv4Addr>::max_depth() - (d + 1)) as u32) {
-                0 => match trie.left {
-                    Some(ref t) => t,
-                    None => break,
-                },
-                _ => match trie.right {
+            trie = if A::bit(address, d) {
+                match trie.right {
                     Some(ref t) => {
-                        current |= address
-                            & 2_u32.pow((Trie::<Ipv4Addr>::max_depth() - (d + 1)) as u32);
+                        current |= 1u128 << (127 - d);
                         t
                     }
                     None => break,
-                },
+                }
+            } else {
+                match trie.left {
+                    Some(ref t) => t,
+                    None => break,
+                }
             };
             d += 1;
         }
         best
     }
-}
 
-impl Trie<Ipv6Addr>
-{
+    // Exact match of the prefix
+    pub fn get_exact(&self, ip: &A, depth: u8) -> Option<&V> {
+        let address = ip.to_bits();
+        let mut trie: &Trie<A, V> = self;
 
-    pub fn add(&mut self, ip: &Ipv6Addr, depth: u8, mut value: Vec<MrtRibEntry>) {
-        let mut trie: &mut Trie<Ipv6Addr> = self;
-        let address: u128 = (*ip).into();
+        for d in 0..depth.min(A::BITS) {
+            let branch = if A::bit(address, d) { &trie.right } else { &trie.left };
+            trie = branch.as_ref()?;
+        }
+        trie.value.as_ref()
+    }
 
-        for d in 0..depth {
-            trie = match address & 2_u128.pow((Trie::<Ipv6Addr>::max_depth() - (d + 1)) as u32) {
-                0 => match trie.left {
-                    Some(ref mut t) => t,
-                    None => {
-                        trie.left = Some(Box::new(Trie::new()));
-                        trie.left.as_mut().unwrap()
-                    }
-                },
-                _ => match trie.right {
-                    Some(ref mut t) => t,
-                    None => {
-                        trie.right = Some(Box::new(Trie::new()));
-                        trie.right.as_mut().unwrap()
-                    }
-                },
-            };
+    pub fn get_exact_mut(&mut self, ip: &A, depth: u8) -> Option<&mut V> {
+        let address = ip.to_bits();
+        let mut trie: &mut Trie<A, V> = self;
+
+        for d in 0..depth.min(A::BITS) {
+            let branch = if A::bit(address, d) { &mut trie.right } else { &mut trie.left };
+            trie = branch.as_mut()?;
         }
-        if let Some(current) = &mut trie.value {
-            current.append(&mut value);
-        } else {
-            trie.value = Some(value);
+        trie.value.as_mut()
+    }
+
+    // Remove the prefix, returning its value, and prune the nodes
+    // left without a value or children
+    pub fn remove(&mut self, ip: &A, depth: u8) -> Option<V> {
+        self.remove_at(ip.to_bits(), 0, depth.min(A::BITS))
+    }
+
+    fn remove_at(&mut self, address: u128, d: u8, depth: u8) -> Option<V> {
+        if d == depth {
+            return self.value.take();
         }
+        let branch = if A::bit(address, d) { &mut self.right } else { &mut self.left };
+        let child = branch.as_mut()?;
+        let value = child.remove_at(address, d + 1, depth);
+        if child.value.is_none() && child.left.is_none() && child.right.is_none() {
+            *branch = None;
+        }
+        value
     }
 
-    pub fn get(&self, ip: &Ipv6Addr, depth: u8) -> Option<(Ipv6Addr, u8, &Vec<MrtRibEntry>)> {
-        let address: u128 = (*ip).into();
-        let mut trie: &Trie<Ipv6Addr> = self;
-        let mut best: Option<(Ipv6Addr, u8, &Vec<MrtRibEntry>)> = None;
-        let mut current: u128 = 0;
+    // All prefixes in the trie, in prefix order: each prefix is followed
+    // by its more-specifics, lower addresses first
+    pub fn iter(&self) -> Iter<'_, A, V> {
+        Iter::new(self, 0, 0)
+    }
 
-        let mut d: u8 = 0;
+    // Prefixes equal to or more specific than the given one, in
+    // prefix order
+    pub fn more_specifics(&self, ip: &A, depth: u8) -> Iter<'_, A, V> {
+        let depth = depth.min(A::BITS);
+        let address = ip.to_bits() & mask(depth);
+        let mut trie: &Trie<A, V> = self;
 
-        loop {
-            // If the current position in the trie has an associated value,
-            // record it as the current best candidate
-            if let Some(v) = &trie.value {
-                best = Some((Ipv6Addr::from(current), d, v))
+        for d in 0..depth {
+            let branch = if A::bit(address, d) { &trie.right } else { &trie.left };
+            match branch {
+                Some(t) => trie = t,
+                None => return Iter::empty(),
             }
+        }
+        Iter::new(trie, address, depth)
+    }
+
+    // Prefixes equal to or less specific than (covering) the given one,
+    // shortest first
+    pub fn less_specifics(&self, ip: &A, depth: u8) -> std::vec::IntoIter<(A, u8, &V)> {
+        let depth = depth.min(A::BITS);
+        let address = ip.to_bits();
+        let mut trie: &Trie<A, V> = self;
+        let mut covering = vec![];
 
-            if d == depth || d == Trie::<Ipv6Addr>::max_depth() {
+        for d in 0..=depth {
+            if let Some(v) = &trie.value {
+                covering.push((A::from_bits(address & mask(d)), d, v));
+            }
+            if d == depth {
                 break;
             }
-
-            // Then choose the next direction, updating the effective
-            // address for that branch
-            trie = match address & 2_u128.pow((Trie::<Ipv6Addr>::max_depth() - (d + 1)) as u32) {
-                0 => match trie.left {
-                    Some(ref t) => t,
-                    None => break,
-                },
-                _ => match trie.right {
-                    Some(ref t) => {
-                        current |= address
-                            & 2_u128.pow((Trie::<Ipv6Addr>::max_depth() - (d + 1)) as u32);
-                        t
-                    }
-                    None => break,
-                },
-            };
-            d += 1;
+            let branch = if A::bit(address, d) { &trie.right } else { &trie.left };
+            match branch {
+                Some(t) => trie = t,
+                None => break,
+            }
         }
-        best
+        covering.into_iter()
+    }
+
+    // Number of prefixes with a value
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    // Number of nodes, and the memory they occupy (not counting any
+    // heap memory owned by the values themselves)
+    pub fn node_count(&self) -> usize {
+        1 + self.left.as_ref().map_or(0, |x| x.node_count())
+            + self.right.as_ref().map_or(0, |x| x.node_count())
+    }
+
+    pub fn memory(&self) -> usize {
+        self.node_count() * size_of::<Trie<A, V>>()
     }
 }
 
-impl<A> Trie<A> {
-    pub fn new() -> Self {
-        Trie {
-            left: None,
-            right: None,
-            value: None,
-            phantom: PhantomData,
+// Left-aligned network mask for a prefix length
+fn mask(depth: u8) -> u128 {
+    if depth == 0 { 0 } else { u128::MAX << (128 - depth as u32) }
+}
+
+// Depth-first, pre-order walk of a (sub-)trie
+pub struct Iter<'a, A, V> {
+    stack: Vec<(&'a Trie<A, V>, u128, u8)>,
+}
+
+impl<'a, A: AddressBits, V> Iter<'a, A, V> {
+    fn new(trie: &'a Trie<A, V>, address: u128, depth: u8) -> Self {
+        Iter { stack: vec![(trie, address, depth)] }
+    }
+
+    fn empty() -> Self {
+        Iter { stack: vec![] }
+    }
+}
+
+impl<'a, A: AddressBits, V> Iterator for Iter<'a, A, V> {
+    type Item = (A, u8, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((trie, address, d)) = self.stack.pop() {
+            // Right pushed first so that the left is visited first
+            if let Some(right) = &trie.right {
+                self.stack.push((right, address | (1u128 << (127 - d)), d + 1));
+            }
+            if let Some(left) = &trie.left {
+                self.stack.push((left, address, d + 1));
+            }
+            if let Some(v) = &trie.value {
+                return Some((A::from_bits(address), d, v));
+            }
         }
+        None
     }
-    // Return the maximum bit depth of the trie
-    pub fn max_depth() -> u8 {
-        size_of::<A>() as u8 * 8
+}
+
+// Tries of lists (eg. the paths for a route) accumulate values
+// added against the same prefix
+impl<A: AddressBits, T> Trie<A, Vec<T>> {
+    pub fn add(&mut self, ip: &A, depth: u8, mut value: Vec<T>) {
+        self.get_or_insert_with(ip, depth, Vec::new).append(&mut value);
+    }
+}
+
+impl<A: AddressBits, V> Default for Trie<A, V> {
+    fn default() -> Self {
+        Trie::new()
     }
 }
 
 use std::fmt;
-use crate::rib::MrtRibEntry;
 
-impl<A> fmt::Display for Trie<A> {
+impl<A, V> fmt::Display for Trie<A, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Trie<{}>", type_name::<A>())
+        write!(f, "Trie<{}, {}>", type_name::<A>(), type_name::<V>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn lookups() {
+        let mut trie: Trie<Ipv4Addr, &str> = Trie::new();
+        assert_eq!(trie.insert(&v4("10.0.0.0"), 8, "10/8"), None);
+        trie.insert(&v4("10.1.0.0"), 16, "10.1/16");
+        trie.insert(&v4("10.1.2.0"), 24, "10.1.2/24");
+        trie.insert(&v4("10.2.0.0"), 16, "10.2/16");
+        assert_eq!(trie.insert(&v4("10.2.0.0"), 16, "10.2/16 again"), Some("10.2/16"));
+
+        assert_eq!(trie.get(&v4("10.1.2.3"), 32).map(|x| (x.0, x.1, *x.2)), Some((v4("10.1.2.0"), 24, "10.1.2/24")));
+        assert_eq!(trie.get(&v4("10.1.2.3"), 20).map(|x| *x.2), Some("10.1/16"));
+        assert_eq!(trie.get(&v4("10.3.0.0"), 32).map(|x| *x.2), Some("10/8"));
+        assert_eq!(trie.get(&v4("11.0.0.0"), 32), None);
+        assert_eq!(trie.get_exact(&v4("10.1.0.0"), 16), Some(&"10.1/16"));
+        assert_eq!(trie.get_exact(&v4("10.1.0.0"), 17), None);
+        assert_eq!(trie.get_exact(&v4("10.0.0.0"), 0), None);
+        *trie.get_exact_mut(&v4("10.1.0.0"), 16).unwrap() = "10.1/16 changed";
+        assert_eq!(trie.get_exact(&v4("10.1.0.0"), 16), Some(&"10.1/16 changed"));
+
+        assert_eq!(trie.iter().map(|x| *x.2).collect::<Vec<&str>>(),
+                   ["10/8", "10.1/16 changed", "10.1.2/24", "10.2/16 again"]);
+        assert_eq!(trie.more_specifics(&v4("10.1.0.0"), 16).map(|x| *x.2).collect::<Vec<&str>>(),
+                   ["10.1/16 changed", "10.1.2/24"]);
+        assert_eq!(trie.more_specifics(&v4("10.3.0.0"), 16).count(), 0);
+        assert_eq!(trie.less_specifics(&v4("10.1.2.0"), 24).map(|x| (x.0, x.1)).collect::<Vec<(Ipv4Addr, u8)>>(),
+                   [(v4("10.0.0.0"), 8), (v4("10.1.0.0"), 16), (v4("10.1.2.0"), 24)]);
+        assert_eq!(trie.len(), 4);
+    }
+
+    #[test]
+    fn ipv6() {
+        let mut trie: Trie<Ipv6Addr, u32> = Trie::new();
+        let prefix: Ipv6Addr = "2001:db8::".parse().unwrap();
+        trie.insert(&prefix, 32, 32);
+        trie.insert(&"2001:db8:1::".parse().unwrap(), 48, 48);
+        trie.insert(&Ipv6Addr::UNSPECIFIED, 0, 0);
+        assert_eq!(trie.get(&"2001:db8:1::1".parse().unwrap(), 128).map(|x| (x.1, *x.2)), Some((48, 48)));
+        assert_eq!(trie.get(&"2001:db8:2::1".parse().unwrap(), 128).map(|x| (x.0, x.1)), Some((prefix, 32)));
+        assert_eq!(trie.get(&"2001:db9::".parse().unwrap(), 128).map(|x| x.1), Some(0));
+        assert_eq!(Trie::<Ipv6Addr, u32>::max_depth(), 128);
+    }
+
+    #[test]
+    fn remove_prunes_nodes() {
+        let mut trie: Trie<Ipv4Addr, Vec<u32>> = Trie::new();
+        trie.add(&v4("10.1.2.0"), 24, vec![1]);
+        trie.add(&v4("10.1.2.0"), 24, vec![2]);
+        trie.add(&v4("10.1.0.0"), 16, vec![3]);
+        assert_eq!(trie.get_exact(&v4("10.1.2.0"), 24), Some(&vec![1, 2]));
+        assert_eq!(trie.node_count(), 25);
+
+        // The /24 goes with the nodes below the /16, which is kept
+        assert_eq!(trie.remove(&v4("10.1.2.0"), 24), Some(vec![1, 2]));
+        assert_eq!(trie.remove(&v4("10.1.2.0"), 24), None);
+        assert_eq!(trie.node_count(), 17);
+        assert_eq!(trie.get(&v4("10.1.2.3"), 32).map(|x| x.1), Some(16));
+
+        assert_eq!(trie.remove(&v4("10.1.0.0"), 16), Some(vec![3]));
+        assert_eq!(trie.node_count(), 1);
+        assert!(trie.is_empty());
     }
 }
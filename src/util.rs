@@ -15,6 +15,26 @@ pub fn friendly_duration(remaining: Duration) -> String {
     }
 }
 
+// RFC 3339 UTC timestamp, eg. 2023-11-14T22:13:20Z
+pub fn rfc3339(secs: u64) -> String {
+    let time = OffsetDateTime::from_unix_timestamp(secs as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            time.year(), time.month() as u8, time.day(), time.hour(), time.minute(), time.second())
+}
+
+// ctime(3) style UTC timestamp, eg. Tue Nov 14 22:13:20 2023
+pub fn ctime(secs: u64) -> String {
+    let time = OffsetDateTime::from_unix_timestamp(secs as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    format!("{:.3} {:.3} {:2} {:02}:{:02}:{:02} {}",
+            time.weekday().to_string(), time.month().to_string(), time.day(),
+            time.hour(), time.minute(), time.second(), time.year())
+}
+
+// Seconds since the Unix epoch
+pub fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
 pub fn trim_newline(s: &mut String) {
     if s.ends_with('\n') {
         s.pop();
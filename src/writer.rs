@@ -0,0 +1,341 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Mutex;
+
+use crate::*;
+
+// Where the output goes: buffered to stdout, or to the file given with -o,
+// optionally split into one file per peer or per collector (-x) and
+// rotated when a file reaches a size (-R). Diagnostics go to stderr,
+// never through here.
+//
+//   -o routes.txt -x peer        routes-192.0.2.1.txt, routes-2001_db8__3.txt...
+//   -o routes.txt -R 100M        routes.txt, routes.txt.1, routes.txt.2...
+//
+// With -x, the routes of each peer (or collector) go to its file, and
+// anything else (headings, summaries) to the -o file itself.
+
+// Write a line (or part of one) of output, in place of println! (print!)
+macro_rules! outln {
+    () => { $crate::writer::write(format_args!("\n")) };
+    ($($arg:tt)*) => { $crate::writer::write(format_args!("{}\n", format_args!($($arg)*))) };
+}
+
+macro_rules! out {
+    ($($arg:tt)*) => { $crate::writer::write(format_args!($($arg)*)) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    None,
+    Peer,
+    Collector,
+}
+
+impl FromStr for Split {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Split, Self::Err> {
+        match s {
+            "peer" => Ok(Split::Peer),
+            "collector" => Ok(Split::Collector),
+            _ => Err(anyhow!("split {} not valid (peer or collector)", s)),
+        }
+    }
+}
+
+// A size in bytes, with an optional K, M or G suffix
+pub fn parse_size(s: &str) -> Result<u64> {
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(size) if size > 0 => Ok(size * multiplier),
+        _ => Err(anyhow!("size {} not valid", s)),
+    }
+}
+
+struct Destination {
+    path: Option<String>,       // None for stdout
+    writer: BufWriter<Box<dyn Write + Send>>,
+    written: u64,       // bytes, since the file was opened
+    rotation: u32,      // number of the current file, 0 for the first
+}
+
+impl Destination {
+    fn stdout() -> Destination {
+        Destination {
+            path: None,
+            writer: BufWriter::new(Box::new(io::stdout())),
+            written: 0,
+            rotation: 0,
+        }
+    }
+
+    fn create(path: &str) -> Result<Destination> {
+        let file = File::create(path).with_context(|| format!("cannot create {}", path))?;
+        Ok(Destination {
+            path: Some(path.to_string()),
+            writer: BufWriter::new(Box::new(file)),
+            written: 0,
+            rotation: 0,
+        })
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.written += text.len() as u64;
+        self.writer.write_all(text.as_bytes())
+    }
+
+    // Start the next file, when this one has reached the size for rotation
+    fn rotate(&mut self, size: Option<u64>) -> Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Ok(());
+        };
+        if size.is_none_or(|size| self.written < size) {
+            return Ok(());
+        }
+        self.writer.flush()?;
+        let rotation = self.rotation + 1;
+        *self = Destination::create(&format!("{}.{}", path, rotation))?;
+        self.path = Some(path);
+        self.rotation = rotation;
+        Ok(())
+    }
+}
+
+struct Output {
+    default: Destination,
+    split: HashMap<String, Destination>,
+    current: Option<String>,    // the key of the split file written to
+    capture: Option<String>,    // output held back, to be paged
+}
+
+impl Output {
+    fn destination(&mut self) -> &mut Destination {
+        match &self.current {
+            Some(key) => self.split.get_mut(key).unwrap_or(&mut self.default),
+            None => &mut self.default,
+        }
+    }
+}
+
+static OUTPUT: Mutex<Option<Output>> = Mutex::new(None);
+
+// Open the output, before the first of it is written
+pub fn open() -> Result<()> {
+    let default = match &GETOPT.output {
+        Some(path) => Destination::create(path)?,
+        None => Destination::stdout(),
+    };
+    *OUTPUT.lock().unwrap() = Some(Output { default, split: HashMap::new(), current: None, capture: None });
+    Ok(())
+}
+
+// The file name for a split: routes.txt for 192.0.2.1 is routes-192.0.2.1.txt
+fn split_path(path: &str, key: &str) -> String {
+    let key = key.replace(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-', "_");
+    let name_start = path.rfind('/').map_or(0, |x| x + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => format!("{}-{}{}", &path[..name_start + dot], key, &path[name_start + dot..]),
+        _ => format!("{}-{}", path, key),
+    }
+}
+
+// Write to a peer's (or collector's) file from now on, or with None, the
+// output itself. A file due for rotation is rotated here, between routes.
+pub fn select(key: Option<String>) {
+    let mut output = OUTPUT.lock().unwrap();
+    let Some(output) = output.as_mut() else {
+        return;
+    };
+    if let Some(key) = &key {
+        if !output.split.contains_key(key) {
+            let path = split_path(output.default.path.as_deref().unwrap_or_default(), key);
+            match Destination::create(&path) {
+                Ok(destination) => { output.split.insert(key.clone(), destination); },
+                Err(e) => failed(e),
+            }
+        }
+    }
+    output.current = key;
+    if let Err(e) = output.destination().rotate(GETOPT.rotate) {
+        failed(e);
+    }
+}
+
+// The file the paths of a peer go to, when splitting
+pub fn split_key(peer: &MrtPeer) -> Option<String> {
+    split_key_by(GETOPT.split, peer)
+}
+
+fn split_key_by(split: Split, peer: &MrtPeer) -> Option<String> {
+    match split {
+        Split::None => None,
+        Split::Peer => Some(peer.peer_address.to_string()),
+        Split::Collector if peer.view_name.is_empty() => Some(peer.collector_id.to_string()),
+        Split::Collector => Some(format!("{}-{}", peer.collector_id, peer.view_name)),
+    }
+}
+
+// The paths of a route by the file each goes to, in the order first seen
+pub fn split_paths(route_entries: &[MrtRibEntry]) -> Vec<(Option<String>, Vec<MrtRibEntry>)> {
+    let mut groups: Vec<(Option<String>, Vec<MrtRibEntry>)> = vec![];
+    for rt in route_entries {
+        let key = split_key(&rt.peer);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(rt.clone()),
+            None => groups.push((key, vec![rt.clone()])),
+        }
+    }
+    groups
+}
+
+// Whether nothing has been written yet to the file being written to (so
+// that a table heading is repeated at the start of each file)
+pub fn at_start() -> bool {
+    let mut output = OUTPUT.lock().unwrap();
+    output.as_mut().is_some_and(|output| {
+        let destination = output.destination();
+        destination.path.is_some() && destination.written == 0
+    })
+}
+
+// Hold back the output from now on, until it's taken with release()
+pub fn capture() {
+    if let Some(output) = OUTPUT.lock().unwrap().as_mut() {
+        output.capture = Some(String::new());
+    }
+}
+
+pub fn release() -> String {
+    OUTPUT.lock().unwrap().as_mut().and_then(|x| x.capture.take()).unwrap_or_default()
+}
+
+// The output written by the function on this thread, for tests, which
+// run on several threads at once
+#[cfg(test)]
+thread_local! {
+    static TEST_OUTPUT: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(test)]
+pub fn test_output<F: FnOnce()>(f: F) -> String {
+    TEST_OUTPUT.set(Some(String::new()));
+    f();
+    TEST_OUTPUT.take().unwrap_or_default()
+}
+
+pub fn write(args: std::fmt::Arguments) {
+    #[cfg(test)]
+    if TEST_OUTPUT.with_borrow_mut(|x| x.as_mut().map(|x| std::fmt::Write::write_fmt(x, args))).is_some() {
+        return;
+    }
+    let mut output = OUTPUT.lock().unwrap();
+    let result = match output.as_mut() {
+        Some(Output { capture: Some(capture), .. }) => std::fmt::Write::write_fmt(capture, args).map_err(io::Error::other),
+        Some(output) => output.destination().write(&args.to_string()),
+        None => io::stdout().write_fmt(args),
+    };
+    if let Err(e) = result {
+        failed(e.into());
+    }
+}
+
+pub fn flush() {
+    let mut output = OUTPUT.lock().unwrap();
+    if let Some(output) = output.as_mut() {
+        for destination in std::iter::once(&mut output.default).chain(output.split.values_mut()) {
+            if let Err(e) = destination.writer.flush() {
+                failed(e.into());
+            }
+        }
+    }
+}
+
+// The output can't be written: a closed pipe (such as to head) ends the
+// program quietly, as it would a filter
+fn failed(e: anyhow::Error) -> ! {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == ErrorKind::BrokenPipe => process::exit(0),
+        _ => {
+            eprintln!("Error writing output: {:#}", e);
+            process::exit(1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(address: &str, view_name: &str) -> MrtPeer {
+        MrtPeer { view_name: view_name.to_string(), ..(*MrtRibEntry::test_path(address, 64500, &[64500], vec![]).peer).clone() }
+    }
+
+    #[test]
+    fn split_naming() {
+        assert_eq!(split_path("routes.txt", "192.0.2.1"), "routes-192.0.2.1.txt");
+        assert_eq!(split_path("routes.txt", "2001:db8::3"), "routes-2001_db8__3.txt");
+        assert_eq!(split_path("out/routes", "192.0.2.1"), "out/routes-192.0.2.1");
+        assert_eq!(split_path("out.d/routes", "192.0.2.1"), "out.d/routes-192.0.2.1");
+        assert_eq!(split_path(".routes", "192.0.2.1"), ".routes-192.0.2.1");
+        assert_eq!(split_path("routes.txt", "192.0.2.254-view 1/a"), "routes-192.0.2.254-view_1_a.txt");
+    }
+
+    #[test]
+    fn split_keys() {
+        let (peer, viewed) = (peer("2001:db8::3", ""), peer("192.0.2.1", "view1"));
+        assert_eq!(split_key_by(Split::None, &peer), None);
+        assert_eq!(split_key_by(Split::Peer, &peer).as_deref(), Some("2001:db8::3"));
+        assert_eq!(split_key_by(Split::Peer, &viewed).as_deref(), Some("192.0.2.1"));
+        assert_eq!(split_key_by(Split::Collector, &peer).as_deref(), Some("192.0.2.254"));
+        assert_eq!(split_key_by(Split::Collector, &viewed).as_deref(), Some("192.0.2.254-view1"));
+        // Without -x, every path goes to the output itself
+        let groups = split_paths(&MrtRibEntry::test_paths());
+        assert_eq!(groups.iter().map(|(key, paths)| (key.clone(), paths.len())).collect::<Vec<_>>(), [(None, 2)]);
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(Split::from_str("peer").unwrap(), Split::Peer);
+        assert_eq!(Split::from_str("collector").unwrap(), Split::Collector);
+        assert_eq!(Split::from_str("view").unwrap_err().to_string(), "split view not valid (peer or collector)");
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("100k").unwrap(), 100 << 10);
+        assert_eq!(parse_size("100M").unwrap(), 100 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        for size in ["0", "M", "-1K", "1T", ""] {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let directory = std::env::temp_dir().join(format!("mrtdump-writer-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("routes.txt").to_string_lossy().to_string();
+        let mut destination = Destination::create(&path).unwrap();
+        // Only between writes, once a file has reached the size
+        for line in 1..=5 {
+            destination.rotate(Some(20)).unwrap();
+            destination.write(&format!("line {}...\n", line)).unwrap();
+        }
+        destination.writer.flush().unwrap();
+        assert_eq!((destination.rotation, destination.path.as_deref()), (2, Some(path.as_str())));
+        let read = |suffix: &str| std::fs::read_to_string(format!("{}{}", path, suffix)).unwrap();
+        assert_eq!(read(""), "line 1...\nline 2...\n");
+        assert_eq!(read(".1"), "line 3...\nline 4...\n");
+        assert_eq!(read(".2"), "line 5...\n");
+        // Without -R, never
+        let mut destination = Destination::create(&path).unwrap();
+        for _ in 0..3 {
+            destination.rotate(None).unwrap();
+            destination.write("line 1...\n").unwrap();
+        }
+        assert_eq!((destination.rotation, destination.written), (0, 30));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}